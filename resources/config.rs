@@ -0,0 +1,28 @@
+//! Config Resource
+//!
+//! Shows the telemetry configuration actually in effect after merging
+//! yeti-config.yaml, environment sections, and OTEL_* env vars.
+
+use yeti_core::prelude::*;
+
+use crate::telemetry::shared;
+
+/// Type alias required by compiler (filename → type name mapping)
+pub type Config = ConfigResource;
+
+#[derive(Default)]
+pub struct ConfigResource;
+
+impl Resource for ConfigResource {
+    fn name(&self) -> &str {
+        "config"
+    }
+
+    /// GET /yeti-telemetry/config — resolved configuration, secrets shown as `***`
+    get!(_req, _ctx, {
+        match shared().effective_config.load_full() {
+            Some(config) => ok((*config).clone()),
+            None => not_found("telemetry writer not configured yet"),
+        }
+    });
+}
@@ -0,0 +1,238 @@
+//! Export Resource
+//!
+//! Downloads the Log, Span or Metric records of a time range as
+//! newline-delimited JSON, for offline analysis.
+
+use std::io::Write;
+use std::sync::Arc;
+
+use yeti_core::prelude::*;
+
+use crate::telemetry::{
+    max_future_skew_secs, now_epoch_ms, parse_param, record_key_at, record_time_secs, scan_records, shared,
+};
+
+/// Type alias required by compiler (filename → type name mapping)
+pub type Export = ExportResource;
+
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// Width of the first key-range scan, in ms of record id time.
+const PAGE_MS: f64 = 60_000.0;
+
+/// Widest a page grows across empty stretches, so a page that lands on a
+/// busy stretch after a sparse one never decodes more than ~an hour of
+/// records at once.
+const MAX_PAGE_MS: f64 = PAGE_MS * 64.0;
+
+#[derive(Default)]
+pub struct ExportResource;
+
+impl Resource for ExportResource {
+    fn name(&self) -> &str {
+        "export"
+    }
+
+    /// GET /yeti-telemetry/export?kind=log|span|metric&since=...&until=...
+    ///
+    /// One record per line, in id (write) order. `since` is required and,
+    /// like `until`, is epoch seconds (inclusive/exclusive). `kind`
+    /// defaults to `log`. The body is streamed a page at a time, gzipped
+    /// when the client accepts it.
+    get!(req, _ctx, {
+        let since = match parse_param(req.query("since")) {
+            Ok(Some(v)) => v,
+            Ok(None) => return bad_request("since is required"),
+            Err(()) => return bad_request("since must be epoch seconds"),
+        };
+        let until = match parse_param(req.query("until")) {
+            Ok(v) => v.unwrap_or(f64::MAX),
+            Err(()) => return bad_request("until must be epoch seconds"),
+        };
+        let kind = req.query("kind").filter(|k| !k.is_empty()).unwrap_or("log");
+        let tables = shared().tables.get();
+        let storages: Vec<_> = match kind {
+            "log" => tables.map(|t| t.log_tables(None)).unwrap_or_default(),
+            "span" => tables.and_then(|t| t.span.clone()).into_iter().collect(),
+            "metric" => tables.and_then(|t| t.metric.clone()).into_iter().collect(),
+            _ => return bad_request("kind must be one of log, span, metric"),
+        };
+        if storages.is_empty() {
+            return not_found(&format!("{} table not available", kind));
+        }
+
+        let gzip = accepts_gzip(req.header("accept-encoding"));
+        let pages = ExportPages {
+            storages,
+            since,
+            until,
+            page_start: (since - max_future_skew_secs()) * 1000.0,
+            end: now_epoch_ms() + 1.0,
+            width: PAGE_MS,
+            gzip: gzip.then(|| flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default())),
+        };
+        let body = futures::stream::unfold(pages, |mut pages| async move {
+            let chunk = pages.next_chunk().await?;
+            Some((chunk, pages))
+        });
+        let headers: &[(&str, &str)] = if gzip {
+            &[("Content-Encoding", "gzip"), ("Vary", "Accept-Encoding")]
+        } else {
+            &[("Vary", "Accept-Encoding")]
+        };
+        ok_stream(NDJSON_CONTENT_TYPE, headers, body)
+    });
+}
+
+/// Position of an export in the tables it reads.
+///
+/// Ids carry their write time, which is never earlier than the timestamp
+/// minus the allowed skew and never later than the request. That span is
+/// scanned one page at a time, so only a page of decoded records is held at
+/// once; pages widen across empty stretches, up to [`MAX_PAGE_MS`].
+struct ExportPages {
+    storages: Vec<Arc<dyn KvBackend>>,
+    since: f64,
+    until: f64,
+    page_start: f64,
+    end: f64,
+    width: f64,
+    /// Present when the response is gzipped; finished after the last page.
+    gzip: Option<flate2::write::GzEncoder<Vec<u8>>>,
+}
+
+impl ExportPages {
+    /// The next non-empty piece of the body, or `None` once it is complete.
+    /// A failed scan ends the body with an error, so the client sees a
+    /// broken transfer rather than a silently short export.
+    async fn next_chunk(&mut self) -> Option<std::io::Result<Vec<u8>>> {
+        while self.page_start < self.end {
+            let page_end = (self.page_start + self.width).min(self.end);
+            let mut records = Vec::new();
+            for storage in &self.storages {
+                let start = record_key_at(self.page_start);
+                match scan_records(storage, start.as_bytes(), record_key_at(page_end).as_bytes()).await {
+                    Ok(page) => records.extend(page),
+                    Err(e) => {
+                        self.end = self.page_start;
+                        self.gzip = None;
+                        return Some(Err(std::io::Error::other(e.to_string())));
+                    }
+                }
+            }
+            // Logs split by `severityTables` are merged back into id order.
+            if self.storages.len() > 1 {
+                records.sort_by(|a, b| a["id"].as_str().cmp(&b["id"].as_str()));
+            }
+            self.width = if records.is_empty() { (self.width * 2.0).min(MAX_PAGE_MS) } else { PAGE_MS };
+            self.page_start = page_end;
+
+            let mut lines = Vec::new();
+            for record in records {
+                if record_time_secs(&record).is_some_and(|ts| ts >= self.since && ts < self.until) {
+                    lines.extend_from_slice(record.to_string().as_bytes());
+                    lines.push(b'\n');
+                }
+            }
+            let chunk = match &mut self.gzip {
+                None => lines,
+                Some(encoder) => {
+                    if let Err(e) = encoder.write_all(&lines) {
+                        return Some(Err(e));
+                    }
+                    std::mem::take(encoder.get_mut())
+                }
+            };
+            if !chunk.is_empty() {
+                return Some(Ok(chunk));
+            }
+        }
+        self.gzip.take().map(|encoder| encoder.finish())
+    }
+}
+
+/// Whether an `Accept-Encoding` header allows gzip, i.e. lists `gzip` (or
+/// `*`) without `q=0`.
+fn accepts_gzip(header: Option<&str>) -> bool {
+    header.is_some_and(|header| {
+        header.split(',').any(|coding| {
+            let mut parts = coding.split(';').map(str::trim);
+            let name = parts.next().unwrap_or("");
+            let refused = parts.any(|p| p.strip_prefix("q=").and_then(|q| q.parse::<f64>().ok()) == Some(0.0));
+            (name.eq_ignore_ascii_case("gzip") || name == "*") && !refused
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::tests::MemoryBackend;
+
+    /// MemoryBackend that remembers the widest range scanned, in ms.
+    #[derive(Default)]
+    struct WidestScan {
+        inner: MemoryBackend,
+        widest_ms: std::sync::Mutex<u64>,
+    }
+
+    impl KvBackend for WidestScan {
+        fn put<'a>(&'a self, key: &'a [u8], value: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+            self.inner.put(key, value)
+        }
+
+        fn get<'a>(&'a self, key: &'a [u8]) -> BoxFuture<'a, Result<Option<Vec<u8>>>> {
+            self.inner.get(key)
+        }
+
+        fn delete<'a>(&'a self, key: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+            self.inner.delete(key)
+        }
+
+        fn scan_range<'a>(
+            &'a self,
+            start: &'a [u8],
+            end: &'a [u8],
+        ) -> BoxFuture<'a, Result<Vec<(Vec<u8>, Vec<u8>)>>> {
+            let key_ms = |key: &[u8]| u64::from_str_radix(&String::from_utf8_lossy(key).replace('-', ""), 16).unwrap();
+            let mut widest = self.widest_ms.lock().unwrap();
+            *widest = (*widest).max(key_ms(end) - key_ms(start));
+            self.inner.scan_range(start, end)
+        }
+    }
+
+    #[tokio::test]
+    async fn sparse_history_is_exported_in_capped_pages() {
+        let backend = Arc::new(WidestScan::default());
+        let storage: Arc<dyn KvBackend> = backend.clone();
+        let now = now_epoch_ms();
+        let day = 86_400_000.0;
+        let written = [now - 30.0 * day, now - 30.0 * day + 1.0, now - 2.0 * day, now - 60_000.0];
+        for (i, ms) in written.iter().enumerate() {
+            let id = format!("{}-7000-8000-{:012x}", record_key_at(*ms), i);
+            let record = json!({ "id": id, "timestamp": format!("{:.6}", ms / 1000.0) });
+            storage.put(id.as_bytes(), &to_storage_bytes(&record).unwrap()).await.unwrap();
+        }
+
+        let since = (now - 31.0 * day) / 1000.0;
+        let mut pages = ExportPages {
+            storages: vec![storage],
+            since,
+            until: f64::MAX,
+            page_start: since * 1000.0,
+            end: now + 1.0,
+            width: PAGE_MS,
+            gzip: None,
+        };
+        let mut ids = Vec::new();
+        while let Some(chunk) = pages.next_chunk().await {
+            for line in String::from_utf8(chunk.unwrap()).unwrap().lines() {
+                let record: serde_json::Value = serde_json::from_str(line).unwrap();
+                ids.push(record["id"].as_str().unwrap().to_string());
+            }
+        }
+        let expected: Vec<_> = (0..written.len()).map(|i| format!("{:012x}", i)).collect();
+        assert_eq!(ids.iter().map(|id| id[id.len() - 12..].to_string()).collect::<Vec<_>>(), expected);
+        assert_eq!(*backend.widest_ms.lock().unwrap(), MAX_PAGE_MS as u64);
+    }
+}
@@ -0,0 +1,54 @@
+//! Health Resource
+//!
+//! Liveness/readiness probe: healthy while the writer loop runs and no
+//! output is persistently failing.
+
+use std::sync::atomic::Ordering;
+
+use yeti_core::prelude::*;
+
+use crate::telemetry::{lock, now_epoch_ms, shared, HEALTH_STALE_MS};
+
+/// Type alias required by compiler (filename → type name mapping)
+pub type Healthz = HealthzResource;
+
+#[derive(Default)]
+pub struct HealthzResource;
+
+impl Resource for HealthzResource {
+    fn name(&self) -> &str {
+        "healthz"
+    }
+
+    /// GET /yeti-telemetry/healthz
+    ///
+    /// 200 when the writer is running and publishing, and no output has
+    /// failed `OUTPUT_FAILURE_THRESHOLD` deliveries in a row; 503 otherwise.
+    /// The body breaks health down per output.
+    get!(_req, _ctx, {
+        let state = shared();
+        let running = state.pipeline.writer_running.load(Ordering::Relaxed);
+        let published_ms = state.health_published_ms.load(Ordering::Relaxed);
+        let stalled = running && (now_epoch_ms() as u64).saturating_sub(published_ms) > HEALTH_STALE_MS;
+        let writer = match (published_ms, running, stalled) {
+            (0, _, _) => "starting",
+            (_, false, _) => "stopped",
+            (_, true, true) => "stalled",
+            (_, true, false) => "running",
+        };
+
+        let outputs = lock(&state.output_health);
+        let failing = outputs.iter().any(|(_, health)| health.is_failing());
+        let healthy = writer == "running" && !failing;
+        let body = json!({
+            "status": if healthy { "ok" } else { "unavailable" },
+            "writer": writer,
+            "outputs": outputs
+                .iter()
+                .map(|(name, health)| (name.clone(), health.to_json()))
+                .collect::<serde_json::Map<_, _>>(),
+        });
+        drop(outputs);
+        status_json(if healthy { 200 } else { 503 }, body)
+    });
+}
@@ -0,0 +1,161 @@
+//! Logs Resource
+//!
+//! Queries stored log records by level, target, and time range, newest first,
+//! without going through the raw Log table API, and purges old records when
+//! `allowPurge` is enabled.
+
+use yeti_core::prelude::*;
+
+use crate::telemetry::{
+    indexed_span_fields, max_future_skew_secs, now_epoch_ms, parse_param, purge_before, record_key_at,
+    record_time_secs, scan_records, shared, RecordKind, WriteTimeCursor, RECORD_KEY_END,
+};
+
+/// Type alias required by compiler (filename → type name mapping)
+pub type Logs = LogsResource;
+
+/// Records returned when `limit` is not given.
+const DEFAULT_LIMIT: usize = 100;
+
+/// Largest accepted `limit`.
+const MAX_LIMIT: usize = 10_000;
+
+/// How far back logs are searched when `since` is not given, in seconds.
+const DEFAULT_WINDOW_SECS: f64 = 3600.0;
+
+#[derive(Default)]
+pub struct LogsResource;
+
+impl Resource for LogsResource {
+    fn name(&self) -> &str {
+        "logs"
+    }
+
+    /// GET /yeti-telemetry/logs?level=...&target=...&since=...&until=...&limit=...
+    ///
+    /// `level` matches case-insensitively, `target` by prefix. With
+    /// `severityTables`, both log tables are searched unless `level` rules
+    /// one out. `since`/`until` are epoch seconds (inclusive/exclusive, both
+    /// optional); `since` defaults to an hour ago and bounds the scan.
+    /// Returns up to `limit` (default 100, max 10000) records, newest first.
+    get!(req, _ctx, {
+        let level = req.query("level").filter(|l| !l.is_empty());
+        let target = req.query("target").filter(|t| !t.is_empty());
+        let since = match parse_param(req.query("since")) {
+            Ok(v) => v.unwrap_or_else(|| now_epoch_ms() / 1000.0 - DEFAULT_WINDOW_SECS),
+            Err(()) => return bad_request("since must be epoch seconds"),
+        };
+        let until = match parse_param(req.query("until")) {
+            Ok(v) => v.unwrap_or(f64::MAX),
+            Err(()) => return bad_request("until must be epoch seconds"),
+        };
+        let limit = match req.query("limit").filter(|l| !l.is_empty()) {
+            None => DEFAULT_LIMIT,
+            Some(l) => match l.parse::<usize>() {
+                Ok(n) if n <= MAX_LIMIT => n,
+                Ok(_) => return bad_request(&format!("limit must be at most {}", MAX_LIMIT)),
+                Err(_) => return bad_request("limit must be a non-negative integer"),
+            },
+        };
+
+        // With `severityTables`, a level filter picks the one table that can
+        // hold it; otherwise both are scanned.
+        let storages = shared().tables.get().map(|t| t.log_tables(level)).unwrap_or_default();
+        if storages.is_empty() {
+            return not_found("Log table not available");
+        }
+
+        // Record ids are assigned at write time, which is never earlier than
+        // the stored timestamp minus the allowed future skew, so `since` can
+        // bound the scan. Replayed and delayed records may be written long
+        // after their timestamp, so `until` only filters.
+        let start = record_key_at((since - max_future_skew_secs()) * 1000.0);
+
+        let mut records: Vec<(f64, serde_json::Value)> = Vec::new();
+        for storage in &storages {
+            records.extend(
+                scan_records(storage, start.as_bytes(), RECORD_KEY_END)
+                    .await?
+                    .into_iter()
+                    .filter(|r| {
+                        let str_field = |key: &str| r.get(key).and_then(|v| v.as_str()).unwrap_or("");
+                        level.is_none_or(|l| str_field("level").eq_ignore_ascii_case(l))
+                            && target.is_none_or(|t| str_field("target").starts_with(t))
+                    })
+                    .filter_map(|r| {
+                        let ts = record_time_secs(&r)?;
+                        (ts >= since && ts < until).then_some((ts, r))
+                    }),
+            );
+        }
+        records.sort_by(|a, b| b.0.total_cmp(&a.0));
+        records.truncate(limit);
+
+        let records: Vec<serde_json::Value> = records.into_iter().map(|(_, r)| r).collect();
+        ok(json!(records))
+    });
+
+    /// DELETE /yeti-telemetry/logs?before=...&kind=log|span|metric
+    ///
+    /// Deletes every record of `kind` (default `log`) stored before `before`
+    /// (epoch seconds) and returns the count. Logs are purged from every
+    /// `severityTables` table, spans along with their SpanIndex entries, and
+    /// metrics along with their MetricRollup windows. Deletes are queued for
+    /// the kind's mirror table too. Requires `allowPurge: true`.
+    delete!(req, _ctx, {
+        let allowed = shared()
+            .effective_config
+            .load_full()
+            .and_then(|c| c["allowPurge"].as_bool())
+            .unwrap_or(false);
+        if !allowed {
+            return status_json(
+                403,
+                json!({ "error": "purging is disabled; set telemetry.allowPurge: true to enable it" }),
+            );
+        }
+        let before = match parse_param(req.query("before")) {
+            Ok(Some(v)) => v,
+            Ok(None) => return bad_request("before is required"),
+            Err(()) => return bad_request("before must be epoch seconds"),
+        };
+        let kind = req.query("kind").filter(|k| !k.is_empty()).unwrap_or("log");
+        let Some(record_kind) = RecordKind::parse(kind) else {
+            return bad_request("kind must be one of log, span, metric");
+        };
+        let Some(tables) = shared().tables.get() else {
+            return not_found(&format!("{} table not available", kind));
+        };
+        let storages: Vec<_> = match record_kind {
+            RecordKind::Log => tables.log_tables(None),
+            RecordKind::Span => tables.span.iter().cloned().collect(),
+            RecordKind::Metric => tables.metric.iter().cloned().collect(),
+        };
+        if storages.is_empty() {
+            return not_found(&format!("{} table not available", kind));
+        }
+
+        let before_ms = before * 1000.0;
+        let indexed = indexed_span_fields();
+        let span_index = tables
+            .span_index
+            .as_ref()
+            .filter(|_| record_kind == RecordKind::Span)
+            .map(|index| (index, indexed.as_slice()));
+        let mut deleted = 0;
+        for storage in &storages {
+            let cursor = &mut WriteTimeCursor::default();
+            let mirror = Some((tables.mirrors.as_ref(), record_kind));
+            deleted += purge_before(storage, cursor, before_ms, mirror, span_index).await?;
+        }
+        // Rollups are keyed by window start, so this drops the windows that
+        // began before the cutoff.
+        let mut rollups_deleted = 0;
+        if let Some(rollups) = tables.metric_rollup.as_ref().filter(|_| record_kind == RecordKind::Metric) {
+            let cursor = &mut WriteTimeCursor::default();
+            rollups_deleted = purge_before(rollups, cursor, before_ms, None, None).await?;
+        }
+        eprintln!("[yeti-telemetry] Purged {} {} records stored before {}", deleted, kind, before);
+        ok(json!({ "kind": kind, "before": before, "deleted": deleted, "rollupsDeleted": rollups_deleted }))
+    });
+}
@@ -0,0 +1,201 @@
+//! Metrics Resource
+//!
+//! Reads stored metric records by name over a time range, optionally
+//! aggregated into fixed time buckets, so simple charts can be drawn from the
+//! Metric table without an OTLP backend.
+
+use std::collections::BTreeMap;
+
+use yeti_core::prelude::*;
+
+use crate::telemetry::{
+    max_future_skew_secs, now_epoch_ms, parse_param, record_key_at, record_time_secs, scan_records, shared,
+    RECORD_KEY_END,
+};
+
+/// Type alias required by compiler (filename → type name mapping)
+pub type Metrics = MetricsResource;
+
+/// Bucket width used when `agg` is given without `bucket`.
+const DEFAULT_BUCKET_SECS: f64 = 60.0;
+
+/// How far back points are searched when `since` is not given, in seconds.
+const DEFAULT_WINDOW_SECS: f64 = 3600.0;
+
+/// Raw points returned when `limit` is not given.
+const DEFAULT_LIMIT: usize = 1000;
+
+/// Largest accepted `limit`.
+const MAX_LIMIT: usize = 10_000;
+
+#[derive(Default)]
+pub struct MetricsResource;
+
+impl Resource for MetricsResource {
+    fn name(&self) -> &str {
+        "metrics"
+    }
+
+    /// GET /yeti-telemetry/metrics?name=...&since=...&until=...&agg=...&bucket=...
+    ///
+    /// `since`/`until` are epoch seconds (inclusive/exclusive, both optional);
+    /// `since` defaults to an hour ago and bounds the scan. Without `agg`,
+    /// returns the latest `limit` (default 1000, max 10000) raw points in time
+    /// order. With `agg=avg|sum|min|max`, returns one value per
+    /// `bucket`-second window (default 60) that has data.
+    get!(req, _ctx, {
+        let name = match req.query("name") {
+            Some(n) if !n.is_empty() => n.to_string(),
+            _ => return bad_request("name is required"),
+        };
+        let since = match parse_param(req.query("since")) {
+            Ok(v) => v.unwrap_or_else(|| now_epoch_ms() / 1000.0 - DEFAULT_WINDOW_SECS),
+            Err(()) => return bad_request("since must be epoch seconds"),
+        };
+        let until = match parse_param(req.query("until")) {
+            Ok(v) => v.unwrap_or(f64::MAX),
+            Err(()) => return bad_request("until must be epoch seconds"),
+        };
+        let agg = match req.query("agg").map(Aggregation::parse) {
+            None => None,
+            Some(Some(agg)) => Some(agg),
+            Some(None) => return bad_request("agg must be one of avg, sum, min, max"),
+        };
+        let bucket_secs = match parse_param(req.query("bucket")) {
+            Ok(Some(b)) if b > 0.0 => b,
+            Ok(None) => DEFAULT_BUCKET_SECS,
+            _ => return bad_request("bucket must be a positive number of seconds"),
+        };
+        let limit = match req.query("limit").filter(|l| !l.is_empty()) {
+            None => DEFAULT_LIMIT,
+            Some(l) => match l.parse::<usize>() {
+                Ok(n) if n <= MAX_LIMIT => n,
+                Ok(_) => return bad_request(&format!("limit must be at most {}", MAX_LIMIT)),
+                Err(_) => return bad_request("limit must be a non-negative integer"),
+            },
+        };
+
+        let storage = match shared().tables.get().and_then(|t| t.metric.clone()) {
+            Some(s) => s,
+            None => return not_found("Metric table not available"),
+        };
+
+        // Point ids are assigned at write time, which is never earlier than
+        // the stored timestamp minus the allowed future skew, so `since`
+        // bounds the scan.
+        let start = record_key_at((since - max_future_skew_secs()) * 1000.0);
+        let mut points: Vec<(f64, f64, serde_json::Value)> =
+            scan_records(&storage, start.as_bytes(), RECORD_KEY_END)
+                .await?
+                .into_iter()
+                .filter(|r| r.get("name").and_then(|v| v.as_str()) == Some(name.as_str()))
+                .filter_map(|r| {
+                    let ts = record_time_secs(&r)?;
+                    let value = r.get("value").and_then(|v| v.as_f64())?;
+                    (ts >= since && ts < until).then_some((ts, value, r))
+                })
+                .collect();
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let Some(agg) = agg else {
+            let skip = points.len().saturating_sub(limit);
+            let raw: Vec<serde_json::Value> = points
+                .into_iter()
+                .skip(skip)
+                .map(|(_, value, r)| {
+                    json!({
+                        "timestamp": r.get("timestamp"),
+                        "value": value,
+                        "attributes": r.get("attributes"),
+                    })
+                })
+                .collect();
+            return ok(json!({ "name": name, "points": raw }));
+        };
+
+        let buckets = aggregate(points.iter().map(|(ts, v, _)| (*ts, *v)), bucket_secs, agg);
+        ok(json!({
+            "name": name,
+            "agg": agg.as_str(),
+            "bucket": bucket_secs,
+            "buckets": buckets,
+        }))
+    });
+}
+
+#[derive(Clone, Copy)]
+enum Aggregation {
+    Avg,
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregation {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "avg" => Some(Self::Avg),
+            "sum" => Some(Self::Sum),
+            "min" => Some(Self::Min),
+            "max" => Some(Self::Max),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Avg => "avg",
+            Self::Sum => "sum",
+            Self::Min => "min",
+            Self::Max => "max",
+        }
+    }
+}
+
+/// Running state of one bucket.
+struct Bucket {
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+/// Group (epoch seconds, value) points into `bucket_secs` windows aligned to
+/// the epoch and reduce each with `agg`. Empty windows are omitted.
+fn aggregate(
+    points: impl Iterator<Item = (f64, f64)>,
+    bucket_secs: f64,
+    agg: Aggregation,
+) -> Vec<serde_json::Value> {
+    let mut buckets: BTreeMap<i64, Bucket> = BTreeMap::new();
+    for (ts, value) in points {
+        let index = (ts / bucket_secs).floor() as i64;
+        let b = buckets.entry(index).or_insert(Bucket {
+            count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        });
+        b.count += 1;
+        b.sum += value;
+        b.min = b.min.min(value);
+        b.max = b.max.max(value);
+    }
+
+    buckets
+        .into_iter()
+        .map(|(index, b)| {
+            let value = match agg {
+                Aggregation::Avg => b.sum / b.count as f64,
+                Aggregation::Sum => b.sum,
+                Aggregation::Min => b.min,
+                Aggregation::Max => b.max,
+            };
+            json!({
+                "start": index as f64 * bucket_secs,
+                "value": value,
+                "count": b.count,
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,26 @@
+//! OpenMetrics Resource
+//!
+//! Renders the in-process HTTP request aggregations in OpenMetrics text format
+//! (counters, duration histogram, `# EOF` terminator) for strict scrapers.
+
+use yeti_core::prelude::*;
+
+use crate::telemetry::{lock, shared, OPENMETRICS_CONTENT_TYPE};
+
+/// Type alias required by compiler (filename → type name mapping)
+pub type Openmetrics = OpenmetricsResource;
+
+#[derive(Default)]
+pub struct OpenmetricsResource;
+
+impl Resource for OpenmetricsResource {
+    fn name(&self) -> &str {
+        "openmetrics"
+    }
+
+    /// GET /yeti-telemetry/openmetrics — HTTP request metrics in OpenMetrics format
+    get!(_req, _ctx, {
+        let body = lock(&shared().http_metrics).render_openmetrics();
+        ok_text(OPENMETRICS_CONTENT_TYPE, body)
+    });
+}
@@ -0,0 +1,29 @@
+//! Prometheus Resource
+//!
+//! Renders the Prometheus output's HTTP request registry in Prometheus text
+//! exposition format. Only available when `prometheus: true` is configured.
+
+use yeti_core::prelude::*;
+
+use crate::telemetry::{lock, shared, PROMETHEUS_CONTENT_TYPE};
+
+/// Type alias required by compiler (filename → type name mapping)
+pub type Prometheus = PrometheusResource;
+
+#[derive(Default)]
+pub struct PrometheusResource;
+
+impl Resource for PrometheusResource {
+    fn name(&self) -> &str {
+        "prometheus"
+    }
+
+    /// GET /yeti-telemetry/prometheus — HTTP request metrics for Prometheus scrapes
+    get!(_req, _ctx, {
+        let Some(metrics) = shared().prometheus.get() else {
+            return not_found("Prometheus output is not enabled");
+        };
+        let body = lock(metrics).render_prometheus();
+        ok_text(PROMETHEUS_CONTENT_TYPE, body)
+    });
+}
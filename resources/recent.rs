@@ -0,0 +1,44 @@
+//! Recent Resource
+//!
+//! Returns the latest records held by the in-memory ring buffer output, for
+//! quick debugging without scanning storage.
+
+use yeti_core::prelude::*;
+
+use crate::telemetry::{lock, shared};
+
+/// Type alias required by compiler (filename → type name mapping)
+pub type Recent = RecentResource;
+
+#[derive(Default)]
+pub struct RecentResource;
+
+impl Resource for RecentResource {
+    fn name(&self) -> &str {
+        "recent"
+    }
+
+    /// GET /yeti-telemetry/recent?kind=log|span|metric&limit=...
+    ///
+    /// Newest first, each as `{ kind, record }`. `limit` defaults to (and is
+    /// capped at) the buffer capacity.
+    get!(req, _ctx, {
+        let kind = match req.query("kind").filter(|k| !k.is_empty()) {
+            None => None,
+            Some(k @ ("log" | "span" | "metric")) => Some(k),
+            Some(_) => return bad_request("kind must be one of log, span, metric"),
+        };
+        let Some(recent) = shared().recent.get() else {
+            return not_found("recent buffer is disabled (recentCapacity: 0)");
+        };
+        let recent = lock(recent);
+        let limit = match req.query("limit").filter(|l| !l.is_empty()) {
+            None => recent.capacity(),
+            Some(l) => match l.parse::<usize>() {
+                Ok(n) => n.min(recent.capacity()),
+                Err(_) => return bad_request("limit must be a non-negative integer"),
+            },
+        };
+        ok(json!(recent.latest(kind, limit)))
+    });
+}
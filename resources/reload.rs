@@ -0,0 +1,32 @@
+//! Reload Resource
+//!
+//! Re-reads yeti-config.yaml and applies sampling, level, rate limit and
+//! retention changes to the running writer without a restart.
+
+use yeti_core::prelude::*;
+
+use crate::telemetry::reload_config;
+
+/// Type alias required by compiler (filename → type name mapping)
+pub type Reload = ReloadResource;
+
+#[derive(Default)]
+pub struct ReloadResource;
+
+impl Resource for ReloadResource {
+    fn name(&self) -> &str {
+        "reload"
+    }
+
+    /// POST /yeti-telemetry/reload
+    ///
+    /// Returns `applied` (changes now in effect) and `restartRequired`
+    /// (changes that only take effect after a restart), each a list of
+    /// `{path, old, new}` with secrets shown as `***`.
+    post!(_req, _ctx, {
+        match reload_config() {
+            Some(diff) => ok(diff),
+            None => not_found("telemetry writer not configured yet"),
+        }
+    });
+}
@@ -0,0 +1,68 @@
+//! Replay Resource
+//!
+//! Re-ingests a file written by the file output into the Log, Span and Metric
+//! tables, e.g. after a storage outage left the tables behind the files.
+
+use yeti_core::prelude::*;
+
+use crate::telemetry::{replay_file, shared};
+
+/// Type alias required by compiler (filename → type name mapping)
+pub type Replay = ReplayResource;
+
+#[derive(Default)]
+pub struct ReplayResource;
+
+impl Resource for ReplayResource {
+    fn name(&self) -> &str {
+        "replay"
+    }
+
+    /// POST /yeti-telemetry/replay?file=telemetry-2024-06-01.jsonl&dryRun=true
+    ///
+    /// `file` names a `.jsonl` or `.jsonl.gz` file in the logs directory.
+    /// Records keep their ids and already-stored ones are skipped, so a file
+    /// can be replayed more than once. `dryRun=true` only reports counts.
+    post!(req, _ctx, {
+        let name = match req.query("file") {
+            Some(f) if !f.is_empty() => f,
+            _ => return bad_request("file is required"),
+        };
+        let plain_name = !name.contains(['/', '\\']) && !name.starts_with('.');
+        if !plain_name || !(name.ends_with(".jsonl") || name.ends_with(".jsonl.gz")) {
+            return bad_request("file must be a .jsonl or .jsonl.gz file name in the logs directory");
+        }
+        let dry_run = match req.query("dryRun") {
+            None | Some("") | Some("false") => false,
+            Some("true") => true,
+            Some(_) => return bad_request("dryRun must be true or false"),
+        };
+        let persist = shared()
+            .effective_config
+            .load_full()
+            .and_then(|c| c["persistToTables"].as_bool())
+            .unwrap_or(true);
+        if !persist {
+            return bad_request("table persistence is disabled (persistToTables: false)");
+        }
+        let (Some(log_dir), Some(tables)) = (shared().log_dir.get(), shared().tables.get()) else {
+            return not_found("telemetry writer not configured yet");
+        };
+        let file = match std::fs::File::open(log_dir.join(name)) {
+            Ok(f) => f,
+            Err(_) => return not_found(&format!("{} not found in the logs directory", name)),
+        };
+
+        let summary = replay_file(tables, file, name.ends_with(".gz"), dry_run).await?;
+        eprintln!(
+            "[yeti-telemetry] Replayed {}{}: {}",
+            name,
+            if dry_run { " (dry run)" } else { "" },
+            summary.to_json()
+        );
+        let mut body = summary.to_json();
+        body["file"] = json!(name);
+        body["dryRun"] = json!(dry_run);
+        ok(body)
+    });
+}
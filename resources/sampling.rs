@@ -0,0 +1,66 @@
+//! Sampling Resource
+//!
+//! Publishes the writer's effective sample rates so producers (or a control
+//! loop) can apply matching head sampling instead of sending events the
+//! writer will drop anyway.
+
+use std::sync::atomic::Ordering;
+
+use yeti_core::prelude::*;
+
+use crate::telemetry::{adaptive_boost_until, shared};
+
+/// Type alias required by compiler (filename → type name mapping)
+pub type Sampling = SamplingResource;
+
+#[derive(Default)]
+pub struct SamplingResource;
+
+impl Resource for SamplingResource {
+    fn name(&self) -> &str {
+        "sampling"
+    }
+
+    /// GET /yeti-telemetry/sampling — configured and observed sample rates
+    ///
+    /// `logSampleRate`/`spanSampleRate` are the authoritative keep fractions
+    /// for the active environment, narrowed per level and target by `sampling`.
+    /// ERROR and WARN events are always kept. While `boostedUntil` is set,
+    /// `adaptiveSampling.boostRatio` raises DEBUG/TRACE logs and spans. `observed`
+    /// reports what the writer actually kept since startup.
+    get!(_req, _ctx, {
+        let config = match shared().effective_config.load_full() {
+            Some(c) => c,
+            None => return not_found("telemetry writer not configured yet"),
+        };
+        let counters = &shared().sampling;
+        let observed = |seen: u64, kept: u64| {
+            json!({
+                "seen": seen,
+                "kept": kept,
+                "ratio": if seen == 0 { 1.0 } else { kept as f64 / seen as f64 },
+            })
+        };
+        ok(json!({
+            "environment": config["environment"],
+            "logSampleRate": config["logSampleRate"],
+            "spanSampleRate": config["spanSampleRate"],
+            "sampling": config["sampling"],
+            "adaptiveSampling": config["adaptiveSampling"],
+            "boostedUntil": adaptive_boost_until(),
+            "alwaysKept": ["ERROR", "WARN"],
+            "decisionKey": "traceId",
+            "decisionHash": "fnv1a64+splitmix64; keep if (hash >> 8) < rate * 2^56",
+            "observed": {
+                "logs": observed(
+                    counters.logs_seen.load(Ordering::Relaxed),
+                    counters.logs_kept.load(Ordering::Relaxed),
+                ),
+                "spans": observed(
+                    counters.spans_seen.load(Ordering::Relaxed),
+                    counters.spans_kept.load(Ordering::Relaxed),
+                ),
+            },
+        }))
+    });
+}
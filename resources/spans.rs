@@ -0,0 +1,86 @@
+//! Spans Resource
+//!
+//! Looks up spans by an `indexedSpanFields` value through the SpanIndex
+//! table, so finding a tenant's spans never decodes the whole Span table.
+
+use yeti_core::prelude::*;
+
+use crate::telemetry::{shared, span_index_prefix};
+
+/// Type alias required by compiler (filename → type name mapping)
+pub type Spans = SpansResource;
+
+/// Spans returned when `limit` is not given.
+const DEFAULT_LIMIT: usize = 100;
+
+/// Largest accepted `limit`.
+const MAX_LIMIT: usize = 10_000;
+
+#[derive(Default)]
+pub struct SpansResource;
+
+impl Resource for SpansResource {
+    fn name(&self) -> &str {
+        "spans"
+    }
+
+    /// GET /yeti-telemetry/spans?field=...&value=...&limit=...
+    ///
+    /// Spans whose `fields` hold `value` for `field`, which must be one of
+    /// `indexedSpanFields`. Returns up to `limit` (default 100, max 10000)
+    /// spans, newest first.
+    get!(req, _ctx, {
+        let Some(field) = req.query("field").filter(|f| !f.is_empty()) else {
+            return bad_request("field is required");
+        };
+        let Some(value) = req.query("value") else {
+            return bad_request("value is required");
+        };
+        let limit = match req.query("limit").filter(|l| !l.is_empty()) {
+            None => DEFAULT_LIMIT,
+            Some(l) => match l.parse::<usize>() {
+                Ok(n) if n <= MAX_LIMIT => n,
+                Ok(_) => return bad_request(&format!("limit must be at most {}", MAX_LIMIT)),
+                Err(_) => return bad_request("limit must be a non-negative integer"),
+            },
+        };
+
+        let indexed = shared()
+            .effective_config
+            .load_full()
+            .and_then(|c| c["indexedSpanFields"].as_array().cloned())
+            .unwrap_or_default();
+        if !indexed.iter().any(|f| f.as_str() == Some(field)) {
+            return bad_request(&format!("'{}' is not one of indexedSpanFields", field));
+        }
+        let tables = shared().tables.get();
+        let (Some(index), Some(storage)) = (
+            tables.and_then(|t| t.span_index.clone()),
+            tables.and_then(|t| t.span.clone()),
+        ) else {
+            return not_found("SpanIndex or Span table not available");
+        };
+
+        // Keys end in the span id, which is time-ordered, so the last
+        // entries of the range are the newest spans.
+        let prefix = span_index_prefix(field, value);
+        let end = format!("{};", &prefix[..prefix.len() - 1]);
+        let entries = index.scan_range(prefix.as_bytes(), end.as_bytes()).await?;
+
+        let mut spans = Vec::new();
+        for (_, bytes) in entries.iter().rev() {
+            if spans.len() >= limit {
+                break;
+            }
+            let Ok(entry) = from_storage_bytes(bytes) else { continue };
+            let Some(id) = entry["spanId"].as_str() else { continue };
+            // Entries can outlive a span deleted by an older purge or TTL.
+            if let Some(bytes) = storage.get(id.as_bytes()).await? {
+                if let Ok(span) = from_storage_bytes(&bytes) {
+                    spans.push(span);
+                }
+            }
+        }
+        ok(json!(spans))
+    });
+}
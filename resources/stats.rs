@@ -0,0 +1,43 @@
+//! Stats Resource
+//!
+//! Summarizes the events received over a rolling window by kind, level and
+//! target, for a quick view of the log mix or an error spike without
+//! scanning storage.
+
+use yeti_core::prelude::*;
+
+use crate::telemetry::{lock, now_epoch_ms, shared};
+
+/// Type alias required by compiler (filename → type name mapping)
+pub type Stats = StatsResource;
+
+/// Targets listed when `top` is not given.
+const DEFAULT_TOP: usize = 10;
+
+#[derive(Default)]
+pub struct StatsResource;
+
+impl Resource for StatsResource {
+    fn name(&self) -> &str {
+        "stats"
+    }
+
+    /// GET /yeti-telemetry/stats?top=...
+    ///
+    /// Log and span counts over the last `statsWindowSecs` (default 300), by
+    /// kind and level, with the `top` (default 10) busiest targets.
+    get!(req, _ctx, {
+        let top = match req.query("top").filter(|t| !t.is_empty()) {
+            None => DEFAULT_TOP,
+            Some(t) => match t.parse::<usize>() {
+                Ok(n) => n,
+                Err(_) => return bad_request("top must be a non-negative integer"),
+            },
+        };
+        let Some(stats) = shared().event_stats.get() else {
+            return not_found("stats are disabled (statsWindowSecs: 0)");
+        };
+        let now_secs = (now_epoch_ms() / 1000.0) as u64;
+        ok(lock(stats).snapshot(now_secs, top))
+    });
+}
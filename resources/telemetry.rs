@@ -8,10 +8,14 @@
 
 use std::fs::{self, File, OpenOptions};
 use std::io::{BufWriter, Write};
+use std::borrow::Cow;
 use std::path::PathBuf;
 use std::pin::Pin;
-use std::sync::Arc;
-use std::time::SystemTime;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Instant, SystemTime};
+use arc_swap::{ArcSwap, ArcSwapOption};
 use yeti_core::prelude::*;
 
 // ============================================================================
@@ -45,39 +49,255 @@ impl Extension for TelemetryExtension {
     fn on_ready(&self, ctx: &ExtensionContext) -> Result<()> {
         eprintln!("[yeti-telemetry] Setting up event subscriber...");
 
-        let log_table = ctx.table("log");
+        let build = shared().build_info.get_or_init(|| BuildInfo::load(ctx.root_dir()));
+        eprintln!(
+            "[yeti-telemetry] Build version {}, commit {}",
+            build.version.as_deref().unwrap_or("unknown"),
+            build.commit.as_deref().unwrap_or("unknown")
+        );
+
+        let mut config = TelemetryConfig::from_config(ctx.root_dir());
+        let live = std::mem::take(&mut config.live);
+
+        let log_table = match &config.severity_tables {
+            Some(tables) => ctx.table(&tables.default),
+            None => ctx.table(DEFAULT_LOG_TABLE),
+        };
         let span_table = ctx.table("span");
         let metric_table = ctx.table("metric");
+        let span_index_table = if config.indexed_span_fields.is_empty() {
+            None
+        } else {
+            let table = ctx.table(SPAN_INDEX_TABLE);
+            if table.is_none() {
+                eprintln!("[yeti-telemetry] WARNING: SpanIndex table not found, indexedSpanFields disabled");
+            }
+            table
+        };
 
-        if let Some(ref log_t) = log_table {
-            let mut writer = TelemetryWriter::new(
-                log_t.storage().clone(),
-                span_table.as_ref().map(|t| t.storage().clone()),
-                metric_table.as_ref().map(|t| t.storage().clone()),
-                log_t.pubsub().cloned(),
-            );
+        if log_table.is_none() {
+            eprintln!("[yeti-telemetry] WARNING: Log table not found, logs go to outputs only");
+        }
+        // Without its table, severe logs stay in the default table.
+        let error_log_table = config.severity_tables.as_ref().and_then(|tables| {
+            let table = ctx.table(&tables.error);
+            if table.is_none() {
+                eprintln!(
+                    "[yeti-telemetry] WARNING: severity table '{}' not found, WARN and ERROR logs go to {}",
+                    tables.error, tables.default
+                );
+            }
+            table
+        });
 
-            // Add file output for JSON Lines rotation
-            let logs_dir = PathBuf::from(ctx.root_dir()).join("logs");
-            writer = writer.add_output(Box::new(FileProvider::new(logs_dir)));
+        // SSE notifications go through whichever table's PubSub is available.
+        let pubsub = [&log_table, &span_table, &metric_table]
+            .iter()
+            .find_map(|t| t.as_ref().and_then(|t| t.pubsub().cloned()));
 
-            // Add OTLP output if configured in yeti-config.yaml
-            match OtlpOutput::from_config(ctx.root_dir()) {
-                Some(otlp) => {
-                    writer = writer.add_output(Box::new(otlp));
-                    eprintln!("[yeti-telemetry] OTLP output configured");
-                }
-                None => {
-                    eprintln!("[yeti-telemetry] OTLP disabled (no otlpEndpoint configured)");
-                }
+        // Names passed to notify_update; they must match the tables the host
+        // registered, or SSE subscribers silently receive nothing.
+        let [log_name, span_name, metric_name] = &config.pubsub_tables;
+        eprintln!(
+            "[yeti-telemetry] PubSub tables: log={}, span={}, metric={}",
+            log_name, span_name, metric_name
+        );
+
+        let mirror = |kind: &str| {
+            let name = config.mirror_tables.get(kind)?;
+            let table = ctx.table(name);
+            if table.is_none() {
+                eprintln!("[yeti-telemetry] WARNING: mirror table '{}' for {} not found", name, kind);
             }
+            table.map(|t| MirrorQueue::new(t.storage().clone(), shared()))
+        };
+        let rollup_table = config.metric_retention.and_then(|_| {
+            let table = ctx.table(METRIC_ROLLUP_TABLE);
+            if table.is_none() {
+                eprintln!("[yeti-telemetry] WARNING: MetricRollup table not found, metricRetention disabled");
+            }
+            table
+        });
+        let mirrors = Arc::new(Mirrors {
+            log: mirror("log"),
+            span: mirror("span"),
+            metric: mirror("metric"),
+        });
 
-            ctx.set_event_subscriber(Box::new(writer));
-            eprintln!("[yeti-telemetry] Event subscriber configured");
-        } else {
-            eprintln!("[yeti-telemetry] WARNING: Log table not found, no event subscriber");
+        let _ = shared().tables.set(TableStores {
+            log: log_table.as_ref().map(|t| t.storage().clone()),
+            error_log: error_log_table.as_ref().map(|t| t.storage().clone()),
+            span: span_table.as_ref().map(|t| t.storage().clone()),
+            metric: metric_table.as_ref().map(|t| t.storage().clone()),
+            metric_rollup: rollup_table.as_ref().map(|t| t.storage().clone()),
+            span_index: span_index_table.as_ref().map(|t| t.storage().clone()),
+            mirrors: Arc::clone(&mirrors),
+            pubsub: pubsub.clone(),
+            pubsub_tables: config.pubsub_tables.clone(),
+        });
+        let file_encoding = FileEncoding {
+            format: config.file_format,
+            envelope: match config.file_schema {
+                FileSchema::Native => config.file_envelope.clone(),
+                FileSchema::Otel => FileEnvelope::Otel(Arc::new(otel_file_resource(ctx.root_dir()))),
+            },
+            intern_fields: config.field_interning,
+        };
+        let file_rotation = config.file_rotation;
+        let compress_rotated_files = config.compress_rotated_files;
+        let output_ops =
+            Arc::new(OutputOps::new(config.max_concurrent_output_ops, OUTPUT_OPS_QUEUE_CAPACITY, shared()));
+        let file_outputs = config.file_outputs.clone();
+        let prometheus_enabled = config.prometheus;
+        let recent_capacity = config.recent_capacity;
+        if config.stats_window_secs > 0 {
+            let _ = shared().event_stats.set(Mutex::new(EventStats::new(config.stats_window_secs)));
+        }
+        let mut effective = config.to_json();
+
+        let mut writer = TelemetryWriter::new(
+            log_table.as_ref().map(|t| t.storage().clone()),
+            span_table.as_ref().map(|t| t.storage().clone()),
+            metric_table.as_ref().map(|t| t.storage().clone()),
+            pubsub.map(|ps| ps as Arc<dyn RecordNotifier>),
+            config,
+        )
+        .with_mirrors(mirrors)
+        .with_error_log_storage(error_log_table.map(|t| t.storage().clone()))
+        .with_rollup_storage(rollup_table.map(|t| t.storage().clone()))
+        .with_span_index_storage(span_index_table.map(|t| t.storage().clone()));
+
+        let telemetry = read_telemetry_section(ctx.root_dir());
+
+        // Upload rotated files to S3-compatible storage when configured
+        let archive = match telemetry.as_ref().and_then(S3Archive::from_config) {
+            Some(_) if matches!(file_rotation.style, RotationStyle::Numbered { .. }) => {
+                eprintln!("[yeti-telemetry] WARNING: archive requires dated rotation; ignoring it");
+                None
+            }
+            Some(archive) => {
+                effective["archive"] = archive.to_json();
+                eprintln!(
+                    "[yeti-telemetry] Archiving rotated files to {}/{}",
+                    archive.endpoint, archive.bucket
+                );
+                Some(Arc::new(archive))
+            }
+            None => None,
+        };
+        let rotated_files = RotatedFiles {
+            compress: compress_rotated_files,
+            archive,
+            ops: output_ops,
+        };
+
+        // Add file output for JSON Lines rotation
+        let logs_dir = PathBuf::from(ctx.root_dir()).join("logs");
+        let _ = shared().log_dir.set(logs_dir.clone());
+        writer = writer.add_output(Box::new(FileProvider::new(
+            logs_dir.clone(),
+            DEFAULT_FILE_PREFIX,
+            file_encoding.clone(),
+            file_rotation,
+            rotated_files.clone(),
+        )));
+
+        // Extra filtered files alongside the main one (e.g. errors only)
+        for output in file_outputs {
+            writer = writer.add_output(Box::new(
+                FileProvider::new(
+                    logs_dir.clone(),
+                    &output.prefix,
+                    file_encoding.clone(),
+                    file_rotation,
+                    rotated_files.clone(),
+                )
+                .with_filter(output.filter),
+            ));
+        }
+
+        // Aggregate HTTP request metrics in-process for the scrape endpoints
+        writer = writer.add_output(Box::new(MetricsAggregator {
+            metrics: shared().http_metrics.clone(),
+        }));
+
+        if recent_capacity > 0 {
+            let records = shared()
+                .recent
+                .get_or_init(|| Arc::new(Mutex::new(RecentRecords::new(recent_capacity))))
+                .clone();
+            writer = writer.add_output(Box::new(RingBufferOutput { records }));
+        }
+
+        if prometheus_enabled {
+            let metrics = shared()
+                .prometheus
+                .get_or_init(|| Arc::new(Mutex::new(HttpMetrics::default())))
+                .clone();
+            writer = writer.add_output(Box::new(PrometheusProvider { metrics }));
+            eprintln!("[yeti-telemetry] Prometheus output configured");
+        }
+
+        if let Some(es) = telemetry.as_ref().and_then(ElasticsearchConfig::from_config) {
+            effective["elasticsearch"] = es.to_json();
+            let url = es.url.clone();
+            if let Some(provider) = ElasticsearchProvider::new(es) {
+                writer = writer.add_output(Box::new(provider));
+                eprintln!("[yeti-telemetry] Elasticsearch output configured ({})", url);
+            }
         }
 
+        if let Some(loki) = telemetry.as_ref().and_then(LokiConfig::from_config) {
+            effective["loki"] = loki.to_json();
+            let url = loki.url.clone();
+            if let Some(provider) = LokiProvider::new(loki) {
+                writer = writer.add_output(Box::new(provider));
+                eprintln!("[yeti-telemetry] Loki output configured ({})", url);
+            }
+        }
+
+        if let Some(kafka) = telemetry.as_ref().and_then(KafkaConfig::from_config) {
+            effective["kafka"] = kafka.to_json();
+            let brokers = kafka.brokers.clone();
+            if let Some(provider) = KafkaProvider::new(kafka) {
+                writer = writer.add_output(Box::new(provider));
+                eprintln!("[yeti-telemetry] Kafka output configured ({})", brokers);
+            }
+        }
+
+        if let Some(syslog) = telemetry.as_ref().and_then(SyslogConfig::from_config) {
+            effective["syslog"] = syslog.to_json();
+            let (address, transport) = (syslog.address.clone(), syslog.transport.as_str());
+            if let Some(provider) = SyslogProvider::new(syslog) {
+                writer = writer.add_output(Box::new(provider));
+                eprintln!("[yeti-telemetry] Syslog output configured ({} over {})", address, transport);
+            }
+        }
+
+        // Add OTLP outputs if configured in yeti-config.yaml, one per destination
+        let otlp_outputs = OtlpOutput::from_config(ctx.root_dir());
+        if otlp_outputs.is_empty() {
+            eprintln!("[yeti-telemetry] OTLP disabled (no otlpEndpoint configured)");
+        }
+        effective["otlp"] = otlp_effective_json(&otlp_outputs);
+        let _ = shared().otlp_breakers.set(
+            otlp_outputs
+                .iter()
+                .map(|otlp| (otlp.config.name.clone(), Arc::clone(&otlp.breaker)))
+                .collect(),
+        );
+        for otlp in otlp_outputs {
+            eprintln!("[yeti-telemetry] OTLP output {} configured", otlp.config.name);
+            writer = writer.add_output(Box::new(otlp));
+        }
+
+        shared().live.store(Arc::new(live));
+        let _ = shared().root_dir.set(ctx.root_dir().to_string());
+        shared().effective_config.store(Some(Arc::new(redact_secrets(effective))));
+
+        ctx.set_event_subscriber(Box::new(writer));
+        eprintln!("[yeti-telemetry] Event subscriber configured");
+
         Ok(())
     }
 }
@@ -97,10 +317,11 @@ impl Resource for TelemetryResource {
         "telemetry"
     }
 
-    /// GET /yeti-telemetry/telemetry — extension status + app registry
+    /// GET /yeti-telemetry/telemetry — extension status, writer counters and
+    /// uptime, and the app registry
     ///
-    /// Note: Cannot check host-side statics from dylib (TLS isolation).
-    /// The writer is always started alongside the extension by app_loader.
+    /// The writer state comes from the shared pipeline counters, which live
+    /// in this dylib (host-side statics aren't visible here).
     get!(_req, ctx, {
         let apps: Vec<serde_json::Value> = ctx.app_registry().iter().map(|a| {
             json!({
@@ -109,588 +330,11726 @@ impl Resource for TelemetryResource {
                 "is_extension": a.is_extension,
             })
         }).collect();
+        let pipeline = &shared().pipeline;
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let started_ms = load(&pipeline.writer_started_ms);
+        let running = pipeline.writer_running.load(Ordering::Relaxed);
+        let status = match (started_ms, running) {
+            (0, _) => "starting",
+            (_, true) => "active",
+            (_, false) => "stopped",
+        };
+        let uptime_secs = if running {
+            (now_epoch_ms() as u64).saturating_sub(started_ms) / 1000
+        } else {
+            0
+        };
+        let [logs, spans, metrics] = pipeline.by_kind.totals();
+        let persist_to_tables = shared()
+            .effective_config
+            .load_full()
+            .and_then(|c| c["persistToTables"].as_bool())
+            .unwrap_or(true);
+        let count_mode = shared()
+            .effective_config
+            .load_full()
+            .is_some_and(|c| c["ingestMode"] == "count");
         ok(json!({
-            "writer": true,
-            "status": "active",
+            "writer": running,
+            "status": status,
+            "startedAt": (started_ms > 0).then(|| format_epoch_ms(started_ms as f64)),
+            "uptimeSecs": uptime_secs,
+            "build": shared().build_info.get().map(BuildInfo::to_json),
+            "counts": {
+                "logs": logs,
+                "spans": spans,
+                "metrics": metrics,
+                "dropped": load(&pipeline.events_dropped),
+            },
+            "persistToTables": persist_to_tables,
+            "ingestMode": if count_mode { "count" } else { "full" },
+            "eventsPerSec": count_mode.then(|| load(&pipeline.events_per_sec)),
+            "pipeline": {
+                "eventsProcessed": load(&pipeline.events_processed),
+                "eventsDropped": load(&pipeline.events_dropped),
+                "unknownLevels": load(&pipeline.unknown_levels),
+                "invalidTimestamps": load(&pipeline.invalid_timestamps),
+                "serializeErrors": load(&pipeline.serialize_errors),
+                "levelFiltered": load(&pipeline.level_filtered),
+                "shortSpansFiltered": load(&pipeline.short_spans_filtered),
+                "transformDropped": load(&pipeline.transform_dropped),
+                "transformErrors": load(&pipeline.transform_errors),
+                "rateLimited": load(&pipeline.rate_limited),
+                "adaptiveBoosts": load(&pipeline.adaptive_boosts),
+                "adaptiveBoostUntil": adaptive_boost_until(),
+                "fileDroppedNoWriter": load(&pipeline.file_dropped_no_writer),
+                "mirrorDropped": load(&pipeline.mirror_dropped),
+                "outputOpsDropped": load(&pipeline.output_ops_dropped),
+                "queueDepth": load(&pipeline.queue_depth),
+                "queueCapacity": load(&pipeline.queue_capacity),
+                "queueHighWater": load(&pipeline.queue_high_water),
+                "queueSaturated": load(&pipeline.queue_saturated),
+                "otlpSkipped": load(&pipeline.otlp_skipped),
+                "lagMs": load(&pipeline.lag_ms),
+                "byKind": RecordKind::ALL.iter().map(|kind| {
+                    let i = kind.index();
+                    (kind.as_str().to_string(), json!({
+                        "processed": load(&pipeline.by_kind.processed[i]),
+                        "dropped": load(&pipeline.by_kind.dropped[i]),
+                        "writeErrors": load(&pipeline.by_kind.write_errors[i]),
+                    }))
+                }).collect::<serde_json::Map<_, _>>(),
+            },
+            "otlpBreaker": otlp_breakers_json(),
             "apps": apps,
         }))
     });
 }
 
 // ============================================================================
-// Telemetry Writer — event processing and persistence
+// Shared State — writer data read by the HTTP resources
 // ============================================================================
 
-/// Trait for pluggable telemetry output providers.
-///
-/// Implementations receive serialized telemetry records (as JSON values)
-/// and can write them to files, forward to OTLP endpoints, etc.
-trait TelemetryOutput: Send {
-    fn write_log(&mut self, record: &serde_json::Value);
-    fn write_span(&mut self, record: &serde_json::Value);
-    fn write_metric(&mut self, record: &serde_json::Value) {
-        let _ = record;
+/// State shared between the writer and the resources. Both live in this dylib,
+/// so a static here is visible to each (unlike host-side statics).
+pub(crate) struct SharedState {
+    pub(crate) http_metrics: Arc<Mutex<HttpMetrics>>,
+    /// Most recent records, kept by the ring buffer output unless disabled.
+    pub(crate) recent: OnceLock<Arc<Mutex<RecentRecords>>>,
+    /// Rolling level and target counts, unless `statsWindowSecs` is 0.
+    pub(crate) event_stats: OnceLock<Mutex<EventStats>>,
+    /// Registry of the Prometheus output; set only when `prometheus: true`.
+    pub(crate) prometheus: OnceLock<Arc<Mutex<HttpMetrics>>>,
+    /// Table storage handles, published by on_ready for the query resources.
+    pub(crate) tables: OnceLock<TableStores>,
+    /// Directory holding the file outputs' JSONL files.
+    pub(crate) log_dir: OnceLock<PathBuf>,
+    /// Fully-resolved configuration with secrets redacted; replaced when a
+    /// reload applies changes.
+    pub(crate) effective_config: ArcSwapOption<serde_json::Value>,
+    /// Settings that `POST /yeti-telemetry/reload` can change while running.
+    pub(crate) live: ArcSwap<LiveSettings>,
+    /// The app root holding yeti-config.yaml, for reloads.
+    pub(crate) root_dir: OnceLock<String>,
+    /// Held while a reload compares and swaps the configuration.
+    reload_lock: Mutex<()>,
+    /// Observed sampling decisions, for the sampling feedback endpoint.
+    pub(crate) sampling: SamplingCounters,
+    /// Health of the pipeline itself, exported as `telemetry.self.*` metrics.
+    pub(crate) pipeline: PipelineCounters,
+    /// Each output's health by name, as last published by the writer.
+    pub(crate) output_health: Mutex<Vec<(String, OutputHealth)>>,
+    /// When the writer last published `output_health` (epoch ms).
+    pub(crate) health_published_ms: AtomicU64,
+    /// Each OTLP output's circuit breaker, by output name.
+    pub(crate) otlp_breakers: OnceLock<Vec<(String, Arc<OtlpBreaker>)>>,
+    /// The app's build version and commit, read by on_ready.
+    pub(crate) build_info: OnceLock<BuildInfo>,
+}
+
+/// Events seen and kept by the writer's sampler, per kind.
+#[derive(Default)]
+pub(crate) struct SamplingCounters {
+    pub(crate) logs_seen: AtomicU64,
+    pub(crate) logs_kept: AtomicU64,
+    pub(crate) spans_seen: AtomicU64,
+    pub(crate) spans_kept: AtomicU64,
+}
+
+/// Internal counters and gauges describing the writer and its outputs.
+#[derive(Default)]
+pub(crate) struct PipelineCounters {
+    /// When the writer's event loop started (epoch ms); 0 until then.
+    pub(crate) writer_started_ms: AtomicU64,
+    /// Set while the event loop runs; cleared once it has shut down.
+    pub(crate) writer_running: AtomicBool,
+    pub(crate) events_processed: AtomicU64,
+    /// Events not written because the sampler dropped them.
+    pub(crate) events_dropped: AtomicU64,
+    pub(crate) future_clamped: AtomicU64,
+    pub(crate) storage_failures: AtomicU64,
+    /// Records that couldn't be encoded for storage and were not stored.
+    pub(crate) serialize_errors: AtomicU64,
+    pub(crate) mirror_failures: AtomicU64,
+    /// Mirror writes dropped because a mirror's queue was full.
+    pub(crate) mirror_dropped: AtomicU64,
+    /// Records an output failed to write.
+    pub(crate) output_failures: AtomicU64,
+    pub(crate) file_bytes_written: AtomicU64,
+    /// Records a file output dropped because its file couldn't be opened.
+    pub(crate) file_dropped_no_writer: AtomicU64,
+    /// Log and span events whose level wasn't recognized (stored as INFO).
+    pub(crate) unknown_levels: AtomicU64,
+    /// Events whose time field was missing or unparseable (stored with the
+    /// current time).
+    pub(crate) invalid_timestamps: AtomicU64,
+    /// Log events below their target's `targetLevels` threshold.
+    pub(crate) level_filtered: AtomicU64,
+    /// Spans dropped for being shorter than `minSpanDurationMs`.
+    pub(crate) short_spans_filtered: AtomicU64,
+    /// Events `transformScript` dropped.
+    pub(crate) transform_dropped: AtomicU64,
+    /// Events `transformScript` failed on.
+    pub(crate) transform_errors: AtomicU64,
+    /// Log and span events dropped by `rateLimits`.
+    pub(crate) rate_limited: AtomicU64,
+    /// Times `adaptiveSampling` started a boost.
+    pub(crate) adaptive_boosts: AtomicU64,
+    /// End of the current `adaptiveSampling` boost (epoch ms); 0 when none
+    /// has run.
+    pub(crate) adaptive_boost_until_ms: AtomicU64,
+    /// Throughput over the last report interval in `ingestMode: count`.
+    pub(crate) events_per_sec: AtomicU64,
+    /// Repeated log lines suppressed by `dedup` (counted in summaries).
+    pub(crate) logs_deduplicated: AtomicU64,
+    /// Per-kind breakdown of the processed, dropped, and storage failure
+    /// counts, exported with a `kind` attribute.
+    pub(crate) by_kind: KindCounters,
+    /// Delay between an event's timestamp and its processing, last observed.
+    pub(crate) lag_ms: AtomicU64,
+    /// Events waiting in the writer's channel, last observed.
+    pub(crate) queue_depth: AtomicU64,
+    pub(crate) queue_capacity: AtomicU64,
+    /// Deepest the channel has been since startup.
+    pub(crate) queue_high_water: AtomicU64,
+    /// Receives that found the channel full: producers were blocked, or
+    /// dropping events if they use non-blocking sends.
+    pub(crate) queue_saturated: AtomicU64,
+    pub(crate) otlp_buffer_dropped: AtomicU64,
+    pub(crate) otlp_spooled: AtomicU64,
+    /// Metric exports that failed after all retries.
+    pub(crate) otlp_export_failures: AtomicU64,
+    /// Records the OTLP output skipped while its circuit breaker was open.
+    pub(crate) otlp_skipped: AtomicU64,
+    /// Log records not indexed: queue full, bulk request failed after its
+    /// retry, or the document was rejected.
+    pub(crate) elasticsearch_dropped: AtomicU64,
+    /// Log records not pushed to Loki: queue full or push failed.
+    pub(crate) loki_dropped: AtomicU64,
+    /// Records not published to Kafka: buffer full or delivery timed out.
+    pub(crate) kafka_dropped: AtomicU64,
+    /// Log records not sent to syslog: queue full or send failed.
+    pub(crate) syslog_dropped: AtomicU64,
+    /// Rotated files uploaded to the archive and deleted locally.
+    pub(crate) archive_uploads: AtomicU64,
+    /// Archive uploads that failed after all retries; the file is kept.
+    pub(crate) archive_failures: AtomicU64,
+    /// Compressions and uploads not run because the output pool's queue was
+    /// full; the file is kept.
+    pub(crate) output_ops_dropped: AtomicU64,
+    /// Spooled batches deleted to keep the spool within its size cap.
+    pub(crate) otlp_spool_dropped: AtomicU64,
+    /// Custom metric records not forwarded because `otlpMaxInstruments`
+    /// names already had instruments.
+    pub(crate) otlp_instruments_rejected: AtomicU64,
+    /// OTLP data points past an instrument's cardinality limit.
+    pub(crate) cardinality_overflows: AtomicU64,
+}
+
+/// Counters kept per record kind, indexed by `RecordKind::index`.
+#[derive(Default)]
+pub(crate) struct KindCounters {
+    pub(crate) processed: [AtomicU64; 3],
+    pub(crate) dropped: [AtomicU64; 3],
+    pub(crate) write_errors: [AtomicU64; 3],
+}
+
+impl KindCounters {
+    /// Events processed so far as `[logs, spans, metrics]`.
+    pub(crate) fn totals(&self) -> [u64; 3] {
+        self.processed.each_ref().map(|c| c.load(Ordering::Relaxed))
     }
 }
 
-/// Background writer that receives tracing events as JSON and persists them
-/// to tables, files, and PubSub for SSE.
-struct TelemetryWriter {
-    log_storage: Arc<dyn KvBackend>,
-    span_storage: Option<Arc<dyn KvBackend>>,
-    metric_storage: Option<Arc<dyn KvBackend>>,
-    pubsub: Option<Arc<PubSubManager>>,
-    outputs: Vec<Box<dyn TelemetryOutput>>,
+/// Storage backends of the telemetry tables that exist in this deployment.
+pub(crate) struct TableStores {
+    pub(crate) log: Option<Arc<dyn KvBackend>>,
+    /// `severityTables` table holding WARN and ERROR logs.
+    pub(crate) error_log: Option<Arc<dyn KvBackend>>,
+    pub(crate) span: Option<Arc<dyn KvBackend>>,
+    pub(crate) metric: Option<Arc<dyn KvBackend>>,
+    /// `metricRetention` rollups, when configured and the table exists.
+    pub(crate) metric_rollup: Option<Arc<dyn KvBackend>>,
+    /// `indexedSpanFields` index, when fields are configured and the table exists.
+    pub(crate) span_index: Option<Arc<dyn KvBackend>>,
+    /// `mirrorTables` backends, shared with the writer.
+    pub(crate) mirrors: Arc<Mirrors>,
+    pub(crate) pubsub: Option<Arc<PubSubManager>>,
+    /// PubSub table names, indexed by `RecordKind::index`.
+    pub(crate) pubsub_tables: [String; 3],
 }
 
-impl TelemetryWriter {
-    fn new(
-        log_storage: Arc<dyn KvBackend>,
-        span_storage: Option<Arc<dyn KvBackend>>,
-        metric_storage: Option<Arc<dyn KvBackend>>,
-        pubsub: Option<Arc<PubSubManager>>,
-    ) -> Self {
-        Self {
-            log_storage,
-            span_storage,
-            metric_storage,
-            pubsub,
-            outputs: Vec::new(),
+impl TableStores {
+    /// The tables holding log records, optionally only those that can hold
+    /// `level`: the default table, then the error table when configured.
+    pub(crate) fn log_tables(&self, level: Option<&str>) -> Vec<Arc<dyn KvBackend>> {
+        let Some(error_log) = &self.error_log else {
+            return self.log.iter().cloned().collect();
+        };
+        match level.map(is_severe_level) {
+            None => self.log.iter().chain(Some(error_log)).cloned().collect(),
+            Some(true) => vec![Arc::clone(error_log)],
+            Some(false) => self.log.iter().cloned().collect(),
         }
     }
+}
 
-    fn add_output(mut self, output: Box<dyn TelemetryOutput>) -> Self {
-        self.outputs.push(output);
-        self
+static SHARED: OnceLock<SharedState> = OnceLock::new();
+
+/// Access the extension's shared state, creating it on first use.
+pub(crate) fn shared() -> &'static SharedState {
+    SHARED.get_or_init(SharedState::new)
+}
+
+impl SharedState {
+    fn new() -> Self {
+        Self {
+            http_metrics: Arc::new(Mutex::new(HttpMetrics::default())),
+            recent: OnceLock::new(),
+            event_stats: OnceLock::new(),
+            prometheus: OnceLock::new(),
+            tables: OnceLock::new(),
+            log_dir: OnceLock::new(),
+            effective_config: ArcSwapOption::empty(),
+            live: ArcSwap::from_pointee(LiveSettings::default()),
+            root_dir: OnceLock::new(),
+            reload_lock: Mutex::new(()),
+            sampling: SamplingCounters::default(),
+            pipeline: PipelineCounters::default(),
+            output_health: Mutex::new(Vec::new()),
+            health_published_ms: AtomicU64::new(0),
+            otlp_breakers: OnceLock::new(),
+            build_info: OnceLock::new(),
+        }
     }
+}
 
-    /// Main event loop — receives JSON events and dispatches by kind.
-    async fn run_loop(mut self, mut rx: tokio::sync::mpsc::Receiver<serde_json::Value>) {
-        eprintln!("[telemetry-writer] Started");
-        let mut log_count: u64 = 0;
-        let mut span_count: u64 = 0;
-        let mut metric_count: u64 = 0;
+/// Record ids are UUIDv7 strings (lowercase hex), so every record key falls in
+/// `[record_key_at(0.0), RECORD_KEY_END)`.
+pub(crate) const RECORD_KEY_END: &[u8] = b"g";
 
-        while let Some(event) = rx.recv().await {
-            let kind = event
-                .get("kind")
-                .and_then(|v| v.as_str())
-                .unwrap_or("unknown");
+/// Table holding the `indexedSpanFields` index, kept out of the Span table
+/// so its entries never show up in span listings.
+const SPAN_INDEX_TABLE: &str = "SpanIndex";
 
-            match kind {
-                "log" => {
-                    log_count += 1;
-                    self.write_log(&event).await;
-                }
-                "span" => {
-                    span_count += 1;
-                    self.write_span(&event).await;
-                }
-                "metric" => {
-                    metric_count += 1;
-                    self.write_metric(&event).await;
-                }
-                _ => {}
-            }
+/// Smallest record key that can have been assigned at or after `ms` (epoch
+/// milliseconds): the UUIDv7 timestamp prefix. Ids are assigned when the
+/// writer stores a record, so this bounds scans by write time.
+pub(crate) fn record_key_at(ms: f64) -> String {
+    let ms = (ms.max(0.0) as u64) & 0xffff_ffff_ffff;
+    format!("{:08x}-{:04x}", ms >> 16, ms & 0xffff)
+}
 
-            // Periodic status (every 1000 events)
-            let total = log_count + span_count + metric_count;
-            if total % 1000 == 0 && total > 0 {
-                eprintln!(
-                    "[telemetry-writer] Processed {} events (logs={}, spans={}, metrics={})",
-                    total, log_count, span_count, metric_count
-                );
-            }
-        }
+/// A stored record's timestamp in epoch seconds; stored timestamps are
+/// "secs.micros" strings.
+pub(crate) fn record_time_secs(record: &serde_json::Value) -> Option<f64> {
+    record.get("timestamp")?.as_str()?.parse().ok()
+}
 
-        eprintln!(
-            "[telemetry-writer] Shutting down (logs={}, spans={}, metrics={})",
-            log_count, span_count, metric_count
-        );
+/// Optional numeric query parameter; `Err` when present but not a number.
+pub(crate) fn parse_param(value: Option<&str>) -> std::result::Result<Option<f64>, ()> {
+    match value {
+        None | Some("") => Ok(None),
+        Some(v) => v.parse().map(Some).map_err(|_| ()),
     }
+}
 
-    async fn write_log(&mut self, event: &serde_json::Value) {
-        let id = generate_id_v7();
-        let timestamp = event.get("timestamp").and_then(|v| v.as_f64()).unwrap_or(0.0);
-        let record = json!({
-            "id": id,
-            "timestamp": format_epoch_ms(timestamp),
-            "level": event.get("level").and_then(|v| v.as_str()).unwrap_or("INFO"),
-            "target": event.get("target").and_then(|v| v.as_str()).unwrap_or(""),
-            "message": event.get("message").and_then(|v| v.as_str()).unwrap_or(""),
-            "fields": serde_json::to_string(
-                event.get("fields").unwrap_or(&json!({}))
-            ).unwrap_or_default(),
-        });
+/// The writer's `maxFutureSkew` in seconds, from the effective configuration.
+/// A record's id is never earlier than its timestamp minus this, so query
+/// resources subtract it from `since` before bounding a scan with
+/// [`record_key_at`].
+pub(crate) fn max_future_skew_secs() -> f64 {
+    shared()
+        .effective_config
+        .load_full()
+        .and_then(|c| c["maxFutureSkew"].as_f64())
+        .unwrap_or(0.0)
+}
 
-        if let Ok(bytes) = to_storage_bytes(&record) {
-            let _ = self.log_storage.put(id.as_bytes(), &bytes).await;
-        }
+/// The writer's `indexedSpanFields`, from the effective configuration.
+pub(crate) fn indexed_span_fields() -> Vec<String> {
+    let config = shared().effective_config.load_full();
+    config
+        .as_ref()
+        .and_then(|c| c["indexedSpanFields"].as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|f| f.as_str().map(str::to_string))
+        .collect()
+}
 
-        if let Some(ref ps) = self.pubsub {
-            ps.notify_update("Log", &id, &record).await;
-        }
+/// Scan `[start, end)` of a table and decode each stored record.
+/// Records that fail to decode are skipped.
+pub(crate) async fn scan_records(
+    storage: &Arc<dyn KvBackend>,
+    start: &[u8],
+    end: &[u8],
+) -> Result<Vec<serde_json::Value>> {
+    let entries = storage.scan_range(start, end).await?;
+    Ok(entries
+        .iter()
+        .filter_map(|(_, bytes)| from_storage_bytes(bytes).ok())
+        .collect())
+}
 
-        for output in &mut self.outputs {
-            output.write_log(&record);
+/// Delete every record stored before `before_ms` (epoch ms) from a table,
+/// returning how many were deleted. Ids are time-ordered, so only the keys
+/// below `record_key_at(before_ms)` are scanned, one write-time page at a
+/// time from `cursor`, which a caller purging the same table repeatedly
+/// keeps so each purge starts where the last one stopped. With `mirror`,
+/// each delete is also queued for that kind's mirror. With `span_index`
+/// (the SpanIndex table and `indexedSpanFields`), each deleted span's index
+/// entries go too; index keys are ordered by field and value rather than
+/// time, so they are found from the spans in each page.
+pub(crate) async fn purge_before(
+    storage: &Arc<dyn KvBackend>,
+    cursor: &mut WriteTimeCursor,
+    before_ms: f64,
+    mirror: Option<(&Mirrors, RecordKind)>,
+    span_index: Option<(&Arc<dyn KvBackend>, &[String])>,
+) -> Result<u64> {
+    let mut deleted = 0;
+    while let Some((start, end)) = cursor.page(before_ms) {
+        let entries = storage
+            .scan_range(record_key_at(start).as_bytes(), record_key_at(end).as_bytes())
+            .await?;
+        cursor.advance(end, entries.len());
+        for (key, bytes) in entries {
+            storage.delete(&key).await?;
+            if let Some((mirrors, kind)) = mirror {
+                mirrors.delete(kind, &key);
+            }
+            if let Some((index, indexed)) = span_index {
+                for index_key in stored_span_index_keys(indexed, &bytes, &key) {
+                    index.delete(index_key.as_bytes()).await?;
+                }
+            }
+            deleted += 1;
         }
     }
+    Ok(deleted)
+}
 
-    async fn write_span(&mut self, event: &serde_json::Value) {
-        let storage = match &self.span_storage {
-            Some(s) => s,
-            None => return,
-        };
+/// Lock a mutex, recovering the data if a panicking holder poisoned it.
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
 
-        let id = generate_id_v7();
-        let start_ms = event.get("startTime").and_then(|v| v.as_f64()).unwrap_or(0.0);
-        let end_ms = event.get("endTime").and_then(|v| v.as_f64()).unwrap_or(0.0);
-        let duration_ms = end_ms - start_ms;
+// ============================================================================
+// Configuration — `telemetry` section of yeti-config.yaml
+// ============================================================================
 
-        let record = json!({
-            "id": id,
-            "name": event.get("name").and_then(|v| v.as_str()).unwrap_or(""),
-            "target": event.get("target").and_then(|v| v.as_str()).unwrap_or(""),
-            "level": event.get("level").and_then(|v| v.as_str()).unwrap_or("INFO"),
-            "startTime": format_epoch_ms(start_ms),
-            "endTime": format_epoch_ms(end_ms),
-            "durationMs": duration_ms,
-            "fields": serde_json::to_string(
-                event.get("fields").unwrap_or(&json!({}))
-            ).unwrap_or_default(),
-        });
+/// Default allowance for producer clocks running ahead of ours (1 hour).
+const DEFAULT_MAX_FUTURE_SKEW_SECS: f64 = 3600.0;
 
-        if let Ok(bytes) = to_storage_bytes(&record) {
-            let _ = storage.put(id.as_bytes(), &bytes).await;
-        }
+/// How often expired records are swept when `recordTtlRules` is set (1 minute).
+const EXPIRY_SWEEP_INTERVAL_MS: f64 = 60_000.0;
 
-        if let Some(ref ps) = self.pubsub {
-            ps.notify_update("Span", &id, &record).await;
-        }
+/// Most records one expiry sweep examines per table; the rest wait for the
+/// next sweep.
+const EXPIRY_SWEEP_MAX_RECORDS: usize = 10_000;
 
-        for output in &mut self.outputs {
-            output.write_span(&record);
-        }
-    }
+/// Most write-time pages one expiry sweep scans per table, empty or not.
+/// Empty pages are a cheap scan each, and this is enough day-wide pages for
+/// a new cursor to cross the decades before a table's first record in one
+/// sweep.
+const EXPIRY_SWEEP_MAX_PAGES: usize = 32_768;
 
-    async fn write_metric(&mut self, event: &serde_json::Value) {
-        let storage = match &self.metric_storage {
-            Some(s) => s,
-            None => return,
-        };
+/// Write-time span of the first page an expiry sweep scans (1 minute).
+const EXPIRY_SWEEP_PAGE_MS: f64 = 60_000.0;
 
-        let id = generate_id_v7();
-        let timestamp = event.get("timestamp").and_then(|v| v.as_f64()).unwrap_or(0.0);
-        let record = json!({
-            "id": id,
-            "name": event.get("name").and_then(|v| v.as_str()).unwrap_or(""),
-            "value": event.get("value").and_then(|v| v.as_f64()).unwrap_or(0.0),
-            "attributes": serde_json::to_string(
-                event.get("attributes").unwrap_or(&json!({}))
-            ).unwrap_or_default(),
-            "timestamp": format_epoch_ms(timestamp),
-        });
+/// Widest write-time page a cursor scans (1 day), so the page that reaches
+/// a table's first records after an empty stretch holds at most a day of
+/// them.
+const EXPIRY_SWEEP_MAX_PAGE_MS: f64 = 86_400_000.0;
 
-        if let Ok(bytes) = to_storage_bytes(&record) {
-            let _ = storage.put(id.as_bytes(), &bytes).await;
-        }
+/// How often `ingestMode: count` logs throughput and updates the rate shown
+/// by the status endpoint.
+const COUNT_REPORT_INTERVAL_MS: f64 = 5_000.0;
 
-        if let Some(ref ps) = self.pubsub {
-            ps.notify_update("Metric", &id, &record).await;
-        }
+/// Table writes buffered per kind before a batch is flushed.
+const TABLE_BATCH_MAX_RECORDS: usize = 100;
 
-        for output in &mut self.outputs {
-            output.write_metric(&record);
-        }
-    }
-}
+/// Longest a buffered table write waits for its batch to fill.
+const TABLE_BATCH_MAX_AGE_MS: f64 = 500.0;
 
-impl EventSubscriber for TelemetryWriter {
-    fn run(
-        self: Box<Self>,
-        rx: tokio::sync::mpsc::Receiver<serde_json::Value>,
-    ) -> Pin<Box<dyn std::future::Future<Output = ()> + Send>> {
-        Box::pin(self.run_loop(rx))
-    }
-}
+/// Rollup window for `metricAggregation` when `windowMs` is not given.
+const DEFAULT_METRIC_WINDOW_MS: f64 = 10_000.0;
 
-/// Format epoch milliseconds as "seconds.millis" string for table storage.
-fn format_epoch_ms(ms: f64) -> String {
-    let secs = (ms / 1000.0) as u64;
-    let millis = (ms % 1000.0) as u32;
-    format!("{}.{:03}", secs, millis)
-}
+/// `metricRetention` defaults: raw points kept 7 days, hourly rollups 90.
+const DEFAULT_METRIC_RAW_DAYS: f64 = 7.0;
+const DEFAULT_METRIC_ROLLUP_DAYS: f64 = 90.0;
+const DEFAULT_METRIC_ROLLUP_INTERVAL_SECS: f64 = 3600.0;
 
-// ============================================================================
-// File Provider — JSON Lines file rotation
-// ============================================================================
+/// Table holding `metricRetention` rollups.
+const METRIC_ROLLUP_TABLE: &str = "MetricRollup";
 
-/// File-based telemetry writer with daily rotation.
-struct FileProvider {
-    log_dir: PathBuf,
-    current_date: String,
-    writer: Option<BufWriter<File>>,
-    current_size: u64,
-    max_file_size: u64,
-    retention_days: u32,
-    write_count: u64,
-}
+/// Write-time width of each raw metric scan while rolling up, so a run that
+/// covers days of windows decodes an hour of points at a time.
+const METRIC_ROLLUP_SCAN_PAGE_MS: f64 = 3_600_000.0;
 
-impl FileProvider {
-    fn new(log_dir: PathBuf) -> Self {
-        let _ = fs::create_dir_all(&log_dir);
-        let current_date = today_string();
+/// PubSub table names for logs, spans and metrics when `pubsubTables`
+/// doesn't override them.
+const DEFAULT_PUBSUB_TABLES: [&str; 3] = ["Log", "Span", "Metric"];
 
-        let mut provider = Self {
-            log_dir,
-            current_date,
-            writer: None,
-            current_size: 0,
-            max_file_size: 100 * 1024 * 1024, // 100MB
-            retention_days: 7,
-            write_count: 0,
-        };
-        provider.open_file();
-        provider
-    }
+/// `dedup` window and threshold when not given.
+const DEFAULT_DEDUP_WINDOW_MS: f64 = 10_000.0;
+const DEFAULT_DEDUP_THRESHOLD: u64 = 1;
 
-    fn write_event(&mut self, event_type: &str, record: &serde_json::Value) {
-        self.maybe_rotate();
+/// `sseCoalescing` rate threshold (notifications per second per table) and
+/// batch interval when not given.
+const DEFAULT_SSE_COALESCE_THRESHOLD: u64 = 100;
+const DEFAULT_SSE_COALESCE_INTERVAL_MS: f64 = 100.0;
 
-        let line = json!({
-            "type": event_type,
-            "data": record,
-        });
+/// Targets with their own `rateLimits` bucket at once; events from targets
+/// beyond this are not limited until the writer restarts.
+const RATE_LIMIT_MAX_TARGETS: usize = 10_000;
 
-        if let Some(ref mut w) = self.writer {
-            if let Ok(bytes) = serde_json::to_vec(&line) {
-                let line_len = bytes.len() as u64 + 1;
-                if w.write_all(&bytes).is_ok() && w.write_all(b"\n").is_ok() {
-                    self.current_size += line_len;
-                    self.write_count += 1;
+/// A throttled target is warned about at most this often.
+const RATE_LIMIT_WARN_INTERVAL_MS: f64 = 60_000.0;
 
-                    if self.write_count % 100 == 0 {
-                        let _ = w.flush();
-                    }
-                }
-            }
+/// Distinct log lines tracked by `dedup` at once; lines beyond this are
+/// written without deduplication until windows close.
+const DEDUP_MAX_KEYS: usize = 10_000;
+
+/// How often the writer publishes output health for `/healthz`, even when
+/// idle. A writer silent for `HEALTH_STALE_MS` is reported as stalled.
+pub(crate) const HEALTH_PUBLISH_INTERVAL_MS: f64 = 1_000.0;
+pub(crate) const HEALTH_STALE_MS: u64 = 30_000;
+
+/// Channel backlog, as a fraction of its capacity, at which the writer warns
+/// that it is falling behind.
+const QUEUE_HIGH_WATER_RATIO: f64 = 0.8;
+
+/// Columns the writer sets on log, span, and metric records, which
+/// `promoteFields` may not overwrite.
+const RECORD_COLUMNS: &[&str] = &[
+    "id", "seq", "writerId", "timestamp", "level", "target", "message", "fields",
+    "traceId", "spanId", "parentSpanId", "name", "startTime", "endTime", "durationMs",
+    "value", "attributes", "originalTimestamp", "originalStartTime", "expiresAt",
+];
+
+/// Upper bound on indexed span fields, keeping per-span index writes bounded.
+const MAX_INDEXED_SPAN_FIELDS: usize = 8;
+
+/// Longer field values are not indexed, keeping index keys small.
+const MAX_INDEXED_VALUE_LEN: usize = 256;
+
+/// Writer settings parsed from the `telemetry` block of yeti-config.yaml.
+struct TelemetryConfig {
+    /// Event timestamps more than this many seconds ahead of now are clamped to now.
+    max_future_skew_secs: f64,
+    /// Span `fields` keys maintained in the `spanidx:` secondary index.
+    indexed_span_fields: Vec<String>,
+    /// `fields` keys copied to top-level columns of log and span records.
+    promote_fields: Vec<String>,
+    /// `fields` keys (lowercase) whose values are replaced before anything
+    /// is stored or output, at any depth.
+    redact_fields: Vec<String>,
+    /// Patterns replaced in log messages before anything is stored or output.
+    redact_patterns: Vec<regex::Regex>,
+    /// Line framing used by the file output.
+    file_envelope: FileEnvelope,
+    /// `jsonl` (default) or `csv` with one file per record kind
+    file_format: FileFormat,
+    /// `native` (default) records, or OTLP JSON export requests
+    file_schema: FileSchema,
+    /// Write repeated `fields` entries to a per-file dictionary and
+    /// reference them by id.
+    field_interning: bool,
+    /// When and how the file output rotates, and how long files are kept.
+    file_rotation: FileRotationConfig,
+    /// Gzip files after rotation.
+    compress_rotated_files: bool,
+    /// Threads compressing and uploading rotated files.
+    max_concurrent_output_ops: usize,
+    /// Additional filtered file outputs written next to the main file.
+    file_outputs: Vec<FileOutputConfig>,
+    /// Record kinds accepted per output name; outputs not listed get all kinds.
+    output_kinds: HashMap<String, KindSet>,
+    /// Priority overrides per output name.
+    output_priorities: HashMap<String, i32>,
+    /// Field-based output routing; first match wins.
+    routes: Vec<RouteRule>,
+    /// Outputs receiving records that match no route; `None` means every
+    /// output no route names.
+    default_outputs: Option<Vec<String>>,
+    /// Active deployment environment (`YETI_ENV`) whose section was applied.
+    environment: String,
+    /// Sampling, level, rate limit and retention settings; moved into
+    /// `shared().live` at startup so reloads can replace them.
+    live: LiveSettings,
+    /// JSONL file fed into the pipeline alongside live events.
+    file_input: Option<FileInput>,
+    /// Per-record expiry rules for logs and spans; first match wins.
+    record_ttl_rules: Vec<TtlRule>,
+    /// Seconds after startup during which sampling and load shedding are
+    /// suspended, so boot diagnostics are captured in full.
+    warmup_secs: f64,
+    /// Spans shorter than this are dropped before anything sees them, unless
+    /// they failed.
+    min_span_duration_ms: Option<f64>,
+    /// Raise DEBUG/TRACE log and span sampling while the ERROR rate is high.
+    adaptive_sampling: Option<AdaptiveSampling>,
+    /// Secondary table per kind (`log`/`span`/`metric`) mirroring every write.
+    mirror_tables: HashMap<String, String>,
+    /// Separate table for WARN and ERROR logs, when `severityTables` is set.
+    severity_tables: Option<SeverityTables>,
+    /// Script run on every event after enrichment.
+    transform: Option<TransformScript>,
+    /// Serve HTTP request metrics for Prometheus scrapes.
+    prometheus: bool,
+    /// Records kept in memory for the `recent` endpoint; 0 disables it.
+    recent_capacity: usize,
+    /// Rolling window of the `stats` endpoint in seconds; 0 disables it.
+    stats_window_secs: u64,
+    /// Roll custom metric points up into windows of this many ms instead of
+    /// storing each one; `None` stores raw points.
+    metric_window_ms: Option<f64>,
+    /// Collapse repeated log lines; `None` writes every one.
+    dedup: Option<DedupConfig>,
+    /// Batch PubSub notifications for busy tables; `None` sends each one.
+    sse_coalescing: Option<CoalesceConfig>,
+    /// Roll metric points up into the MetricRollup table and expire both;
+    /// `None` keeps raw points until purged.
+    metric_retention: Option<MetricRetention>,
+    /// Store records in the Log/Span/Metric tables and notify PubSub. When
+    /// false, records only reach the outputs.
+    persist_to_tables: bool,
+    /// Accept `DELETE /yeti-telemetry/logs` purges.
+    allow_purge: bool,
+    /// Where record ids come from.
+    id_source: IdSource,
+    /// `count` only tallies events, for benchmarking the dispatch path.
+    ingest_mode: IngestMode,
+    /// Table names for PubSub notifications, indexed by `RecordKind::index`.
+    pubsub_tables: [String; 3],
+    /// Write an ERROR log for every span whose `status` field is ERROR.
+    derive_error_logs: bool,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            max_future_skew_secs: DEFAULT_MAX_FUTURE_SKEW_SECS,
+            indexed_span_fields: Vec::new(),
+            promote_fields: Vec::new(),
+            redact_fields: Vec::new(),
+            redact_patterns: Vec::new(),
+            file_envelope: FileEnvelope::Wrapped,
+            file_format: FileFormat::Jsonl,
+            file_schema: FileSchema::Native,
+            field_interning: false,
+            file_rotation: FileRotationConfig::default(),
+            compress_rotated_files: true,
+            max_concurrent_output_ops: DEFAULT_MAX_CONCURRENT_OUTPUT_OPS,
+            file_outputs: Vec::new(),
+            output_kinds: HashMap::new(),
+            output_priorities: HashMap::new(),
+            routes: Vec::new(),
+            default_outputs: None,
+            environment: active_environment(),
+            live: LiveSettings::default(),
+            file_input: None,
+            record_ttl_rules: Vec::new(),
+            warmup_secs: 0.0,
+            min_span_duration_ms: None,
+            adaptive_sampling: None,
+            mirror_tables: HashMap::new(),
+            severity_tables: None,
+            transform: None,
+            prometheus: false,
+            recent_capacity: DEFAULT_RECENT_CAPACITY,
+            stats_window_secs: DEFAULT_STATS_WINDOW_SECS,
+            metric_window_ms: None,
+            dedup: None,
+            sse_coalescing: None,
+            metric_retention: None,
+            persist_to_tables: true,
+            allow_purge: false,
+            id_source: IdSource::Generate,
+            ingest_mode: IngestMode::Full,
+            pubsub_tables: DEFAULT_PUBSUB_TABLES.map(str::to_string),
+            derive_error_logs: false,
         }
     }
+}
 
-    fn maybe_rotate(&mut self) {
-        let today = today_string();
-        let size_exceeded = self.current_size >= self.max_file_size;
-
-        if today != self.current_date || size_exceeded {
-            if let Some(ref mut w) = self.writer {
-                let _ = w.flush();
-            }
-            self.writer = None;
-            self.current_date = today;
-            self.current_size = 0;
-            self.open_file();
-            self.cleanup_old_files();
+impl TelemetryConfig {
+    /// Parse writer settings from yeti-config.yaml, falling back to defaults
+    /// when the file or the `telemetry` section is missing.
+    fn from_config(root_dir: &str) -> Self {
+        match read_telemetry_section(root_dir) {
+            Some(telemetry) => Self::from_section(&telemetry, root_dir),
+            None => Self::default(),
         }
     }
 
-    fn open_file(&mut self) {
-        let filename = format!("telemetry-{}.jsonl", self.current_date);
-        let path = self.log_dir.join(&filename);
+    /// Parse writer settings from an already-loaded `telemetry` section.
+    /// Relative paths (`fileInput`, enrichment files) resolve against
+    /// `root_dir`.
+    fn from_section(telemetry: &serde_json::Value, root_dir: &str) -> Self {
+        let mut config = Self::default();
 
-        match OpenOptions::new().create(true).append(true).open(&path) {
-            Ok(file) => {
-                self.current_size = file.metadata().map(|m| m.len()).unwrap_or(0);
-                self.writer = Some(BufWriter::new(file));
-            }
-            Err(e) => {
-                eprintln!("[file-provider] Failed to open {}: {}", path.display(), e);
+        if let Some(skew) = telemetry.get("maxFutureSkew").and_then(|v| v.as_f64()) {
+            if skew >= 0.0 {
+                config.max_future_skew_secs = skew;
+            } else {
+                eprintln!(
+                    "[yeti-telemetry] Ignoring negative maxFutureSkew ({}), using {}s",
+                    skew, DEFAULT_MAX_FUTURE_SKEW_SECS
+                );
             }
         }
-    }
 
-    fn cleanup_old_files(&self) {
-        let cutoff = SystemTime::now()
-            - std::time::Duration::from_secs(u64::from(self.retention_days) * 86400);
+        if let Some(fields) = telemetry.get("indexedSpanFields").and_then(|v| v.as_array()) {
+            config.indexed_span_fields = fields
+                .iter()
+                .filter_map(|f| f.as_str())
+                .filter(|f| !f.is_empty())
+                .map(|f| f.to_string())
+                .collect();
+            if config.indexed_span_fields.len() > MAX_INDEXED_SPAN_FIELDS {
+                eprintln!(
+                    "[yeti-telemetry] indexedSpanFields limited to the first {} entries",
+                    MAX_INDEXED_SPAN_FIELDS
+                );
+                config.indexed_span_fields.truncate(MAX_INDEXED_SPAN_FIELDS);
+            }
+        }
 
-        if let Ok(entries) = fs::read_dir(&self.log_dir) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if path.extension().and_then(|e| e.to_str()) == Some("jsonl") {
-                    if let Ok(meta) = path.metadata() {
-                        if let Ok(modified) = meta.modified() {
-                            if modified < cutoff {
-                                let _ = fs::remove_file(&path);
-                                eprintln!(
-                                    "[file-provider] Cleaned up old file: {}",
-                                    path.display()
-                                );
-                            }
+        if let Some(fields) = telemetry.get("promoteFields").and_then(|v| v.as_array()) {
+            for field in fields.iter().filter_map(|f| f.as_str()).filter(|f| !f.is_empty()) {
+                if RECORD_COLUMNS.contains(&field) {
+                    eprintln!(
+                        "[yeti-telemetry] Ignoring promoteFields entry '{}': it is a record column",
+                        field
+                    );
+                } else if !config.promote_fields.iter().any(|f| f == field) {
+                    config.promote_fields.push(field.to_string());
+                }
+            }
+        }
+
+        if let Some(fields) = telemetry.get("redactFields").and_then(|v| v.as_array()) {
+            config.redact_fields = fields
+                .iter()
+                .filter_map(|f| f.as_str())
+                .filter(|f| !f.is_empty())
+                .map(|f| f.to_ascii_lowercase())
+                .collect();
+        }
+
+        if let Some(patterns) = telemetry.get("redactPatterns").and_then(|v| v.as_array()) {
+            for pattern in patterns.iter().filter_map(|p| p.as_str()) {
+                match regex::Regex::new(pattern) {
+                    Ok(re) => config.redact_patterns.push(re),
+                    Err(e) => eprintln!(
+                        "[yeti-telemetry] Ignoring invalid redactPatterns entry '{}': {}",
+                        pattern, e
+                    ),
+                }
+            }
+        }
+
+        if let Some(envelope) = telemetry.get("fileEnvelope").and_then(|v| v.as_str()) {
+            match FileEnvelope::parse(envelope) {
+                Some(e) => config.file_envelope = e,
+                None => eprintln!(
+                    "[yeti-telemetry] Unknown fileEnvelope '{}', using 'wrapped'",
+                    envelope
+                ),
+            }
+        }
+
+        if let Some(format) = telemetry.get("fileFormat").and_then(|v| v.as_str()) {
+            match FileFormat::parse(format) {
+                Some(f) => config.file_format = f,
+                None => eprintln!(
+                    "[yeti-telemetry] Unknown fileFormat '{}', using 'jsonl'",
+                    format
+                ),
+            }
+        }
+
+        match telemetry.get("fileSchema").and_then(|v| v.as_str()) {
+            None | Some("native") => {}
+            Some("otel") if config.file_format == FileFormat::Csv => {
+                eprintln!("[yeti-telemetry] Ignoring fileSchema 'otel': it requires fileFormat 'jsonl'");
+            }
+            Some("otel") => config.file_schema = FileSchema::Otel,
+            Some(other) => eprintln!(
+                "[yeti-telemetry] Unknown fileSchema '{}', using 'native'",
+                other
+            ),
+        }
+
+        if telemetry.get("fieldInterning").and_then(|v| v.as_bool()).unwrap_or(false) {
+            if config.file_format == FileFormat::Csv
+                || config.file_schema == FileSchema::Otel
+                || config.file_envelope == FileEnvelope::None
+            {
+                eprintln!(
+                    "[yeti-telemetry] Ignoring fieldInterning: it requires fileFormat 'jsonl' with the 'wrapped' or 'flattened' envelope"
+                );
+            } else {
+                config.field_interning = true;
+            }
+        }
+
+        match telemetry.get("rotationStyle").and_then(|v| v.as_str()) {
+            None | Some("dated") => {}
+            Some("numbered") => {
+                let count = telemetry
+                    .get("rotationCount")
+                    .and_then(|v| v.as_u64())
+                    .filter(|c| *c > 0)
+                    .map(|c| c as u32)
+                    .unwrap_or(DEFAULT_ROTATION_COUNT);
+                config.file_rotation.style = RotationStyle::Numbered { count };
+            }
+            Some(other) => eprintln!(
+                "[yeti-telemetry] Unknown rotationStyle '{}', using 'dated'",
+                other
+            ),
+        }
+
+        if let Some(section) = telemetry.get("fileRotation") {
+            config.file_rotation.apply_section(section);
+        }
+        config.live.retention_days = config.file_rotation.retention_days;
+
+        if let Some(compress) = telemetry.get("compressRotatedFiles").and_then(|v| v.as_bool()) {
+            config.compress_rotated_files = compress;
+        }
+
+        if let Some(section) = telemetry.get("metricAggregation") {
+            if section.get("enabled").and_then(|v| v.as_bool()) == Some(true) {
+                let window = match section.get("windowMs").map(|v| v.as_f64()) {
+                    None => DEFAULT_METRIC_WINDOW_MS,
+                    Some(Some(ms)) if ms >= 1.0 => ms.floor(),
+                    Some(_) => {
+                        eprintln!(
+                            "[yeti-telemetry] Ignoring metricAggregation.windowMs (must be >= 1), using {}",
+                            DEFAULT_METRIC_WINDOW_MS
+                        );
+                        DEFAULT_METRIC_WINDOW_MS
+                    }
+                };
+                config.metric_window_ms = Some(window);
+            }
+        }
+
+        if let Some(section) = telemetry.get("dedup") {
+            config.dedup = Some(DedupConfig::parse(section));
+        }
+
+        if let Some(section) = telemetry.get("sseCoalescing") {
+            config.sse_coalescing = Some(CoalesceConfig::parse(section));
+        }
+
+        if let Some(section) = telemetry.get("metricRetention") {
+            config.metric_retention = Some(MetricRetention::parse(section));
+        }
+
+        if let Some(persist) = telemetry.get("persistToTables").and_then(|v| v.as_bool()) {
+            config.persist_to_tables = persist;
+        }
+
+        match telemetry.get("ingestMode").and_then(|v| v.as_str()) {
+            None | Some("full") => {}
+            Some("count") => config.ingest_mode = IngestMode::Count,
+            Some(other) => {
+                eprintln!("[yeti-telemetry] Unknown ingestMode '{}', using 'full'", other);
+            }
+        }
+
+        if let Some(allow) = telemetry.get("allowPurge").and_then(|v| v.as_bool()) {
+            config.allow_purge = allow;
+        }
+
+        match telemetry.get("idSource").and_then(|v| v.as_str()) {
+            None | Some("generate") => {}
+            Some("field") => match telemetry.get("idField").and_then(|v| v.as_str()) {
+                Some(field) if !field.is_empty() => config.id_source = IdSource::Field(field.to_string()),
+                _ => eprintln!("[yeti-telemetry] idSource 'field' needs idField; generating ids"),
+            },
+            Some(other) => {
+                eprintln!("[yeti-telemetry] Unknown idSource '{}', using 'generate'", other);
+            }
+        }
+
+        if let Some(derive) = telemetry.get("deriveErrorLogs").and_then(|v| v.as_bool()) {
+            config.derive_error_logs = derive;
+        }
+
+        if let Some(section) = telemetry.get("pubsubTables") {
+            for kind in RecordKind::ALL {
+                match section.get(kind.as_str()).map(|v| v.as_str()) {
+                    None => {}
+                    Some(Some(name)) if !name.is_empty() => {
+                        config.pubsub_tables[kind.index()] = name.to_string();
+                    }
+                    Some(_) => eprintln!(
+                        "[yeti-telemetry] Ignoring pubsubTables.{} (must be a table name)",
+                        kind.as_str()
+                    ),
+                }
+            }
+        }
+
+        if let Some(outputs) = telemetry.get("fileOutputs").and_then(|v| v.as_array()) {
+            for output in outputs.iter().filter_map(FileOutputConfig::parse) {
+                if config.file_outputs.iter().any(|o| o.prefix == output.prefix) {
+                    eprintln!(
+                        "[yeti-telemetry] Ignoring duplicate fileOutputs prefix '{}'",
+                        output.prefix
+                    );
+                    continue;
+                }
+                config.file_outputs.push(output);
+            }
+        }
+
+        if let Some(prometheus) = telemetry.get("prometheus").and_then(|v| v.as_bool()) {
+            config.prometheus = prometheus;
+        }
+
+        if let Some(capacity) = telemetry.get("recentCapacity").and_then(|v| v.as_u64()) {
+            config.recent_capacity = capacity as usize;
+        }
+        match telemetry.get("maxConcurrentOutputOps").map(|v| v.as_u64()) {
+            None => {}
+            Some(Some(ops)) if ops > 0 => config.max_concurrent_output_ops = ops as usize,
+            Some(_) => eprintln!(
+                "[yeti-telemetry] Ignoring maxConcurrentOutputOps (must be a positive whole number), using {}",
+                DEFAULT_MAX_CONCURRENT_OUTPUT_OPS
+            ),
+        }
+        match telemetry.get("statsWindowSecs").map(|v| v.as_u64()) {
+            None => {}
+            Some(Some(secs)) => config.stats_window_secs = secs,
+            Some(None) => eprintln!(
+                "[yeti-telemetry] Ignoring statsWindowSecs (must be a whole number of seconds), using {}",
+                DEFAULT_STATS_WINDOW_SECS
+            ),
+        }
+
+        if let Some(outputs) = telemetry.get("outputs").and_then(|v| v.as_object()) {
+            for (name, output) in outputs {
+                if let Some(kinds) = output.get("kinds").and_then(|v| v.as_array()) {
+                    let mut set = KindSet {
+                        log: false,
+                        span: false,
+                        metric: false,
+                    };
+                    for kind in kinds.iter().filter_map(|k| k.as_str()) {
+                        match RecordKind::parse(kind) {
+                            Some(RecordKind::Log) => set.log = true,
+                            Some(RecordKind::Span) => set.span = true,
+                            Some(RecordKind::Metric) => set.metric = true,
+                            None => eprintln!(
+                                "[yeti-telemetry] Unknown kind '{}' for output '{}'",
+                                kind, name
+                            ),
                         }
                     }
+                    config.output_kinds.insert(name.clone(), set);
+                }
+                if let Some(priority) = output.get("priority").and_then(|v| v.as_i64()) {
+                    config.output_priorities.insert(name.clone(), priority as i32);
+                }
+            }
+        }
+
+        if let Some(rate) = parse_sample_rate(telemetry, "logSampleRate") {
+            config.live.log_sample_rate = rate;
+        }
+        if let Some(rate) = parse_sample_rate(telemetry, "spanSampleRate") {
+            config.live.span_sample_rate = rate;
+        }
+        if let Some(section) = telemetry.get("sampling") {
+            config.live.sampling = SamplingRules::parse(section);
+        }
+        config.live.target_levels = TargetLevels::parse(
+            telemetry.get("targetLevels"),
+            telemetry.get("defaultTargetLevel"),
+        );
+        if let Some(section) = telemetry.get("rateLimits") {
+            config.live.rate_limits = RateLimits::parse(section);
+        }
+        if config.live.log_sample_rate < 1.0 || config.live.span_sample_rate < 1.0 {
+            eprintln!(
+                "[yeti-telemetry] Sampling ({}): logs={}, spans={}",
+                config.environment, config.live.log_sample_rate, config.live.span_sample_rate
+            );
+        }
+        if !config.live.sampling.is_empty() {
+            eprintln!(
+                "[yeti-telemetry] Sampling rules ({}): {}",
+                config.environment,
+                config.live.sampling.to_json()
+            );
+        }
+        if let Some(section) = telemetry.get("adaptiveSampling") {
+            config.adaptive_sampling = AdaptiveSampling::parse(section);
+        }
+
+        config.file_input = FileInput::from_config(telemetry, root_dir);
+
+        if let Some(warmup) = telemetry.get("warmupSecs").and_then(|v| v.as_f64()) {
+            config.warmup_secs = warmup.max(0.0);
+        }
+
+        match telemetry.get("minSpanDurationMs") {
+            None => {}
+            Some(value) => match value.as_f64() {
+                Some(ms) if ms >= 0.0 => config.min_span_duration_ms = (ms > 0.0).then_some(ms),
+                _ => eprintln!(
+                    "[yeti-telemetry] Ignoring minSpanDurationMs {}: expected a non-negative number",
+                    value
+                ),
+            },
+        }
+
+        if let Some(mirrors) = telemetry.get("mirrorTables").and_then(|v| v.as_object()) {
+            for (kind, table) in mirrors {
+                match (RecordKind::parse(kind), table.as_str()) {
+                    (Some(_), Some(table)) => {
+                        config.mirror_tables.insert(kind.clone(), table.to_string());
+                    }
+                    _ => eprintln!("[yeti-telemetry] Ignoring mirrorTables entry '{}'", kind),
+                }
+            }
+        }
+
+        if let Some(path) = telemetry.get("transformScript").and_then(|v| v.as_str()).filter(|p| !p.is_empty()) {
+            config.transform = TransformScript::from_config(telemetry, PathBuf::from(root_dir).join(path));
+        }
+
+        if let Some(section) = telemetry.get("severityTables") {
+            config.severity_tables = SeverityTables::parse(section);
+        }
+
+        if let Some(rules) = telemetry.get("enrichment").and_then(|v| v.as_array()) {
+            config.live.enrichment = rules
+                .iter()
+                .filter_map(|rule| Enrichment::parse(rule, root_dir))
+                .collect();
+        }
+
+        if let Some(rules) = telemetry.get("recordTtlRules").and_then(|v| v.as_array()) {
+            config.record_ttl_rules = rules.iter().filter_map(TtlRule::parse).collect();
+        }
+
+        if let Some(rules) = telemetry.get("routes").and_then(|v| v.as_array()) {
+            config.routes = rules.iter().filter_map(RouteRule::parse).collect();
+        }
+        if let Some(names) = telemetry.get("defaultOutputs").and_then(|v| v.as_array()) {
+            config.default_outputs = Some(names.iter().filter_map(|n| n.as_str()).map(str::to_string).collect());
+        }
+
+        config
+    }
+}
+
+impl TelemetryConfig {
+    /// The resolved settings in config-file key names, for the config endpoint.
+    fn to_json(&self) -> serde_json::Value {
+        let mut outputs: serde_json::Map<String, serde_json::Value> = self
+            .output_kinds
+            .iter()
+            .map(|(name, kinds)| (name.clone(), json!({ "kinds": kinds.names() })))
+            .collect();
+        for (name, priority) in &self.output_priorities {
+            outputs.entry(name.clone()).or_insert_with(|| json!({}))["priority"] = json!(priority);
+        }
+        // File, routing, transform, sampling and table settings are built
+        // separately to keep the object below within `json!`'s recursion limit.
+        let file_settings = json!({
+            "fileEnvelope": self.file_envelope.as_str(),
+            "fileFormat": self.file_format.as_str(),
+            "fileSchema": self.file_schema.as_str(),
+            "fieldInterning": self.field_interning,
+        });
+        let routing = json!({
+            "routes": self
+                .routes
+                .iter()
+                .map(|rule| json!({
+                    "match": rule.fields.iter().cloned().collect::<serde_json::Map<_, _>>(),
+                    "outputs": rule.outputs,
+                }))
+                .collect::<Vec<_>>(),
+            "defaultOutputs": self.default_outputs,
+        });
+        let transform_settings = json!({
+            "transformScript": self.transform.as_ref().map(|t| t.path.display().to_string()),
+            "transformMaxOperations": self.transform.as_ref().map(|t| t.max_operations),
+            "transformOnError": self.transform.as_ref().map(|t| if t.drop_on_error { "drop" } else { "keep" }),
+        });
+        let sampling_settings = json!({
+            "adaptiveSampling": self.adaptive_sampling.as_ref().map(AdaptiveSampling::to_json),
+        });
+        let table_settings = json!({
+            "mirrorTables": self.mirror_tables,
+            "severityTables": self.severity_tables.as_ref().map(|t| json!({ "error": t.error, "default": t.default })),
+        });
+        let mut config = json!({
+            "environment": self.environment,
+            "maxFutureSkew": self.max_future_skew_secs,
+            "indexedSpanFields": self.indexed_span_fields,
+            "promoteFields": self.promote_fields,
+            "redactFields": self.redact_fields,
+            "redactPatterns": self
+                .redact_patterns
+                .iter()
+                .map(|re| re.as_str())
+                .collect::<Vec<_>>(),
+            "rotationStyle": self.file_rotation.style.as_str(),
+            "rotationCount": match self.file_rotation.style {
+                RotationStyle::Numbered { count } => json!(count),
+                RotationStyle::Dated => serde_json::Value::Null,
+            },
+            "fileRotation": {
+                "maxFileSizeMb": self.file_rotation.max_file_size / (1024 * 1024),
+                "retentionDays": self.file_rotation.retention_days,
+                "rotationInterval": self.file_rotation.interval.as_str(),
+                "flushIntervalMs": self.file_rotation.flush_interval.as_millis() as u64,
+            },
+            "compressRotatedFiles": self.compress_rotated_files,
+            "maxConcurrentOutputOps": self.max_concurrent_output_ops,
+            "fileOutputs": self
+                .file_outputs
+                .iter()
+                .map(|output| json!({
+                    "prefix": output.prefix,
+                    "level": output.filter.min_level,
+                    "target": output.filter.target_prefix,
+                }))
+                .collect::<Vec<_>>(),
+            "outputs": outputs,
+            "logSampleRate": self.live.log_sample_rate,
+            "spanSampleRate": self.live.span_sample_rate,
+            "sampling": self.live.sampling.to_json(),
+            "targetLevels": self.live.target_levels.to_json(),
+            "defaultTargetLevel": self.live.target_levels.default,
+            "rateLimits": self.live.rate_limits.to_json(),
+            "fileInput": self.file_input.as_ref().map(|input| json!({
+                "path": input.path.display().to_string(),
+                "pace": input.pace.as_str(),
+            })),
+            "warmupSecs": self.warmup_secs,
+            "minSpanDurationMs": self.min_span_duration_ms,
+            "enrichment": self
+                .live
+                .enrichment
+                .iter()
+                .map(|e| {
+                    let digest = sample_hash(&serde_json::to_string(&e.lookup).unwrap_or_default());
+                    json!({
+                        "source": e.source,
+                        "targets": e.targets,
+                        "lookupFile": e.lookup_file,
+                        "entries": e.lookup.len(),
+                        "digest": format!("{:016x}", digest),
+                    })
+                })
+                .collect::<Vec<_>>(),
+            "recordTtlRules": self
+                .record_ttl_rules
+                .iter()
+                .map(|rule| json!({
+                    "level": rule.level,
+                    "target": rule.target_prefix,
+                    "ttl": rule.ttl_secs,
+                }))
+                .collect::<Vec<_>>(),
+            "prometheus": self.prometheus,
+            "recentCapacity": self.recent_capacity,
+            "statsWindowSecs": self.stats_window_secs,
+            "metricAggregation": {
+                "enabled": self.metric_window_ms.is_some(),
+                "windowMs": self.metric_window_ms.unwrap_or(DEFAULT_METRIC_WINDOW_MS),
+            },
+            "dedup": self.dedup.as_ref().map(|d| json!({
+                "windowMs": d.window_ms,
+                "threshold": d.threshold,
+            })),
+            "sseCoalescing": self.sse_coalescing.as_ref().map(|c| json!({
+                "thresholdPerSec": c.threshold_per_sec,
+                "intervalMs": c.interval_ms,
+            })),
+            "metricRetention": self.metric_retention.as_ref().map(|r| json!({
+                "rawDays": r.raw_days,
+                "rollupDays": r.rollup_days,
+                "rollupInterval": r.interval_ms / 1000.0,
+            })),
+            "persistToTables": self.persist_to_tables,
+            "ingestMode": self.ingest_mode.as_str(),
+            "allowPurge": self.allow_purge,
+            "idSource": match self.id_source {
+                IdSource::Generate => "generate",
+                IdSource::Field(_) => "field",
+            },
+            "idField": match self.id_source {
+                IdSource::Generate => None,
+                IdSource::Field(ref field) => Some(field),
+            },
+            "deriveErrorLogs": self.derive_error_logs,
+            "pubsubTables": {
+                "log": self.pubsub_tables[RecordKind::Log.index()],
+                "span": self.pubsub_tables[RecordKind::Span.index()],
+                "metric": self.pubsub_tables[RecordKind::Metric.index()],
+            },
+            "otlp": null,
+        });
+        if let Some(config) = config.as_object_mut() {
+            for settings in [file_settings, routing, transform_settings, sampling_settings, table_settings] {
+                if let serde_json::Value::Object(settings) = settings {
+                    config.extend(settings);
+                }
+            }
+        }
+        config
+    }
+}
+
+/// A `recordTtlRules` entry. Records whose level equals `level` and whose
+/// target starts with `target` (either may be omitted) expire `ttl` after
+/// their timestamp.
+#[derive(Clone, Debug)]
+struct TtlRule {
+    level: Option<String>,
+    target_prefix: Option<String>,
+    ttl_secs: f64,
+}
+
+impl TtlRule {
+    fn parse(rule: &serde_json::Value) -> Option<Self> {
+        let ttl_secs = match rule.get("ttl").and_then(parse_duration_secs) {
+            Some(ttl) => ttl,
+            None => {
+                eprintln!("[yeti-telemetry] Ignoring recordTtlRules entry without a valid ttl: {}", rule);
+                return None;
+            }
+        };
+        Some(Self {
+            level: rule.get("level").and_then(|v| v.as_str()).map(|s| s.to_string()),
+            target_prefix: rule.get("target").and_then(|v| v.as_str()).map(|s| s.to_string()),
+            ttl_secs,
+        })
+    }
+
+    fn matches(&self, level: &str, target: &str) -> bool {
+        self.level.as_deref().is_none_or(|l| l.eq_ignore_ascii_case(level))
+            && self.target_prefix.as_deref().is_none_or(|t| target.starts_with(t))
+    }
+}
+
+/// Table the Log records go to without `severityTables`, or when it doesn't
+/// name a `default`; the name the host registers the Log table under.
+const DEFAULT_LOG_TABLE: &str = "log";
+
+/// `severityTables`: WARN and ERROR logs are stored in `error`, every other
+/// log in `default`, so error queries scan a small table.
+#[derive(Clone, Debug)]
+struct SeverityTables {
+    error: String,
+    default: String,
+}
+
+impl SeverityTables {
+    fn parse(section: &serde_json::Value) -> Option<Self> {
+        let table = |key: &str| section.get(key).and_then(|v| v.as_str()).filter(|t| !t.is_empty());
+        let Some(error) = table("error") else {
+            eprintln!("[yeti-telemetry] Ignoring severityTables without an error table: {}", section);
+            return None;
+        };
+        Some(Self {
+            error: error.to_string(),
+            default: table("default").unwrap_or(DEFAULT_LOG_TABLE).to_string(),
+        })
+    }
+}
+
+/// Whether a stored log level belongs in the `severityTables` error table.
+pub(crate) fn is_severe_level(level: &str) -> bool {
+    level.eq_ignore_ascii_case("ERROR") || level.eq_ignore_ascii_case("WARN")
+}
+
+/// A `routes` entry. Records whose `fields` hold every `match` value go only
+/// to the named outputs.
+#[derive(Clone, Debug)]
+struct RouteRule {
+    fields: Vec<(String, serde_json::Value)>,
+    outputs: Vec<String>,
+}
+
+impl RouteRule {
+    fn parse(rule: &serde_json::Value) -> Option<Self> {
+        let fields: Vec<(String, serde_json::Value)> = rule
+            .get("match")
+            .and_then(|v| v.as_object())
+            .map(|m| m.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+            .unwrap_or_default();
+        let outputs: Vec<String> = rule
+            .get("outputs")
+            .and_then(|v| v.as_array())
+            .map(|names| names.iter().filter_map(|n| n.as_str()).map(str::to_string).collect())
+            .unwrap_or_default();
+        if fields.is_empty() || outputs.is_empty() {
+            eprintln!("[yeti-telemetry] Ignoring routes entry without match fields or outputs: {}", rule);
+            return None;
+        }
+        Some(Self { fields, outputs })
+    }
+
+    /// Values compare as JSON, so `audit: true` matches a boolean field but
+    /// not the string `"true"`.
+    fn matches(&self, record: &serde_json::Value) -> bool {
+        let Some(fields) = record.get("fields") else {
+            return false;
+        };
+        self.fields.iter().all(|(key, value)| fields.get(key) == Some(value))
+    }
+}
+
+/// Seconds from a number, or a string with an `s`/`m`/`h`/`d` suffix.
+fn parse_duration_secs(value: &serde_json::Value) -> Option<f64> {
+    let secs = match value {
+        serde_json::Value::String(s) => {
+            let s = s.trim();
+            let (num, unit) = match s.char_indices().last()? {
+                (i, 's') => (&s[..i], 1.0),
+                (i, 'm') => (&s[..i], 60.0),
+                (i, 'h') => (&s[..i], 3600.0),
+                (i, 'd') => (&s[..i], 86400.0),
+                _ => (s, 1.0),
+            };
+            num.trim().parse::<f64>().ok()? * unit
+        }
+        other => other.as_f64()?,
+    };
+    (secs > 0.0).then_some(secs)
+}
+
+/// Replace values of secret-looking keys (API keys, tokens, passwords,
+/// auth headers) with `***`, recursively.
+fn redact_secrets(mut value: serde_json::Value) -> serde_json::Value {
+    const SECRET_MARKERS: [&str; 6] = [
+        "key", "token", "password", "secret", "authorization", "credential",
+    ];
+    fn walk(value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::Object(map) => {
+                for (key, v) in map.iter_mut() {
+                    let lower = key.to_ascii_lowercase();
+                    if v.is_string() && SECRET_MARKERS.iter().any(|m| lower.contains(m)) {
+                        *v = json!("***");
+                    } else {
+                        walk(v);
+                    }
+                }
+            }
+            serde_json::Value::Array(items) => items.iter_mut().for_each(walk),
+            _ => {}
+        }
+    }
+    walk(&mut value);
+    value
+}
+
+/// Settings the writer reads per event through `shared().live`, so
+/// `POST /yeti-telemetry/reload` can swap them without a restart.
+pub(crate) struct LiveSettings {
+    /// Fraction of log events kept (0.0–1.0).
+    log_sample_rate: f64,
+    /// Fraction of span events kept (0.0–1.0).
+    span_sample_rate: f64,
+    /// Per-level and per-target keep fractions, applied on top of the rates above.
+    sampling: SamplingRules,
+    /// Minimum log level per target prefix, applied before sampling.
+    target_levels: TargetLevels,
+    /// Events per second allowed per target; ERROR events are exempt.
+    rate_limits: RateLimits,
+    /// Days rotated files are kept before cleanup deletes them.
+    retention_days: u32,
+    /// Lookup-table enrichments applied to every event, in order.
+    enrichment: Vec<Enrichment>,
+}
+
+impl Default for LiveSettings {
+    fn default() -> Self {
+        Self {
+            log_sample_rate: 1.0,
+            span_sample_rate: 1.0,
+            sampling: SamplingRules::default(),
+            target_levels: TargetLevels::default(),
+            rate_limits: RateLimits::default(),
+            retention_days: DEFAULT_RETENTION_DAYS,
+            enrichment: Vec::new(),
+        }
+    }
+}
+
+/// `targetLevels` and `defaultTargetLevel`: the minimum level a log event
+/// needs to be kept, by target prefix.
+#[derive(Default)]
+struct TargetLevels {
+    /// Threshold for targets matching no prefix; `None` keeps everything.
+    default: Option<&'static str>,
+    /// Sorted longest prefix first, so the most specific target wins.
+    targets: Vec<(String, &'static str)>,
+}
+
+impl TargetLevels {
+    fn parse(section: Option<&serde_json::Value>, default: Option<&serde_json::Value>) -> Self {
+        let level = |key: &str, value: &serde_json::Value| {
+            let parsed = value.as_str().map(normalize_level).filter(|(_, known)| *known);
+            if parsed.is_none() {
+                eprintln!("[yeti-telemetry] Ignoring {} (must be a level name)", key);
+            }
+            parsed.map(|(level, _)| level)
+        };
+        let mut rules = Self {
+            default: default.and_then(|v| level("defaultTargetLevel", v)),
+            targets: Vec::new(),
+        };
+        match section.map(|s| s.as_object()) {
+            None => {}
+            Some(None) => eprintln!("[yeti-telemetry] Ignoring targetLevels (must be a map)"),
+            Some(Some(map)) => {
+                for (prefix, value) in map {
+                    if let Some(min) = level(&format!("targetLevels.{}", prefix), value) {
+                        rules.targets.push((prefix.clone(), min));
+                    }
+                }
+            }
+        }
+        rules.targets.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+        rules
+    }
+
+    /// Whether a log event at `level` from `target` passes its most specific
+    /// target threshold, or the default when no prefix matches.
+    fn allows(&self, level: &str, target: &str) -> bool {
+        let min = self
+            .targets
+            .iter()
+            .find(|(prefix, _)| target.starts_with(prefix.as_str()))
+            .map(|(_, min)| *min)
+            .or(self.default);
+        min.is_none_or(|min| level_rank(level) >= level_rank(min))
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        self.targets
+            .iter()
+            .map(|(prefix, min)| (prefix.clone(), json!(min)))
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    }
+}
+
+/// `rateLimits`: events per second allowed for each target, by target
+/// prefix, with `default` for targets matching no prefix.
+#[derive(Default)]
+struct RateLimits {
+    default: Option<f64>,
+    /// Sorted longest prefix first, so the most specific target wins.
+    targets: Vec<(String, f64)>,
+}
+
+impl RateLimits {
+    fn parse(section: &serde_json::Value) -> Self {
+        let mut limits = Self::default();
+        let Some(map) = section.as_object() else {
+            eprintln!("[yeti-telemetry] Ignoring rateLimits (must be a map)");
+            return limits;
+        };
+        for (key, value) in map {
+            let Some(rate) = value.as_f64().filter(|r| *r > 0.0) else {
+                eprintln!("[yeti-telemetry] Ignoring rateLimits.{} (must be a positive number)", key);
+                continue;
+            };
+            if key == "default" {
+                limits.default = Some(rate);
+            } else {
+                limits.targets.push((key.clone(), rate));
+            }
+        }
+        limits.targets.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+        limits
+    }
+
+    fn is_empty(&self) -> bool {
+        self.default.is_none() && self.targets.is_empty()
+    }
+
+    /// Events per second allowed for `target`, if it's limited.
+    fn limit(&self, target: &str) -> Option<f64> {
+        self.targets
+            .iter()
+            .find(|(prefix, _)| target.starts_with(prefix.as_str()))
+            .map(|(_, rate)| *rate)
+            .or(self.default)
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let mut map: serde_json::Map<String, serde_json::Value> = self
+            .targets
+            .iter()
+            .map(|(prefix, rate)| (prefix.clone(), json!(rate)))
+            .collect();
+        if let Some(rate) = self.default {
+            map.insert("default".to_string(), json!(rate));
+        }
+        map.into()
+    }
+}
+
+/// Token bucket for one target: holds up to one second's worth of events, and
+/// at least one so limits below 1/s still let an event through now and then.
+struct TokenBucket {
+    tokens: f64,
+    updated_ms: f64,
+    /// Events dropped since the target was last warned about.
+    dropped: u64,
+    warned_ms: Option<f64>,
+}
+
+/// Per-target token buckets for `rateLimits`, plus the warnings owed for
+/// targets that started or kept being throttled.
+#[derive(Default)]
+struct RateLimiter {
+    buckets: HashMap<String, TokenBucket>,
+    /// (target, events dropped, limit) awaiting a WARN record.
+    warnings: Vec<(String, u64, f64)>,
+}
+
+impl RateLimiter {
+    /// Take a token for an event from `target` limited to `limit` events per
+    /// second; `false` when the bucket is empty and the event must be dropped.
+    fn allow(&mut self, target: &str, limit: f64, now: f64) -> bool {
+        let capacity = limit.max(1.0);
+        if !self.buckets.contains_key(target) {
+            if self.buckets.len() >= RATE_LIMIT_MAX_TARGETS {
+                return true;
+            }
+            self.buckets.insert(
+                target.to_string(),
+                TokenBucket {
+                    tokens: capacity,
+                    updated_ms: now,
+                    dropped: 0,
+                    warned_ms: None,
+                },
+            );
+        }
+        let Some(bucket) = self.buckets.get_mut(target) else { return true };
+        let elapsed_secs = (now - bucket.updated_ms).max(0.0) / 1000.0;
+        bucket.tokens = (bucket.tokens + elapsed_secs * limit).min(capacity);
+        bucket.updated_ms = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return true;
+        }
+        bucket.dropped += 1;
+        if bucket.warned_ms.is_none_or(|at| now - at >= RATE_LIMIT_WARN_INTERVAL_MS) {
+            bucket.warned_ms = Some(now);
+            self.warnings.push((target.to_string(), bucket.dropped, limit));
+            bucket.dropped = 0;
+        }
+        false
+    }
+}
+
+/// The WARN log written when `target` is being throttled by `rateLimits`.
+fn rate_limit_warning(target: &str, dropped: u64, limit: f64, now: f64) -> serde_json::Value {
+    json!({
+        "kind": "log",
+        "timestamp": now,
+        "level": "WARN",
+        "target": "yeti_telemetry::rate_limit",
+        "message": format!(
+            "Target '{}' exceeded its rate limit of {} events/s; dropped {} events",
+            target, limit, dropped
+        ),
+        "fields": {
+            "throttledTarget": target,
+            "limitPerSec": limit,
+            "dropped": dropped,
+        },
+    })
+}
+
+/// The `sampling` section: keep fractions by level (`debug: 0.1`) and by
+/// target prefix (`targets: { hyper: 0.05 }`).
+#[derive(Default)]
+struct SamplingRules {
+    /// Keyed by upper-case level name.
+    levels: HashMap<String, f64>,
+    /// Sorted longest prefix first, so the most specific target wins.
+    targets: Vec<(String, f64)>,
+}
+
+impl SamplingRules {
+    fn parse(section: &serde_json::Value) -> Self {
+        let mut rules = Self::default();
+        let Some(map) = section.as_object() else {
+            eprintln!("[yeti-telemetry] Ignoring sampling (must be a map)");
+            return rules;
+        };
+        for (key, value) in map {
+            if key == "targets" {
+                for (prefix, _) in value.as_object().into_iter().flatten() {
+                    if let Some(rate) = parse_sample_rate(value, prefix) {
+                        rules.targets.push((prefix.clone(), rate));
+                    }
                 }
+                continue;
+            }
+            let level = key.to_ascii_uppercase();
+            if ALWAYS_KEPT_LEVELS.contains(&level.as_str()) {
+                eprintln!("[yeti-telemetry] Ignoring sampling.{} ({} is always kept)", key, level);
+                continue;
+            }
+            if let Some(rate) = parse_sample_rate(section, key) {
+                rules.levels.insert(level, rate);
+            }
+        }
+        rules.targets.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+        rules
+    }
+
+    fn is_empty(&self) -> bool {
+        self.levels.is_empty() && self.targets.is_empty()
+    }
+
+    /// Keep fraction for an event: the lowest of the kind's base rate, its
+    /// level's rate, and its most specific target rate. ERROR and WARN are
+    /// always kept.
+    fn rate(&self, level: &str, target: &str, base: f64) -> f64 {
+        let level = level.to_ascii_uppercase();
+        if ALWAYS_KEPT_LEVELS.contains(&level.as_str()) {
+            return 1.0;
+        }
+        let mut rate = base;
+        if let Some(r) = self.levels.get(&level) {
+            rate = rate.min(*r);
+        }
+        if let Some((_, r)) = self.targets.iter().find(|(prefix, _)| target.starts_with(prefix.as_str())) {
+            rate = rate.min(*r);
+        }
+        rate
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let mut out: serde_json::Map<String, serde_json::Value> = self
+            .levels
+            .iter()
+            .map(|(level, rate)| (level.to_ascii_lowercase(), json!(rate)))
+            .collect();
+        if !self.targets.is_empty() {
+            let targets: serde_json::Map<String, serde_json::Value> = self
+                .targets
+                .iter()
+                .map(|(prefix, rate)| (prefix.clone(), json!(rate)))
+                .collect();
+            out.insert("targets".to_string(), targets.into());
+        }
+        out.into()
+    }
+}
+
+/// Levels never dropped by sampling.
+const ALWAYS_KEPT_LEVELS: [&str; 2] = ["ERROR", "WARN"];
+
+/// `adaptiveSampling` keep fraction while boosted, when `boostRatio` is unset.
+const DEFAULT_ADAPTIVE_BOOST_RATIO: f64 = 1.0;
+
+/// How long an `adaptiveSampling` boost lasts, when `cooldownSeconds` is unset.
+const DEFAULT_ADAPTIVE_COOLDOWN_SECS: f64 = 300.0;
+
+/// Window the ERROR rate is averaged over, when `windowSeconds` is unset.
+const DEFAULT_ADAPTIVE_WINDOW_SECS: u64 = 60;
+
+/// `adaptiveSampling`: while ERROR events arrive faster than the threshold,
+/// DEBUG/TRACE logs and spans are kept at `boost_ratio` or more, so the
+/// context around an incident is captured in full.
+#[derive(Clone, Debug)]
+struct AdaptiveSampling {
+    /// ERROR events per second, averaged over `window_secs`, that start a
+    /// boost.
+    error_rate_threshold: f64,
+    /// Keep fraction of boosted events; the regular rate applies when higher.
+    boost_ratio: f64,
+    /// How long a boost lasts after the rate was last above the threshold.
+    cooldown_secs: f64,
+    window_secs: u64,
+}
+
+impl AdaptiveSampling {
+    fn parse(section: &serde_json::Value) -> Option<Self> {
+        let Some(error_rate_threshold) = section
+            .get("errorRateThreshold")
+            .and_then(|v| v.as_f64())
+            .filter(|t| *t > 0.0)
+        else {
+            eprintln!("[yeti-telemetry] Ignoring adaptiveSampling: errorRateThreshold must be a positive number");
+            return None;
+        };
+        let positive = |key: &str| {
+            let value = section.get(key)?;
+            let parsed = value.as_f64().filter(|v| *v > 0.0);
+            if parsed.is_none() {
+                eprintln!("[yeti-telemetry] Ignoring adaptiveSampling.{} (must be a positive number)", key);
+            }
+            parsed
+        };
+        Some(Self {
+            error_rate_threshold,
+            boost_ratio: parse_sample_rate(section, "boostRatio").unwrap_or(DEFAULT_ADAPTIVE_BOOST_RATIO),
+            cooldown_secs: positive("cooldownSeconds").unwrap_or(DEFAULT_ADAPTIVE_COOLDOWN_SECS),
+            window_secs: positive("windowSeconds")
+                .map(|secs| (secs.ceil() as u64).max(1))
+                .unwrap_or(DEFAULT_ADAPTIVE_WINDOW_SECS),
+        })
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "errorRateThreshold": self.error_rate_threshold,
+            "boostRatio": self.boost_ratio,
+            "cooldownSeconds": self.cooldown_secs,
+            "windowSeconds": self.window_secs,
+        })
+    }
+}
+
+/// End of the running `adaptiveSampling` boost ("secs.micros"), if any.
+pub(crate) fn adaptive_boost_until() -> Option<String> {
+    let until = shared().pipeline.adaptive_boost_until_ms.load(Ordering::Relaxed) as f64;
+    (until > now_epoch_ms()).then(|| format_epoch_ms(until))
+}
+
+/// Writer state for `adaptiveSampling`.
+#[derive(Default)]
+struct AdaptiveBoost {
+    /// ERROR events per epoch second, oldest first, covering one window.
+    errors: VecDeque<(u64, u64)>,
+    /// End of the running boost (epoch ms).
+    until_ms: Option<f64>,
+}
+
+impl AdaptiveBoost {
+    /// Count an ERROR event at `now` (epoch ms). While the rate is above the
+    /// threshold each one starts or extends the boost; returns true when it
+    /// starts one.
+    fn record_error(&mut self, settings: &AdaptiveSampling, now: f64) -> bool {
+        let second = (now / 1000.0) as u64;
+        match self.errors.back_mut() {
+            Some((at, count)) if *at == second => *count += 1,
+            _ => self.errors.push_back((second, 1)),
+        }
+        while self.errors.front().is_some_and(|(at, _)| at + settings.window_secs <= second) {
+            self.errors.pop_front();
+        }
+        let total: u64 = self.errors.iter().map(|(_, count)| count).sum();
+        if total as f64 / settings.window_secs as f64 <= settings.error_rate_threshold {
+            return false;
+        }
+        let started = self.until_ms.is_none();
+        self.until_ms = Some(now + settings.cooldown_secs * 1000.0);
+        started
+    }
+}
+
+/// Parse a 0.0–1.0 sample rate, warning about and ignoring invalid values.
+fn parse_sample_rate(telemetry: &serde_json::Value, key: &str) -> Option<f64> {
+    let rate = telemetry.get(key)?.as_f64();
+    match rate {
+        Some(r) if (0.0..=1.0).contains(&r) => Some(r),
+        _ => {
+            eprintln!("[yeti-telemetry] Ignoring {} (must be 0.0-1.0)", key);
+            None
+        }
+    }
+}
+
+/// Read the `telemetry` section of yeti-config.yaml as JSON, with the active
+/// environment's overrides applied.
+fn read_telemetry_section(root_dir: &str) -> Option<serde_json::Value> {
+    let config_path = PathBuf::from(root_dir).join("yeti-config.yaml");
+    let contents = fs::read_to_string(&config_path).ok()?;
+    let yaml: serde_json::Value = serde_yaml::from_str(&contents).ok()?;
+    let mut telemetry = yaml.get("telemetry").cloned()?;
+    apply_environment_section(&mut telemetry, &active_environment());
+    Some(telemetry)
+}
+
+/// Active deployment environment from `YETI_ENV`.
+fn active_environment() -> String {
+    std::env::var("YETI_ENV").unwrap_or_else(|_| "development".to_string())
+}
+
+/// Overlay `telemetry.environments.<env>` onto the section's top-level keys, so
+/// one config file can e.g. sample production harder than staging.
+fn apply_environment_section(telemetry: &mut serde_json::Value, env: &str) {
+    let overrides = telemetry
+        .get("environments")
+        .and_then(|e| e.get(env))
+        .and_then(|e| e.as_object())
+        .cloned();
+    if let (Some(overrides), Some(base)) = (overrides, telemetry.as_object_mut()) {
+        for (key, value) in overrides {
+            base.insert(key, value);
+        }
+    }
+}
+
+// ============================================================================
+// Config Reload — live settings replaced without a restart
+// ============================================================================
+
+/// Effective-config paths `reload_config` applies to the running writer,
+/// with everything below them. Any other change needs a restart.
+const RELOADABLE_SETTINGS: &[&str] = &[
+    "logSampleRate",
+    "spanSampleRate",
+    "sampling",
+    "targetLevels",
+    "defaultTargetLevel",
+    "rateLimits",
+    "fileRotation.retentionDays",
+    "enrichment",
+];
+
+/// Re-read yeti-config.yaml, swap in the changed `RELOADABLE_SETTINGS` and
+/// report every difference from the configuration in effect. `None` until
+/// the writer is configured.
+///
+/// Both sides are compared with secrets redacted, so a changed token or
+/// password is not detected.
+pub(crate) fn reload_config() -> Option<serde_json::Value> {
+    let state = shared();
+    let root_dir = state.root_dir.get()?;
+    let _guard = lock(&state.reload_lock);
+    let current = state.effective_config.load_full()?;
+    let (live, reread) = resolve_config(root_dir);
+
+    let mut changes = Vec::new();
+    diff_config("", &current, &reread, &mut changes);
+    let reloadable = |path: &str| {
+        RELOADABLE_SETTINGS
+            .iter()
+            .any(|s| path == *s || path.strip_prefix(s).is_some_and(|rest| rest.starts_with('.')))
+    };
+    let (applied, restart_required): (Vec<_>, Vec<_>) =
+        changes.into_iter().partition(|(path, _, _)| reloadable(path));
+
+    if !applied.is_empty() {
+        state.live.store(Arc::new(live));
+        let mut effective = (*current).clone();
+        for setting in RELOADABLE_SETTINGS {
+            let pointer = format!("/{}", setting.replace('.', "/"));
+            if let (Some(slot), Some(value)) = (effective.pointer_mut(&pointer), reread.pointer(&pointer)) {
+                *slot = value.clone();
+            }
+        }
+        state.effective_config.store(Some(Arc::new(effective)));
+    }
+    eprintln!(
+        "[yeti-telemetry] Config reloaded: {} change(s) applied, {} need a restart",
+        applied.len(),
+        restart_required.len()
+    );
+
+    let entries = |changes: Vec<(String, serde_json::Value, serde_json::Value)>| {
+        changes
+            .into_iter()
+            .map(|(path, old, new)| json!({ "path": path, "old": old, "new": new }))
+            .collect::<Vec<_>>()
+    };
+    Some(json!({
+        "applied": entries(applied),
+        "restartRequired": entries(restart_required),
+    }))
+}
+
+/// The live settings and the redacted effective configuration `on_ready`
+/// would build from the current yeti-config.yaml and environment.
+fn resolve_config(root_dir: &str) -> (LiveSettings, serde_json::Value) {
+    let mut config = TelemetryConfig::from_config(root_dir);
+    let mut effective = config.to_json();
+    let live = std::mem::take(&mut config.live);
+    let numbered = matches!(config.file_rotation.style, RotationStyle::Numbered { .. });
+
+    if let Some(telemetry) = read_telemetry_section(root_dir) {
+        if let Some(archive) = S3Archive::from_config(&telemetry).filter(|_| !numbered) {
+            effective["archive"] = archive.to_json();
+        }
+        if let Some(es) = ElasticsearchConfig::from_config(&telemetry) {
+            effective["elasticsearch"] = es.to_json();
+        }
+        if let Some(loki) = LokiConfig::from_config(&telemetry) {
+            effective["loki"] = loki.to_json();
+        }
+        if let Some(kafka) = KafkaConfig::from_config(&telemetry) {
+            effective["kafka"] = kafka.to_json();
+        }
+        if let Some(syslog) = SyslogConfig::from_config(&telemetry) {
+            effective["syslog"] = syslog.to_json();
+        }
+    }
+    effective["otlp"] = otlp_effective_json(&OtlpOutput::from_config(root_dir));
+    (live, redact_secrets(effective))
+}
+
+/// Collect `(path, old, new)` for every leaf that differs, with dotted
+/// paths. Objects are compared key by key; anything else, arrays included,
+/// as a whole. A key missing on one side compares as null.
+fn diff_config(
+    path: &str,
+    old: &serde_json::Value,
+    new: &serde_json::Value,
+    changes: &mut Vec<(String, serde_json::Value, serde_json::Value)>,
+) {
+    match (old, new) {
+        (serde_json::Value::Object(a), serde_json::Value::Object(b)) => {
+            let keys: std::collections::BTreeSet<&String> = a.keys().chain(b.keys()).collect();
+            for key in keys {
+                let child = if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                let null = serde_json::Value::Null;
+                diff_config(&child, a.get(key).unwrap_or(&null), b.get(key).unwrap_or(&null), changes);
+            }
+        }
+        _ if old != new => changes.push((path.to_string(), old.clone(), new.clone())),
+        _ => {}
+    }
+}
+
+// ============================================================================
+// Telemetry Writer — event processing and persistence
+// ============================================================================
+
+/// Trait for pluggable telemetry output providers.
+///
+/// Implementations receive serialized telemetry records (as JSON values)
+/// and can write them to files, forward to OTLP endpoints, etc.
+trait TelemetryOutput: Send {
+    /// Short identifier used to address this output in config
+    /// (`telemetry.outputs.<name>`).
+    fn name(&self) -> &str;
+    /// Invocation order within each record; higher runs first. See
+    /// [`OUTPUT_PRIORITY_TABLE`] for where table writes fall.
+    fn priority(&self) -> i32 {
+        OUTPUT_PRIORITY_NETWORK
+    }
+    fn write_log(&mut self, record: &serde_json::Value);
+    fn write_span(&mut self, record: &serde_json::Value);
+    fn write_metric(&mut self, record: &serde_json::Value) {
+        let _ = record;
+    }
+    /// Push anything the output has buffered to its destination. Called for
+    /// every output when the writer shuts down.
+    fn flush(&mut self) {}
+    /// Whether records are reaching the destination, for `/healthz`.
+    fn health(&self) -> OutputHealth {
+        OutputHealth::Healthy
+    }
+}
+
+/// Consecutive delivery failures after which an output counts as failing
+/// and `/healthz` answers 503.
+const OUTPUT_FAILURE_THRESHOLD: u64 = 3;
+
+/// Health reported by an output.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum OutputHealth {
+    Healthy,
+    /// Recent deliveries failed, but fewer than `OUTPUT_FAILURE_THRESHOLD`
+    /// in a row.
+    Degraded(String),
+    /// At least `OUTPUT_FAILURE_THRESHOLD` deliveries in a row failed.
+    Failing(String),
+}
+
+impl OutputHealth {
+    /// Health after `consecutive` failed deliveries, the last with `error`.
+    fn from_failures(consecutive: u64, error: &str) -> Self {
+        match consecutive {
+            0 => Self::Healthy,
+            n if n < OUTPUT_FAILURE_THRESHOLD => Self::Degraded(format!("{} failed in a row: {}", n, error)),
+            n => Self::Failing(format!("{} failed in a row: {}", n, error)),
+        }
+    }
+
+    pub(crate) fn is_failing(&self) -> bool {
+        matches!(self, Self::Failing(_))
+    }
+
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        match self {
+            Self::Healthy => json!({ "status": "healthy" }),
+            Self::Degraded(detail) => json!({ "status": "degraded", "detail": detail }),
+            Self::Failing(detail) => json!({ "status": "failing", "detail": detail }),
+        }
+    }
+}
+
+/// Consecutive delivery failures of an output, updated by the thread that
+/// delivers and read by `TelemetryOutput::health`.
+#[derive(Default)]
+struct HealthTracker {
+    consecutive_failures: AtomicU64,
+    last_error: Mutex<String>,
+}
+
+impl HealthTracker {
+    fn succeeded(&self) {
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+    }
+
+    fn failed(&self, error: &str) {
+        *lock(&self.last_error) = error.to_string();
+        self.consecutive_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn health(&self) -> OutputHealth {
+        let consecutive = self.consecutive_failures.load(Ordering::Relaxed);
+        OutputHealth::from_failures(consecutive, &lock(&self.last_error))
+    }
+}
+
+/// Durable local outputs, invoked before the record reaches the tables.
+const OUTPUT_PRIORITY_FILE: i32 = 200;
+/// Position of the writer's own table write and PubSub notification. Outputs
+/// above it complete before the record is stored; the rest run after.
+const OUTPUT_PRIORITY_TABLE: i32 = 100;
+/// Best-effort exports, invoked last.
+const OUTPUT_PRIORITY_NETWORK: i32 = 0;
+
+/// Record kinds handled by the writer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum RecordKind {
+    Log,
+    Span,
+    Metric,
+}
+
+impl RecordKind {
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value {
+            "log" => Some(Self::Log),
+            "span" => Some(Self::Span),
+            "metric" => Some(Self::Metric),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Log => "log",
+            Self::Span => "span",
+            Self::Metric => "metric",
+        }
+    }
+
+    const ALL: [Self; 3] = [Self::Log, Self::Span, Self::Metric];
+
+    /// Position in per-kind counter arrays.
+    fn index(self) -> usize {
+        match self {
+            Self::Log => 0,
+            Self::Span => 1,
+            Self::Metric => 2,
+        }
+    }
+}
+
+/// Set of record kinds an output accepts.
+#[derive(Clone, Copy, Debug)]
+struct KindSet {
+    log: bool,
+    span: bool,
+    metric: bool,
+}
+
+impl KindSet {
+    const ALL: Self = Self {
+        log: true,
+        span: true,
+        metric: true,
+    };
+
+    fn names(&self) -> Vec<&'static str> {
+        [(self.log, "log"), (self.span, "span"), (self.metric, "metric")]
+            .iter()
+            .filter(|(on, _)| *on)
+            .map(|(_, name)| *name)
+            .collect()
+    }
+
+    fn contains(&self, kind: RecordKind) -> bool {
+        match kind {
+            RecordKind::Log => self.log,
+            RecordKind::Span => self.span,
+            RecordKind::Metric => self.metric,
+        }
+    }
+}
+
+/// An output together with the record kinds routed to it.
+struct OutputSlot {
+    output: Box<dyn TelemetryOutput>,
+    kinds: KindSet,
+    priority: i32,
+    /// Whether each of `routes`, by index, names this output.
+    routes: Vec<bool>,
+    /// Receives records that match no route.
+    default_route: bool,
+}
+
+impl OutputSlot {
+    fn write(&mut self, kind: RecordKind, record: &serde_json::Value) {
+        if !self.kinds.contains(kind) {
+            return;
+        }
+        match kind {
+            RecordKind::Log => self.output.write_log(record),
+            RecordKind::Span => self.output.write_span(record),
+            RecordKind::Metric => self.output.write_metric(record),
+        }
+    }
+}
+
+/// Background writer that receives tracing events as JSON and persists them
+/// to tables, files, and PubSub for SSE.
+struct TelemetryWriter {
+    log_storage: Option<Arc<dyn KvBackend>>,
+    span_storage: Option<Arc<dyn KvBackend>>,
+    metric_storage: Option<Arc<dyn KvBackend>>,
+    pubsub: Option<Arc<dyn RecordNotifier>>,
+    outputs: Vec<OutputSlot>,
+    /// Live settings and counters the writer reads and updates; `shared()`
+    /// except in tests.
+    state: &'static SharedState,
+    config: TelemetryConfig,
+    /// Events whose timestamp was clamped for being too far in the future.
+    future_clamped: u64,
+    /// Events dropped by sampling.
+    sampled_out: u64,
+    ids: IdGenerator,
+    /// Identifies this writer instance; `seq` restarts with each instance.
+    writer_id: String,
+    /// Last sequence number assigned per table: Log, Span, Metric (by
+    /// `RecordKind::index`), then the `severityTables` error table.
+    seq: [u64; 4],
+    /// When expired records were last swept from the tables (epoch ms).
+    last_expiry_sweep_ms: f64,
+    /// Each distinct `recordTtlRules` TTL (seconds, shortest first) with
+    /// how far it has walked each swept table (Log, error Log, Span).
+    expiry_cursors: Vec<(f64, [WriteTimeCursor; 3])>,
+    /// Writer start time (epoch ms), for the warmup window.
+    started_ms: f64,
+    /// Secondary backends receiving a copy of every table write.
+    mirrors: Arc<Mirrors>,
+    /// Whether the current excursion above the queue high-water mark has
+    /// been logged.
+    queue_warned: bool,
+    /// Table writes waiting to be flushed, per kind.
+    log_batch: TableBatch,
+    span_batch: TableBatch,
+    metric_batch: TableBatch,
+    /// Open metric windows when `metricAggregation` is enabled.
+    metric_rollup: MetricRollup,
+    /// Repeated log lines being counted when `dedup` is enabled.
+    log_dedup: LogDedup,
+    /// PubSub notifications held back when `sseCoalescing` is enabled.
+    notify_coalescer: NotifyCoalescer,
+    /// Tallies for `ingestMode: count`.
+    ingest_count: IngestCount,
+    /// Token buckets for `rateLimits`.
+    rate_limiter: RateLimiter,
+    /// ERROR rate and running boost for `adaptiveSampling`.
+    adaptive_boost: AdaptiveBoost,
+    /// When output health was last published (epoch ms).
+    health_published_ms: f64,
+    /// MetricRollup table, when `metricRetention` is set and it exists.
+    rollup_storage: Option<Arc<dyn KvBackend>>,
+    /// `severityTables` table for WARN and ERROR logs, with its own batch.
+    error_log_storage: Option<Arc<dyn KvBackend>>,
+    error_log_batch: TableBatch,
+    /// `indexedSpanFields` index table, with its own batch.
+    span_index_storage: Option<Arc<dyn KvBackend>>,
+    span_index_batch: TableBatch,
+    /// First `metricRetention` window not yet rolled up; `None` until the
+    /// first run.
+    rollup_next_window: Option<i64>,
+    /// How far `metricRetention` has purged raw points and rollups.
+    retention_cursors: [WriteTimeCursor; 2],
+    /// Rate-limits warnings about records that failed to encode.
+    serialize_warning: FailureWarning,
+    /// Rate-limits warnings about events `transformScript` failed on.
+    transform_warning: FailureWarning,
+}
+
+impl TelemetryWriter {
+    fn new(
+        log_storage: Option<Arc<dyn KvBackend>>,
+        span_storage: Option<Arc<dyn KvBackend>>,
+        metric_storage: Option<Arc<dyn KvBackend>>,
+        pubsub: Option<Arc<dyn RecordNotifier>>,
+        config: TelemetryConfig,
+    ) -> Self {
+        let mut ids = IdGenerator::new(Arc::new(SystemClock));
+        let writer_id = ids.next_id();
+        // Without table persistence there are no table writes, index entries,
+        // expiry sweeps or PubSub notifications; records only reach outputs.
+        let persist = config.persist_to_tables;
+        if !persist {
+            eprintln!("[telemetry-writer] Table persistence disabled (persistToTables: false)");
+        }
+        if config.ingest_mode == IngestMode::Count {
+            eprintln!("[telemetry-writer] Count mode: events are counted, not stored or forwarded (ingestMode: count)");
+        }
+        let mut ttls: Vec<f64> = config.record_ttl_rules.iter().map(|rule| rule.ttl_secs).collect();
+        ttls.sort_by(f64::total_cmp);
+        ttls.dedup();
+        Self {
+            log_storage: log_storage.filter(|_| persist),
+            span_storage: span_storage.filter(|_| persist),
+            metric_storage: metric_storage.filter(|_| persist),
+            pubsub: pubsub.filter(|_| persist),
+            outputs: Vec::new(),
+            state: shared(),
+            config,
+            future_clamped: 0,
+            sampled_out: 0,
+            ids,
+            writer_id,
+            seq: [0; 4],
+            last_expiry_sweep_ms: now_epoch_ms(),
+            expiry_cursors: ttls.into_iter().map(|ttl| (ttl, Default::default())).collect(),
+            started_ms: now_epoch_ms(),
+            mirrors: Arc::default(),
+            queue_warned: false,
+            log_batch: TableBatch::default(),
+            span_batch: TableBatch::default(),
+            metric_batch: TableBatch::default(),
+            metric_rollup: MetricRollup::default(),
+            log_dedup: LogDedup::default(),
+            notify_coalescer: NotifyCoalescer::default(),
+            ingest_count: IngestCount {
+                reported_ms: now_epoch_ms(),
+                ..IngestCount::default()
+            },
+            rate_limiter: RateLimiter::default(),
+            adaptive_boost: AdaptiveBoost::default(),
+            health_published_ms: 0.0,
+            rollup_storage: None,
+            error_log_storage: None,
+            error_log_batch: TableBatch::default(),
+            span_index_storage: None,
+            span_index_batch: TableBatch::default(),
+            rollup_next_window: None,
+            retention_cursors: Default::default(),
+            serialize_warning: FailureWarning::default(),
+            transform_warning: FailureWarning::default(),
+        }
+    }
+
+    /// Read live settings from, and count into, `state` instead of the
+    /// extension's shared state, so tests can run writers side by side.
+    #[cfg(test)]
+    fn with_state(mut self, state: &'static SharedState) -> Self {
+        self.state = state;
+        self
+    }
+
+    /// Mirror every table write to secondary backends, e.g. a hot standby or
+    /// an analytics store. Ignored when table persistence is off.
+    fn with_mirrors(mut self, mirrors: Arc<Mirrors>) -> Self {
+        if self.config.persist_to_tables {
+            self.mirrors = mirrors;
+        }
+        self
+    }
+
+    /// Store WARN and ERROR logs here instead of the Log table. Ignored when
+    /// table persistence is off.
+    fn with_error_log_storage(mut self, storage: Option<Arc<dyn KvBackend>>) -> Self {
+        if self.log_storage.is_some() {
+            self.error_log_storage = storage;
+        }
+        self
+    }
+
+    /// Store `metricRetention` rollups here. Without it, raw metrics are
+    /// neither rolled up nor expired.
+    fn with_rollup_storage(mut self, storage: Option<Arc<dyn KvBackend>>) -> Self {
+        if self.metric_storage.is_some() {
+            self.rollup_storage = storage;
+        }
+        self
+    }
+
+    /// Store the `indexedSpanFields` index here. Without it, spans are not
+    /// indexed.
+    fn with_span_index_storage(mut self, storage: Option<Arc<dyn KvBackend>>) -> Self {
+        if self.span_storage.is_some() {
+            self.span_index_storage = storage;
+        }
+        self
+    }
+
+    fn add_output(mut self, output: Box<dyn TelemetryOutput>) -> Self {
+        let kinds = self
+            .config
+            .output_kinds
+            .get(output.name())
+            .copied()
+            .unwrap_or(KindSet::ALL);
+        let priority = self
+            .config
+            .output_priorities
+            .get(output.name())
+            .copied()
+            .unwrap_or_else(|| output.priority());
+        let routes: Vec<bool> = self
+            .config
+            .routes
+            .iter()
+            .map(|rule| rule.outputs.iter().any(|name| name == output.name()))
+            .collect();
+        let default_route = match &self.config.default_outputs {
+            Some(names) => names.iter().any(|name| name == output.name()),
+            None => !routes.contains(&true),
+        };
+        // Stable by priority, so equal priorities keep registration order.
+        let at = self.outputs.partition_point(|slot| slot.priority >= priority);
+        self.outputs.insert(at, OutputSlot { output, kinds, priority, routes, default_route });
+        self
+    }
+
+    /// Pass a record to the outputs on one side of the table write:
+    /// `before_table` selects those prioritized above it. With `routes`, only
+    /// the outputs of the first matching route, or the default outputs when
+    /// none matches, receive it.
+    fn run_outputs(&mut self, kind: RecordKind, record: &serde_json::Value, before_table: bool) {
+        let route = self.config.routes.iter().position(|rule| rule.matches(record));
+        for slot in &mut self.outputs {
+            let routed = route.map_or(slot.default_route, |i| slot.routes[i]);
+            if routed && (slot.priority > OUTPUT_PRIORITY_TABLE) == before_table {
+                slot.write(kind, record);
+            }
+        }
+    }
+
+    /// Warn about `routes` and `defaultOutputs` names that no output has, as
+    /// records routed only there would reach no output at all.
+    fn warn_unknown_route_outputs(&self) {
+        let names = self
+            .config
+            .routes
+            .iter()
+            .flat_map(|rule| &rule.outputs)
+            .chain(self.config.default_outputs.iter().flatten());
+        let mut warned: Vec<&String> = Vec::new();
+        for name in names {
+            if !warned.contains(&name) && !self.outputs.iter().any(|slot| slot.output.name() == name) {
+                eprintln!("[telemetry-writer] WARNING: routes name unknown output '{}'", name);
+                warned.push(name);
+            }
+        }
+    }
+
+    /// Main event loop — receives JSON events and dispatches by kind.
+    async fn run_loop(mut self, mut rx: tokio::sync::mpsc::Receiver<serde_json::Value>) {
+        eprintln!("[telemetry-writer] Started");
+        self.warn_unknown_route_outputs();
+        let pipeline = &self.state.pipeline;
+        pipeline.writer_started_ms.store(now_epoch_ms() as u64, Ordering::Relaxed);
+        pipeline.writer_running.store(true, Ordering::Relaxed);
+        self.publish_health(now_epoch_ms());
+
+        loop {
+            // Wait for the next event, but no longer than the oldest buffered
+            // table write may stay unflushed.
+            let next = match self.next_flush_deadline() {
+                None => rx.recv().await,
+                Some(deadline) => {
+                    let wait = (deadline - now_epoch_ms()).max(0.0);
+                    let wait = std::time::Duration::from_millis(wait as u64);
+                    match tokio::time::timeout(wait, rx.recv()).await {
+                        Ok(next) => next,
+                        Err(_) => {
+                            self.flush_due().await;
+                            continue;
+                        }
+                    }
+                }
+            };
+            let Some(event) = next else { break };
+            self.observe_queue(&rx);
+            self.process_one(event).await;
+        }
+
+        // Don't lose the tail: write whatever is still buffered.
+        self.flush_all().await;
+        for slot in &mut self.outputs {
+            slot.output.flush();
+        }
+
+        pipeline.writer_running.store(false, Ordering::Relaxed);
+        let [log_count, span_count, metric_count] = pipeline.by_kind.totals();
+        eprintln!(
+            "[telemetry-writer] Shutting down (logs={}, spans={}, metrics={}, sampled_out={}, future_clamped={})",
+            log_count, span_count, metric_count, self.sampled_out, self.future_clamped
+        );
+    }
+
+    /// Run one event through the pipeline exactly as the event loop does:
+    /// enrichment, filtering, sampling and the table and output writes,
+    /// followed by the periodic maintenance (due flushes, expiry sweeps,
+    /// rollups). Table writes may still be buffered afterwards; `flush_all`
+    /// writes them out.
+    async fn process_one(&mut self, mut event: serde_json::Value) {
+        let pipeline = &self.state.pipeline;
+        if self.config.ingest_mode == IngestMode::Count {
+            self.count_event(&event);
+            return;
+        }
+
+        for enrichment in &self.state.live.load().enrichment {
+            enrichment.apply(&mut event);
+        }
+        let Some(event) = self.transform(event) else {
+            self.after_event().await;
+            return;
+        };
+        let kind = event
+            .get("kind")
+            .and_then(|v| v.as_str())
+            .unwrap_or("unknown");
+        pipeline.events_processed.fetch_add(1, Ordering::Relaxed);
+        if let Some(kind) = RecordKind::parse(kind) {
+            pipeline.by_kind.processed[kind.index()].fetch_add(1, Ordering::Relaxed);
+        }
+        let stats_kind = RecordKind::parse(kind).filter(|k| matches!(k, RecordKind::Log | RecordKind::Span));
+        if let (Some(stats), Some(kind)) = (self.state.event_stats.get(), stats_kind) {
+            let target = event.get("target").and_then(|v| v.as_str()).unwrap_or("");
+            lock(stats).record((now_epoch_ms() / 1000.0) as u64, kind, level_of(&event).0, target);
+        }
+        if stats_kind.is_some() && level_of(&event).0 == "ERROR" {
+            self.record_error();
+        }
+
+        match kind {
+            "log" if !self.passes_target_level(&event) => {
+                pipeline.level_filtered.fetch_add(1, Ordering::Relaxed);
+            }
+            "log" | "span" if !self.within_rate_limit(&event) => {
+                pipeline.rate_limited.fetch_add(1, Ordering::Relaxed);
+            }
+            "log" => {
+                let counters = &self.state.sampling;
+                counters.logs_seen.fetch_add(1, Ordering::Relaxed);
+                let rate = self.state.live.load().log_sample_rate;
+                if self.keep_sampled(RecordKind::Log, &event, rate) {
+                    counters.logs_kept.fetch_add(1, Ordering::Relaxed);
+                    self.write_log(&event).await;
+                }
+            }
+            "span" => {
+                let counters = &self.state.sampling;
+                counters.spans_seen.fetch_add(1, Ordering::Relaxed);
+                let rate = self.state.live.load().span_sample_rate;
+                if self.keep_sampled(RecordKind::Span, &event, rate) {
+                    counters.spans_kept.fetch_add(1, Ordering::Relaxed);
+                    self.write_span(&event).await;
+                }
+            }
+            "metric" => {
+                self.write_metric(&event).await;
+            }
+            _ => {}
+        }
+
+        self.after_event().await;
+    }
+
+    /// Periodic work after each event: rate limit warnings, due flushes,
+    /// expiry sweeps, rollups and the status line.
+    async fn after_event(&mut self) {
+        let pipeline = &self.state.pipeline;
+        self.warn_rate_limited().await;
+        self.flush_due().await;
+
+        if self.adaptive_boost.until_ms.is_some_and(|until| now_epoch_ms() >= until) {
+            self.adaptive_boost.until_ms = None;
+            eprintln!("[telemetry-writer] Adaptive sampling boost ended; regular sampling resumed");
+        }
+
+        if !self.config.record_ttl_rules.is_empty()
+            && now_epoch_ms() - self.last_expiry_sweep_ms >= EXPIRY_SWEEP_INTERVAL_MS
+        {
+            self.sweep_expired().await;
+        }
+
+        if let Some(retention) = self.config.metric_retention {
+            if self.rollup_due(&retention, now_epoch_ms()) {
+                self.roll_up_metrics(&retention).await;
+            }
+        }
+
+        // Periodic status (every 1000 events)
+        let [log_count, span_count, metric_count] = pipeline.by_kind.totals();
+        let total = log_count + span_count + metric_count;
+        if total % 1000 == 0 && total > 0 {
+            eprintln!(
+                "[telemetry-writer] Processed {} events (logs={}, spans={}, metrics={}, sampled_out={}, future_clamped={}, queue={}/{}, queue_high_water={}, queue_saturated={})",
+                total,
+                log_count,
+                span_count,
+                metric_count,
+                self.sampled_out,
+                self.future_clamped,
+                pipeline.queue_depth.load(Ordering::Relaxed),
+                pipeline.queue_capacity.load(Ordering::Relaxed),
+                pipeline.queue_high_water.load(Ordering::Relaxed),
+                pipeline.queue_saturated.load(Ordering::Relaxed),
+            );
+        }
+    }
+
+    /// Run `transformScript` on an event. `None` when the script drops it,
+    /// or when it fails and `transformOnError` is `drop`; a failed event is
+    /// otherwise kept as it was.
+    fn transform(&mut self, event: serde_json::Value) -> Option<serde_json::Value> {
+        let Some(script) = &self.config.transform else {
+            return Some(event);
+        };
+        let pipeline = &self.state.pipeline;
+        match script.apply(&event) {
+            Ok(Some(event)) => Some(event),
+            Ok(None) => {
+                pipeline.transform_dropped.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            Err(e) => {
+                pipeline.transform_errors.fetch_add(1, Ordering::Relaxed);
+                if let Some(count) = self.transform_warning.record(1) {
+                    eprintln!(
+                        "[telemetry-writer] WARNING: transformScript failed on {} event(s), {}; latest: {}",
+                        count,
+                        if script.drop_on_error { "dropped" } else { "kept unchanged" },
+                        e
+                    );
+                }
+                (!script.drop_on_error).then_some(event)
+            }
+        }
+    }
+
+    fn batch(&mut self, kind: RecordKind) -> (&mut TableBatch, Option<&Arc<dyn KvBackend>>) {
+        match kind {
+            RecordKind::Log => (&mut self.log_batch, self.log_storage.as_ref()),
+            RecordKind::Span => (&mut self.span_batch, self.span_storage.as_ref()),
+            RecordKind::Metric => (&mut self.metric_batch, self.metric_storage.as_ref()),
+        }
+    }
+
+    /// Count a record that couldn't be encoded for its table, warning at most
+    /// once per `FAILURE_WARN_INTERVAL_MS`. The record still reaches PubSub
+    /// and the outputs.
+    fn serialize_failed(&mut self, kind: RecordKind, id: &str, error: &dyn std::fmt::Display) {
+        self.state.pipeline.serialize_errors.fetch_add(1, Ordering::Relaxed);
+        if let Some(count) = self.serialize_warning.record(1) {
+            eprintln!(
+                "[telemetry-writer] WARNING: {} {} record(s) not stored because they couldn't be encoded; latest {}: {}",
+                count,
+                kind.as_str(),
+                id,
+                error
+            );
+        }
+    }
+
+    /// Queue a table write, flushing the kind's batch once it is full.
+    /// Mirrors receive the write immediately.
+    async fn queue_put(&mut self, kind: RecordKind, key: &[u8], value: &[u8]) {
+        self.mirrors.put(kind, key, value);
+        let state = self.state;
+        let (batch, storage) = self.batch(kind);
+        let Some(storage) = storage.cloned() else { return };
+        if batch.push(key, value) >= TABLE_BATCH_MAX_RECORDS {
+            batch.flush(kind, &storage, state).await;
+        }
+    }
+
+    /// Queue a log record's table write: to the `severityTables` error table
+    /// for WARN and ERROR when it is configured, else as `queue_put`.
+    async fn queue_log_put(&mut self, level: &str, key: &[u8], value: &[u8]) {
+        let Some(storage) = self.error_log_storage.clone().filter(|_| is_severe_level(level)) else {
+            return self.queue_put(RecordKind::Log, key, value).await;
+        };
+        self.mirrors.put(RecordKind::Log, key, value);
+        if self.error_log_batch.push(key, value) >= TABLE_BATCH_MAX_RECORDS {
+            self.error_log_batch.flush(RecordKind::Log, &storage, self.state).await;
+        }
+    }
+
+    /// Record the channel's backlog after a receive, and warn once each time
+    /// it climbs past the high-water mark.
+    fn observe_queue(&mut self, rx: &tokio::sync::mpsc::Receiver<serde_json::Value>) {
+        let depth = rx.len();
+        let capacity = rx.max_capacity();
+        let pipeline = &self.state.pipeline;
+        pipeline.queue_depth.store(depth as u64, Ordering::Relaxed);
+        pipeline.queue_capacity.store(capacity as u64, Ordering::Relaxed);
+        pipeline.queue_high_water.fetch_max(depth as u64, Ordering::Relaxed);
+        // The receive just freed a slot, so the channel was full before it.
+        if depth + 1 >= capacity {
+            pipeline.queue_saturated.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let threshold = (capacity as f64 * QUEUE_HIGH_WATER_RATIO) as usize;
+        if depth >= threshold && !self.queue_warned {
+            self.queue_warned = true;
+            eprintln!(
+                "[telemetry-writer] WARNING: event queue at {}/{}; the writer is falling behind (saturated {} times)",
+                depth,
+                capacity,
+                pipeline.queue_saturated.load(Ordering::Relaxed)
+            );
+        } else if depth < threshold / 2 {
+            self.queue_warned = false;
+        }
+    }
+
+    /// Earliest time (epoch ms) a buffered batch must be flushed, or a metric
+    /// or dedup window closed.
+    fn next_flush_deadline(&self) -> Option<f64> {
+        let batch = [
+            &self.log_batch,
+            &self.error_log_batch,
+            &self.span_batch,
+            &self.metric_batch,
+            &self.span_index_batch,
+        ]
+        .iter()
+        .filter_map(|b| b.opened_ms)
+        .reduce(f64::min)
+        .map(|opened| opened + TABLE_BATCH_MAX_AGE_MS);
+        let window = self
+            .config
+            .metric_window_ms
+            .and_then(|w| self.metric_rollup.next_close_ms(w));
+        let dedup = self.log_dedup.next_close_ms();
+        let notify = self
+            .config
+            .sse_coalescing
+            .as_ref()
+            .and_then(|c| self.notify_coalescer.next_send_ms(c));
+        let health = Some(self.health_published_ms + HEALTH_PUBLISH_INTERVAL_MS);
+        batch
+            .into_iter()
+            .chain(window)
+            .chain(dedup)
+            .chain(notify)
+            .chain(health)
+            .reduce(f64::min)
+    }
+
+    /// Publish each output's health for `/healthz`.
+    fn publish_health(&mut self, now: f64) {
+        self.health_published_ms = now;
+        let health = self
+            .outputs
+            .iter()
+            .map(|slot| (slot.output.name().to_string(), slot.output.health()))
+            .collect();
+        *lock(&self.state.output_health) = health;
+        self.state.health_published_ms.store(now as u64, Ordering::Relaxed);
+    }
+
+    /// Write the metric and dedup windows that have closed, send coalesced
+    /// notifications whose interval has passed, then flush each batch that
+    /// has waited `TABLE_BATCH_MAX_AGE_MS`.
+    async fn flush_due(&mut self) {
+        let now = now_epoch_ms();
+        self.close_metric_windows(now).await;
+        self.close_dedup_windows(now).await;
+        self.send_coalesced(now).await;
+        if now - self.health_published_ms >= HEALTH_PUBLISH_INTERVAL_MS {
+            self.publish_health(now);
+        }
+        let state = self.state;
+        for kind in RecordKind::ALL {
+            let (batch, storage) = self.batch(kind);
+            let due = batch.opened_ms.is_some_and(|at| now - at >= TABLE_BATCH_MAX_AGE_MS);
+            if let (true, Some(storage)) = (due, storage.cloned()) {
+                batch.flush(kind, &storage, state).await;
+            }
+        }
+        let due = self.error_log_batch.opened_ms.is_some_and(|at| now - at >= TABLE_BATCH_MAX_AGE_MS);
+        if let (true, Some(storage)) = (due, self.error_log_storage.clone()) {
+            self.error_log_batch.flush(RecordKind::Log, &storage, self.state).await;
+        }
+        let due = self.span_index_batch.opened_ms.is_some_and(|at| now - at >= TABLE_BATCH_MAX_AGE_MS);
+        if let (true, Some(storage)) = (due, self.span_index_storage.clone()) {
+            self.span_index_batch.flush(RecordKind::Span, &storage, self.state).await;
+        }
+    }
+
+    async fn flush_all(&mut self) {
+        self.close_metric_windows(f64::INFINITY).await;
+        self.close_dedup_windows(f64::INFINITY).await;
+        self.send_coalesced(f64::INFINITY).await;
+        let state = self.state;
+        for kind in RecordKind::ALL {
+            let (batch, storage) = self.batch(kind);
+            if let Some(storage) = storage.cloned() {
+                batch.flush(kind, &storage, state).await;
+            }
+        }
+        if let Some(storage) = self.error_log_storage.clone() {
+            self.error_log_batch.flush(RecordKind::Log, &storage, self.state).await;
+        }
+        if let Some(storage) = self.span_index_storage.clone() {
+            self.span_index_batch.flush(RecordKind::Span, &storage, self.state).await;
+        }
+    }
+
+    /// Expiry for a log or span under `recordTtlRules`, as a stored
+    /// timestamp string. `None` leaves the record to table-level retention.
+    fn expires_at(&self, level: &str, target: &str, timestamp_ms: f64) -> Option<String> {
+        self.config
+            .record_ttl_rules
+            .iter()
+            .find(|rule| rule.matches(level, target))
+            .map(|rule| format_epoch_ms(timestamp_ms + rule.ttl_secs * 1000.0))
+    }
+
+    /// Delete log and span records whose `expiresAt` has passed, along with
+    /// their span index entries.
+    ///
+    /// A record under a rule expires at most that rule's TTL (plus
+    /// `maxFutureSkew`) after it was written. Each distinct TTL has its own
+    /// cursor per table, which walks keys written before that TTL's horizon,
+    /// resuming where the last sweep stopped; a record it passes that is not
+    /// yet due belongs to a longer TTL, whose cursor reaches it later. Tables
+    /// are walked in pages of write time, at most a day wide, and each sweep
+    /// examines at most `EXPIRY_SWEEP_MAX_RECORDS` records and
+    /// `EXPIRY_SWEEP_MAX_PAGES` pages per table, so neither a large backlog
+    /// nor the empty years before a table's first record stall the writer.
+    async fn sweep_expired(&mut self) {
+        let now = now_epoch_ms();
+        self.last_expiry_sweep_ms = now;
+
+        let mut deleted = 0;
+        let tables = [
+            (RecordKind::Log, self.log_storage.clone()),
+            (RecordKind::Log, self.error_log_storage.clone()),
+            (RecordKind::Span, self.span_storage.clone()),
+        ];
+        for (slot, (kind, storage)) in tables.into_iter().enumerate() {
+            let Some(storage) = storage else { continue };
+            let (mut examined, mut pages) = (0, 0);
+            for rule in 0..self.expiry_cursors.len() {
+                let horizon = now - (self.expiry_cursors[rule].0 + self.config.max_future_skew_secs) * 1000.0;
+                while examined < EXPIRY_SWEEP_MAX_RECORDS && pages < EXPIRY_SWEEP_MAX_PAGES {
+                    let Some((start, end)) = self.expiry_cursors[rule].1[slot].page(horizon) else { break };
+                    let entries = match storage
+                        .scan_range(record_key_at(start).as_bytes(), record_key_at(end).as_bytes())
+                        .await
+                    {
+                        Ok(entries) => entries,
+                        Err(_) => break,
+                    };
+                    self.expiry_cursors[rule].1[slot].advance(end, entries.len());
+                    examined += entries.len();
+                    pages += 1;
+                    for (key, bytes) in entries {
+                        if self.delete_if_expired(kind, &storage, &key, &bytes, now).await {
+                            deleted += 1;
+                        }
+                    }
+                }
+            }
+        }
+
+        if deleted > 0 {
+            eprintln!("[telemetry-writer] Expired {} records", deleted);
+        }
+    }
+
+    /// Delete one stored record, and its span index entries, if its
+    /// `expiresAt` is at or before `now`. Returns whether it was deleted.
+    async fn delete_if_expired(
+        &mut self,
+        kind: RecordKind,
+        storage: &Arc<dyn KvBackend>,
+        key: &[u8],
+        bytes: &[u8],
+        now: f64,
+    ) -> bool {
+        let Ok(record) = from_storage_bytes(bytes) else { return false };
+        let expired = record
+            .get("expiresAt")
+            .and_then(parse_stored_time)
+            .is_some_and(|at| at <= now);
+        if !expired {
+            return false;
+        }
+        self.mirrors.delete(kind, key);
+        let deleted = storage.delete(key).await.is_ok();
+
+        if let Some(index) = self.span_index_storage.as_ref().filter(|_| kind == RecordKind::Span) {
+            for index_key in stored_span_index_keys(&self.config.indexed_span_fields, bytes, key) {
+                let _ = index.delete(index_key.as_bytes()).await;
+            }
+        }
+        deleted
+    }
+
+    /// Whether a `metricRetention` window has completed since the last run.
+    fn rollup_due(&self, retention: &MetricRetention, now: f64) -> bool {
+        self.rollup_storage.is_some()
+            && self
+                .rollup_next_window
+                .is_none_or(|next| (next + 1) as f64 * retention.interval_ms <= now)
+    }
+
+    /// Roll the metric points of each completed `rollupInterval` window into
+    /// the MetricRollup table, then delete raw points older than `rawDays`
+    /// and rollups older than `rollupDays`.
+    ///
+    /// Rollup ids come from the window and series, so recomputing a window
+    /// overwrites its record instead of adding to it. The previous window is
+    /// recomputed on each run to pick up late points. The first run after
+    /// startup starts at the oldest window whose points are all still
+    /// stored, so a partially expired window is never rewritten.
+    async fn roll_up_metrics(&mut self, retention: &MetricRetention) {
+        let (Some(raw), Some(rollups)) = (self.metric_storage.clone(), self.rollup_storage.clone()) else {
+            return;
+        };
+        self.metric_batch.flush(RecordKind::Metric, &raw, self.state).await;
+
+        let now = now_epoch_ms();
+        let interval = retention.interval_ms;
+        let raw_cutoff = now - retention.raw_days * 86_400_000.0;
+        let floor = (raw_cutoff / interval).ceil() as i64;
+        let from = self.rollup_next_window.map_or(floor, |next| (next - 1).max(floor));
+        let to = (now / interval).floor() as i64;
+
+        if from < to {
+            // Points of the windows were written from the first window's
+            // start minus the allowed skew; later writes land in the next
+            // run's recompute of the previous window.
+            let skew_ms = self.config.max_future_skew_secs * 1000.0;
+            let end_ms = to as f64 * interval + skew_ms;
+            let mut page_start = from as f64 * interval - skew_ms;
+            let mut windows: HashMap<RollupKey, MetricWindow> = HashMap::new();
+            while page_start < end_ms {
+                let page_end = (page_start + METRIC_ROLLUP_SCAN_PAGE_MS).min(end_ms);
+                let (start, end) = (record_key_at(page_start), record_key_at(page_end));
+                let points = match scan_records(&raw, start.as_bytes(), end.as_bytes()).await {
+                    Ok(points) => points,
+                    Err(e) => {
+                        eprintln!("[telemetry-writer] Metric rollup scan failed: {}", e);
+                        return;
+                    }
+                };
+                page_start = page_end;
+                for point in &points {
+                    let Some(ts) = point.get("timestamp").and_then(parse_stored_time) else { continue };
+                    let window = (ts / interval).floor() as i64;
+                    if window < from || window >= to {
+                        continue;
+                    }
+                    let key = RollupKey {
+                        name: point["name"].as_str().unwrap_or("").to_string(),
+                        attributes: point["attributes"].as_str().unwrap_or("{}").to_string(),
+                        window,
+                    };
+                    // Records written by `metricAggregation` already summarize a window.
+                    let value = point["value"].as_f64().unwrap_or(0.0);
+                    let count = point["count"].as_u64().unwrap_or(1);
+                    let w = windows.entry(key).or_insert(MetricWindow {
+                        min: f64::INFINITY,
+                        max: f64::NEG_INFINITY,
+                        sum: 0.0,
+                        count: 0,
+                    });
+                    w.min = w.min.min(point["min"].as_f64().unwrap_or(value));
+                    w.max = w.max.max(point["max"].as_f64().unwrap_or(value));
+                    w.sum += point["sum"].as_f64().unwrap_or(value);
+                    w.count += count;
+                }
+            }
+
+            let mut written = 0;
+            for (key, window) in &windows {
+                let record = metric_rollup_record(key, window, interval);
+                let id = record["id"].as_str().unwrap_or_default().to_string();
+                let bytes = match to_storage_bytes(&record) {
+                    Ok(bytes) => bytes,
+                    Err(e) => {
+                        self.serialize_failed(RecordKind::Metric, &id, &e);
+                        continue;
+                    }
+                };
+                match rollups.put(id.as_bytes(), &bytes).await {
+                    Ok(()) => written += 1,
+                    Err(e) => {
+                        self.state.pipeline.storage_failures.fetch_add(1, Ordering::Relaxed);
+                        eprintln!("[telemetry-writer] Failed to store metric rollup {}: {}", id, e);
+                    }
+                }
+            }
+            if written > 0 {
+                eprintln!(
+                    "[telemetry-writer] Rolled up {} metric series over {} window(s)",
+                    written,
+                    to - from
+                );
+            }
+        }
+        self.rollup_next_window = Some(to.max(from));
+
+        let rollup_cutoff = now - retention.rollup_days * 86_400_000.0;
+        let raw_mirror = Some((&*self.mirrors, RecordKind::Metric));
+        let [raw_cursor, rollup_cursor] = &mut self.retention_cursors;
+        for (storage, cursor, cutoff, mirror, what) in [
+            (&raw, raw_cursor, raw_cutoff, raw_mirror, "metric points"),
+            (&rollups, rollup_cursor, rollup_cutoff, None, "metric rollups"),
+        ] {
+            match purge_before(storage, cursor, cutoff, mirror, None).await {
+                Ok(0) => {}
+                Ok(deleted) => eprintln!("[telemetry-writer] Expired {} {}", deleted, what),
+                Err(e) => eprintln!("[telemetry-writer] Failed to expire {}: {}", what, e),
+            }
+        }
+    }
+
+    /// Next sequence number for the table a `kind` record at `level` is
+    /// stored in. Each table counts on its own, so consumers detect loss as
+    /// gaps in `seq` among one table's records sharing a `writerId`.
+    fn next_seq(&mut self, kind: RecordKind, level: &str) -> u64 {
+        let table = if kind == RecordKind::Log && self.error_log_storage.is_some() && is_severe_level(level) {
+            3
+        } else {
+            kind.index()
+        };
+        self.seq[table] += 1;
+        self.seq[table]
+    }
+
+    /// The `idField` value of `event` under `idSource: field`, stored as the
+    /// record's `upstreamId`. The record key stays a generated UUIDv7, so
+    /// write-time scans, purges and expiry cover every record.
+    fn upstream_id(&self, event: &serde_json::Value) -> Option<String> {
+        let IdSource::Field(ref field) = self.config.id_source else {
+            return None;
+        };
+        let value = event
+            .get(field.as_str())
+            .or_else(|| event_field(event, "fields").and_then(|f| f.get(field.as_str())));
+        match value? {
+            serde_json::Value::String(s) if !s.is_empty() => Some(s.clone()),
+            serde_json::Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Whether the startup warmup window is still open. Policies that drop
+    /// or throttle events stand down until it closes.
+    fn in_warmup(&self) -> bool {
+        now_epoch_ms() - self.started_ms < self.config.warmup_secs * 1000.0
+    }
+
+    /// Whether a log event meets its target's `targetLevels` threshold.
+    fn passes_target_level(&self, event: &serde_json::Value) -> bool {
+        let str_field = |key: &str| event_field(event, key).and_then(|v| v.as_str()).unwrap_or("");
+        self.state
+            .live
+            .load()
+            .target_levels
+            .allows(normalize_level(str_field("level")).0, str_field("target"))
+    }
+
+    /// Whether a log or span event fits its target's `rateLimits` budget.
+    /// ERROR events always pass, and nothing is throttled during warmup.
+    fn within_rate_limit(&mut self, event: &serde_json::Value) -> bool {
+        let live = self.state.live.load();
+        if live.rate_limits.is_empty() || self.in_warmup() {
+            return true;
+        }
+        let str_field = |key: &str| event_field(event, key).and_then(|v| v.as_str()).unwrap_or("");
+        let level = normalize_level(str_field("level")).0;
+        let target = str_field("target");
+        match live.rate_limits.limit(target) {
+            Some(limit) if level_rank(level) < level_rank("ERROR") => {
+                self.rate_limiter.allow(target, limit, now_epoch_ms())
+            }
+            _ => true,
+        }
+    }
+
+    /// Write a WARN log for each target that started being throttled, or is
+    /// still throttled a `RATE_LIMIT_WARN_INTERVAL_MS` after its last one.
+    async fn warn_rate_limited(&mut self) {
+        if self.rate_limiter.warnings.is_empty() {
+            return;
+        }
+        let now = now_epoch_ms();
+        for (target, dropped, limit) in std::mem::take(&mut self.rate_limiter.warnings) {
+            eprintln!(
+                "[telemetry-writer] Rate limiting target '{}' at {} events/s ({} dropped)",
+                target, limit, dropped
+            );
+            self.store_log(&rate_limit_warning(&target, dropped, limit, now), None).await;
+        }
+    }
+
+    /// `adaptiveSampling`: count an ERROR log or span event, starting or
+    /// extending a boost while the rate is above the threshold.
+    fn record_error(&mut self) {
+        let Some(settings) = &self.config.adaptive_sampling else {
+            return;
+        };
+        let now = now_epoch_ms();
+        let started = self.adaptive_boost.record_error(settings, now);
+        let pipeline = &self.state.pipeline;
+        if let Some(until) = self.adaptive_boost.until_ms {
+            pipeline.adaptive_boost_until_ms.store(until as u64, Ordering::Relaxed);
+        }
+        if started {
+            pipeline.adaptive_boosts.fetch_add(1, Ordering::Relaxed);
+            eprintln!(
+                "[telemetry-writer] ERROR rate above {}/s over {}s: keeping at least {} of DEBUG/TRACE logs and spans for {}s",
+                settings.error_rate_threshold, settings.window_secs, settings.boost_ratio, settings.cooldown_secs
+            );
+        }
+    }
+
+    /// Sampling decision for an event whose kind has the given base keep
+    /// rate, narrowed by the level and target rules, and raised for DEBUG/TRACE
+    /// logs and spans during an `adaptiveSampling` boost. Counts drops.
+    fn keep_sampled(&mut self, kind: RecordKind, event: &serde_json::Value, base_rate: f64) -> bool {
+        let str_field = |key: &str| event_field(event, key).and_then(|v| v.as_str()).unwrap_or("");
+        let level = normalize_level(str_field("level")).0;
+        let mut rate = self.state.live.load().sampling.rate(level, str_field("target"), base_rate);
+        if let (Some(settings), Some(_)) = (&self.config.adaptive_sampling, self.adaptive_boost.until_ms) {
+            if kind == RecordKind::Span || matches!(level, "DEBUG" | "TRACE") {
+                rate = rate.max(settings.boost_ratio);
+            }
+        }
+        if rate >= 1.0 || self.in_warmup() {
+            return true;
+        }
+        let keep = sample_keep(&sampling_key(event), rate);
+        if !keep {
+            self.sampled_out += 1;
+            let pipeline = &self.state.pipeline;
+            pipeline.events_dropped.fetch_add(1, Ordering::Relaxed);
+            pipeline.by_kind.dropped[kind.index()].fetch_add(1, Ordering::Relaxed);
+        }
+        keep
+    }
+
+    /// The event's `fields` with `redactFields` applied; borrowed when there
+    /// is nothing to redact.
+    fn redacted_fields<'a>(&self, event: &'a serde_json::Value) -> Option<Cow<'a, serde_json::Value>> {
+        let fields = event_field(event, "fields")?;
+        if self.config.redact_fields.is_empty() {
+            return Some(Cow::Borrowed(fields));
+        }
+        let mut fields = fields.clone();
+        redact_keys(&mut fields, &self.config.redact_fields);
+        Some(Cow::Owned(fields))
+    }
+
+    /// A log message with every `redactPatterns` match replaced.
+    fn redacted_message<'a>(&self, message: &'a str) -> Cow<'a, str> {
+        let mut message = Cow::Borrowed(message);
+        for re in &self.config.redact_patterns {
+            if let Cow::Owned(replaced) = re.replace_all(&message, REDACTED) {
+                message = Cow::Owned(replaced);
+            }
+        }
+        message
+    }
+
+    /// Copy the `promoteFields` keys from an event's fields to top-level
+    /// columns of its record; missing keys become null. The full `fields`
+    /// string is kept as is.
+    fn promote_fields(&self, record: &mut serde_json::Value, fields: Option<&serde_json::Value>) {
+        for key in &self.config.promote_fields {
+            let value = fields.and_then(|f| f.get(key)).cloned();
+            record[key.as_str()] = value.unwrap_or(serde_json::Value::Null);
+        }
+    }
+
+    /// Clamp an event timestamp (epoch ms) that lies further in the future than
+    /// `maxFutureSkew` allows. Returns the timestamp to store, or `None` if the
+    /// original is within bounds.
+    fn clamp_future(&mut self, ms: f64) -> Option<f64> {
+        let now = now_epoch_ms();
+        if ms > now + self.config.max_future_skew_secs * 1000.0 {
+            self.future_clamped += 1;
+            self.state.pipeline.future_clamped.fetch_add(1, Ordering::Relaxed);
+            Some(now)
+        } else {
+            None
+        }
+    }
+
+    async fn write_log(&mut self, event: &serde_json::Value) {
+        if let Some(dedup) = self.config.dedup.clone() {
+            let now = now_epoch_ms();
+            self.close_dedup_windows(now).await;
+            if self.log_dedup.observe(event, &dedup, now) {
+                self.state.pipeline.logs_deduplicated.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        }
+        self.store_log(event, None).await;
+    }
+
+    /// Write the summaries of dedup windows that ended by `now` (epoch ms):
+    /// the last suppressed occurrence, with `repeatCount` set to the number
+    /// suppressed.
+    async fn close_dedup_windows(&mut self, now: f64) {
+        for (last, repeat_count) in self.log_dedup.take_closed(now) {
+            self.store_log(&last, Some(repeat_count)).await;
+        }
+    }
+
+    /// `ingestMode: count`: tally the event by kind and level, and every
+    /// `COUNT_REPORT_INTERVAL_MS` log the throughput and publish it for the
+    /// status endpoint. Nothing is stored, notified or forwarded.
+    fn count_event(&mut self, event: &serde_json::Value) {
+        let pipeline = &self.state.pipeline;
+        let processed = pipeline.events_processed.fetch_add(1, Ordering::Relaxed) + 1;
+        let kind = RecordKind::parse(event.get("kind").and_then(|v| v.as_str()).unwrap_or(""));
+        if let Some(kind) = kind {
+            pipeline.by_kind.processed[kind.index()].fetch_add(1, Ordering::Relaxed);
+        }
+        if matches!(kind, Some(RecordKind::Log | RecordKind::Span)) {
+            let level = event_field(event, "level")
+                .and_then(|v| v.as_str())
+                .map_or("INFO", |l| normalize_level(l).0);
+            self.ingest_count.levels[level_rank(level).min(4) as usize] += 1;
+        }
+
+        let now = now_epoch_ms();
+        let count = &mut self.ingest_count;
+        let elapsed_ms = now - count.reported_ms;
+        if elapsed_ms < COUNT_REPORT_INTERVAL_MS {
+            return;
+        }
+        let rate = (processed - count.reported_events) as f64 * 1000.0 / elapsed_ms;
+        pipeline.events_per_sec.store(rate.round() as u64, Ordering::Relaxed);
+        count.reported_ms = now;
+        count.reported_events = processed;
+        let [logs, spans, metrics] = pipeline.by_kind.totals();
+        let [trace, debug, info, warn, error] = count.levels;
+        eprintln!(
+            "[telemetry-writer] Count mode: {:.0} events/s, {} total (logs={}, spans={}, metrics={}; TRACE={}, DEBUG={}, INFO={}, WARN={}, ERROR={})",
+            rate, processed, logs, spans, metrics, trace, debug, info, warn, error
+        );
+    }
+
+    /// Notify PubSub of a new record, or hold it for the next array
+    /// notification while its table is above the `sseCoalescing` threshold.
+    async fn notify(&mut self, kind: RecordKind, id: &str, record: &serde_json::Value) {
+        let Some(ps) = self.pubsub.clone() else { return };
+        if let Some(ref coalescing) = self.config.sse_coalescing {
+            let now = now_epoch_ms();
+            if self.notify_coalescer.should_hold(kind, coalescing, now) {
+                self.notify_coalescer.hold(kind, id, record.clone(), now);
+                return;
+            }
+        }
+        ps.notify_update(&self.config.pubsub_tables[kind.index()], id, record).await;
+    }
+
+    /// Send the notifications held for at least `intervalMs` by `now`. A
+    /// single held record goes out as usual; several go out as one array,
+    /// under the id of the last.
+    async fn send_coalesced(&mut self, now: f64) {
+        let (Some(ps), Some(coalescing)) = (self.pubsub.clone(), self.config.sse_coalescing.as_ref()) else {
+            return;
+        };
+        for (kind, mut held) in self.notify_coalescer.take_due(now - coalescing.interval_ms) {
+            let table = &self.config.pubsub_tables[kind.index()];
+            if held.len() == 1 {
+                let (id, record) = held.remove(0);
+                ps.notify_update(table, &id, &record).await;
+                continue;
+            }
+            let Some(last_id) = held.last().map(|(id, _)| id.clone()) else { continue };
+            let records: Vec<serde_json::Value> = held.into_iter().map(|(_, r)| r).collect();
+            ps.notify_update(table, &last_id, &json!(records)).await;
+        }
+    }
+
+    async fn store_log(&mut self, event: &serde_json::Value, repeat_count: Option<u64>) {
+        let id = self.ids.next_id();
+        let level = event_level(self.state, event);
+        let seq = self.next_seq(RecordKind::Log, level);
+        let original = event_time(self.state, event, "timestamp");
+        note_lag(self.state, original);
+        let clamped = self.clamp_future(original);
+        let timestamp = clamped.unwrap_or(original);
+        let fields = self.redacted_fields(event);
+        let message = event_field(event, "message").and_then(|v| v.as_str()).unwrap_or("");
+        let mut record = json!({
+            "id": id,
+            "seq": seq,
+            "writerId": self.writer_id,
+            "timestamp": format_epoch_ms(timestamp),
+            "level": level,
+            "target": event.get("target").and_then(|v| v.as_str()).unwrap_or(""),
+            "message": self.redacted_message(message),
+            "fields": serde_json::to_string(
+                fields.as_deref().unwrap_or(&json!({}))
+            ).unwrap_or_default(),
+        });
+        if clamped.is_some() {
+            record["originalTimestamp"] = json!(format_epoch_ms(original));
+        }
+        if let Some(upstream) = self.upstream_id(event) {
+            record["upstreamId"] = json!(upstream);
+        }
+        if let Some(count) = repeat_count {
+            record["repeatCount"] = json!(count);
+        }
+        self.promote_fields(&mut record, fields.as_deref());
+        if let Some(at) = self.expires_at(
+            record["level"].as_str().unwrap_or(""),
+            record["target"].as_str().unwrap_or(""),
+            timestamp,
+        ) {
+            record["expiresAt"] = json!(at);
+        }
+
+        self.run_outputs(RecordKind::Log, &record, true);
+
+        match to_storage_bytes(&record) {
+            Ok(bytes) => {
+                let level = record["level"].as_str().unwrap_or("");
+                self.queue_log_put(level, id.as_bytes(), &bytes).await
+            }
+            Err(e) => self.serialize_failed(RecordKind::Log, &id, &e),
+        }
+
+        self.notify(RecordKind::Log, &id, &record).await;
+
+        self.run_outputs(RecordKind::Log, &record, false);
+    }
+
+    async fn write_span(&mut self, event: &serde_json::Value) {
+        // A span missing one end is treated as instantaneous at the other.
+        let start = event_field(event, "startTime").and_then(parse_event_time);
+        let end = event_field(event, "endTime").and_then(parse_event_time);
+        let (start_ms, end_ms) = match (start, end) {
+            (Some(start), Some(end)) => (start, end),
+            (Some(at), None) => {
+                note_invalid_time(self.state, event, "endTime");
+                (at, at)
+            }
+            (None, Some(at)) => {
+                note_invalid_time(self.state, event, "startTime");
+                (at, at)
+            }
+            (None, None) => {
+                note_invalid_time(self.state, event, "startTime");
+                let now = now_epoch_ms();
+                (now, now)
+            }
+        };
+        let duration_ms = end_ms - start_ms;
+
+        // Filtered before an id or seq is assigned, so stored spans keep a
+        // gapless seq.
+        if self.config.min_span_duration_ms.is_some_and(|min| duration_ms < min) && !is_error_span(event) {
+            self.state.pipeline.short_spans_filtered.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        let id = self.ids.next_id();
+        let seq = self.next_seq(RecordKind::Span, "");
+
+        // A fast producer clock shifts the whole span back so it ends now,
+        // preserving its duration.
+        let original_start = start_ms;
+        note_lag(self.state, end_ms);
+        let clamped = self.clamp_future(end_ms);
+        let (start_ms, end_ms) = match clamped {
+            Some(now) => (now - duration_ms, now),
+            None => (start_ms, end_ms),
+        };
+        let fields = self.redacted_fields(event);
+
+        let mut record = json!({
+            "id": id,
+            "seq": seq,
+            "writerId": self.writer_id,
+            "traceId": span_link(event, "traceId", "trace_id"),
+            "spanId": span_link(event, "spanId", "span_id"),
+            "parentSpanId": span_link(event, "parentSpanId", "parent_span_id"),
+            "name": event.get("name").and_then(|v| v.as_str()).unwrap_or(""),
+            "target": event.get("target").and_then(|v| v.as_str()).unwrap_or(""),
+            "level": event_level(self.state, event),
+            "startTime": format_epoch_ms(start_ms),
+            "endTime": format_epoch_ms(end_ms),
+            "durationMs": duration_ms,
+            "fields": serde_json::to_string(
+                fields.as_deref().unwrap_or(&json!({}))
+            ).unwrap_or_default(),
+        });
+        if clamped.is_some() {
+            record["originalStartTime"] = json!(format_epoch_ms(original_start));
+        }
+        if let Some(upstream) = self.upstream_id(event) {
+            record["upstreamId"] = json!(upstream);
+        }
+        self.promote_fields(&mut record, fields.as_deref());
+        if let Some(at) = self.expires_at(
+            record["level"].as_str().unwrap_or(""),
+            record["target"].as_str().unwrap_or(""),
+            end_ms,
+        ) {
+            record["expiresAt"] = json!(at);
+        }
+
+        self.run_outputs(RecordKind::Span, &record, true);
+
+        match to_storage_bytes(&record) {
+            Ok(bytes) => self.queue_put(RecordKind::Span, id.as_bytes(), &bytes).await,
+            Err(e) => self.serialize_failed(RecordKind::Span, &id, &e),
+        }
+
+        if let (Some(fields), Some(index)) = (&fields, self.span_index_storage.clone()) {
+            for key in span_index_keys(&self.config.indexed_span_fields, fields, &id) {
+                let Ok(entry) = to_storage_bytes(&span_index_entry(&key, &id)) else { continue };
+                if self.span_index_batch.push(key.as_bytes(), &entry) >= TABLE_BATCH_MAX_RECORDS {
+                    self.span_index_batch.flush(RecordKind::Span, &index, self.state).await;
+                }
+            }
+        }
+
+        self.notify(RecordKind::Span, &id, &record).await;
+
+        self.run_outputs(RecordKind::Span, &record, false);
+
+        // Only spans derive logs, so a derived log never derives again.
+        let failed = fields
+            .as_deref()
+            .and_then(|f| f.get("status"))
+            .and_then(|v| v.as_str())
+            == Some("ERROR");
+        if failed && self.config.derive_error_logs {
+            self.store_log(&error_log_for_span(&record, end_ms), None).await;
+        }
+    }
+
+    async fn write_metric(&mut self, event: &serde_json::Value) {
+        let original = event_time(self.state, event, "timestamp");
+        note_lag(self.state, original);
+        let clamped = self.clamp_future(original);
+        let timestamp = clamped.unwrap_or(original);
+
+        if let Some(window_ms) = self.config.metric_window_ms {
+            self.metric_rollup.add(
+                event.get("name").and_then(|v| v.as_str()).unwrap_or(""),
+                serde_json::to_string(event.get("attributes").unwrap_or(&json!({}))).unwrap_or_default(),
+                event.get("value").and_then(|v| v.as_f64()).unwrap_or(0.0),
+                timestamp,
+                window_ms,
+            );
+            return;
+        }
+
+        let id = self.ids.next_id();
+        let seq = self.next_seq(RecordKind::Metric, "");
+        let mut record = json!({
+            "id": id,
+            "seq": seq,
+            "writerId": self.writer_id,
+            "name": event.get("name").and_then(|v| v.as_str()).unwrap_or(""),
+            "value": event.get("value").and_then(|v| v.as_f64()).unwrap_or(0.0),
+            "attributes": serde_json::to_string(
+                event.get("attributes").unwrap_or(&json!({}))
+            ).unwrap_or_default(),
+            "timestamp": format_epoch_ms(timestamp),
+        });
+        if clamped.is_some() {
+            record["originalTimestamp"] = json!(format_epoch_ms(original));
+        }
+        if let Some(upstream) = self.upstream_id(event) {
+            record["upstreamId"] = json!(upstream);
+        }
+        self.store_metric(&id, &record).await;
+    }
+
+    /// Write metric records for every window that ended by `now` (epoch ms):
+    /// `value` is the window's average, alongside its min, max, sum, count,
+    /// and bounds.
+    async fn close_metric_windows(&mut self, now: f64) {
+        let Some(window_ms) = self.config.metric_window_ms else { return };
+        for (key, window) in self.metric_rollup.take_closed(now, window_ms) {
+            let id = self.ids.next_id();
+            let seq = self.next_seq(RecordKind::Metric, "");
+            let start_ms = key.window as f64 * window_ms;
+            let record = json!({
+                "id": id,
+                "seq": seq,
+                "writerId": self.writer_id,
+                "name": key.name,
+                "value": window.sum / window.count as f64,
+                "attributes": key.attributes,
+                "timestamp": format_epoch_ms(start_ms),
+                "min": window.min,
+                "max": window.max,
+                "sum": window.sum,
+                "count": window.count,
+                "windowStart": format_epoch_ms(start_ms),
+                "windowEnd": format_epoch_ms(start_ms + window_ms),
+            });
+            self.store_metric(&id, &record).await;
+        }
+    }
+
+    async fn store_metric(&mut self, id: &str, record: &serde_json::Value) {
+        self.run_outputs(RecordKind::Metric, record, true);
+
+        match to_storage_bytes(record) {
+            Ok(bytes) => self.queue_put(RecordKind::Metric, id.as_bytes(), &bytes).await,
+            Err(e) => self.serialize_failed(RecordKind::Metric, id, &e),
+        }
+
+        self.notify(RecordKind::Metric, id, record).await;
+
+        self.run_outputs(RecordKind::Metric, record, false);
+    }
+}
+
+/// `dedup` settings: a log line seen more than `threshold` times within
+/// `window_ms` of its first occurrence has the rest collapsed into one
+/// summary record.
+#[derive(Clone, Debug)]
+struct DedupConfig {
+    window_ms: f64,
+    threshold: u64,
+}
+
+impl DedupConfig {
+    fn parse(section: &serde_json::Value) -> Self {
+        let window_ms = match section.get("windowMs").map(|v| v.as_f64()) {
+            None => DEFAULT_DEDUP_WINDOW_MS,
+            Some(Some(ms)) if ms >= 1.0 => ms.floor(),
+            Some(_) => {
+                eprintln!(
+                    "[yeti-telemetry] Ignoring dedup.windowMs (must be >= 1), using {}",
+                    DEFAULT_DEDUP_WINDOW_MS
+                );
+                DEFAULT_DEDUP_WINDOW_MS
+            }
+        };
+        let threshold = match section.get("threshold").map(|v| v.as_u64()) {
+            None => DEFAULT_DEDUP_THRESHOLD,
+            Some(Some(n)) if n >= 1 => n,
+            Some(_) => {
+                eprintln!(
+                    "[yeti-telemetry] Ignoring dedup.threshold (must be >= 1), using {}",
+                    DEFAULT_DEDUP_THRESHOLD
+                );
+                DEFAULT_DEDUP_THRESHOLD
+            }
+        };
+        Self { window_ms, threshold }
+    }
+}
+
+/// `idSource`: whether records carry an upstream id next to their
+/// generated one.
+#[derive(Clone, Debug, PartialEq)]
+enum IdSource {
+    Generate,
+    /// Store this field (top level, then inside `fields`) as `upstreamId`.
+    Field(String),
+}
+
+/// What the writer does with each event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum IngestMode {
+    /// Store, notify and forward records as configured.
+    Full,
+    /// Count events by kind and level and do nothing else, so the cost of
+    /// the dispatch path and channel can be measured on its own.
+    Count,
+}
+
+impl IngestMode {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Full => "full",
+            Self::Count => "count",
+        }
+    }
+}
+
+/// Events seen in `ingestMode: count`, and the throughput report state.
+#[derive(Default)]
+struct IngestCount {
+    /// Log and span events by level, indexed TRACE..ERROR.
+    levels: [u64; 5],
+    /// When the last report was made (epoch ms) and `events_processed` then.
+    reported_ms: f64,
+    reported_events: u64,
+}
+
+/// `sseCoalescing` settings: once a table sees more than `threshold_per_sec`
+/// notifications within a second, further ones are held and sent together
+/// every `interval_ms`.
+#[derive(Clone, Debug)]
+struct CoalesceConfig {
+    threshold_per_sec: u64,
+    interval_ms: f64,
+}
+
+impl CoalesceConfig {
+    fn parse(section: &serde_json::Value) -> Self {
+        let threshold_per_sec = match section.get("thresholdPerSec").map(|v| v.as_u64()) {
+            None => DEFAULT_SSE_COALESCE_THRESHOLD,
+            Some(Some(n)) => n,
+            Some(None) => {
+                eprintln!(
+                    "[yeti-telemetry] Ignoring sseCoalescing.thresholdPerSec (must be >= 0), using {}",
+                    DEFAULT_SSE_COALESCE_THRESHOLD
+                );
+                DEFAULT_SSE_COALESCE_THRESHOLD
+            }
+        };
+        let interval_ms = match section.get("intervalMs").map(|v| v.as_f64()) {
+            None => DEFAULT_SSE_COALESCE_INTERVAL_MS,
+            Some(Some(ms)) if ms >= 1.0 => ms.floor(),
+            Some(_) => {
+                eprintln!(
+                    "[yeti-telemetry] Ignoring sseCoalescing.intervalMs (must be >= 1), using {}",
+                    DEFAULT_SSE_COALESCE_INTERVAL_MS
+                );
+                DEFAULT_SSE_COALESCE_INTERVAL_MS
+            }
+        };
+        Self { threshold_per_sec, interval_ms }
+    }
+}
+
+/// Per-table notification rate and the notifications held while a table is
+/// over its threshold. The rate is counted over fixed one-second windows.
+#[derive(Default)]
+struct NotifyCoalescer {
+    rate_window_ms: [f64; 3],
+    rate_count: [u64; 3],
+    /// Held (id, record) pairs, and when the first of them was held.
+    held: [Vec<(String, serde_json::Value)>; 3],
+    held_since_ms: [Option<f64>; 3],
+}
+
+impl NotifyCoalescer {
+    /// Count a notification for `kind`; `true` when it should be held. Once
+    /// a batch is open, later notifications join it to keep their order.
+    fn should_hold(&mut self, kind: RecordKind, config: &CoalesceConfig, now: f64) -> bool {
+        let i = kind.index();
+        if now - self.rate_window_ms[i] >= 1000.0 {
+            self.rate_window_ms[i] = now;
+            self.rate_count[i] = 0;
+        }
+        self.rate_count[i] += 1;
+        self.held_since_ms[i].is_some() || self.rate_count[i] > config.threshold_per_sec
+    }
+
+    fn hold(&mut self, kind: RecordKind, id: &str, record: serde_json::Value, now: f64) {
+        let i = kind.index();
+        self.held[i].push((id.to_string(), record));
+        self.held_since_ms[i].get_or_insert(now);
+    }
+
+    /// When the earliest held batch is due (epoch ms).
+    fn next_send_ms(&self, config: &CoalesceConfig) -> Option<f64> {
+        self.held_since_ms
+            .iter()
+            .flatten()
+            .copied()
+            .reduce(f64::min)
+            .map(|since| since + config.interval_ms)
+    }
+
+    /// Remove the batches held since `cutoff` (epoch ms) or earlier.
+    fn take_due(&mut self, cutoff: f64) -> Vec<(RecordKind, Vec<(String, serde_json::Value)>)> {
+        let mut due = Vec::new();
+        for kind in RecordKind::ALL {
+            let i = kind.index();
+            if self.held_since_ms[i].is_some_and(|since| since <= cutoff) {
+                self.held_since_ms[i] = None;
+                due.push((kind, std::mem::take(&mut self.held[i])));
+            }
+        }
+        due
+    }
+}
+
+/// Occurrences of one log line within its current window.
+struct DedupEntry {
+    count: u64,
+    /// Occurrences past the threshold, and the latest of them, written as
+    /// the window's summary.
+    suppressed: u64,
+    last_suppressed: Option<serde_json::Value>,
+}
+
+/// Repeated log lines keyed by (level, target, message). A window opens at a
+/// line's first occurrence (by arrival time) and lasts `windowMs`; the first
+/// `threshold` occurrences are written as usual and the rest suppressed.
+/// Closed windows must be taken before observing events at a later time.
+#[derive(Default)]
+struct LogDedup {
+    entries: HashMap<(&'static str, String, String), DedupEntry>,
+    /// Keys in the order their windows close; every window has the same
+    /// length, so that's the order they opened.
+    closing: VecDeque<(f64, (&'static str, String, String))>,
+}
+
+impl LogDedup {
+    /// Count `event`; `true` when it should be suppressed.
+    fn observe(&mut self, event: &serde_json::Value, config: &DedupConfig, now: f64) -> bool {
+        let level = event_field(event, "level")
+            .and_then(|v| v.as_str())
+            .map_or("INFO", |l| normalize_level(l).0);
+        let key = (
+            level,
+            event.get("target").and_then(|v| v.as_str()).unwrap_or("").to_string(),
+            event_field(event, "message").and_then(|v| v.as_str()).unwrap_or("").to_string(),
+        );
+        if let Some(entry) = self.entries.get_mut(&key) {
+            entry.count += 1;
+            if entry.count <= config.threshold {
+                return false;
+            }
+            entry.suppressed += 1;
+            entry.last_suppressed = Some(event.clone());
+            return true;
+        }
+        if self.entries.len() < DEDUP_MAX_KEYS {
+            self.entries.insert(
+                key.clone(),
+                DedupEntry {
+                    count: 1,
+                    suppressed: 0,
+                    last_suppressed: None,
+                },
+            );
+            self.closing.push_back((now + config.window_ms, key));
+        }
+        false
+    }
+
+    /// When the earliest open window ends (epoch ms).
+    fn next_close_ms(&self) -> Option<f64> {
+        self.closing.front().map(|(end, _)| *end)
+    }
+
+    /// Remove the windows that ended by `now` (epoch ms), returning the last
+    /// suppressed event and suppressed count of each that suppressed any.
+    fn take_closed(&mut self, now: f64) -> Vec<(serde_json::Value, u64)> {
+        let mut summaries = Vec::new();
+        while self.closing.front().is_some_and(|(end, _)| *end <= now) {
+            let Some((_, key)) = self.closing.pop_front() else { break };
+            if let Some(DedupEntry {
+                suppressed,
+                last_suppressed: Some(last),
+                ..
+            }) = self.entries.remove(&key)
+            {
+                summaries.push((last, suppressed));
+            }
+        }
+        summaries
+    }
+}
+
+/// Log event recording a failed span, for `deriveErrorLogs`: an ERROR from
+/// the span's target at its end time, with fields linking back to it.
+fn error_log_for_span(span: &serde_json::Value, end_ms: f64) -> serde_json::Value {
+    let name = span.get("name").and_then(|v| v.as_str()).unwrap_or("");
+    json!({
+        "kind": "log",
+        "timestamp": end_ms,
+        "level": "ERROR",
+        "target": span.get("target"),
+        "message": format!("Span '{}' failed", name),
+        "fields": {
+            "derivedFrom": "span",
+            "spanRecordId": span.get("id"),
+            "spanName": name,
+            "traceId": span.get("traceId"),
+            "spanId": span.get("spanId"),
+            "durationMs": span.get("durationMs"),
+        },
+    })
+}
+
+/// Identifies one rollup: a metric series and the index of its window
+/// (`timestamp / windowMs`, aligned to the epoch).
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct RollupKey {
+    name: String,
+    /// The series' attributes as stored (a JSON string).
+    attributes: String,
+    window: i64,
+}
+
+/// Running summary of one window's points.
+struct MetricWindow {
+    min: f64,
+    max: f64,
+    sum: f64,
+    count: u64,
+}
+
+/// Custom metric points accumulated per series and window until the window
+/// closes. Windows follow the points' own timestamps; a point arriving for
+/// a window that was already written opens a new rollup for it, written
+/// separately.
+#[derive(Default)]
+struct MetricRollup {
+    windows: HashMap<RollupKey, MetricWindow>,
+    /// Lowest open window index, so the next close is found without a scan.
+    earliest: Option<i64>,
+}
+
+impl MetricRollup {
+    fn add(&mut self, name: &str, attributes: String, value: f64, timestamp_ms: f64, window_ms: f64) {
+        let window = (timestamp_ms / window_ms).floor() as i64;
+        let key = RollupKey {
+            name: name.to_string(),
+            attributes,
+            window,
+        };
+        let w = self.windows.entry(key).or_insert(MetricWindow {
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            sum: 0.0,
+            count: 0,
+        });
+        w.min = w.min.min(value);
+        w.max = w.max.max(value);
+        w.sum += value;
+        w.count += 1;
+        self.earliest = Some(self.earliest.map_or(window, |e| e.min(window)));
+    }
+
+    /// When the earliest open window ends (epoch ms).
+    fn next_close_ms(&self, window_ms: f64) -> Option<f64> {
+        self.earliest.map(|w| (w + 1) as f64 * window_ms)
+    }
+
+    /// Remove and return the windows that end at or before `now` (epoch ms),
+    /// oldest first.
+    fn take_closed(&mut self, now: f64, window_ms: f64) -> Vec<(RollupKey, MetricWindow)> {
+        if self.next_close_ms(window_ms).is_none_or(|end| end > now) {
+            return Vec::new();
+        }
+        let closed_keys: Vec<RollupKey> = self
+            .windows
+            .keys()
+            .filter(|k| (k.window + 1) as f64 * window_ms <= now)
+            .cloned()
+            .collect();
+        let mut closed: Vec<(RollupKey, MetricWindow)> = closed_keys
+            .into_iter()
+            .filter_map(|k| self.windows.remove_entry(&k))
+            .collect();
+        closed.sort_by(|a, b| {
+            (a.0.window, &a.0.name, &a.0.attributes).cmp(&(b.0.window, &b.0.name, &b.0.attributes))
+        });
+        self.earliest = self.windows.keys().map(|k| k.window).min();
+        closed
+    }
+}
+
+/// `metricRetention`: raw metric points are kept `raw_days`, rolled up per
+/// `interval_ms` window into rollups kept `rollup_days`.
+#[derive(Clone, Copy, Debug)]
+struct MetricRetention {
+    raw_days: f64,
+    rollup_days: f64,
+    interval_ms: f64,
+}
+
+impl MetricRetention {
+    fn parse(section: &serde_json::Value) -> Self {
+        let days = |key: &str, default: f64| match section.get(key).map(|v| v.as_f64()) {
+            None => default,
+            Some(Some(days)) if days > 0.0 => days,
+            Some(_) => {
+                eprintln!(
+                    "[yeti-telemetry] Ignoring metricRetention.{} (must be a positive number of days), using {}",
+                    key, default
+                );
+                default
+            }
+        };
+        let raw_days = days("rawDays", DEFAULT_METRIC_RAW_DAYS);
+        let rollup_days = days("rollupDays", DEFAULT_METRIC_ROLLUP_DAYS);
+        let interval_secs = match section.get("rollupInterval").map(parse_duration_secs) {
+            None => DEFAULT_METRIC_ROLLUP_INTERVAL_SECS,
+            Some(Some(secs)) if secs >= 60.0 && secs <= raw_days * 86400.0 => secs.floor(),
+            Some(_) => {
+                eprintln!(
+                    "[yeti-telemetry] Ignoring metricRetention.rollupInterval (must be between 1m and rawDays), using {}s",
+                    DEFAULT_METRIC_ROLLUP_INTERVAL_SECS
+                );
+                DEFAULT_METRIC_ROLLUP_INTERVAL_SECS
+            }
+        };
+        Self {
+            raw_days,
+            rollup_days,
+            interval_ms: interval_secs * 1000.0,
+        }
+    }
+}
+
+/// MetricRollup record for one series and window. The id is the window's
+/// start key followed by a hash of the series, so it sorts by time and is
+/// the same every time the window is rolled up.
+fn metric_rollup_record(key: &RollupKey, window: &MetricWindow, interval_ms: f64) -> serde_json::Value {
+    let start_ms = key.window as f64 * interval_ms;
+    let series = sample_hash(&format!("{}\0{}", key.name, key.attributes));
+    json!({
+        "id": format!("{}-{:016x}", record_key_at(start_ms), series),
+        "name": key.name,
+        "attributes": key.attributes,
+        "timestamp": format_epoch_ms(start_ms),
+        "windowStart": format_epoch_ms(start_ms),
+        "windowEnd": format_epoch_ms(start_ms + interval_ms),
+        "value": window.sum / window.count as f64,
+        "count": window.count,
+        "sum": window.sum,
+        "min": window.min,
+        "max": window.max,
+    })
+}
+
+/// Progress of a walk through one table in record write time, used by the
+/// expiry sweep and by purges.
+///
+/// Pages start one `EXPIRY_SWEEP_PAGE_MS` wide. An empty page doubles the
+/// next one, up to `EXPIRY_SWEEP_MAX_PAGE_MS`, so idle stretches cost one
+/// cheap empty scan per day while no page can load more than a day of
+/// records; a page holding more than `EXPIRY_SWEEP_MAX_RECORDS` records
+/// halves the next one.
+pub(crate) struct WriteTimeCursor {
+    /// Write time (epoch ms) up to which the table has been swept.
+    swept_ms: f64,
+    page_ms: f64,
+}
+
+impl Default for WriteTimeCursor {
+    fn default() -> Self {
+        Self {
+            swept_ms: 0.0,
+            page_ms: EXPIRY_SWEEP_PAGE_MS,
+        }
+    }
+}
+
+impl WriteTimeCursor {
+    /// The next page's write-time bounds, or `None` once the cursor has
+    /// reached `horizon`.
+    fn page(&self, horizon: f64) -> Option<(f64, f64)> {
+        (self.swept_ms < horizon).then(|| (self.swept_ms, (self.swept_ms + self.page_ms).min(horizon)))
+    }
+
+    /// Mark everything before `end` swept after a page of `records` records.
+    fn advance(&mut self, end: f64, records: usize) {
+        self.swept_ms = end;
+        if records == 0 {
+            self.page_ms = (self.page_ms * 2.0).min(EXPIRY_SWEEP_MAX_PAGE_MS);
+        } else if records > EXPIRY_SWEEP_MAX_RECORDS {
+            self.page_ms = (self.page_ms / 2.0).max(1.0);
+        }
+    }
+}
+
+/// Table writes of one kind waiting to be flushed together.
+///
+/// KvBackend has no multi-key put, so a flush issues the buffered puts
+/// concurrently rather than one round-trip at a time.
+#[derive(Default)]
+struct TableBatch {
+    entries: Vec<(Vec<u8>, Vec<u8>)>,
+    /// When the first buffered write was queued (epoch ms); `None` when empty.
+    opened_ms: Option<f64>,
+    /// Rate-limits warnings about failed puts.
+    failure_warning: FailureWarning,
+}
+
+impl TableBatch {
+    /// Buffer a write, returning the number of buffered writes.
+    fn push(&mut self, key: &[u8], value: &[u8]) -> usize {
+        self.opened_ms.get_or_insert_with(now_epoch_ms);
+        self.entries.push((key.to_vec(), value.to_vec()));
+        self.entries.len()
+    }
+
+    async fn flush(&mut self, kind: RecordKind, storage: &Arc<dyn KvBackend>, state: &SharedState) {
+        self.opened_ms = None;
+        let entries = std::mem::take(&mut self.entries);
+        if entries.is_empty() {
+            return;
+        }
+        let puts = entries.iter().map(|(key, value)| storage.put(key, value));
+        let errors: Vec<_> = futures::future::join_all(puts)
+            .await
+            .into_iter()
+            .filter_map(|result| result.err())
+            .collect();
+        if let Some(latest) = errors.last() {
+            let failed = errors.len() as u64;
+            let pipeline = &state.pipeline;
+            pipeline.storage_failures.fetch_add(failed, Ordering::Relaxed);
+            pipeline.by_kind.write_errors[kind.index()].fetch_add(failed, Ordering::Relaxed);
+            if let Some(count) = self.failure_warning.record(failed) {
+                eprintln!(
+                    "[telemetry-writer] WARNING: {} {} table write(s) failed; latest error: {}",
+                    count,
+                    kind.as_str(),
+                    latest
+                );
+            }
+        }
+    }
+}
+
+/// Minimum gap between warnings about a recurring write or encode failure.
+const FAILURE_WARN_INTERVAL_MS: f64 = 60_000.0;
+
+/// Rate limit for warnings about a recurring failure: the first is logged
+/// at once, later ones at most once per `FAILURE_WARN_INTERVAL_MS`, each
+/// with the number of failures since the last warning.
+#[derive(Default)]
+struct FailureWarning {
+    last_warned_ms: Option<f64>,
+    /// Failures not yet included in a warning.
+    pending: u64,
+}
+
+impl FailureWarning {
+    /// Count `failures`; returns the count to report when a warning is due.
+    fn record(&mut self, failures: u64) -> Option<u64> {
+        self.pending += failures;
+        let now = now_epoch_ms();
+        if self.last_warned_ms.is_some_and(|at| now - at < FAILURE_WARN_INTERVAL_MS) {
+            return None;
+        }
+        self.last_warned_ms = Some(now);
+        Some(std::mem::take(&mut self.pending))
+    }
+}
+
+/// Replacement for redacted field values and message fragments.
+const REDACTED: &str = "[REDACTED]";
+
+/// Replace the value of every key in `keys` (lowercase; matched ignoring
+/// case) with [`REDACTED`], in nested objects and arrays too.
+fn redact_keys(value: &mut serde_json::Value, keys: &[String]) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, v) in map.iter_mut() {
+                if keys.iter().any(|k| k.eq_ignore_ascii_case(key)) {
+                    *v = json!(REDACTED);
+                } else {
+                    redact_keys(v, keys);
+                }
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for item in items {
+                redact_keys(item, keys);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Canonical level (TRACE, DEBUG, INFO, WARN, ERROR) for a producer's level
+/// name, accepting common variants in any case. Unrecognized names map to
+/// INFO and are reported with `false`.
+fn normalize_level(level: &str) -> (&'static str, bool) {
+    match level.trim().to_ascii_lowercase().as_str() {
+        "trace" | "trc" | "verbose" => ("TRACE", true),
+        "debug" | "dbg" => ("DEBUG", true),
+        "info" | "inf" | "information" | "informational" | "notice" => ("INFO", true),
+        "warn" | "wrn" | "warning" => ("WARN", true),
+        "error" | "err" | "fatal" | "critical" | "crit" | "alert" | "emerg" | "emergency"
+        | "panic" | "severe" => ("ERROR", true),
+        _ => ("INFO", false),
+    }
+}
+
+/// Whether a span event failed: `fields.status` is `ERROR` or its level
+/// normalizes to ERROR.
+fn is_error_span(event: &serde_json::Value) -> bool {
+    let status = event_field(event, "fields").and_then(|f| f.get("status")).and_then(|v| v.as_str());
+    let level = event_field(event, "level").and_then(|v| v.as_str()).map(|l| normalize_level(l).0);
+    status == Some("ERROR") || level == Some("ERROR")
+}
+
+/// Normalized level of a log or span event, and whether it was recognized;
+/// INFO when it has none.
+fn level_of(event: &serde_json::Value) -> (&'static str, bool) {
+    match event_field(event, "level").and_then(|v| v.as_str()) {
+        Some(raw) if !raw.trim().is_empty() => normalize_level(raw),
+        _ => ("INFO", true),
+    }
+}
+
+/// [`level_of`] for a record being stored. An unrecognized level is
+/// counted, so this is called once per record, and the first one is logged
+/// so the misconfigured emitter can be found.
+fn event_level(state: &SharedState, event: &serde_json::Value) -> &'static str {
+    let (level, known) = level_of(event);
+    if !known && state.pipeline.unknown_levels.fetch_add(1, Ordering::Relaxed) == 0 {
+        eprintln!(
+            "[telemetry-writer] WARNING: unknown level '{}' from target '{}', stored as INFO",
+            event_field(event, "level").and_then(|v| v.as_str()).unwrap_or(""),
+            event.get("target").and_then(|v| v.as_str()).unwrap_or("")
+        );
+    }
+    level
+}
+
+/// Trace linkage id of a span event, read from the event itself or its
+/// fields (camelCase or snake_case). Empty when the producer sent none.
+fn span_link<'a>(event: &'a serde_json::Value, key: &str, snake: &str) -> &'a str {
+    let fields = event_field(event, "fields");
+    event
+        .get(key)
+        .or_else(|| fields.and_then(|f| f.get(key)))
+        .or_else(|| fields.and_then(|f| f.get(snake)))
+        .and_then(|v| v.as_str())
+        .unwrap_or("")
+}
+
+/// Record how far behind the event's own timestamp the writer is running.
+fn note_lag(state: &SharedState, event_ms: f64) {
+    if event_ms > 0.0 {
+        let lag = (now_epoch_ms() - event_ms).max(0.0) as u64;
+        state.pipeline.lag_ms.store(lag, Ordering::Relaxed);
+    }
+}
+
+/// Receiver of the writer's PubSub notifications: the host's
+/// `PubSubManager`, or a recorder in tests.
+trait RecordNotifier: Send + Sync {
+    fn notify_update<'a>(
+        &'a self,
+        table: &'a str,
+        id: &'a str,
+        record: &'a serde_json::Value,
+    ) -> Pin<Box<dyn std::future::Future<Output = ()> + Send + 'a>>;
+}
+
+impl RecordNotifier for PubSubManager {
+    fn notify_update<'a>(
+        &'a self,
+        table: &'a str,
+        id: &'a str,
+        record: &'a serde_json::Value,
+    ) -> Pin<Box<dyn std::future::Future<Output = ()> + Send + 'a>> {
+        Box::pin(PubSubManager::notify_update(self, table, id, record))
+    }
+}
+
+impl EventSubscriber for TelemetryWriter {
+    fn run(
+        self: Box<Self>,
+        rx: tokio::sync::mpsc::Receiver<serde_json::Value>,
+    ) -> Pin<Box<dyn std::future::Future<Output = ()> + Send>> {
+        let input = match self.config.file_input.clone() {
+            Some(input) => input,
+            None => return Box::pin(self.run_loop(rx)),
+        };
+
+        // Merge the file input with live dispatch; the loop ends once both the
+        // host channel has closed and the file is exhausted.
+        Box::pin(async move {
+            let (tx, merged) = tokio::sync::mpsc::channel(FILE_INPUT_CHANNEL_CAPACITY);
+            let live_tx = tx.clone();
+            tokio::spawn(async move {
+                let mut rx = rx;
+                while let Some(event) = rx.recv().await {
+                    if live_tx.send(event).await.is_err() {
+                        break;
+                    }
+                }
+            });
+            tokio::task::spawn_blocking(move || input.feed(tx));
+            self.run_loop(merged).await
+        })
+    }
+}
+
+/// Mirror writes waiting per mirror before further writes are dropped.
+const MIRROR_QUEUE_CAPACITY: usize = 10_000;
+
+/// Secondary storage backends, per kind, that receive a copy of each write.
+///
+/// Each mirror has one task applying its writes in order from a bounded
+/// queue, so mirror writes never hold up the writer loop and a put is never
+/// overtaken by an earlier delete. When the queue is full the write is
+/// dropped and counted. Failures are counted and logged but otherwise
+/// ignored; a mirror may lag or miss records the primary has.
+#[derive(Default)]
+pub(crate) struct Mirrors {
+    log: Option<MirrorQueue>,
+    span: Option<MirrorQueue>,
+    metric: Option<MirrorQueue>,
+}
+
+impl Mirrors {
+    fn queue(&self, kind: RecordKind) -> Option<&MirrorQueue> {
+        match kind {
+            RecordKind::Log => self.log.as_ref(),
+            RecordKind::Span => self.span.as_ref(),
+            RecordKind::Metric => self.metric.as_ref(),
+        }
+    }
+
+    fn put(&self, kind: RecordKind, key: &[u8], value: &[u8]) {
+        if let Some(queue) = self.queue(kind) {
+            queue.send(MirrorOp::Put(key.to_vec(), value.to_vec()));
+        }
+    }
+
+    pub(crate) fn delete(&self, kind: RecordKind, key: &[u8]) {
+        if let Some(queue) = self.queue(kind) {
+            queue.send(MirrorOp::Delete(key.to_vec()));
+        }
+    }
+}
+
+enum MirrorOp {
+    Put(Vec<u8>, Vec<u8>),
+    Delete(Vec<u8>),
+}
+
+/// One mirror backend and the queue its task drains. The task is started on
+/// the first write, from within the writer's runtime.
+struct MirrorQueue {
+    backend: Arc<dyn KvBackend>,
+    tx: OnceLock<tokio::sync::mpsc::Sender<MirrorOp>>,
+    /// Counts dropped and failed mirror writes.
+    state: &'static SharedState,
+}
+
+impl MirrorQueue {
+    fn new(backend: Arc<dyn KvBackend>, state: &'static SharedState) -> Self {
+        Self {
+            backend,
+            tx: OnceLock::new(),
+            state,
+        }
+    }
+
+    fn send(&self, op: MirrorOp) {
+        let tx = self.tx.get_or_init(|| {
+            let (tx, mut rx) = tokio::sync::mpsc::channel(MIRROR_QUEUE_CAPACITY);
+            let backend = Arc::clone(&self.backend);
+            let state = self.state;
+            tokio::spawn(async move {
+                while let Some(op) = rx.recv().await {
+                    let result = match &op {
+                        MirrorOp::Put(key, value) => backend.put(key, value).await.map_err(|e| ("put", e)),
+                        MirrorOp::Delete(key) => backend.delete(key).await.map_err(|e| ("delete", e)),
+                    };
+                    if let Err((op, e)) = result {
+                        mirror_failed(state, op, e);
+                    }
+                }
+            });
+            tx
+        });
+        if tx.try_send(op).is_err() {
+            self.state.pipeline.mirror_dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn mirror_failed(state: &SharedState, op: &str, err: impl std::fmt::Display) {
+    let failures = state.pipeline.mirror_failures.fetch_add(1, Ordering::Relaxed) + 1;
+    if failures.is_power_of_two() {
+        eprintln!(
+            "[telemetry-writer] Mirror {} failed ({} failures so far): {}",
+            op, failures, err
+        );
+    }
+}
+
+/// SpanIndex keys for a span: `<field>:<value>:<span_id>`, one per
+/// configured field holding a scalar value. The trailing span id keeps keys
+/// unique, so all spans with a value are found by scanning the
+/// [`span_index_prefix`] range.
+fn span_index_keys(indexed: &[String], fields: &serde_json::Value, span_id: &str) -> Vec<String> {
+    indexed
+        .iter()
+        .filter_map(|field| {
+            let value = match fields.get(field)? {
+                serde_json::Value::String(s) => s.clone(),
+                serde_json::Value::Number(n) => n.to_string(),
+                serde_json::Value::Bool(b) => b.to_string(),
+                _ => return None,
+            };
+            if value.len() > MAX_INDEXED_VALUE_LEN {
+                return None;
+            }
+            Some(format!("{}{}", span_index_prefix(field, &value), span_id))
+        })
+        .collect()
+}
+
+/// SpanIndex keys of a stored span record, whose `fields` are a JSON string.
+fn stored_span_index_keys(indexed: &[String], bytes: &[u8], key: &[u8]) -> Vec<String> {
+    let fields = from_storage_bytes(bytes)
+        .ok()
+        .and_then(|record| serde_json::from_str::<serde_json::Value>(record.get("fields")?.as_str()?).ok());
+    match fields {
+        Some(fields) => span_index_keys(indexed, &fields, &String::from_utf8_lossy(key)),
+        None => Vec::new(),
+    }
+}
+
+/// Key prefix of the SpanIndex entries for `field` = `value`. `%` and `:` in
+/// either are percent-encoded so a value containing `:` can't collide with
+/// another field or value; the range ends at the same prefix with its
+/// trailing `:` replaced by `;`.
+pub(crate) fn span_index_prefix(field: &str, value: &str) -> String {
+    let escape = |s: &str| s.replace('%', "%25").replace(':', "%3A");
+    format!("{}:{}:", escape(field), escape(value))
+}
+
+/// A SpanIndex record: its key and the span it points at.
+fn span_index_entry(key: &str, span_id: &str) -> serde_json::Value {
+    json!({ "id": key, "spanId": span_id })
+}
+
+// ============================================================================
+// Record IDs — UUIDv7 with an injectable clock
+// ============================================================================
+
+/// Millisecond clock used for record ids.
+pub trait Clock: Send + Sync {
+    fn now_ms(&self) -> u64;
+}
+
+/// Wall clock used in production.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now_ms(&self) -> u64 {
+        now_epoch_ms() as u64
+    }
+}
+
+/// Manually advanced clock, so tests can step time deterministically and
+/// assert id ordering across millisecond boundaries.
+#[derive(Default)]
+pub struct ManualClock {
+    ms: AtomicU64,
+}
+
+impl ManualClock {
+    pub fn new(start_ms: u64) -> Self {
+        Self {
+            ms: AtomicU64::new(start_ms),
+        }
+    }
+
+    pub fn advance(&self, ms: u64) {
+        self.ms.fetch_add(ms, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_ms(&self) -> u64 {
+        self.ms.load(Ordering::SeqCst)
+    }
+}
+
+/// UUIDv7 generator (RFC 9562) with strictly increasing output.
+///
+/// The 12-bit `rand_a` field holds a counter that restarts each millisecond;
+/// if it overflows, or the clock steps backwards, the timestamp is carried
+/// forward from the last id, so ids always sort in generation order.
+pub struct IdGenerator {
+    clock: Arc<dyn Clock>,
+    last_ms: u64,
+    counter: u16,
+    rng: u64,
+}
+
+impl IdGenerator {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0)
+            ^ (u64::from(std::process::id()) << 32);
+        Self {
+            clock,
+            last_ms: 0,
+            counter: 0,
+            // xorshift state must be non-zero
+            rng: seed | 1,
+        }
+    }
+
+    pub fn next_id(&mut self) -> String {
+        let now = self.clock.now_ms();
+        if now > self.last_ms {
+            self.last_ms = now;
+            self.counter = 0;
+        } else if self.counter >= 0x0fff {
+            self.last_ms += 1;
+            self.counter = 0;
+        } else {
+            self.counter += 1;
+        }
+
+        let ms = self.last_ms & 0xffff_ffff_ffff;
+        let rand_b = self.next_random() & 0x3fff_ffff_ffff_ffff;
+        format!(
+            "{:08x}-{:04x}-{:04x}-{:04x}-{:012x}",
+            ms >> 16,
+            ms & 0xffff,
+            0x7000 | self.counter,
+            0x8000 | (rand_b >> 48),
+            rand_b & 0xffff_ffff_ffff
+        )
+    }
+
+    /// xorshift64* — ids need uniqueness, not cryptographic randomness.
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        self.rng.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+// ============================================================================
+// Event Schema — tolerant reads across producer schema versions
+// ============================================================================
+
+/// Newest producer event schema the writer understands. Events without a
+/// `schemaVersion` are version 1.
+const EVENT_SCHEMA_VERSION: u64 = 2;
+
+/// Fields renamed in event schema v2: (v1 name, v2 name). The v1 names are the
+/// canonical names used throughout the writer.
+const SCHEMA_V2_RENAMES: [(&str, &str); 6] = [
+    ("level", "severity"),
+    ("message", "body"),
+    ("fields", "attributes"),
+    ("timestamp", "timeUnixMs"),
+    ("startTime", "startTimeUnixMs"),
+    ("endTime", "endTimeUnixMs"),
+];
+
+/// Read a field by its canonical (v1) name from an event of any supported
+/// schema version.
+///
+/// The location matching the event's `schemaVersion` is tried first, then
+/// the other one, so producers mid-rollout (or mislabelled) still land their
+/// data. Compatibility window: the writer accepts the current and previous
+/// schema versions; the older one is retired only after every producer emits
+/// the newer.
+fn event_field<'a>(event: &'a serde_json::Value, canonical: &str) -> Option<&'a serde_json::Value> {
+    let renamed = SCHEMA_V2_RENAMES
+        .iter()
+        .find(|(v1, _)| *v1 == canonical)
+        .map(|(_, v2)| *v2);
+    let renamed = match renamed {
+        Some(r) => r,
+        None => return event.get(canonical),
+    };
+    let version = event
+        .get("schemaVersion")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(1)
+        .min(EVENT_SCHEMA_VERSION);
+    let (preferred, fallback) = if version >= 2 {
+        (renamed, canonical)
+    } else {
+        (canonical, renamed)
+    };
+    event.get(preferred).or_else(|| event.get(fallback))
+}
+
+/// Epoch ms from an event time field: a number or numeric string of epoch
+/// ms, or an RFC 3339 string (`2024-06-01T12:00:00.250Z`, any offset).
+fn parse_event_time(value: &serde_json::Value) -> Option<f64> {
+    match value {
+        serde_json::Value::Number(n) => n.as_f64(),
+        serde_json::Value::String(s) => {
+            let s = s.trim();
+            match s.parse::<f64>() {
+                Ok(ms) => ms.is_finite().then_some(ms),
+                Err(_) => parse_rfc3339_ms(s),
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Epoch ms of an RFC 3339 date-time. A space or lowercase `t` may separate
+/// date and time, and the fraction may have any number of digits.
+fn parse_rfc3339_ms(s: &str) -> Option<f64> {
+    let bytes = s.as_bytes();
+    let num = |range: std::ops::Range<usize>| {
+        s.get(range)
+            .filter(|p| p.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|p| p.parse::<u64>().ok())
+    };
+    let (year, month, day) = (num(0..4)?, num(5..7)?, num(8..10)?);
+    let (hour, minute, second) = (num(11..13)?, num(14..16)?, num(17..19)?);
+    let separators = bytes[4] == b'-'
+        && bytes[7] == b'-'
+        && matches!(bytes[10], b'T' | b't' | b' ')
+        && bytes[13] == b':'
+        && bytes[16] == b':';
+    let valid = year >= 1970
+        && (1..=12).contains(&month)
+        && (1..=31).contains(&day)
+        && hour < 24
+        && minute < 60
+        && second <= 60;
+    if !separators || !valid {
+        return None;
+    }
+
+    let mut rest = &s[19..];
+    let mut fraction = 0.0;
+    if let Some(digits) = rest.strip_prefix('.') {
+        let len = digits.bytes().take_while(u8::is_ascii_digit).count();
+        if len == 0 {
+            return None;
+        }
+        fraction = format!("0.{}", &digits[..len]).parse::<f64>().ok()?;
+        rest = &digits[len..];
+    }
+    let offset_secs = match rest {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = match rest.as_bytes().first()? {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            if rest.len() != 6 || rest.as_bytes()[3] != b':' {
+                return None;
+            }
+            let hours = rest.get(1..3)?.parse::<i64>().ok().filter(|h| *h < 24)?;
+            let minutes = rest.get(4..6)?.parse::<i64>().ok().filter(|m| *m < 60)?;
+            sign * (hours * 3600 + minutes * 60)
+        }
+    };
+    let secs = date_to_days(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    Some(((secs as i64 - offset_secs) as f64 + fraction) * 1000.0)
+}
+
+/// An event's time field (`timestamp`) in epoch ms, or the current time
+/// when it is missing or unparseable.
+fn event_time(state: &SharedState, event: &serde_json::Value, canonical: &str) -> f64 {
+    event_field(event, canonical)
+        .and_then(parse_event_time)
+        .unwrap_or_else(|| {
+            note_invalid_time(state, event, canonical);
+            now_epoch_ms()
+        })
+}
+
+/// Count an event stored with the current time in place of its own. The
+/// first one is logged so the emitter can be found.
+fn note_invalid_time(state: &SharedState, event: &serde_json::Value, canonical: &str) {
+    if state.pipeline.invalid_timestamps.fetch_add(1, Ordering::Relaxed) == 0 {
+        eprintln!(
+            "[telemetry-writer] WARNING: invalid {} {} from target '{}', stored with the current time",
+            canonical,
+            event_field(event, canonical).map_or("(missing)".to_string(), |v| v.to_string()),
+            event.get("target").and_then(|v| v.as_str()).unwrap_or("")
+        );
+    }
+}
+
+/// Deterministic sampling key: the trace id when the event carries one, so
+/// every span and log of a trace gets the same decision; otherwise the
+/// event's own identity.
+fn sampling_key(event: &serde_json::Value) -> String {
+    let trace_id = event
+        .get("traceId")
+        .or_else(|| event_field(event, "fields").and_then(|f| f.get("traceId")))
+        .or_else(|| event_field(event, "fields").and_then(|f| f.get("trace_id")))
+        .and_then(|v| v.as_str());
+    if let Some(trace_id) = trace_id {
+        return trace_id.to_string();
+    }
+    let str_field = |key: &str| event_field(event, key).and_then(|v| v.as_str()).unwrap_or("");
+    format!(
+        "{}|{}|{}|{}",
+        str_field("target"),
+        str_field("name"),
+        str_field("message"),
+        event_field(event, "timestamp")
+            .or_else(|| event_field(event, "startTime"))
+            .and_then(parse_event_time)
+            .unwrap_or(0.0)
+    )
+}
+
+/// Stable, well-distributed 64-bit hash of a sampling key: FNV-1a followed by
+/// the splitmix64 finalizer. FNV alone leaves structured ids (sequential or
+/// time-prefixed) clustered; the finalizer spreads them over the full range,
+/// so comparing the top bits against a threshold yields the configured rate
+/// regardless of id structure. Stable across builds, so producers can
+/// reproduce the writer's decisions.
+fn sample_hash(key: &str) -> u64 {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in key.as_bytes() {
+        h ^= u64::from(*b);
+        h = h.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    h ^= h >> 30;
+    h = h.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h ^= h >> 27;
+    h = h.wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^ (h >> 31)
+}
+
+/// Keep decision for a sampling key: the hash's upper 56 bits compared
+/// against `rate * 2^56`.
+fn sample_keep(key: &str, rate: f64) -> bool {
+    const SCALE: f64 = (1u64 << 56) as f64;
+    (sample_hash(key) >> 8) < (rate * SCALE) as u64
+}
+
+/// Current wall-clock time in epoch milliseconds.
+pub(crate) fn now_epoch_ms() -> f64 {
+    SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs_f64() * 1000.0)
+        .unwrap_or(0.0)
+}
+
+/// Format epoch milliseconds as a "seconds.micros" string for table storage.
+///
+/// Rounds to the nearest microsecond in integer space, so a value just below
+/// a whole second carries into the seconds instead of being truncated.
+/// Negative, NaN, and infinite inputs format as "0.000000".
+fn format_epoch_ms(ms: f64) -> String {
+    let micros = (ms * 1000.0).round();
+    let micros = if micros.is_finite() && micros > 0.0 { micros as u64 } else { 0 };
+    format!("{}.{:06}", micros / 1_000_000, micros % 1_000_000)
+}
+
+// ============================================================================
+// Transform Script — user Rhai code run on every event
+// ============================================================================
+
+/// Operations a transform may run per event when `transformMaxOperations`
+/// is not set.
+const DEFAULT_TRANSFORM_MAX_OPERATIONS: u64 = 100_000;
+
+/// `transformScript`: a Rhai script whose `transform(event)` function gets
+/// each event as a map and returns the event to keep, possibly modified, or
+/// `()` to drop it.
+///
+/// Scripts are sandboxed: Rhai has no file, network or process access, and
+/// `eval` is disabled. Each call is bounded by `max_operations` and by limits
+/// on call depth and on string, array and map sizes, so a runaway script
+/// fails the event instead of stalling the writer.
+struct TransformScript {
+    path: PathBuf,
+    engine: rhai::Engine,
+    ast: rhai::AST,
+    /// Variables and constants left by the script's top-level statements,
+    /// which run once at load rather than before every call.
+    scope: rhai::Scope<'static>,
+    max_operations: u64,
+    /// Drop events the script fails on instead of keeping them unchanged.
+    drop_on_error: bool,
+}
+
+impl TransformScript {
+    /// Compile the script once at startup. Without a compiled script events
+    /// pass through untransformed, so failures are logged as warnings.
+    fn from_config(telemetry: &serde_json::Value, path: PathBuf) -> Option<Self> {
+        let max_operations = telemetry
+            .get("transformMaxOperations")
+            .and_then(|v| v.as_u64())
+            .filter(|n| *n > 0)
+            .unwrap_or(DEFAULT_TRANSFORM_MAX_OPERATIONS);
+        let drop_on_error = match telemetry.get("transformOnError").and_then(|v| v.as_str()) {
+            None | Some("keep") => false,
+            Some("drop") => true,
+            Some(other) => {
+                eprintln!("[yeti-telemetry] Unknown transformOnError '{}', using 'keep'", other);
+                false
+            }
+        };
+
+        let mut engine = rhai::Engine::new();
+        engine.set_max_operations(max_operations);
+        engine.set_max_call_levels(32);
+        engine.set_max_expr_depths(64, 32);
+        engine.set_max_string_size(1024 * 1024);
+        engine.set_max_array_size(10_000);
+        engine.set_max_map_size(10_000);
+        engine.disable_symbol("eval");
+        // Rhai prints to stdout by default; keep script output with ours.
+        engine.on_print(|text| eprintln!("[yeti-telemetry] transformScript: {}", text));
+        engine.on_debug(|text, _, pos| eprintln!("[yeti-telemetry] transformScript debug at {}: {}", pos, text));
+
+        let ast = match engine.compile_file(path.clone()) {
+            Ok(ast) => ast,
+            Err(e) => {
+                eprintln!(
+                    "[yeti-telemetry] WARNING: transformScript {} not loaded, events are not transformed: {}",
+                    path.display(),
+                    e
+                );
+                return None;
+            }
+        };
+        if !ast.iter_functions().any(|f| f.name == "transform" && f.params.len() == 1) {
+            eprintln!(
+                "[yeti-telemetry] WARNING: transformScript {} defines no transform(event) function, events are not transformed",
+                path.display()
+            );
+            return None;
+        }
+        let mut scope = rhai::Scope::new();
+        if let Err(e) = engine.run_ast_with_scope(&mut scope, &ast) {
+            eprintln!(
+                "[yeti-telemetry] WARNING: transformScript {} failed to initialize, events are not transformed: {}",
+                path.display(),
+                e
+            );
+            return None;
+        }
+        eprintln!("[yeti-telemetry] Transform script loaded from {}", path.display());
+        Some(Self {
+            path,
+            engine,
+            ast,
+            scope,
+            max_operations,
+            drop_on_error,
+        })
+    }
+
+    /// The transformed event, or `None` when the script dropped it.
+    fn apply(&self, event: &serde_json::Value) -> std::result::Result<Option<serde_json::Value>, String> {
+        let input = rhai::serde::to_dynamic(event).map_err(|e| e.to_string())?;
+        // Each call starts from the scope the top-level statements left, so
+        // one event can't leak state into the next.
+        let options = rhai::CallFnOptions::new().eval_ast(false);
+        let output: rhai::Dynamic = self
+            .engine
+            .call_fn_with_options(options, &mut self.scope.clone(), &self.ast, "transform", (input,))
+            .map_err(|e| e.to_string())?;
+        if output.is_unit() {
+            return Ok(None);
+        }
+        if !output.is_map() {
+            return Err(format!("transform returned {}, expected a map or ()", output.type_name()));
+        }
+        rhai::serde::from_dynamic(&output).map(Some).map_err(|e| e.to_string())
+    }
+}
+
+// ============================================================================
+// Enrichment — fields added from static lookup tables
+// ============================================================================
+
+/// Adds fields to events from a lookup keyed by one of their field values,
+/// e.g. `service_id` → service name and owning team, so producers don't all
+/// have to carry that metadata.
+#[derive(Clone, Debug)]
+struct Enrichment {
+    /// Field whose value is looked up.
+    source: String,
+    /// Fields copied from the matched entry; empty copies every entry key.
+    targets: Vec<String>,
+    /// `lookupFile` as configured, when the lookup came from a file.
+    lookup_file: Option<String>,
+    lookup: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Enrichment {
+    /// Parse an `enrichment` entry. The lookup is inline (`lookup`) or a JSON
+    /// or YAML file (`lookupFile`, relative to the app root) loaded here, at
+    /// startup and again on each config reload.
+    fn parse(rule: &serde_json::Value, root_dir: &str) -> Option<Self> {
+        let source = rule.get("source").and_then(|v| v.as_str())?.to_string();
+        let lookup_file = rule.get("lookupFile").and_then(|v| v.as_str());
+        let lookup = match (rule.get("lookup"), lookup_file) {
+            (Some(inline), _) => inline.as_object().cloned(),
+            (None, Some(file)) => {
+                let path = PathBuf::from(root_dir).join(file);
+                let parsed = fs::read_to_string(&path)
+                    .ok()
+                    .and_then(|contents| serde_yaml::from_str::<serde_json::Value>(&contents).ok());
+                if parsed.is_none() {
+                    eprintln!("[yeti-telemetry] Failed to load enrichment lookup {}", path.display());
+                }
+                parsed.and_then(|v| v.as_object().cloned())
+            }
+            (None, None) => None,
+        };
+        let Some(lookup) = lookup else {
+            eprintln!("[yeti-telemetry] Ignoring enrichment for '{}': no usable lookup", source);
+            return None;
+        };
+        let targets = rule
+            .get("targets")
+            .and_then(|v| v.as_array())
+            .map(|t| t.iter().filter_map(|f| f.as_str()).map(|f| f.to_string()).collect())
+            .unwrap_or_default();
+        Some(Self {
+            source,
+            targets,
+            lookup_file: lookup_file.filter(|_| rule.get("lookup").is_none()).map(str::to_string),
+            lookup,
+        })
+    }
+
+    /// Add the matched entry's fields to the event's `fields` (or metric
+    /// `attributes`). Fields the event already carries are left alone.
+    fn apply(&self, event: &mut serde_json::Value) {
+        let key = if event.get("fields").is_some() { "fields" } else { "attributes" };
+        let Some(fields) = event.get_mut(key).and_then(|v| v.as_object_mut()) else {
+            return;
+        };
+        let source = match fields.get(&self.source) {
+            Some(serde_json::Value::String(s)) => s.clone(),
+            Some(other @ (serde_json::Value::Number(_) | serde_json::Value::Bool(_))) => other.to_string(),
+            _ => return,
+        };
+        let Some(entry) = self.lookup.get(&source) else { return };
+
+        match entry {
+            serde_json::Value::Object(values) => {
+                for (name, value) in values {
+                    if self.targets.is_empty() || self.targets.contains(name) {
+                        fields.entry(name.clone()).or_insert_with(|| value.clone());
+                    }
+                }
+            }
+            scalar => {
+                if let Some(target) = self.targets.first() {
+                    fields.entry(target.clone()).or_insert_with(|| scalar.clone());
+                }
+            }
+        }
+    }
+}
+
+// ============================================================================
+// File Provider — JSON Lines file rotation
+// ============================================================================
+
+/// How FileProvider frames each record on its JSON line.
+#[derive(Clone, Debug, PartialEq)]
+enum FileEnvelope {
+    /// `{"type": "log", "data": {...}}` (default)
+    Wrapped,
+    /// The record itself with a `_type` field added
+    Flattened,
+    /// The raw record; the kind is inferred from its fields (e.g. `message`,
+    /// `durationMs`, `value`)
+    None,
+    /// `fileSchema: otel`: an OTLP JSON export request per record, under
+    /// this OTLP JSON `resource`
+    Otel(Arc<serde_json::Value>),
+}
+
+impl FileEnvelope {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Wrapped => "wrapped",
+            Self::Flattened => "flattened",
+            Self::None => "none",
+            Self::Otel(_) => "otel",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "wrapped" => Some(Self::Wrapped),
+            "flattened" => Some(Self::Flattened),
+            "none" => Some(Self::None),
+            _ => None,
+        }
+    }
+}
+
+/// Record schema of FileProvider's JSON lines.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FileSchema {
+    /// Stored records, framed by `fileEnvelope` (default)
+    Native,
+    /// OTLP JSON encoding, importable by OpenTelemetry tooling
+    Otel,
+}
+
+impl FileSchema {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Native => "native",
+            Self::Otel => "otel",
+        }
+    }
+}
+
+/// Line format of FileProvider's files.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FileFormat {
+    /// One JSON record per line, all kinds in one file (default)
+    Jsonl,
+    /// RFC 4180 CSV with a header row. Each kind has its own columns, so
+    /// logs, spans and metrics go to `<prefix>-log`, `<prefix>-span` and
+    /// `<prefix>-metric` files.
+    Csv,
+}
+
+impl FileFormat {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Jsonl => "jsonl",
+            Self::Csv => "csv",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "jsonl" => Some(Self::Jsonl),
+            "csv" => Some(Self::Csv),
+            _ => None,
+        }
+    }
+}
+
+/// How a FileProvider encodes records: `fileFormat`, the envelope (from
+/// `fileEnvelope` or `fileSchema`) and `fieldInterning`.
+#[derive(Clone, Debug)]
+struct FileEncoding {
+    format: FileFormat,
+    envelope: FileEnvelope,
+    /// Intern repeated `fields` entries; JSON lines only.
+    intern_fields: bool,
+}
+
+/// What one FileSink writes: JSON lines of every kind, or CSV rows of one.
+#[derive(Clone, Debug)]
+enum FileLayout {
+    /// JSON lines in this envelope, with `fields` interning when `true`.
+    Jsonl(FileEnvelope, bool),
+    Csv(RecordKind),
+}
+
+impl FileLayout {
+    fn extension(&self) -> &'static str {
+        match self {
+            Self::Jsonl(..) => "jsonl",
+            Self::Csv(_) => "csv",
+        }
+    }
+}
+
+/// A record framed by a `Wrapped`, `Flattened` or `None` envelope.
+fn frame_file_line(envelope: &FileEnvelope, event_type: &str, record: &serde_json::Value) -> serde_json::Value {
+    match envelope {
+        FileEnvelope::Wrapped => json!({
+            "type": event_type,
+            "data": record,
+        }),
+        FileEnvelope::Flattened => {
+            let mut flat = record.clone();
+            if let Some(obj) = flat.as_object_mut() {
+                obj.insert("_type".to_string(), json!(event_type));
+            }
+            flat
+        }
+        FileEnvelope::None | FileEnvelope::Otel(_) => record.clone(),
+    }
+}
+
+/// Append `line` and a newline; false if it can't be encoded.
+fn push_json_line(bytes: &mut Vec<u8>, line: &serde_json::Value) -> bool {
+    if serde_json::to_writer(&mut *bytes, line).is_err() {
+        return false;
+    }
+    bytes.push(b'\n');
+    true
+}
+
+/// Line type of a `fieldInterning` dictionary entry.
+const FIELD_DICT_TYPE: &str = "fieldDict";
+
+/// Most dictionary entries per file, and most candidates tracked; past
+/// either, new entries stay inline.
+const FIELD_DICT_MAX_ENTRIES: usize = 10_000;
+
+/// Longest string value that is interned.
+const FIELD_DICT_MAX_VALUE_LEN: usize = 256;
+
+/// `fieldInterning`: the dictionary of one file. A `fields` entry (key and
+/// string or boolean value) gets an id the second time it appears in the
+/// file, so one-off values such as request ids never enter it. From then on
+/// records carry the id in `fieldRefs` instead of the entry. Ids are defined
+/// by `fieldDict` lines, each written just before the first record that
+/// uses it, so the file is readable front to back on its own.
+#[derive(Default)]
+struct FieldInterner {
+    /// Id per (key, JSON-encoded value).
+    ids: HashMap<(String, String), u64>,
+    /// Entries seen once in this file.
+    candidates: std::collections::HashSet<(String, String)>,
+}
+
+impl FieldInterner {
+    /// Start a new dictionary, for a new file.
+    fn reset(&mut self) {
+        self.ids.clear();
+        self.candidates.clear();
+    }
+
+    /// Move the record's interned `fields` entries to `fieldRefs`, and
+    /// return the dictionary entries that must be written before it.
+    fn intern(&mut self, record: &mut serde_json::Value) -> Vec<serde_json::Value> {
+        let mut new_entries = Vec::new();
+        let Some(obj) = record.as_object_mut() else {
+            return new_entries;
+        };
+        let Some(mut fields) = obj
+            .get("fields")
+            .and_then(|v| v.as_str())
+            .and_then(|s| serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(s).ok())
+        else {
+            return new_entries;
+        };
+
+        let mut refs = Vec::new();
+        fields.retain(|key, value| {
+            let internable = match value {
+                serde_json::Value::String(s) => s.len() <= FIELD_DICT_MAX_VALUE_LEN,
+                serde_json::Value::Bool(_) => true,
+                _ => false,
+            };
+            if !internable {
+                return true;
+            }
+            let entry = (key.clone(), value.to_string());
+            if let Some(id) = self.ids.get(&entry) {
+                refs.push(*id);
+                return false;
+            }
+            if self.candidates.remove(&entry) && self.ids.len() < FIELD_DICT_MAX_ENTRIES {
+                let id = self.ids.len() as u64;
+                new_entries.push(json!({ "id": id, "key": key, "value": value }));
+                self.ids.insert(entry, id);
+                refs.push(id);
+                return false;
+            }
+            if self.candidates.len() < FIELD_DICT_MAX_ENTRIES {
+                self.candidates.insert(entry);
+            }
+            true
+        });
+        if !refs.is_empty() {
+            obj.insert("fields".to_string(), json!(serde_json::Value::Object(fields).to_string()));
+            obj.insert("fieldRefs".to_string(), json!(refs));
+        }
+        new_entries
+    }
+}
+
+/// Reader side of `fieldInterning`: collects `fieldDict` lines and puts the
+/// entries a record references back into its `fields`.
+#[derive(Default)]
+struct FieldDictionary {
+    entries: HashMap<u64, (String, serde_json::Value)>,
+}
+
+impl FieldDictionary {
+    /// The line with its record's `fields` restored, or `None` for a
+    /// dictionary line. Ids missing from the dictionary (e.g. a file read
+    /// from the middle) are dropped.
+    fn resolve(&mut self, mut line: serde_json::Value) -> Option<serde_json::Value> {
+        let wrapped = line.get("type").is_some() && line.get("data").is_some();
+        let line_type = if wrapped { line.get("type") } else { line.get("_type") };
+        if line_type.and_then(|v| v.as_str()) == Some(FIELD_DICT_TYPE) {
+            let entry = if wrapped { &line["data"] } else { &line };
+            if let (Some(id), Some(key)) = (entry["id"].as_u64(), entry["key"].as_str()) {
+                self.entries.insert(id, (key.to_string(), entry["value"].clone()));
+            }
+            return None;
+        }
+
+        let record = if wrapped { &mut line["data"] } else { &mut line };
+        if let Some(obj) = record.as_object_mut() {
+            if let Some(refs) = obj.remove("fieldRefs") {
+                let mut fields: serde_json::Map<String, serde_json::Value> = obj
+                    .get("fields")
+                    .and_then(|v| v.as_str())
+                    .and_then(|s| serde_json::from_str(s).ok())
+                    .unwrap_or_default();
+                for id in refs.as_array().into_iter().flatten().filter_map(|v| v.as_u64()) {
+                    if let Some((key, value)) = self.entries.get(&id) {
+                        fields.insert(key.clone(), value.clone());
+                    }
+                }
+                obj.insert("fields".to_string(), json!(serde_json::Value::Object(fields).to_string()));
+            }
+        }
+        Some(line)
+    }
+}
+
+/// CSV columns per record kind, in file order. Values are the record's
+/// top-level fields; missing ones are left empty.
+fn csv_columns(kind: RecordKind) -> &'static [&'static str] {
+    match kind {
+        RecordKind::Log => &[
+            "id", "timestamp", "level", "target", "message", "fields", "seq", "writerId", "repeatCount",
+        ],
+        RecordKind::Span => &[
+            "id", "traceId", "spanId", "parentSpanId", "name", "target", "level", "startTime", "endTime",
+            "durationMs", "fields", "seq", "writerId",
+        ],
+        RecordKind::Metric => &[
+            "id", "name", "value", "attributes", "timestamp", "min", "max", "sum", "count", "windowStart",
+            "windowEnd", "seq", "writerId",
+        ],
+    }
+}
+
+/// One CSV row (CRLF-terminated) for `record`, columns per `csv_columns`.
+/// Strings are written as-is, `null` as an empty field, and anything else as
+/// its JSON text.
+fn csv_row(kind: RecordKind, record: &serde_json::Value) -> String {
+    let mut row = String::new();
+    for (i, column) in csv_columns(kind).iter().enumerate() {
+        if i > 0 {
+            row.push(',');
+        }
+        match record.get(*column) {
+            None | Some(serde_json::Value::Null) => {}
+            Some(serde_json::Value::String(s)) => push_csv_field(&mut row, s),
+            Some(other) => push_csv_field(&mut row, &other.to_string()),
+        }
+    }
+    row.push_str("\r\n");
+    row
+}
+
+/// Append `value`, quoted with doubled inner quotes when it contains a comma,
+/// quote or line break.
+fn push_csv_field(out: &mut String, value: &str) {
+    if value.contains([',', '"', '\r', '\n']) {
+        out.push('"');
+        out.push_str(&value.replace('"', "\"\""));
+        out.push('"');
+    } else {
+        out.push_str(value);
+    }
+}
+
+/// File naming and retention scheme for FileProvider.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RotationStyle {
+    /// `telemetry-YYYY-MM-DD.jsonl`, removed after the retention period (default)
+    Dated,
+    /// Active `telemetry.jsonl` plus `telemetry.1.jsonl` .. `telemetry.<count>.jsonl`,
+    /// shifted on rotation like logrotate; the oldest file is dropped.
+    Numbered { count: u32 },
+}
+
+impl RotationStyle {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Dated => "dated",
+            Self::Numbered { .. } => "numbered",
+        }
+    }
+}
+
+/// Default number of rotated files kept in numbered rotation.
+const DEFAULT_ROTATION_COUNT: u32 = 5;
+
+/// Defaults for the `fileRotation` section.
+const DEFAULT_MAX_FILE_SIZE_MB: u64 = 100;
+const DEFAULT_FILE_FLUSH_INTERVAL_MS: u64 = 5_000;
+const DEFAULT_RETENTION_DAYS: u32 = 7;
+
+/// How often dated files roll over to a new period.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RotationInterval {
+    Daily,
+    /// `telemetry-YYYY-MM-DD-HH.jsonl`
+    Hourly,
+}
+
+impl RotationInterval {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Daily => "daily",
+            Self::Hourly => "hourly",
+        }
+    }
+}
+
+/// FileProvider rotation settings: the `fileRotation` section plus
+/// `rotationStyle`/`rotationCount`.
+#[derive(Clone, Copy, Debug)]
+struct FileRotationConfig {
+    style: RotationStyle,
+    interval: RotationInterval,
+    /// Bytes written before the file rotates regardless of period.
+    max_file_size: u64,
+    retention_days: u32,
+    /// Longest a written record waits in the buffer before it's flushed.
+    flush_interval: std::time::Duration,
+}
+
+impl Default for FileRotationConfig {
+    fn default() -> Self {
+        Self {
+            style: RotationStyle::Dated,
+            interval: RotationInterval::Daily,
+            max_file_size: DEFAULT_MAX_FILE_SIZE_MB * 1024 * 1024,
+            retention_days: DEFAULT_RETENTION_DAYS,
+            flush_interval: std::time::Duration::from_millis(DEFAULT_FILE_FLUSH_INTERVAL_MS),
+        }
+    }
+}
+
+impl FileRotationConfig {
+    /// Apply `maxFileSizeMb`, `retentionDays`, `rotationInterval` and
+    /// `flushIntervalMs`, keeping the default for any value that is missing
+    /// or invalid.
+    fn apply_section(&mut self, section: &serde_json::Value) {
+        match section.get("maxFileSizeMb").map(|v| v.as_f64()) {
+            None => {}
+            Some(Some(mb)) if mb > 0.0 => self.max_file_size = (mb * 1024.0 * 1024.0) as u64,
+            Some(_) => eprintln!(
+                "[yeti-telemetry] Ignoring fileRotation.maxFileSizeMb (must be > 0), using {}MB",
+                DEFAULT_MAX_FILE_SIZE_MB
+            ),
+        }
+        match section.get("retentionDays").map(|v| v.as_f64()) {
+            None => {}
+            Some(Some(days)) if days > 0.0 => self.retention_days = days.ceil() as u32,
+            Some(_) => eprintln!(
+                "[yeti-telemetry] Ignoring fileRotation.retentionDays (must be > 0), using {}",
+                DEFAULT_RETENTION_DAYS
+            ),
+        }
+        match section.get("rotationInterval").and_then(|v| v.as_str()) {
+            None | Some("daily") => {}
+            Some("hourly") => self.interval = RotationInterval::Hourly,
+            Some(other) => eprintln!(
+                "[yeti-telemetry] Unknown fileRotation.rotationInterval '{}', using 'daily'",
+                other
+            ),
+        }
+        match section.get("flushIntervalMs").map(|v| v.as_u64()) {
+            None => {}
+            Some(Some(ms)) if ms > 0 => self.flush_interval = std::time::Duration::from_millis(ms),
+            Some(_) => eprintln!(
+                "[yeti-telemetry] Ignoring fileRotation.flushIntervalMs (must be > 0), using {}",
+                DEFAULT_FILE_FLUSH_INTERVAL_MS
+            ),
+        }
+    }
+}
+
+/// Filename prefix of the main file output.
+const DEFAULT_FILE_PREFIX: &str = "telemetry";
+
+/// Record filter for a `fileOutputs` entry. Records at or above `level` whose
+/// target starts with `target` are written; either may be omitted.
+#[derive(Clone, Debug, Default)]
+struct FileFilter {
+    min_level: Option<String>,
+    target_prefix: Option<String>,
+}
+
+impl FileFilter {
+    /// Records without a level (metrics) never pass a level filter.
+    fn matches(&self, record: &serde_json::Value) -> bool {
+        let field = |key: &str| record.get(key).and_then(|v| v.as_str());
+        self.min_level.as_deref().is_none_or(|min| {
+            field("level").is_some_and(|level| level_rank(level) >= level_rank(min))
+        }) && self
+            .target_prefix
+            .as_deref()
+            .is_none_or(|t| field("target").is_some_and(|target| target.starts_with(t)))
+    }
+}
+
+/// Ordering of tracing level names for `level` filters; unknown names rank as INFO.
+fn level_rank(level: &str) -> u8 {
+    match level.to_ascii_uppercase().as_str() {
+        "TRACE" => 0,
+        "DEBUG" => 1,
+        "WARN" | "WARNING" => 3,
+        "ERROR" => 4,
+        "FATAL" | "CRITICAL" => 5,
+        _ => 2,
+    }
+}
+
+/// A `fileOutputs` entry: an extra file output named `<prefix>-<period>.jsonl`
+/// that only receives records passing its filter. Rotation, envelope and
+/// compression follow the main file settings.
+#[derive(Clone, Debug)]
+struct FileOutputConfig {
+    prefix: String,
+    filter: FileFilter,
+}
+
+impl FileOutputConfig {
+    fn parse(entry: &serde_json::Value) -> Option<Self> {
+        let prefix = entry.get("prefix").and_then(|v| v.as_str()).unwrap_or("");
+        let valid = !prefix.is_empty()
+            && prefix != DEFAULT_FILE_PREFIX
+            && prefix
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if !valid {
+            eprintln!("[yeti-telemetry] Ignoring fileOutputs entry with invalid prefix: {}", entry);
+            return None;
+        }
+        Some(Self {
+            prefix: prefix.to_string(),
+            filter: FileFilter {
+                min_level: entry.get("level").and_then(|v| v.as_str()).map(|s| s.to_string()),
+                target_prefix: entry.get("target").and_then(|v| v.as_str()).map(|s| s.to_string()),
+            },
+        })
+    }
+}
+
+/// Records queued per file output before writes block the telemetry writer.
+const FILE_QUEUE_CAPACITY: usize = 10_000;
+
+/// After a file fails to open, writes retry the open at most this often...
+const FILE_REOPEN_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+/// ...and the failure is logged at most this often.
+const FILE_OPEN_ERROR_LOG_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Work queued for the file thread.
+enum FileCommand {
+    /// Write a record of the given kind (`log`, `span`, `metric`).
+    Write(&'static str, serde_json::Value),
+    /// Flush the file, then acknowledge.
+    Flush(std::sync::mpsc::SyncSender<()>),
+}
+
+/// File-based telemetry output with daily or hourly rotation.
+///
+/// In CSV mode the thread owns one file per record kind, each rotated on its
+/// own. Records are handed to a dedicated thread that owns the file, so slow disk
+/// I/O (NFS, a full disk) doesn't stall event processing. The queue is
+/// bounded: once it fills, writes block until the thread catches up, so a
+/// stuck disk applies backpressure instead of growing memory. Dropping the
+/// provider drains the queue, flushes, and joins the thread.
+struct FileProvider {
+    /// Output name: `file` for the main file, `file:<prefix>` for `fileOutputs`.
+    name: String,
+    /// Records failing the filter are skipped; `None` writes everything.
+    filter: Option<FileFilter>,
+    sender: Option<std::sync::mpsc::SyncSender<FileCommand>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+    /// Open and write failures on the file thread.
+    health: Arc<HealthTracker>,
+    /// Counts records the file thread couldn't be handed.
+    state: &'static SharedState,
+}
+
+impl FileProvider {
+    fn new(
+        log_dir: PathBuf,
+        prefix: &str,
+        encoding: FileEncoding,
+        rotation: FileRotationConfig,
+        rotated: RotatedFiles,
+    ) -> Self {
+        let name = if prefix == DEFAULT_FILE_PREFIX {
+            "file".to_string()
+        } else {
+            format!("file:{}", prefix)
+        };
+        let (sender, receiver) = std::sync::mpsc::sync_channel(FILE_QUEUE_CAPACITY);
+        let prefix = prefix.to_string();
+        let state = rotated.state();
+        let health = Arc::new(HealthTracker::default());
+        let tracker = Arc::clone(&health);
+        let thread = std::thread::Builder::new()
+            .name(format!("telemetry-{}", name))
+            .spawn(move || {
+                let sink = |prefix: &str, layout| {
+                    FileSink::new(log_dir.clone(), prefix, layout, rotation, rotated.clone(), tracker.clone())
+                };
+                // Indexed by `RecordKind::index` in CSV mode.
+                let mut sinks: Vec<FileSink> = match encoding.format {
+                    FileFormat::Jsonl => {
+                        vec![sink(&prefix, FileLayout::Jsonl(encoding.envelope, encoding.intern_fields))]
+                    }
+                    FileFormat::Csv => RecordKind::ALL
+                        .iter()
+                        .map(|kind| sink(&format!("{}-{}", prefix, kind.as_str()), FileLayout::Csv(*kind)))
+                        .collect(),
+                };
+                // Buffered records are flushed at least every flush interval,
+                // whether or not more arrive, so a quiet service's last lines
+                // reach the disk promptly. Ends once the provider drops its
+                // sender and the queue is empty.
+                let flush_interval = rotation.flush_interval;
+                let mut last_flush = Instant::now();
+                loop {
+                    let wait = flush_interval.saturating_sub(last_flush.elapsed());
+                    match receiver.recv_timeout(wait) {
+                        Ok(FileCommand::Write(event_type, record)) => {
+                            let index = match RecordKind::parse(event_type) {
+                                Some(kind) if sinks.len() > 1 => kind.index(),
+                                _ => 0,
+                            };
+                            sinks[index].write_event(event_type, &record);
+                        }
+                        Ok(FileCommand::Flush(ack)) => {
+                            sinks.iter_mut().for_each(FileSink::flush);
+                            last_flush = Instant::now();
+                            let _ = ack.send(());
+                        }
+                        Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {}
+                        Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
+                    }
+                    if last_flush.elapsed() >= flush_interval {
+                        sinks.iter_mut().for_each(FileSink::flush);
+                        last_flush = Instant::now();
+                    }
+                }
+                sinks.iter_mut().for_each(FileSink::flush);
+            });
+        let (sender, thread) = match thread {
+            Ok(thread) => (Some(sender), Some(thread)),
+            Err(e) => {
+                eprintln!("[file-provider] Failed to start {} thread: {}", name, e);
+                (None, None)
+            }
+        };
+        Self {
+            name,
+            filter: None,
+            sender,
+            thread,
+            health,
+            state,
+        }
+    }
+
+    fn with_filter(mut self, filter: FileFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    fn write_event(&mut self, event_type: &'static str, record: &serde_json::Value) {
+        if self.filter.as_ref().is_some_and(|f| !f.matches(record)) {
+            return;
+        }
+        let sent = self
+            .sender
+            .as_ref()
+            .is_some_and(|s| s.send(FileCommand::Write(event_type, record.clone())).is_ok());
+        if !sent {
+            self.state.pipeline.output_failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Drop for FileProvider {
+    fn drop(&mut self) {
+        self.sender = None;
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                eprintln!("[file-provider] {} thread panicked", self.name);
+            }
+        }
+    }
+}
+
+/// The file side of a FileProvider, owned by its thread: the open file plus
+/// rotation, compression and retention.
+struct FileSink {
+    log_dir: PathBuf,
+    /// Filename stem: `<prefix>-<period>.<ext>` or `<prefix>.<ext>`.
+    prefix: String,
+    layout: FileLayout,
+    rotation_style: RotationStyle,
+    interval: RotationInterval,
+    /// Period the active file belongs to (`YYYY-MM-DD` or `YYYY-MM-DD-HH`).
+    current_period: String,
+    writer: Option<BufWriter<File>>,
+    current_size: u64,
+    max_file_size: u64,
+    write_count: u64,
+    rotated: RotatedFiles,
+    /// Set while there is no open file: earliest time to retry the open.
+    reopen_at: Option<Instant>,
+    /// When an open failure was last logged.
+    open_error_logged: Option<Instant>,
+    /// Records dropped since the file was last open.
+    dropped_without_file: u64,
+    /// Dictionary of the active file when `fieldInterning` is on.
+    interner: Option<FieldInterner>,
+    health: Arc<HealthTracker>,
+}
+
+impl FileSink {
+    fn new(
+        log_dir: PathBuf,
+        prefix: &str,
+        layout: FileLayout,
+        rotation: FileRotationConfig,
+        rotated: RotatedFiles,
+        health: Arc<HealthTracker>,
+    ) -> Self {
+        let _ = fs::create_dir_all(&log_dir);
+        let current_period = period_string(rotation.interval);
+        let interner = matches!(layout, FileLayout::Jsonl(_, true)).then(FieldInterner::default);
+
+        let mut sink = Self {
+            log_dir,
+            prefix: prefix.to_string(),
+            layout,
+            rotation_style: rotation.style,
+            interval: rotation.interval,
+            current_period,
+            writer: None,
+            current_size: 0,
+            max_file_size: rotation.max_file_size,
+            write_count: 0,
+            rotated,
+            reopen_at: None,
+            open_error_logged: None,
+            dropped_without_file: 0,
+            interner,
+            health,
+        };
+        if sink.rotated.archive.is_some() {
+            sink.archive_leftovers();
+        }
+        if sink.rotated.compress {
+            sink.compress_leftovers();
+        }
+        sink.open_file();
+        sink
+    }
+
+    fn flush(&mut self) {
+        if let Some(ref mut w) = self.writer {
+            let _ = w.flush();
+        }
+    }
+
+    /// Whether `name` is one of this output's files (active, rotated or
+    /// compressed), and not another output's that merely shares a prefix.
+    fn owns_file(&self, name: &str) -> bool {
+        let Some(rest) = name.strip_prefix(self.prefix.as_str()) else {
+            return false;
+        };
+        let own_suffix = rest.starts_with('.')
+            || rest
+                .strip_prefix('-')
+                .is_some_and(|r| r.starts_with(|c: char| c.is_ascii_digit()));
+        let ext = self.layout.extension();
+        own_suffix
+            && name
+                .strip_suffix(".gz")
+                .unwrap_or(name)
+                .rsplit_once('.')
+                .is_some_and(|(_, e)| e == ext)
+    }
+
+    fn write_event(&mut self, event_type: &str, record: &serde_json::Value) {
+        self.maybe_rotate();
+
+        // Rotation can't run without an open file, so a failed open (disk
+        // full, permissions) is retried here rather than losing every record
+        // until the next period.
+        if self.writer.is_none() && self.reopen_at.is_none_or(|at| Instant::now() >= at) {
+            self.open_file();
+        }
+        if self.writer.is_none() {
+            self.dropped_without_file += 1;
+            let pipeline = &self.rotated.state().pipeline;
+            pipeline.file_dropped_no_writer.fetch_add(1, Ordering::Relaxed);
+            pipeline.output_failures.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        let line = match &self.layout {
+            FileLayout::Jsonl(envelope, _) => {
+                // New dictionary entries are written just before the first
+                // record that references them.
+                let mut bytes = Vec::new();
+                let interned;
+                let mut record = record;
+                if let Some(ref mut interner) = self.interner {
+                    let mut copy = record.clone();
+                    for entry in interner.intern(&mut copy) {
+                        push_json_line(&mut bytes, &frame_file_line(envelope, FIELD_DICT_TYPE, &entry));
+                    }
+                    interned = copy;
+                    record = &interned;
+                }
+                let line = match envelope {
+                    FileEnvelope::Otel(resource) => match otlp_json_line(event_type, record, resource) {
+                        Some(line) => line,
+                        None => return,
+                    },
+                    _ => frame_file_line(envelope, event_type, record),
+                };
+                push_json_line(&mut bytes, &line).then_some(bytes)
+            }
+            FileLayout::Csv(kind) => Some(csv_row(*kind, record).into_bytes()),
+        };
+
+        if let Some(ref mut w) = self.writer {
+            if let Some(bytes) = line {
+                let line_len = bytes.len() as u64;
+                let written = w.write_all(&bytes);
+                if let Err(ref e) = written {
+                    self.health.failed(&e.to_string());
+                }
+                if written.is_ok() {
+                    self.health.succeeded();
+                    self.current_size += line_len;
+                    self.write_count += 1;
+                    self.rotated.state().pipeline.file_bytes_written.fetch_add(line_len, Ordering::Relaxed);
+
+                    if self.write_count % 100 == 0 {
+                        let _ = w.flush();
+                    }
+                } else {
+                    self.rotated.state().pipeline.output_failures.fetch_add(1, Ordering::Relaxed);
+                    // Entries in a failed write may be missing from the file.
+                    if let Some(ref mut interner) = self.interner {
+                        interner.reset();
+                    }
+                }
+            }
+        }
+    }
+
+    fn maybe_rotate(&mut self) {
+        let period = period_string(self.interval);
+        let size_exceeded = self.current_size >= self.max_file_size;
+
+        if period != self.current_period || size_exceeded {
+            if let Some(ref mut w) = self.writer {
+                let _ = w.flush();
+            }
+            self.writer = None;
+            let closed = self.log_dir.join(self.active_filename());
+            self.current_period = period;
+            self.current_size = 0;
+            match self.rotation_style {
+                RotationStyle::Dated => {
+                    // A file rotated for size keeps its period; give it a free
+                    // sequence suffix so the new file starts empty.
+                    let rotated = if size_exceeded && closed == self.log_dir.join(self.active_filename()) {
+                        let target = self.next_size_rotation_path();
+                        match fs::rename(&closed, &target) {
+                            Ok(()) => target,
+                            Err(e) => {
+                                eprintln!("[file-provider] Failed to rotate {}: {}", closed.display(), e);
+                                closed
+                            }
+                        }
+                    } else {
+                        closed
+                    };
+                    self.open_file();
+                    self.rotated.finish(rotated);
+                    self.cleanup_old_files();
+                }
+                RotationStyle::Numbered { count } => {
+                    self.shift_numbered_files(count);
+                    self.open_file();
+                    if self.rotated.compress {
+                        let rotated = format!("{}.1.{}", self.prefix, self.layout.extension());
+                        self.rotated.finish(self.log_dir.join(rotated));
+                    }
+                }
+            }
+        }
+    }
+
+    fn active_filename(&self) -> String {
+        match self.rotation_style {
+            RotationStyle::Dated => format!("{}-{}.{}", self.prefix, self.current_period, self.layout.extension()),
+            RotationStyle::Numbered { .. } => format!("{}.{}", self.prefix, self.layout.extension()),
+        }
+    }
+
+    /// First unused `<prefix>-<period>.<n>.<ext>` name for the current
+    /// period, checking compressed names too.
+    fn next_size_rotation_path(&self) -> PathBuf {
+        let mut n = 1;
+        loop {
+            let plain = self.log_dir.join(format!(
+                "{}-{}.{}.{}",
+                self.prefix,
+                self.current_period,
+                n,
+                self.layout.extension()
+            ));
+            if !plain.exists() && !gz_path(&plain).exists() {
+                return plain;
+            }
+            n += 1;
+        }
+    }
+
+    /// Finish work a crash interrupted: drop partial `.gz.tmp` files and
+    /// compress rotated files that are still plain.
+    fn compress_leftovers(&self) {
+        let active = self.active_filename();
+        let Ok(entries) = fs::read_dir(&self.log_dir) else { return };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.ends_with(".gz.tmp") {
+                let _ = fs::remove_file(&path);
+            } else if self.owns_file(&name) && !name.ends_with(".gz") && name != active {
+                self.rotated.finish(path);
+            }
+        }
+    }
+
+    /// Upload rotated files left behind by a crash or a failed upload. Runs
+    /// before `compress_leftovers`, whose jobs upload what they compress.
+    fn archive_leftovers(&self) {
+        let active = self.active_filename();
+        let Ok(entries) = fs::read_dir(&self.log_dir) else { return };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let finished = name.ends_with(".gz") || !self.rotated.compress;
+            if self.owns_file(&name) && finished && name != active {
+                self.rotated.upload(entry.path());
+            }
+        }
+    }
+
+    fn open_file(&mut self) {
+        let path = self.log_dir.join(self.active_filename());
+
+        match OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(file) => {
+                self.current_size = file.metadata().map(|m| m.len()).unwrap_or(0);
+                let mut writer = BufWriter::new(file);
+                // A CSV file starts with its header; appending to a non-empty
+                // one (e.g. after a restart) continues under the existing header.
+                if let FileLayout::Csv(kind) = self.layout {
+                    if self.current_size == 0 {
+                        let header = format!("{}\r\n", csv_columns(kind).join(","));
+                        match writer.write_all(header.as_bytes()) {
+                            Ok(()) => self.current_size = header.len() as u64,
+                            Err(e) => self.health.failed(&e.to_string()),
+                        }
+                    }
+                }
+                self.writer = Some(writer);
+                self.reopen_at = None;
+                self.health.succeeded();
+                // Each file carries its own dictionary.
+                if let Some(ref mut interner) = self.interner {
+                    interner.reset();
+                }
+                if self.dropped_without_file > 0 {
+                    eprintln!(
+                        "[file-provider] Opened {} after dropping {} records while no file was open",
+                        path.display(),
+                        self.dropped_without_file
+                    );
+                    self.dropped_without_file = 0;
+                }
+            }
+            Err(e) => {
+                let now = Instant::now();
+                self.reopen_at = Some(now + FILE_REOPEN_INTERVAL);
+                self.health.failed(&format!("cannot open {}: {}", path.display(), e));
+                if self
+                    .open_error_logged
+                    .is_none_or(|at| now.duration_since(at) >= FILE_OPEN_ERROR_LOG_INTERVAL)
+                {
+                    self.open_error_logged = Some(now);
+                    eprintln!(
+                        "[file-provider] Failed to open {}: {} (retrying; {} records dropped so far)",
+                        path.display(),
+                        e,
+                        self.dropped_without_file
+                    );
+                }
+            }
+        }
+    }
+
+    /// logrotate-style shift: drop `<prefix>.<count>.<ext>`, rename each
+    /// `<prefix>.<n>.<ext>` to `<n+1>`, and move the active file to `.1`.
+    fn shift_numbered_files(&self, count: u32) {
+        let ext = self.layout.extension();
+        let numbered = |n: u32| self.log_dir.join(format!("{}.{}.{}", self.prefix, n, ext));
+        let _ = fs::remove_file(numbered(count));
+        let _ = fs::remove_file(gz_path(&numbered(count)));
+        for n in (1..count).rev() {
+            for (from, to) in [
+                (numbered(n), numbered(n + 1)),
+                (gz_path(&numbered(n)), gz_path(&numbered(n + 1))),
+            ] {
+                if from.exists() {
+                    if let Err(e) = fs::rename(&from, to) {
+                        eprintln!("[file-provider] Failed to rotate {}: {}", from.display(), e);
+                    }
+                }
+            }
+        }
+        let active = self.log_dir.join(self.active_filename());
+        if let Err(e) = fs::rename(&active, numbered(1)) {
+            eprintln!("[file-provider] Failed to rotate {}: {}", active.display(), e);
+        }
+    }
+
+    /// Delete this output's files older than the retention period. A dated
+    /// file's age is measured from the end of the period in its name, so
+    /// backups or syncs that touch it don't extend its life. Numbered files
+    /// fall back to their modification time. With an archive, expired files
+    /// are ones whose upload failed; they're retried instead of deleted.
+    /// Retention comes from the live settings, so a reload takes effect at
+    /// the next rotation.
+    fn cleanup_old_files(&self) {
+        self.cleanup_files_before(SystemTime::now(), self.rotated.state().live.load().retention_days);
+    }
+
+    /// Remove (or archive) this output's files whose period ended more than
+    /// `retention_days` before `now`. A file ending exactly at the cutoff is
+    /// kept.
+    fn cleanup_files_before(&self, now: SystemTime, retention_days: u32) {
+        let cutoff = now - std::time::Duration::from_secs(u64::from(retention_days) * 86400);
+
+        if let Ok(entries) = fs::read_dir(&self.log_dir) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if !self.owns_file(&name) {
+                    continue;
+                }
+                let ended = match period_end_from_filename(&self.prefix, &name) {
+                    Some(secs) => std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs),
+                    None => match path.metadata().and_then(|m| m.modified()) {
+                        Ok(modified) => modified,
+                        Err(_) => continue,
+                    },
+                };
+                if ended < cutoff && name != self.active_filename() {
+                    if self.rotated.archive.is_some() {
+                        self.rotated.upload(path);
+                        continue;
+                    }
+                    let _ = fs::remove_file(&path);
+                    eprintln!("[file-provider] Cleaned up old file: {}", path.display());
+                }
+            }
+        }
+    }
+}
+
+/// `<path>.gz`
+fn gz_path(path: &std::path::Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".gz");
+    PathBuf::from(name)
+}
+
+/// Threads compressing and uploading rotated files when
+/// `maxConcurrentOutputOps` is not set.
+const DEFAULT_MAX_CONCURRENT_OUTPUT_OPS: usize = 4;
+
+/// Compressions and uploads waiting for a thread before further ones are
+/// dropped.
+const OUTPUT_OPS_QUEUE_CAPACITY: usize = 256;
+
+/// What a file output does with a file once it is rotated out.
+#[derive(Clone)]
+struct RotatedFiles {
+    /// Gzip files once they're rotated out; the active file stays plain.
+    compress: bool,
+    /// Upload rotated files here, deleting them locally once stored.
+    archive: Option<Arc<S3Archive>>,
+    /// Runs the compressions and uploads, shared by all file outputs.
+    ops: Arc<OutputOps>,
+}
+
+impl RotatedFiles {
+    /// Gzip a rotated file off the file thread, then upload the `.gz` with
+    /// an archive. Without compression the file is only uploaded.
+    ///
+    /// The archive is written to `<file>.gz.tmp`, synced, and renamed into
+    /// place before the original is removed, so a crash leaves either the
+    /// intact original or a complete archive — never a truncated `.gz`.
+    fn finish(&self, path: PathBuf) {
+        if !self.compress {
+            return self.upload(path);
+        }
+        let archive = self.archive.clone();
+        let state = self.ops.state;
+        self.ops.submit("compression", path, move |path| match gzip_file(path) {
+            Ok(()) => {
+                if let Some(archive) = archive {
+                    archive.upload(&gz_path(path), state);
+                }
+            }
+            Err(e) => eprintln!("[file-provider] Failed to compress {}: {}", path.display(), e),
+        });
+    }
+
+    /// Upload `path` off the file thread, then delete it locally.
+    fn upload(&self, path: PathBuf) {
+        if let Some(archive) = self.archive.clone() {
+            let state = self.ops.state;
+            self.ops.submit("upload", path, move |path| archive.upload(path, state));
+        }
+    }
+
+    /// Live settings and counters of the file outputs.
+    fn state(&self) -> &'static SharedState {
+        self.ops.state
+    }
+}
+
+type OutputOp = Box<dyn FnOnce() + Send>;
+
+/// A fixed set of threads running slow output work: compressing and
+/// uploading rotated files. Work waits in a bounded queue; once that is
+/// full, further work is dropped and counted rather than piling up threads
+/// during a slow-upload backlog. A dropped file stays on disk, and startup
+/// (or, for uploads, retention) picks it up again.
+struct OutputOps {
+    sender: std::sync::mpsc::SyncSender<OutputOp>,
+    state: &'static SharedState,
+}
+
+impl OutputOps {
+    fn new(workers: usize, queue_capacity: usize, state: &'static SharedState) -> Self {
+        let (sender, receiver) = std::sync::mpsc::sync_channel::<OutputOp>(queue_capacity);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for n in 0..workers {
+            let receiver = Arc::clone(&receiver);
+            let spawned = std::thread::Builder::new()
+                .name(format!("telemetry-output-op-{}", n))
+                .spawn(move || loop {
+                    // The lock is released before the job runs, so idle
+                    // workers wait on the queue while others are busy.
+                    let job = lock(&receiver).recv();
+                    match job {
+                        Ok(job) => job(),
+                        Err(_) => break,
+                    }
+                });
+            if let Err(e) = spawned {
+                eprintln!("[file-provider] Failed to start output thread {}: {}", n, e);
+            }
+        }
+        Self { sender, state }
+    }
+
+    /// Queue `job` (the `action` named in logs) to run on `path`, or drop it
+    /// if the queue is full.
+    fn submit(&self, action: &str, path: PathBuf, job: impl FnOnce(&std::path::Path) + Send + 'static) {
+        let shown = path.display().to_string();
+        if self.sender.try_send(Box::new(move || job(&path))).is_err() {
+            self.state.pipeline.output_ops_dropped.fetch_add(1, Ordering::Relaxed);
+            eprintln!(
+                "[file-provider] Output queue full, skipping the {} of {}; it is retried at the next startup",
+                action, shown
+            );
+        }
+    }
+}
+
+fn gzip_file(path: &std::path::Path) -> std::io::Result<()> {
+    let target = gz_path(path);
+    let mut tmp_name = target.as_os_str().to_owned();
+    tmp_name.push(".tmp");
+    let tmp = PathBuf::from(tmp_name);
+
+    let result = (|| {
+        let mut input = File::open(path)?;
+        let mut encoder = flate2::write::GzEncoder::new(
+            BufWriter::new(File::create(&tmp)?),
+            flate2::Compression::default(),
+        );
+        std::io::copy(&mut input, &mut encoder)?;
+        let file = encoder.finish()?.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        fs::rename(&tmp, &target)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+        return result;
+    }
+    fs::remove_file(path)
+}
+
+/// Current rotation period (UTC): `YYYY-MM-DD`, or `YYYY-MM-DD-HH` when hourly.
+///
+/// If the system clock reads before the epoch, returns [`CLOCK_ERROR_PERIOD`]
+/// rather than a plausible-looking 1970 date, and warns once.
+fn period_string(interval: RotationInterval) -> String {
+    static CLOCK_WARNED: AtomicBool = AtomicBool::new(false);
+    let secs = match SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+        Ok(d) => d.as_secs(),
+        Err(_) => {
+            if !CLOCK_WARNED.swap(true, Ordering::Relaxed) {
+                eprintln!(
+                    "[file-provider] WARNING: system clock is before 1970; writing to the {} period",
+                    CLOCK_ERROR_PERIOD
+                );
+            }
+            return CLOCK_ERROR_PERIOD.to_string();
+        }
+    };
+    match interval {
+        RotationInterval::Daily => date_to_string(secs),
+        RotationInterval::Hourly => format!("{}-{:02}", date_to_string(secs), (secs % 86400) / 3600),
+    }
+}
+
+/// Period used while the system clock is unusable. Not a valid date, so
+/// retention ages these files by modification time.
+const CLOCK_ERROR_PERIOD: &str = "0000-00-00";
+
+/// UTC date (`YYYY-MM-DD`) of an epoch-seconds timestamp.
+fn date_to_string(secs: u64) -> String {
+    let (year, month, day) = days_to_date(secs / 86400);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// End of the period named by a dated file (`<prefix>-YYYY-MM-DD[-HH]`,
+/// with any `.N` size suffix and `.jsonl[.gz]` or `.csv[.gz]` extension), as epoch seconds.
+/// `None` for names without a valid date.
+fn period_end_from_filename(prefix: &str, name: &str) -> Option<u64> {
+    let stem = name.strip_prefix(prefix)?.strip_prefix('-')?;
+    let stem = stem.split('.').next()?;
+    let mut parts = stem.split('-');
+    let mut field = |len: usize| {
+        parts
+            .next()
+            .filter(|p| p.len() == len && p.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|p| p.parse::<u64>().ok())
+    };
+    let (year, month, day) = (field(4)?, field(2)?, field(2)?);
+    let hour = field(2);
+    let valid = year >= 1970
+        && (1..=12).contains(&month)
+        && (1..=31).contains(&day)
+        && hour.is_none_or(|h| h < 24);
+    if !valid {
+        return None;
+    }
+    let start = date_to_days(year, month, day) * 86400;
+    Some(match hour {
+        Some(hour) => start + (hour + 1) * 3600,
+        None => start + 86400,
+    })
+}
+
+/// Days since the Unix epoch for a civil date (inverse of `days_to_date`).
+fn date_to_days(year: u64, month: u64, day: u64) -> u64 {
+    // Algorithm from http://howardhinnant.github.io/date_algorithms.html
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    (era * 146097 + doe).saturating_sub(719468)
+}
+
+/// Last day representable as a four-digit year (9999-12-31).
+const MAX_DATE_DAYS: u64 = 2_932_896;
+
+/// Civil date (year, month, day) for days since the Unix epoch. Values past
+/// 9999-12-31 (e.g. seconds passed by mistake) clamp to that date instead of
+/// overflowing.
+fn days_to_date(days_since_epoch: u64) -> (u64, u64, u64) {
+    // Algorithm from http://howardhinnant.github.io/date_algorithms.html
+    let z = days_since_epoch.min(MAX_DATE_DAYS) + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let y = yoe + era * 400;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = if m <= 2 { y + 1 } else { y };
+    (y, m, d)
+}
+
+impl TelemetryOutput for FileProvider {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Wait until the file thread has written everything queued so far and
+    /// flushed the file.
+    fn flush(&mut self) {
+        let Some(ref sender) = self.sender else { return };
+        let (ack, done) = std::sync::mpsc::sync_channel(1);
+        if sender.send(FileCommand::Flush(ack)).is_ok() {
+            let _ = done.recv();
+        }
+    }
+
+    fn priority(&self) -> i32 {
+        OUTPUT_PRIORITY_FILE
+    }
+
+    fn write_log(&mut self, record: &serde_json::Value) {
+        self.write_event("log", record);
+    }
+
+    fn write_span(&mut self, record: &serde_json::Value) {
+        self.write_event("span", record);
+    }
+
+    fn write_metric(&mut self, record: &serde_json::Value) {
+        self.write_event("metric", record);
+    }
+
+    fn health(&self) -> OutputHealth {
+        if self.sender.is_none() {
+            return OutputHealth::Failing("file thread not running".to_string());
+        }
+        self.health.health()
+    }
+}
+
+// ============================================================================
+// Archive — rotated files uploaded to S3-compatible storage
+// ============================================================================
+
+/// Upload attempts per file before it is left for the next retention pass.
+const ARCHIVE_MAX_ATTEMPTS: u32 = 3;
+const ARCHIVE_INITIAL_BACKOFF: std::time::Duration = std::time::Duration::from_secs(2);
+const ARCHIVE_REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(300);
+
+/// Uploads rotated files to an S3-compatible bucket (AWS S3, MinIO, R2) with
+/// path-style `PUT` requests signed with AWS Signature Version 4. The local
+/// file is deleted only after its upload succeeds.
+struct S3Archive {
+    /// Scheme and authority, e.g. `https://s3.us-east-1.amazonaws.com`.
+    endpoint: String,
+    /// `Host` header value for signing.
+    host: String,
+    bucket: String,
+    /// Object key prefix, empty or ending in `/`.
+    prefix: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+    session_token: Option<String>,
+    client: reqwest::blocking::Client,
+    /// Files being uploaded, so rotation and retention never upload one twice.
+    in_flight: Mutex<std::collections::HashSet<PathBuf>>,
+}
+
+impl S3Archive {
+    /// Parse the `telemetry.archive` section. Credentials come from
+    /// `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and, if set,
+    /// `AWS_SESSION_TOKEN`. `None` when the section is absent or unusable.
+    fn from_config(telemetry: &serde_json::Value) -> Option<Self> {
+        let section = telemetry.get("archive")?;
+        let str_field = |key: &str| {
+            section
+                .get(key)
+                .and_then(|v| v.as_str())
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+        };
+        let disabled = |why: &str| {
+            eprintln!("[yeti-telemetry] Archive disabled: {}", why);
+            None
+        };
+
+        let Some(endpoint) = str_field("s3Endpoint") else {
+            return disabled("archive.s3Endpoint is required");
+        };
+        let Some(bucket) = str_field("bucket") else {
+            return disabled("archive.bucket is required");
+        };
+        let url = match reqwest::Url::parse(&endpoint) {
+            Ok(url) if url.host_str().is_some() => url,
+            _ => return disabled(&format!("invalid s3Endpoint '{}'", endpoint)),
+        };
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+        let (Ok(access_key), Ok(secret_key)) = (
+            std::env::var("AWS_ACCESS_KEY_ID"),
+            std::env::var("AWS_SECRET_ACCESS_KEY"),
+        ) else {
+            return disabled("AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY must be set");
+        };
+        let client = match reqwest::blocking::Client::builder()
+            .timeout(ARCHIVE_REQUEST_TIMEOUT)
+            .build()
+        {
+            Ok(c) => c,
+            Err(e) => return disabled(&format!("failed to create HTTP client: {}", e)),
+        };
+        let prefix = str_field("prefix")
+            .map(|p| format!("{}/", p.trim_matches('/')))
+            .unwrap_or_default();
+
+        Some(Self {
+            endpoint: format!("{}://{}", url.scheme(), host),
+            host,
+            bucket,
+            prefix,
+            region: str_field("region")
+                .or_else(|| std::env::var("AWS_REGION").ok())
+                .unwrap_or_else(|| "us-east-1".to_string()),
+            access_key,
+            secret_key,
+            session_token: std::env::var("AWS_SESSION_TOKEN").ok().filter(|t| !t.is_empty()),
+            client,
+            in_flight: Mutex::new(std::collections::HashSet::new()),
+        })
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "s3Endpoint": self.endpoint,
+            "bucket": self.bucket,
+            "prefix": self.prefix,
+            "region": self.region,
+        })
+    }
+
+    /// Upload `path` and delete it once stored, retrying transient failures
+    /// with backoff. On failure the file stays on disk for a later attempt.
+    fn upload(&self, path: &std::path::Path, state: &SharedState) {
+        if !path.exists() || !lock(&self.in_flight).insert(path.to_path_buf()) {
+            return;
+        }
+        let result = self.upload_with_retry(path);
+        lock(&self.in_flight).remove(path);
+
+        let pipeline = &state.pipeline;
+        match result {
+            Ok(key) => {
+                pipeline.archive_uploads.fetch_add(1, Ordering::Relaxed);
+                if let Err(e) = fs::remove_file(path) {
+                    eprintln!("[archive] Uploaded {} but failed to delete it: {}", path.display(), e);
+                } else {
+                    eprintln!("[archive] Archived {} to s3://{}/{}", path.display(), self.bucket, key);
+                }
+            }
+            Err(e) => {
+                pipeline.archive_failures.fetch_add(1, Ordering::Relaxed);
+                eprintln!("[archive] Failed to upload {}, keeping it locally: {}", path.display(), e);
+            }
+        }
+    }
+
+    fn upload_with_retry(&self, path: &std::path::Path) -> std::result::Result<String, String> {
+        let name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .ok_or_else(|| "not a file".to_string())?;
+        let key = format!("{}{}", self.prefix, name);
+        let body = fs::read(path).map_err(|e| e.to_string())?;
+
+        let mut backoff = ARCHIVE_INITIAL_BACKOFF;
+        let mut attempt = 1;
+        loop {
+            let (retryable, error) = match self.put_object(&key, &body) {
+                Ok(()) => return Ok(key),
+                Err(failure) => failure,
+            };
+            if !retryable || attempt >= ARCHIVE_MAX_ATTEMPTS {
+                return Err(error);
+            }
+            std::thread::sleep(backoff);
+            backoff *= 2;
+            attempt += 1;
+        }
+    }
+
+    /// One signed `PUT`. Errors say whether a retry may help (connection
+    /// failures, 429, and 5xx).
+    fn put_object(&self, key: &str, body: &[u8]) -> std::result::Result<(), (bool, String)> {
+        let path = format!("/{}/{}", uri_encode(&self.bucket, false), uri_encode(key, true));
+        let payload_hash = hex(&sha256(body));
+        let now = SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let amz_date = format!(
+            "{}T{:02}{:02}{:02}Z",
+            date_to_string(now).replace('-', ""),
+            now % 86400 / 3600,
+            now % 3600 / 60,
+            now % 60
+        );
+
+        let mut headers = vec![
+            ("host".to_string(), self.host.clone()),
+            ("x-amz-content-sha256".to_string(), payload_hash.clone()),
+            ("x-amz-date".to_string(), amz_date.clone()),
+        ];
+        if let Some(ref token) = self.session_token {
+            headers.push(("x-amz-security-token".to_string(), token.clone()));
+        }
+        let authorization = sigv4_authorization(
+            &SigV4Credentials {
+                access_key: &self.access_key,
+                secret_key: &self.secret_key,
+                region: &self.region,
+                service: "s3",
+            },
+            "PUT",
+            &path,
+            &headers,
+            &payload_hash,
+            &amz_date,
+        );
+
+        let mut request = self
+            .client
+            .put(format!("{}{}", self.endpoint, path))
+            .header("Authorization", authorization)
+            .body(body.to_vec());
+        for (name, value) in headers.iter().filter(|(name, _)| name != "host") {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        match request.send() {
+            Ok(response) if response.status().is_success() => Ok(()),
+            Ok(response) => {
+                let status = response.status();
+                let retryable = status.as_u16() == 429 || status.is_server_error();
+                let body = response.text().unwrap_or_default();
+                Err((retryable, format!("HTTP {}: {}", status, body.trim())))
+            }
+            Err(e) => Err((true, e.to_string())),
+        }
+    }
+}
+
+/// Signing scope for [`sigv4_authorization`].
+struct SigV4Credentials<'a> {
+    access_key: &'a str,
+    secret_key: &'a str,
+    region: &'a str,
+    service: &'a str,
+}
+
+/// `Authorization` header value for an AWS Signature Version 4 request
+/// without a query string. `headers` are the signed headers with lowercase
+/// names; `amz_date` is the request's `x-amz-date` (`YYYYMMDDTHHMMSSZ`).
+fn sigv4_authorization(
+    creds: &SigV4Credentials,
+    method: &str,
+    path: &str,
+    headers: &[(String, String)],
+    payload_hash: &str,
+    amz_date: &str,
+) -> String {
+    let mut headers: Vec<&(String, String)> = headers.iter().collect();
+    headers.sort_by(|a, b| a.0.cmp(&b.0));
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical_request = format!(
+        "{}\n{}\n\n{}\n{}\n{}",
+        method, path, canonical_headers, signed_headers, payload_hash
+    );
+
+    let date = &amz_date[..8];
+    let scope = format!("{}/{}/{}/aws4_request", date, creds.region, creds.service);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex(&sha256(canonical_request.as_bytes()))
+    );
+
+    let key = hmac_sha256(format!("AWS4{}", creds.secret_key).as_bytes(), date.as_bytes());
+    let key = hmac_sha256(&key, creds.region.as_bytes());
+    let key = hmac_sha256(&key, creds.service.as_bytes());
+    let key = hmac_sha256(&key, b"aws4_request");
+    let signature = hex(&hmac_sha256(&key, string_to_sign.as_bytes()));
+
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        creds.access_key, scope, signed_headers, signature
+    )
+}
+
+fn sha256(data: &[u8]) -> Vec<u8> {
+    use sha2::Digest;
+    sha2::Sha256::digest(data).to_vec()
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    use hmac::Mac;
+    let mut mac = hmac::Hmac::<sha2::Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// SigV4 URI encoding: every byte except unreserved characters is
+/// percent-encoded; `/` is kept when `keep_slash` (object key paths).
+fn uri_encode(input: &str, keep_slash: bool) -> String {
+    let mut out = String::with_capacity(input.len());
+    for b in input.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => out.push(b as char),
+            b'/' if keep_slash => out.push('/'),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+// ============================================================================
+// File Input — JSONL records fed back into the live pipeline
+// ============================================================================
+
+const FILE_INPUT_CHANNEL_CAPACITY: usize = 1024;
+
+/// Pacing of a file input: the gaps between the original event timestamps,
+/// or as fast as the writer accepts events.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum InputPace {
+    Original,
+    Fast,
+}
+
+impl InputPace {
+    fn as_str(self) -> &'static str {
+        match self {
+            InputPace::Original => "original",
+            InputPace::Fast => "fast",
+        }
+    }
+}
+
+/// A JSONL file read into the writer as if its events came from the core
+/// dispatch. Accepts raw events (with `kind`) as well as FileProvider output
+/// in any envelope, so captured telemetry files can be fed back verbatim.
+#[derive(Clone, Debug)]
+struct FileInput {
+    path: PathBuf,
+    pace: InputPace,
+}
+
+impl FileInput {
+    /// `fileInput` is either a path or `{ path, pace }`; relative paths
+    /// resolve against the app root.
+    fn from_config(telemetry: &serde_json::Value, root_dir: &str) -> Option<Self> {
+        let section = telemetry.get("fileInput")?;
+        let (path, pace) = match section {
+            serde_json::Value::String(path) => (path.as_str(), None),
+            other => (
+                other.get("path").and_then(|v| v.as_str())?,
+                other.get("pace").and_then(|v| v.as_str()),
+            ),
+        };
+        let pace = match pace {
+            None | Some("fast") => InputPace::Fast,
+            Some("original") => InputPace::Original,
+            Some(other) => {
+                eprintln!("[yeti-telemetry] Unknown fileInput pace '{}', using 'fast'", other);
+                InputPace::Fast
+            }
+        };
+        Some(Self {
+            path: PathBuf::from(root_dir).join(path),
+            pace,
+        })
+    }
+
+    /// Read the file line by line into `tx`. Runs on a blocking thread.
+    fn feed(self, tx: tokio::sync::mpsc::Sender<serde_json::Value>) {
+        use std::io::BufRead;
+
+        let file = match File::open(&self.path) {
+            Ok(f) => f,
+            Err(e) => {
+                eprintln!(
+                    "[telemetry-input] Failed to open {}: {}",
+                    self.path.display(),
+                    e
+                );
+                return;
+            }
+        };
+        eprintln!(
+            "[telemetry-input] Reading {} (pace={})",
+            self.path.display(),
+            self.pace.as_str()
+        );
+
+        let mut fed: u64 = 0;
+        let mut skipped: u64 = 0;
+        let mut last_ms: Option<f64> = None;
+        let mut dictionary = FieldDictionary::default();
+        for line in std::io::BufReader::new(file).lines() {
+            let Ok(line) = line else { break };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let Ok(line) = serde_json::from_str(&line) else {
+                skipped += 1;
+                continue;
+            };
+            let Some(line) = dictionary.resolve(line) else {
+                continue;
+            };
+            let Some(event) = line_to_event(line) else {
+                skipped += 1;
+                continue;
+            };
+
+            if self.pace == InputPace::Original {
+                let ms = event_time_ms(&event);
+                if let (Some(prev), Some(ms)) = (last_ms, ms) {
+                    if ms > prev {
+                        std::thread::sleep(std::time::Duration::from_millis((ms - prev) as u64));
+                    }
+                }
+                last_ms = ms.or(last_ms);
+            }
+
+            if tx.blocking_send(event).is_err() {
+                break;
+            }
+            fed += 1;
+        }
+
+        eprintln!(
+            "[telemetry-input] Finished {} (fed={}, skipped={})",
+            self.path.display(),
+            fed,
+            skipped
+        );
+    }
+}
+
+/// Convert one input line to a writer event. Lines carrying `kind` are raw
+/// events; anything else is treated as a stored record and converted back:
+/// the envelope is unwrapped, "secs.micros" timestamps become epoch ms, and
+/// JSON-string `fields`/`attributes` are parsed. Storage identity (`id`,
+/// `seq`, `writerId`) is dropped so the writer assigns fresh values.
+fn line_to_event(line: serde_json::Value) -> Option<serde_json::Value> {
+    if line.get("kind").is_some() {
+        return Some(line);
+    }
+
+    let (kind, mut record) = unwrap_file_line(line)?;
+    let obj = record.as_object_mut()?;
+    for key in ["id", "seq", "writerId", "durationMs"] {
+        obj.remove(key);
+    }
+    for key in ["timestamp", "startTime", "endTime"] {
+        if let Some(ms) = obj.get(key).and_then(parse_stored_time) {
+            obj.insert(key.to_string(), json!(ms));
+        }
+    }
+    for key in ["fields", "attributes"] {
+        if let Some(parsed) = obj
+            .get(key)
+            .and_then(|v| v.as_str())
+            .and_then(|s| serde_json::from_str::<serde_json::Value>(s).ok())
+        {
+            obj.insert(key.to_string(), parsed);
+        }
+    }
+    obj.insert("kind".to_string(), json!(kind.as_str()));
+    Some(record)
+}
+
+/// The stored record in a FileProvider line, and its kind, for any
+/// envelope. Without an envelope the kind is inferred from the columns.
+fn unwrap_file_line(line: serde_json::Value) -> Option<(RecordKind, serde_json::Value)> {
+    let (kind, record) = match (line.get("type").and_then(|v| v.as_str()), line.get("data")) {
+        (Some(kind), Some(data)) => (kind.to_string(), data.clone()),
+        _ => {
+            let mut record = line;
+            let obj = record.as_object_mut()?;
+            let kind = match obj.remove("_type") {
+                Some(serde_json::Value::String(kind)) => kind,
+                _ if obj.contains_key("startTime") => "span".to_string(),
+                _ if obj.contains_key("value") => "metric".to_string(),
+                _ => "log".to_string(),
+            };
+            (kind, record)
+        }
+    };
+    Some((RecordKind::parse(&kind)?, record))
+}
+
+/// Epoch ms from a stored "secs.micros" string, or a number already in ms.
+fn parse_stored_time(value: &serde_json::Value) -> Option<f64> {
+    match value {
+        serde_json::Value::String(s) => s.parse::<f64>().ok().map(|secs| secs * 1000.0),
+        other => other.as_f64(),
+    }
+}
+
+/// The timestamp used to pace an event: its time, or a span's start.
+fn event_time_ms(event: &serde_json::Value) -> Option<f64> {
+    event_field(event, "timestamp")
+        .or_else(|| event_field(event, "startTime"))
+        .and_then(parse_event_time)
+}
+
+// ============================================================================
+// Replay — file output re-ingested into the tables
+// ============================================================================
+
+/// Lines read ahead of the table writes during a replay.
+const REPLAY_CHANNEL_CAPACITY: usize = 1024;
+
+/// Outcome of [`replay_file`], per kind where it matters.
+#[derive(Default)]
+pub(crate) struct ReplaySummary {
+    /// Records written, or that would be written in a dry run.
+    pub(crate) written: [u64; 3],
+    /// Records already in their table, left untouched.
+    pub(crate) existing: u64,
+    /// Lines that aren't stored records (unparseable, or without an `id`).
+    pub(crate) skipped: u64,
+}
+
+impl ReplaySummary {
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        json!({
+            "written": {
+                "logs": self.written[RecordKind::Log.index()],
+                "spans": self.written[RecordKind::Span.index()],
+                "metrics": self.written[RecordKind::Metric.index()],
+            },
+            "existing": self.existing,
+            "skipped": self.skipped,
+        })
+    }
+}
+
+/// Write the records in a FileProvider file (plain or gzipped, any
+/// envelope) back into the tables and notify PubSub, bypassing the writer
+/// and its outputs so nothing is written to the files again. Each record
+/// keeps its `id`; records whose id is already stored are skipped, so
+/// replaying a file twice writes nothing the second time. A dry run only
+/// counts.
+pub(crate) async fn replay_file(
+    tables: &TableStores,
+    file: File,
+    gzipped: bool,
+    dry_run: bool,
+) -> Result<ReplaySummary> {
+    let indexed_span_fields = indexed_span_fields();
+
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Option<serde_json::Value>>(REPLAY_CHANNEL_CAPACITY);
+    let reader = tokio::task::spawn_blocking(move || {
+        use std::io::BufRead;
+
+        let input: Box<dyn std::io::Read> = if gzipped {
+            Box::new(flate2::read::GzDecoder::new(file))
+        } else {
+            Box::new(file)
+        };
+        let mut dictionary = FieldDictionary::default();
+        for line in std::io::BufReader::new(input).lines() {
+            let Ok(line) = line else { break };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let parsed = match serde_json::from_str(&line) {
+                Ok(line) => match dictionary.resolve(line) {
+                    Some(line) => Some(line),
+                    // `fieldInterning` dictionary entries aren't records.
+                    None => continue,
+                },
+                Err(_) => None,
+            };
+            if tx.blocking_send(parsed).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut summary = ReplaySummary::default();
+    while let Some(line) = rx.recv().await {
+        let Some((kind, record)) = line.and_then(unwrap_file_line) else {
+            summary.skipped += 1;
+            continue;
+        };
+        let Some(id) = record.get("id").and_then(|v| v.as_str()).map(str::to_string) else {
+            summary.skipped += 1;
+            continue;
+        };
+        let storage = match kind {
+            RecordKind::Log => {
+                let level = record.get("level").and_then(|v| v.as_str());
+                tables.log_tables(Some(level.unwrap_or(""))).into_iter().next()
+            }
+            RecordKind::Span => tables.span.clone(),
+            RecordKind::Metric => tables.metric.clone(),
+        };
+        let Some(storage) = storage else {
+            summary.skipped += 1;
+            continue;
+        };
+        if storage.get(id.as_bytes()).await?.is_some() {
+            summary.existing += 1;
+            continue;
+        }
+        summary.written[kind.index()] += 1;
+        if dry_run {
+            continue;
+        }
+
+        storage.put(id.as_bytes(), &to_storage_bytes(&record)?).await?;
+        if let (RecordKind::Span, Some(index)) = (kind, &tables.span_index) {
+            let fields = record
+                .get("fields")
+                .and_then(|v| v.as_str())
+                .and_then(|s| serde_json::from_str::<serde_json::Value>(s).ok());
+            if let Some(fields) = fields {
+                for key in span_index_keys(&indexed_span_fields, &fields, &id) {
+                    index.put(key.as_bytes(), &to_storage_bytes(&span_index_entry(&key, &id))?).await?;
+                }
+            }
+        }
+        if let Some(ref ps) = tables.pubsub {
+            ps.notify_update(&tables.pubsub_tables[kind.index()], &id, &record).await;
+        }
+    }
+    let _ = reader.await;
+    Ok(summary)
+}
+
+// ============================================================================
+// HTTP Request Metrics — shared span parsing and in-process aggregation
+// ============================================================================
+
+/// One HTTP request observation derived from an `http.request` span record.
+struct HttpRequestSample {
+    method: String,
+    route: String,
+    status: String,
+    duration_secs: f64,
+    is_error: bool,
+    /// Values of the requested `fields` keys the span carries, in the order
+    /// asked for.
+    labels: Vec<(String, String)>,
+}
+
+impl HttpRequestSample {
+    /// Extract request dimensions from a span record, plus the `label_fields`
+    /// present in its `fields`. Returns None for spans that are not HTTP
+    /// requests.
+    fn from_span(record: &serde_json::Value, label_fields: &[String]) -> Option<Self> {
+        let target = record.get("target").and_then(|v| v.as_str()).unwrap_or("");
+        if target != "http.request" {
+            return None;
+        }
+
+        let fields_str = record.get("fields").and_then(|v| v.as_str()).unwrap_or("{}");
+        let fields: serde_json::Value =
+            serde_json::from_str(fields_str).unwrap_or(json!({}));
+
+        let field = |key: &str, default: &str| {
+            fields
+                .get(key)
+                .and_then(|v| v.as_str())
+                .unwrap_or(default)
+                .to_string()
+        };
+
+        // Calculate duration from durationMs field (stored in milliseconds)
+        let duration_ms = record
+            .get("durationMs")
+            .and_then(|v| v.as_f64())
+            .unwrap_or(0.0);
+
+        Some(Self {
+            method: field("http.method", "UNKNOWN"),
+            route: field("http.route", "/"),
+            status: field("http.status_code", "0"),
+            duration_secs: duration_ms / 1000.0,
+            is_error: fields.get("status").and_then(|v| v.as_str()) == Some("ERROR"),
+            labels: label_fields
+                .iter()
+                .filter_map(|key| {
+                    let value = match fields.get(key)? {
+                        serde_json::Value::String(s) => s.clone(),
+                        serde_json::Value::Null => return None,
+                        other => other.to_string(),
+                    };
+                    Some((key.clone(), value))
+                })
+                .collect(),
+        })
+    }
+}
+
+/// Upper bounds (seconds) of the request duration histogram buckets.
+const DURATION_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Content type for OpenMetrics text exposition.
+pub(crate) const OPENMETRICS_CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Content type for Prometheus text exposition.
+pub(crate) const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Accumulated values for one (method, route, status) series.
+#[derive(Default)]
+struct HttpSeries {
+    requests: u64,
+    errors: u64,
+    /// Per-bucket (non-cumulative) counts; the final slot is +Inf.
+    buckets: [u64; DURATION_BUCKETS.len() + 1],
+    duration_sum: f64,
+}
+
+/// In-process aggregation of HTTP request metrics, with the same dimensions
+/// OtlpOutput exports. Rendered on demand by the metrics resources.
+#[derive(Default)]
+pub(crate) struct HttpMetrics {
+    series: std::collections::BTreeMap<(String, String, String), HttpSeries>,
+}
+
+impl HttpMetrics {
+    fn record(&mut self, sample: &HttpRequestSample) {
+        let key = (
+            sample.method.clone(),
+            sample.route.clone(),
+            sample.status.clone(),
+        );
+        let series = self.series.entry(key).or_default();
+        series.requests += 1;
+        if sample.is_error {
+            series.errors += 1;
+        }
+        let slot = DURATION_BUCKETS
+            .iter()
+            .position(|le| sample.duration_secs <= *le)
+            .unwrap_or(DURATION_BUCKETS.len());
+        series.buckets[slot] += 1;
+        series.duration_sum += sample.duration_secs;
+    }
+
+    /// Render all series in OpenMetrics text format, terminated by `# EOF`.
+    pub(crate) fn render_openmetrics(&self) -> String {
+        let mut out = self.render(TextFormat::OpenMetrics);
+        out.push_str("# EOF\n");
+        out
+    }
+
+    /// Render all series in the Prometheus text exposition format (0.0.4).
+    pub(crate) fn render_prometheus(&self) -> String {
+        self.render(TextFormat::Prometheus)
+    }
+
+    fn render(&self, format: TextFormat) -> String {
+        let mut out = String::new();
+
+        format.header(&mut out, "http_server_requests", "counter", None, "Total number of HTTP requests.");
+        for (key, series) in &self.series {
+            out.push_str(&format!(
+                "http_server_requests_total{{{}}} {}\n",
+                series_labels(key),
+                series.requests
+            ));
+        }
+
+        format.header(
+            &mut out,
+            "http_server_request_duration_seconds",
+            "histogram",
+            Some("seconds"),
+            "HTTP request duration in seconds.",
+        );
+        for (key, series) in &self.series {
+            let labels = series_labels(key);
+            let mut cumulative = 0;
+            for (i, le) in DURATION_BUCKETS.iter().enumerate() {
+                cumulative += series.buckets[i];
+                out.push_str(&format!(
+                    "http_server_request_duration_seconds_bucket{{{},le=\"{}\"}} {}\n",
+                    labels, le, cumulative
+                ));
+            }
+            out.push_str(&format!(
+                "http_server_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}\n",
+                labels, series.requests
+            ));
+            out.push_str(&format!(
+                "http_server_request_duration_seconds_sum{{{}}} {}\n",
+                labels, series.duration_sum
+            ));
+            out.push_str(&format!(
+                "http_server_request_duration_seconds_count{{{}}} {}\n",
+                labels, series.requests
+            ));
+        }
+
+        format.header(&mut out, "http_server_errors", "counter", None, "Total number of HTTP errors.");
+        for (key, series) in &self.series {
+            out.push_str(&format!(
+                "http_server_errors_total{{{}}} {}\n",
+                series_labels(key),
+                series.errors
+            ));
+        }
+
+        out
+    }
+}
+
+/// Text exposition formats HttpMetrics can render. Samples are identical;
+/// only the metadata lines differ.
+#[derive(Clone, Copy)]
+enum TextFormat {
+    OpenMetrics,
+    Prometheus,
+}
+
+impl TextFormat {
+    /// Emit the metadata lines for one metric family. OpenMetrics names
+    /// counter families without `_total` and supports `# UNIT`; Prometheus
+    /// names the family after its sample.
+    fn header(self, out: &mut String, family: &str, kind: &str, unit: Option<&str>, help: &str) {
+        match self {
+            Self::OpenMetrics => {
+                out.push_str(&format!("# TYPE {} {}\n", family, kind));
+                if let Some(unit) = unit {
+                    out.push_str(&format!("# UNIT {} {}\n", family, unit));
+                }
+                out.push_str(&format!("# HELP {} {}\n", family, help));
+            }
+            Self::Prometheus => {
+                let name = if kind == "counter" {
+                    format!("{}_total", family)
+                } else {
+                    family.to_string()
+                };
+                out.push_str(&format!("# HELP {} {}\n", name, help));
+                out.push_str(&format!("# TYPE {} {}\n", name, kind));
+            }
+        }
+    }
+}
+
+fn series_labels((method, route, status): &(String, String, String)) -> String {
+    format!(
+        "method=\"{}\",route=\"{}\",status_code=\"{}\"",
+        escape_label_value(method),
+        escape_label_value(route),
+        escape_label_value(status)
+    )
+}
+
+/// Escape a label value per the OpenMetrics/Prometheus text format.
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Output that feeds `http.request` spans into the shared HttpMetrics registry.
+struct MetricsAggregator {
+    metrics: Arc<Mutex<HttpMetrics>>,
+}
+
+impl TelemetryOutput for MetricsAggregator {
+    fn name(&self) -> &str {
+        "aggregator"
+    }
+
+    fn write_log(&mut self, _record: &serde_json::Value) {}
+
+    fn write_span(&mut self, record: &serde_json::Value) {
+        if let Some(sample) = HttpRequestSample::from_span(record, &[]) {
+            lock(&self.metrics).record(&sample);
+        }
+    }
+}
+
+// ============================================================================
+// Ring Buffer — most recent records, in memory
+// ============================================================================
+
+/// Records kept for the `recent` endpoint when `recentCapacity` is not set.
+const DEFAULT_RECENT_CAPACITY: usize = 1000;
+
+/// Fixed-capacity window over the latest records of every kind, oldest
+/// evicted first.
+pub(crate) struct RecentRecords {
+    capacity: usize,
+    records: VecDeque<(RecordKind, serde_json::Value)>,
+}
+
+impl RecentRecords {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    fn push(&mut self, kind: RecordKind, record: &serde_json::Value) {
+        if self.records.len() >= self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back((kind, record.clone()));
+    }
+
+    /// Up to `limit` records, newest first, optionally of one kind
+    /// (`log`, `span`, or `metric`).
+    pub(crate) fn latest(&self, kind: Option<&str>, limit: usize) -> Vec<serde_json::Value> {
+        self.records
+            .iter()
+            .rev()
+            .filter(|(k, _)| kind.is_none_or(|kind| k.as_str() == kind))
+            .take(limit)
+            .map(|(k, record)| json!({ "kind": k.as_str(), "record": record }))
+            .collect()
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+/// Output that keeps the latest records in memory, so debugging doesn't
+/// require a table scan.
+struct RingBufferOutput {
+    records: Arc<Mutex<RecentRecords>>,
+}
+
+impl TelemetryOutput for RingBufferOutput {
+    fn name(&self) -> &str {
+        "recent"
+    }
+
+    fn write_log(&mut self, record: &serde_json::Value) {
+        lock(&self.records).push(RecordKind::Log, record);
+    }
+
+    fn write_span(&mut self, record: &serde_json::Value) {
+        lock(&self.records).push(RecordKind::Span, record);
+    }
+
+    fn write_metric(&mut self, record: &serde_json::Value) {
+        lock(&self.records).push(RecordKind::Metric, record);
+    }
+}
+
+// ============================================================================
+// Event Stats — rolling level and target counts
+// ============================================================================
+
+/// Window of the `stats` endpoint when `statsWindowSecs` is not set.
+const DEFAULT_STATS_WINDOW_SECS: u64 = 300;
+
+/// Slots per window; counts expire one slot at a time.
+const STATS_SLOTS: u64 = 60;
+
+/// Distinct targets counted per slot; the rest are counted as `(other)`.
+const STATS_MAX_TARGETS: usize = 1000;
+
+/// Log and span counts by kind, level and target over the last
+/// `window_secs`, kept as per-slot tallies so old events age out without
+/// storing them.
+pub(crate) struct EventStats {
+    window_secs: u64,
+    slot_secs: u64,
+    /// Oldest first.
+    slots: VecDeque<StatsSlot>,
+}
+
+#[derive(Default)]
+struct StatsSlot {
+    start_secs: u64,
+    kinds: HashMap<&'static str, u64>,
+    levels: HashMap<&'static str, u64>,
+    targets: HashMap<String, u64>,
+}
+
+impl EventStats {
+    fn new(window_secs: u64) -> Self {
+        Self {
+            window_secs,
+            slot_secs: (window_secs / STATS_SLOTS).max(1),
+            slots: VecDeque::new(),
+        }
+    }
+
+    fn record(&mut self, now_secs: u64, kind: RecordKind, level: &'static str, target: &str) {
+        let start_secs = now_secs - now_secs % self.slot_secs;
+        if self.slots.back().is_none_or(|slot| slot.start_secs != start_secs) {
+            self.expire(now_secs);
+            self.slots.push_back(StatsSlot { start_secs, ..StatsSlot::default() });
+        }
+        let Some(slot) = self.slots.back_mut() else { return };
+        *slot.kinds.entry(kind.as_str()).or_default() += 1;
+        *slot.levels.entry(level).or_default() += 1;
+        let target = if slot.targets.len() < STATS_MAX_TARGETS || slot.targets.contains_key(target) {
+            target
+        } else {
+            "(other)"
+        };
+        *slot.targets.entry(target.to_string()).or_default() += 1;
+    }
+
+    /// Drop slots that ended before the window.
+    fn expire(&mut self, now_secs: u64) {
+        let cutoff = now_secs.saturating_sub(self.window_secs);
+        while self.slots.front().is_some_and(|slot| slot.start_secs + self.slot_secs <= cutoff) {
+            self.slots.pop_front();
+        }
+    }
+
+    /// Totals over the window, with the `top` busiest targets.
+    pub(crate) fn snapshot(&mut self, now_secs: u64, top: usize) -> serde_json::Value {
+        self.expire(now_secs);
+        let mut kinds: HashMap<&str, u64> = HashMap::new();
+        let mut levels: HashMap<&str, u64> = HashMap::new();
+        let mut targets: HashMap<&str, u64> = HashMap::new();
+        for slot in &self.slots {
+            for (kind, n) in &slot.kinds {
+                *kinds.entry(kind).or_default() += n;
+            }
+            for (level, n) in &slot.levels {
+                *levels.entry(level).or_default() += n;
+            }
+            for (target, n) in &slot.targets {
+                *targets.entry(target).or_default() += n;
+            }
+        }
+        let mut targets: Vec<(&str, u64)> = targets.into_iter().collect();
+        targets.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        let level_counts: serde_json::Map<String, serde_json::Value> = ["TRACE", "DEBUG", "INFO", "WARN", "ERROR"]
+            .iter()
+            .map(|level| (level.to_string(), json!(levels.get(level).copied().unwrap_or(0))))
+            .collect();
+        json!({
+            "windowSecs": self.window_secs,
+            "since": self.slots.front().map(|slot| slot.start_secs),
+            "total": kinds.values().sum::<u64>(),
+            "byKind": {
+                "log": kinds.get("log").copied().unwrap_or(0),
+                "span": kinds.get("span").copied().unwrap_or(0),
+            },
+            "byLevel": level_counts,
+            "distinctTargets": targets.len(),
+            "topTargets": targets
+                .iter()
+                .take(top)
+                .map(|(target, count)| json!({ "target": target, "count": count }))
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// Pull-based alternative to OTLP: keeps its own HTTP metrics registry,
+/// rendered in Prometheus text format by the `prometheus` resource.
+struct PrometheusProvider {
+    metrics: Arc<Mutex<HttpMetrics>>,
+}
+
+impl TelemetryOutput for PrometheusProvider {
+    fn name(&self) -> &str {
+        "prometheus"
+    }
+
+    fn write_log(&mut self, _record: &serde_json::Value) {}
+
+    fn write_span(&mut self, record: &serde_json::Value) {
+        if let Some(sample) = HttpRequestSample::from_span(record, &[]) {
+            lock(&self.metrics).record(&sample);
+        }
+    }
+}
+
+// ============================================================================
+// Batch Delivery — background threads for HTTP push outputs
+// ============================================================================
+
+/// Records queued per push output. When the queue is full the record is
+/// dropped rather than stalling the writer.
+const PUSH_QUEUE_CAPACITY: usize = 10_000;
+
+/// Timeout for a single push request.
+const PUSH_REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Work queued for a BatchThread.
+enum BatchCommand {
+    Record(serde_json::Value),
+    /// Deliver everything queued so far, then acknowledge.
+    Flush(std::sync::mpsc::SyncSender<()>),
+}
+
+/// Thread that delivers queued records in batches: once `batch_size` records
+/// are waiting, or `interval` after the last delivery, whichever comes first.
+/// `deliver` reports whether the batch got through, which drives `health`.
+/// Dropping it delivers whatever is queued and joins the thread.
+struct BatchThread {
+    name: String,
+    sender: Option<std::sync::mpsc::SyncSender<BatchCommand>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+    health: Arc<HealthTracker>,
+}
+
+impl BatchThread {
+    fn spawn(
+        name: &str,
+        batch_size: usize,
+        interval: std::time::Duration,
+        mut deliver: impl FnMut(Vec<serde_json::Value>) -> std::result::Result<(), String> + Send + 'static,
+    ) -> Option<Self> {
+        use std::sync::mpsc::RecvTimeoutError;
+
+        let (sender, receiver) = std::sync::mpsc::sync_channel(PUSH_QUEUE_CAPACITY);
+        let health = Arc::new(HealthTracker::default());
+        let tracker = Arc::clone(&health);
+        let thread = std::thread::Builder::new()
+            .name(format!("telemetry-{}", name))
+            .spawn(move || {
+                let mut batch = Vec::with_capacity(batch_size);
+                let mut deadline = std::time::Instant::now() + interval;
+                loop {
+                    let wait = deadline.saturating_duration_since(std::time::Instant::now());
+                    let mut ack = None;
+                    let closed = match receiver.recv_timeout(wait) {
+                        Ok(BatchCommand::Record(record)) => {
+                            batch.push(record);
+                            if batch.len() < batch_size {
+                                continue;
+                            }
+                            false
+                        }
+                        Ok(BatchCommand::Flush(sender)) => {
+                            ack = Some(sender);
+                            false
+                        }
+                        Err(RecvTimeoutError::Timeout) => false,
+                        Err(RecvTimeoutError::Disconnected) => true,
+                    };
+                    if !batch.is_empty() {
+                        match deliver(std::mem::replace(&mut batch, Vec::with_capacity(batch_size))) {
+                            Ok(()) => tracker.succeeded(),
+                            Err(e) => tracker.failed(&e),
+                        }
+                    }
+                    if let Some(ack) = ack {
+                        let _ = ack.send(());
+                    }
+                    if closed {
+                        break;
+                    }
+                    deadline = std::time::Instant::now() + interval;
+                }
+            });
+        match thread {
+            Ok(thread) => Some(Self {
+                name: name.to_string(),
+                sender: Some(sender),
+                thread: Some(thread),
+                health,
+            }),
+            Err(e) => {
+                eprintln!("[yeti-telemetry] Failed to start {} thread: {}", name, e);
+                None
+            }
+        }
+    }
+
+    /// Queue a record; `false` if the queue is full or the thread is gone.
+    fn send(&self, record: &serde_json::Value) -> bool {
+        self.sender
+            .as_ref()
+            .is_some_and(|s| s.try_send(BatchCommand::Record(record.clone())).is_ok())
+    }
+
+    /// Deliver everything queued so far and wait for it.
+    fn flush(&self) {
+        let Some(ref sender) = self.sender else { return };
+        let (ack, done) = std::sync::mpsc::sync_channel(1);
+        if sender.send(BatchCommand::Flush(ack)).is_ok() {
+            let _ = done.recv();
+        }
+    }
+}
+
+impl Drop for BatchThread {
+    fn drop(&mut self) {
+        self.sender = None;
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                eprintln!("[yeti-telemetry] {} thread panicked", self.name);
+            }
+        }
+    }
+}
+
+/// RFC 3339 UTC timestamp with millisecond precision.
+fn format_rfc3339(secs: f64) -> String {
+    let ms = (secs.max(0.0) * 1000.0).round() as u64;
+    let (whole, frac) = (ms / 1000, ms % 1000);
+    let time = whole % 86400;
+    format!(
+        "{}T{:02}:{:02}:{:02}.{:03}Z",
+        date_to_string(whole),
+        time / 3600,
+        time % 3600 / 60,
+        time % 60,
+        frac
+    )
+}
+
+// ============================================================================
+// Elasticsearch Provider — log records indexed via the bulk API
+// ============================================================================
+
+const DEFAULT_ELASTICSEARCH_INDEX: &str = "yeti-logs";
+const DEFAULT_ELASTICSEARCH_BATCH_SIZE: usize = 500;
+const DEFAULT_ELASTICSEARCH_FLUSH_INTERVAL_MS: u64 = 5000;
+
+/// The `telemetry.elasticsearch` section.
+struct ElasticsearchConfig {
+    /// Cluster base URL; `/_bulk` is appended.
+    url: String,
+    /// Index name prefix; records go to `<index>-YYYY.MM.DD` by record date.
+    index: String,
+    batch_size: usize,
+    flush_interval: std::time::Duration,
+    /// Basic-auth credentials, with `${VAR}` interpolated.
+    username: Option<String>,
+    password: Option<String>,
+    /// Gzip bulk request bodies.
+    gzip: bool,
+}
+
+impl ElasticsearchConfig {
+    fn from_config(telemetry: &serde_json::Value) -> Option<Self> {
+        let section = telemetry.get("elasticsearch")?;
+        let url = section
+            .get("url")
+            .and_then(|v| v.as_str())
+            .filter(|s| !s.is_empty())?
+            .trim_end_matches('/')
+            .to_string();
+        let credential = |key: &str| -> std::result::Result<Option<String>, String> {
+            section
+                .get(key)
+                .and_then(|v| v.as_str())
+                .map(interpolate_env)
+                .transpose()
+        };
+        let (username, password) = match (credential("username"), credential("password")) {
+            (Ok(username), Ok(password)) => (username, password),
+            (Err(var), _) | (_, Err(var)) => {
+                eprintln!(
+                    "[yeti-telemetry] Elasticsearch output disabled: environment variable {} is not set",
+                    var
+                );
+                return None;
+            }
+        };
+        Some(Self {
+            url,
+            index: section
+                .get("index")
+                .and_then(|v| v.as_str())
+                .filter(|s| !s.is_empty())
+                .unwrap_or(DEFAULT_ELASTICSEARCH_INDEX)
+                .to_string(),
+            batch_size: section
+                .get("batchSize")
+                .and_then(|v| v.as_u64())
+                .filter(|n| *n > 0)
+                .map(|n| n as usize)
+                .unwrap_or(DEFAULT_ELASTICSEARCH_BATCH_SIZE),
+            flush_interval: std::time::Duration::from_millis(
+                section
+                    .get("flushIntervalMs")
+                    .and_then(|v| v.as_u64())
+                    .filter(|n| *n > 0)
+                    .unwrap_or(DEFAULT_ELASTICSEARCH_FLUSH_INTERVAL_MS),
+            ),
+            username,
+            password,
+            gzip: section.get("gzip").and_then(|v| v.as_bool()).unwrap_or(true),
+        })
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "url": self.url,
+            "index": self.index,
+            "batchSize": self.batch_size,
+            "flushIntervalMs": self.flush_interval.as_millis() as u64,
+            "username": self.username,
+            "password": self.password.as_ref().map(|_| "***"),
+            "gzip": self.gzip,
+        })
+    }
+
+    /// Daily index for a record, e.g. `yeti-logs-2024.06.01`.
+    fn index_for(&self, record: &serde_json::Value) -> String {
+        let secs = record_time_secs(record).unwrap_or_else(|| now_epoch_ms() / 1000.0);
+        format!("{}-{}", self.index, date_to_string(secs.max(0.0) as u64).replace('-', "."))
+    }
+}
+
+/// Output that indexes log records into Elasticsearch or OpenSearch with the
+/// `_bulk` API from a background thread. A failed request is retried once;
+/// after that its records are dropped and counted.
+struct ElasticsearchProvider {
+    batches: BatchThread,
+}
+
+impl ElasticsearchProvider {
+    fn new(config: ElasticsearchConfig) -> Option<Self> {
+        let client = match reqwest::blocking::Client::builder()
+            .timeout(PUSH_REQUEST_TIMEOUT)
+            .build()
+        {
+            Ok(c) => c,
+            Err(e) => {
+                eprintln!("[elasticsearch] Failed to create HTTP client: {}", e);
+                return None;
+            }
+        };
+        let (batch_size, interval) = (config.batch_size, config.flush_interval);
+        let batches = BatchThread::spawn("elasticsearch", batch_size, interval, move |records| {
+            bulk_index(&client, &config, records)
+        })?;
+        Some(Self { batches })
+    }
+}
+
+/// Send one `_bulk` request, retrying once on a transport error or error
+/// status. Per-document rejections in a successful response are not retried,
+/// and don't count as a failed delivery.
+/// The request body an HTTP output sends: gzipped when `gzip` is set, with
+/// the `Content-Encoding` to send alongside. Falls back to the plain body if
+/// compression fails.
+fn http_output_body(body: Vec<u8>, gzip: bool) -> (Vec<u8>, Option<&'static str>) {
+    if !gzip {
+        return (body, None);
+    }
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    match encoder.write_all(&body).and_then(|_| encoder.finish()) {
+        Ok(compressed) => (compressed, Some("gzip")),
+        Err(_) => (body, None),
+    }
+}
+
+fn bulk_index(
+    client: &reqwest::blocking::Client,
+    config: &ElasticsearchConfig,
+    records: Vec<serde_json::Value>,
+) -> std::result::Result<(), String> {
+    let mut body = String::new();
+    for record in &records {
+        let mut doc = record.clone();
+        // The stored `fields` string becomes an object the cluster can map.
+        if let Some(fields) = doc.get("fields").and_then(|v| v.as_str()) {
+            if let Ok(parsed) = serde_json::from_str::<serde_json::Value>(fields) {
+                doc["fields"] = parsed;
+            }
+        }
+        if let Some(secs) = record_time_secs(record) {
+            doc["@timestamp"] = json!(format_rfc3339(secs));
+        }
+        body.push_str(&json!({ "index": { "_index": config.index_for(record) } }).to_string());
+        body.push('\n');
+        body.push_str(&doc.to_string());
+        body.push('\n');
+    }
+
+    let (body, encoding) = http_output_body(body.into_bytes(), config.gzip);
+    let send = || -> std::result::Result<serde_json::Value, String> {
+        let mut request = client
+            .post(format!("{}/_bulk", config.url))
+            .header("Content-Type", "application/x-ndjson")
+            .body(body.clone());
+        if let Some(encoding) = encoding {
+            request = request.header("Content-Encoding", encoding);
+        }
+        if let Some(ref username) = config.username {
+            request = request.basic_auth(username, config.password.as_ref());
+        }
+        let response = request.send().map_err(|e| e.to_string())?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(format!("HTTP {}", status));
+        }
+        // The documents were accepted; an unreadable body only loses the
+        // per-item report, so it must not trigger a resend.
+        Ok(response
+            .text()
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default())
+    };
+
+    match send().or_else(|_| send()) {
+        Ok(result) => {
+            if result.get("errors").and_then(|v| v.as_bool()) != Some(true) {
+                return Ok(());
+            }
+            let failed: Vec<&serde_json::Value> = result
+                .get("items")
+                .and_then(|v| v.as_array())
+                .map(|items| {
+                    items
+                        .iter()
+                        .filter_map(|item| item.get("index"))
+                        .filter(|r| r.get("error").is_some())
+                        .collect()
+                })
+                .unwrap_or_default();
+            shared()
+                .pipeline
+                .elasticsearch_dropped
+                .fetch_add(failed.len() as u64, Ordering::Relaxed);
+            if let Some(first) = failed.first() {
+                eprintln!(
+                    "[elasticsearch] {} of {} records rejected, e.g. {}",
+                    failed.len(),
+                    records.len(),
+                    first["error"]
+                );
+            }
+            Ok(())
+        }
+        Err(e) => {
+            shared()
+                .pipeline
+                .elasticsearch_dropped
+                .fetch_add(records.len() as u64, Ordering::Relaxed);
+            eprintln!("[elasticsearch] Bulk request failed after retry, dropped {} records: {}", records.len(), e);
+            Err(e)
+        }
+    }
+}
+
+impl TelemetryOutput for ElasticsearchProvider {
+    fn name(&self) -> &str {
+        "elasticsearch"
+    }
+
+    fn write_log(&mut self, record: &serde_json::Value) {
+        if !self.batches.send(record) {
+            shared().pipeline.elasticsearch_dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn write_span(&mut self, _record: &serde_json::Value) {}
+
+    fn flush(&mut self) {
+        self.batches.flush();
+    }
+
+    fn health(&self) -> OutputHealth {
+        self.batches.health.health()
+    }
+}
+
+// ============================================================================
+// Loki Provider — log records pushed to Grafana Loki
+// ============================================================================
+
+const DEFAULT_LOKI_BATCH_SIZE: usize = 1000;
+const DEFAULT_LOKI_FLUSH_INTERVAL_MS: u64 = 2000;
+
+/// Attempts per push while Loki answers 429 or a server error.
+const LOKI_MAX_ATTEMPTS: u32 = 5;
+
+/// Backoff after the first 429 without a `Retry-After`; doubles per attempt.
+const LOKI_INITIAL_BACKOFF: std::time::Duration = std::time::Duration::from_secs(1);
+const LOKI_MAX_BACKOFF: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// The `telemetry.loki` section.
+struct LokiConfig {
+    /// Push URL; `/loki/api/v1/push` is appended to a bare base URL.
+    url: String,
+    /// `X-Scope-OrgID` for multi-tenant Loki, with `${VAR}` interpolated.
+    tenant: Option<String>,
+    batch_size: usize,
+    flush_interval: std::time::Duration,
+    /// Gzip push request bodies.
+    gzip: bool,
+}
+
+impl LokiConfig {
+    fn from_config(telemetry: &serde_json::Value) -> Option<Self> {
+        let section = telemetry.get("loki")?;
+        let url = section
+            .get("url")
+            .and_then(|v| v.as_str())
+            .filter(|s| !s.is_empty())?
+            .trim_end_matches('/');
+        let url = if url.ends_with("/loki/api/v1/push") {
+            url.to_string()
+        } else {
+            format!("{}/loki/api/v1/push", url)
+        };
+        let tenant = match section.get("tenant").and_then(|v| v.as_str()).map(interpolate_env) {
+            None => None,
+            Some(Ok(tenant)) => Some(tenant),
+            Some(Err(var)) => {
+                eprintln!(
+                    "[yeti-telemetry] Loki output disabled: environment variable {} is not set",
+                    var
+                );
+                return None;
+            }
+        };
+        Some(Self {
+            url,
+            tenant,
+            batch_size: section
+                .get("batchSize")
+                .and_then(|v| v.as_u64())
+                .filter(|n| *n > 0)
+                .map(|n| n as usize)
+                .unwrap_or(DEFAULT_LOKI_BATCH_SIZE),
+            flush_interval: std::time::Duration::from_millis(
+                section
+                    .get("flushIntervalMs")
+                    .and_then(|v| v.as_u64())
+                    .filter(|n| *n > 0)
+                    .unwrap_or(DEFAULT_LOKI_FLUSH_INTERVAL_MS),
+            ),
+            gzip: section.get("gzip").and_then(|v| v.as_bool()).unwrap_or(true),
+        })
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "url": self.url,
+            "tenant": self.tenant,
+            "batchSize": self.batch_size,
+            "flushIntervalMs": self.flush_interval.as_millis() as u64,
+            "gzip": self.gzip,
+        })
+    }
+}
+
+/// Output that pushes log records to Loki from a background thread, one
+/// stream per `level`/`target` pair. Spans and metrics are ignored.
+struct LokiProvider {
+    batches: BatchThread,
+}
+
+impl LokiProvider {
+    fn new(config: LokiConfig) -> Option<Self> {
+        let client = match reqwest::blocking::Client::builder()
+            .timeout(PUSH_REQUEST_TIMEOUT)
+            .build()
+        {
+            Ok(c) => c,
+            Err(e) => {
+                eprintln!("[loki] Failed to create HTTP client: {}", e);
+                return None;
+            }
+        };
+        let (batch_size, interval) = (config.batch_size, config.flush_interval);
+        let batches = BatchThread::spawn("loki", batch_size, interval, move |records| {
+            loki_push(&client, &config, records)
+        })?;
+        Some(Self { batches })
+    }
+}
+
+/// Record timestamp ("secs.micros") as Unix nanoseconds, parsed from the
+/// digits to avoid float rounding.
+fn record_unix_nanos(record: &serde_json::Value) -> Option<u128> {
+    let ts = record.get("timestamp")?.as_str()?;
+    let (secs, frac) = ts.split_once('.').unwrap_or((ts, ""));
+    let secs: u64 = secs.parse().ok()?;
+    let frac = format!("{:0<9}", frac.get(..frac.len().min(9))?);
+    let nanos: u64 = frac.parse().ok()?;
+    Some(u128::from(secs) * 1_000_000_000 + u128::from(nanos))
+}
+
+/// Loki push payload: records grouped into streams by level and target,
+/// each stream's entries in time order. The line is the record as JSON, so
+/// LogQL's `| json` exposes every column.
+fn loki_payload(records: &[serde_json::Value]) -> serde_json::Value {
+    let mut streams: HashMap<(String, String), Vec<(u128, String)>> = HashMap::new();
+    for record in records {
+        let str_field = |key: &str| record.get(key).and_then(|v| v.as_str()).unwrap_or("").to_string();
+        let ts = record_unix_nanos(record).unwrap_or((now_epoch_ms() * 1_000_000.0) as u128);
+        streams
+            .entry((str_field("level"), str_field("target")))
+            .or_default()
+            .push((ts, record.to_string()));
+    }
+    let streams: Vec<serde_json::Value> = streams
+        .into_iter()
+        .map(|((level, target), mut values)| {
+            values.sort_by_key(|(ts, _)| *ts);
+            json!({
+                "stream": { "level": level, "target": target },
+                // Loki takes nanosecond timestamps as decimal strings.
+                "values": values
+                    .into_iter()
+                    .map(|(ts, line)| json!([ts.to_string(), line]))
+                    .collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+    json!({ "streams": streams })
+}
+
+/// Push one batch. 429 and server errors are retried with backoff (honoring
+/// `Retry-After` seconds); other failures drop the batch. Dropped records
+/// are counted.
+fn loki_push(
+    client: &reqwest::blocking::Client,
+    config: &LokiConfig,
+    records: Vec<serde_json::Value>,
+) -> std::result::Result<(), String> {
+    let (body, encoding) = http_output_body(loki_payload(&records).to_string().into_bytes(), config.gzip);
+    let mut backoff = LOKI_INITIAL_BACKOFF;
+    let mut attempt = 1;
+    let error = loop {
+        let mut request = client
+            .post(&config.url)
+            .header("Content-Type", "application/json")
+            .body(body.clone());
+        if let Some(encoding) = encoding {
+            request = request.header("Content-Encoding", encoding);
+        }
+        if let Some(ref tenant) = config.tenant {
+            request = request.header("X-Scope-OrgID", tenant);
+        }
+        let (retryable, error, retry_after) = match request.send() {
+            Ok(response) if response.status().is_success() => return Ok(()),
+            Ok(response) => {
+                let status = response.status();
+                let retry_after = response
+                    .headers()
+                    .get("Retry-After")
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.trim().parse::<u64>().ok())
+                    .map(std::time::Duration::from_secs);
+                let body = response.text().unwrap_or_default();
+                let retryable = status.as_u16() == 429 || status.is_server_error();
+                (retryable, format!("HTTP {}: {}", status, body.trim()), retry_after)
+            }
+            Err(e) => (true, e.to_string(), None),
+        };
+        if !retryable || attempt >= LOKI_MAX_ATTEMPTS {
+            break error;
+        }
+        std::thread::sleep(retry_after.unwrap_or(backoff).min(LOKI_MAX_BACKOFF));
+        backoff = (backoff * 2).min(LOKI_MAX_BACKOFF);
+        attempt += 1;
+    };
+    shared()
+        .pipeline
+        .loki_dropped
+        .fetch_add(records.len() as u64, Ordering::Relaxed);
+    eprintln!(
+        "[loki] Push failed after {} attempt(s), dropped {} records: {}",
+        attempt,
+        records.len(),
+        error
+    );
+    Err(error)
+}
+
+impl TelemetryOutput for LokiProvider {
+    fn name(&self) -> &str {
+        "loki"
+    }
+
+    fn write_log(&mut self, record: &serde_json::Value) {
+        if !self.batches.send(record) {
+            shared().pipeline.loki_dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn write_span(&mut self, _record: &serde_json::Value) {}
+
+    fn flush(&mut self) {
+        self.batches.flush();
+    }
+
+    fn health(&self) -> OutputHealth {
+        self.batches.health.health()
+    }
+}
+
+// ============================================================================
+// Kafka Provider — records published to Kafka topics
+// ============================================================================
+
+const DEFAULT_KAFKA_LINGER_MS: u64 = 100;
+const DEFAULT_KAFKA_BATCH_SIZE: u64 = 10_000;
+const DEFAULT_KAFKA_BUFFER_SIZE: u64 = 100_000;
+const DEFAULT_KAFKA_MESSAGE_TIMEOUT_MS: u64 = 300_000;
+
+/// Longest a flush waits for buffered messages to be delivered.
+const KAFKA_FLUSH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// SASL credentials for the `telemetry.kafka` section.
+struct KafkaSasl {
+    /// `PLAIN`, `SCRAM-SHA-256` or `SCRAM-SHA-512`.
+    mechanism: String,
+    username: String,
+    password: String,
+}
+
+/// The `telemetry.kafka` section.
+struct KafkaConfig {
+    /// Comma-separated `host:port` bootstrap list.
+    brokers: String,
+    /// Topic per record kind, indexed by `RecordKind::index`; kinds without
+    /// one aren't published.
+    topics: [Option<String>; 3],
+    /// How long the producer waits to fill a batch.
+    linger_ms: u64,
+    /// Messages per batch sent to a partition.
+    batch_size: u64,
+    /// Messages buffered in memory while brokers are slow or down.
+    buffer_size: u64,
+    /// How long a buffered message is retried before it is dropped.
+    message_timeout_ms: u64,
+    security_protocol: Option<String>,
+    sasl: Option<KafkaSasl>,
+}
+
+impl KafkaConfig {
+    fn from_config(telemetry: &serde_json::Value) -> Option<Self> {
+        let section = telemetry.get("kafka")?;
+        let disabled = |why: String| {
+            eprintln!("[yeti-telemetry] Kafka output disabled: {}", why);
+            None
+        };
+
+        let brokers = match section.get("brokers") {
+            Some(serde_json::Value::String(b)) => b.clone(),
+            Some(serde_json::Value::Array(items)) => items
+                .iter()
+                .filter_map(|v| v.as_str())
+                .collect::<Vec<_>>()
+                .join(","),
+            _ => String::new(),
+        };
+        if brokers.is_empty() {
+            return disabled("kafka.brokers is required".to_string());
+        }
+
+        let topic = section.get("topic").and_then(|v| v.as_str()).filter(|t| !t.is_empty());
+        let topics = RecordKind::ALL.map(|kind| {
+            section
+                .get("topics")
+                .and_then(|t| t.get(kind.as_str()))
+                .and_then(|v| v.as_str())
+                .filter(|t| !t.is_empty())
+                .or(topic)
+                .map(str::to_string)
+        });
+        if topics.iter().all(Option::is_none) {
+            return disabled("kafka.topic or kafka.topics is required".to_string());
+        }
+
+        let sasl = match section.get("sasl") {
+            None => None,
+            Some(sasl) => {
+                let field = |key: &str| {
+                    let value = sasl.get(key).and_then(|v| v.as_str()).unwrap_or("");
+                    interpolate_env(value)
+                        .map_err(|var| format!("environment variable {} is not set", var))
+                };
+                let (username, password) = match (field("username"), field("password")) {
+                    (Ok(u), Ok(p)) => (u, p),
+                    (Err(e), _) | (_, Err(e)) => return disabled(e),
+                };
+                Some(KafkaSasl {
+                    mechanism: sasl
+                        .get("mechanism")
+                        .and_then(|v| v.as_str())
+                        .unwrap_or("PLAIN")
+                        .to_ascii_uppercase(),
+                    username,
+                    password,
+                })
+            }
+        };
+
+        let number = |key: &str, default: u64| {
+            section
+                .get(key)
+                .and_then(|v| v.as_u64())
+                .filter(|n| *n > 0)
+                .unwrap_or(default)
+        };
+        Some(Self {
+            brokers,
+            topics,
+            linger_ms: section
+                .get("lingerMs")
+                .and_then(|v| v.as_u64())
+                .unwrap_or(DEFAULT_KAFKA_LINGER_MS),
+            batch_size: number("batchSize", DEFAULT_KAFKA_BATCH_SIZE),
+            buffer_size: number("bufferSize", DEFAULT_KAFKA_BUFFER_SIZE),
+            message_timeout_ms: number("messageTimeoutMs", DEFAULT_KAFKA_MESSAGE_TIMEOUT_MS),
+            security_protocol: section
+                .get("securityProtocol")
+                .and_then(|v| v.as_str())
+                .map(str::to_string),
+            sasl,
+        })
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "brokers": self.brokers,
+            "topics": {
+                "log": self.topics[RecordKind::Log.index()],
+                "span": self.topics[RecordKind::Span.index()],
+                "metric": self.topics[RecordKind::Metric.index()],
+            },
+            "lingerMs": self.linger_ms,
+            "batchSize": self.batch_size,
+            "bufferSize": self.buffer_size,
+            "messageTimeoutMs": self.message_timeout_ms,
+            "securityProtocol": self.protocol(),
+            "sasl": self.sasl.as_ref().map(|s| json!({
+                "mechanism": s.mechanism,
+                "username": s.username,
+                "password": "***",
+            })),
+        })
+    }
+
+    /// `security.protocol`: as configured, else `SASL_SSL` with credentials
+    /// and `PLAINTEXT` without.
+    fn protocol(&self) -> &str {
+        match (&self.security_protocol, &self.sasl) {
+            (Some(p), _) => p,
+            (None, Some(_)) => "SASL_SSL",
+            (None, None) => "PLAINTEXT",
+        }
+    }
+
+    fn client_config(&self) -> rdkafka::ClientConfig {
+        let mut config = rdkafka::ClientConfig::new();
+        config
+            .set("bootstrap.servers", &self.brokers)
+            .set("linger.ms", self.linger_ms.to_string())
+            .set("batch.num.messages", self.batch_size.to_string())
+            .set("queue.buffering.max.messages", self.buffer_size.to_string())
+            .set("message.timeout.ms", self.message_timeout_ms.to_string())
+            .set("security.protocol", self.protocol());
+        if let Some(ref sasl) = self.sasl {
+            config
+                .set("sasl.mechanism", &sasl.mechanism)
+                .set("sasl.username", &sasl.username)
+                .set("sasl.password", &sasl.password);
+        }
+        config
+    }
+}
+
+/// Counts messages the producer gave up on after `messageTimeoutMs`, and
+/// tracks delivery health.
+struct KafkaDeliveryContext {
+    health: Arc<HealthTracker>,
+}
+
+impl rdkafka::ClientContext for KafkaDeliveryContext {}
+
+impl rdkafka::producer::ProducerContext for KafkaDeliveryContext {
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, result: &rdkafka::producer::DeliveryResult<'_>, _opaque: ()) {
+        match result {
+            Ok(_) => self.health.succeeded(),
+            Err((e, _)) => {
+                self.health.failed(&e.to_string());
+                kafka_dropped(&e.to_string());
+            }
+        }
+    }
+}
+
+/// Count a dropped message; the first drop is logged.
+fn kafka_dropped(reason: &str) {
+    if shared().pipeline.kafka_dropped.fetch_add(1, Ordering::Relaxed) == 0 {
+        eprintln!(
+            "[kafka] Dropped a record ({}); further drops are counted in telemetry.self.kafka.dropped",
+            reason
+        );
+    }
+}
+
+/// Output that publishes each record as JSON to its kind's topic, keyed by
+/// record `id`. librdkafka batches messages on its own thread and buffers
+/// up to `bufferSize` while brokers are unavailable; past that, or once a
+/// message has waited `messageTimeoutMs`, records are dropped and counted.
+struct KafkaProvider {
+    producer: rdkafka::producer::ThreadedProducer<KafkaDeliveryContext>,
+    topics: [Option<String>; 3],
+    /// Shared with the delivery context.
+    health: Arc<HealthTracker>,
+}
+
+impl KafkaProvider {
+    fn new(config: KafkaConfig) -> Option<Self> {
+        let health = Arc::new(HealthTracker::default());
+        let context = KafkaDeliveryContext {
+            health: Arc::clone(&health),
+        };
+        match config.client_config().create_with_context(context) {
+            Ok(producer) => Some(Self {
+                health,
+                producer,
+                topics: config.topics,
+            }),
+            Err(e) => {
+                eprintln!("[kafka] Failed to create producer: {}", e);
+                None
+            }
+        }
+    }
+
+    fn publish(&self, kind: RecordKind, record: &serde_json::Value) {
+        let Some(ref topic) = self.topics[kind.index()] else { return };
+        let Ok(payload) = serde_json::to_vec(record) else { return };
+        let key = record.get("id").and_then(|v| v.as_str()).unwrap_or("");
+        let message = rdkafka::producer::BaseRecord::to(topic).key(key).payload(&payload);
+        if let Err((e, _)) = self.producer.send(message) {
+            self.health.failed(&e.to_string());
+            kafka_dropped(&e.to_string());
+        }
+    }
+}
+
+impl TelemetryOutput for KafkaProvider {
+    fn name(&self) -> &str {
+        "kafka"
+    }
+
+    fn write_log(&mut self, record: &serde_json::Value) {
+        self.publish(RecordKind::Log, record);
+    }
+
+    fn write_span(&mut self, record: &serde_json::Value) {
+        self.publish(RecordKind::Span, record);
+    }
+
+    fn write_metric(&mut self, record: &serde_json::Value) {
+        self.publish(RecordKind::Metric, record);
+    }
+
+    fn flush(&mut self) {
+        use rdkafka::producer::Producer;
+        if let Err(e) = self.producer.flush(KAFKA_FLUSH_TIMEOUT) {
+            eprintln!("[kafka] Flush incomplete: {}", e);
+        }
+    }
+
+    fn health(&self) -> OutputHealth {
+        self.health.health()
+    }
+}
+
+// ============================================================================
+// Syslog Provider — log records sent as RFC 5424 messages
+// ============================================================================
+
+/// Records per delivery round on the syslog thread, and the longest a record
+/// waits for one.
+const SYSLOG_BATCH_SIZE: usize = 100;
+const SYSLOG_FLUSH_INTERVAL: std::time::Duration = std::time::Duration::from_millis(200);
+
+/// Timeout for connecting to and writing to a TCP syslog server.
+const SYSLOG_TCP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// SD-ID of the element carrying a record's `fields`. 32473 is the private
+/// enterprise number reserved for examples, which rsyslog accepts as-is.
+const SYSLOG_SD_ID: &str = "fields@32473";
+
+/// RFC 5424 caps APP-NAME at 48 characters and PARAM-NAME at 32.
+const SYSLOG_APP_NAME_MAX: usize = 48;
+const SYSLOG_PARAM_NAME_MAX: usize = 32;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum SyslogTransport {
+    Udp,
+    Tcp,
+}
+
+impl SyslogTransport {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Udp => "udp",
+            Self::Tcp => "tcp",
+        }
+    }
+}
+
+/// The `telemetry.syslog` section.
+struct SyslogConfig {
+    /// `host:port` of the syslog server.
+    address: String,
+    transport: SyslogTransport,
+    /// Facility code (0-23); `local0` is 16.
+    facility: u8,
+}
+
+impl SyslogConfig {
+    fn from_config(telemetry: &serde_json::Value) -> Option<Self> {
+        let section = telemetry.get("syslog")?;
+        let address = section
+            .get("address")
+            .and_then(|v| v.as_str())
+            .filter(|s| !s.is_empty())?
+            .to_string();
+        let transport = match section.get("transport").and_then(|v| v.as_str()) {
+            None | Some("udp") => SyslogTransport::Udp,
+            Some("tcp") => SyslogTransport::Tcp,
+            Some(other) => {
+                eprintln!("[yeti-telemetry] Unknown syslog.transport '{}', using 'udp'", other);
+                SyslogTransport::Udp
+            }
+        };
+        let facility = match section.get("facility") {
+            None => 1,
+            Some(value) => match syslog_facility(value) {
+                Some(code) => code,
+                None => {
+                    eprintln!("[yeti-telemetry] Unknown syslog.facility {}, using 'user'", value);
+                    1
+                }
+            },
+        };
+        Some(Self {
+            address,
+            transport,
+            facility,
+        })
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "address": self.address,
+            "transport": self.transport.as_str(),
+            "facility": SYSLOG_FACILITIES[self.facility as usize],
+        })
+    }
+}
+
+/// Facility names by code.
+const SYSLOG_FACILITIES: [&str; 24] = [
+    "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron",
+    "authpriv", "ftp", "ntp", "security", "console", "solaris-cron", "local0", "local1",
+    "local2", "local3", "local4", "local5", "local6", "local7",
+];
+
+/// Facility code from a name (`local0`) or a number (16).
+fn syslog_facility(value: &serde_json::Value) -> Option<u8> {
+    if let Some(code) = value.as_u64() {
+        return (code < 24).then_some(code as u8);
+    }
+    let name = value.as_str()?.trim().to_ascii_lowercase();
+    SYSLOG_FACILITIES
+        .iter()
+        .position(|f| *f == name)
+        .map(|code| code as u8)
+}
+
+/// Syslog severity for a record level: ERROR is `err` (3), WARN `warning`
+/// (4), INFO `informational` (6), and DEBUG/TRACE `debug` (7).
+fn syslog_severity(level: &str) -> u8 {
+    match level_rank(level) {
+        0 | 1 => 7,
+        2 => 6,
+        3 => 4,
+        _ => 3,
+    }
+}
+
+/// `value` limited to printable US-ASCII without spaces, as RFC 5424 requires
+/// for header fields, cut to `max` characters; `-` (the nil value) if empty.
+fn syslog_header_field(value: &str, max: usize) -> String {
+    let field: String = value
+        .chars()
+        .map(|c| if c.is_ascii_graphic() { c } else { '_' })
+        .take(max)
+        .collect();
+    if field.is_empty() {
+        "-".to_string()
+    } else {
+        field
+    }
+}
+
+/// STRUCTURED-DATA for a record: one `fields@32473` element with a parameter
+/// per field, or `-` without fields. Names drop the characters RFC 5424
+/// forbids in them; values escape `"`, `\` and `]`.
+fn syslog_structured_data(record: &serde_json::Value) -> String {
+    let fields = record
+        .get("fields")
+        .and_then(|v| v.as_str())
+        .and_then(|s| serde_json::from_str::<serde_json::Value>(s).ok());
+    let Some(serde_json::Value::Object(fields)) = fields else {
+        return "-".to_string();
+    };
+    let mut element = format!("[{}", SYSLOG_SD_ID);
+    let mut params = 0;
+    for (key, value) in &fields {
+        let name: String = key
+            .chars()
+            .filter(|c| c.is_ascii_graphic() && !matches!(c, '=' | ']' | '"'))
+            .take(SYSLOG_PARAM_NAME_MAX)
+            .collect();
+        if name.is_empty() {
+            continue;
+        }
+        let value = match value {
+            serde_json::Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        let mut escaped = String::with_capacity(value.len());
+        for c in value.chars() {
+            if matches!(c, '"' | '\\' | ']') {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        element.push_str(&format!(" {}=\"{}\"", name, escaped));
+        params += 1;
+    }
+    if params == 0 {
+        return "-".to_string();
+    }
+    element.push(']');
+    element
+}
+
+/// A log record as an RFC 5424 message:
+/// `<PRI>1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA MSG`,
+/// with the target as APP-NAME and no MSGID.
+fn syslog_message(record: &serde_json::Value, facility: u8, hostname: &str) -> String {
+    let str_field = |key: &str| record.get(key).and_then(|v| v.as_str()).unwrap_or("");
+    let priority = u16::from(facility) * 8 + u16::from(syslog_severity(str_field("level")));
+    let timestamp = record_time_secs(record).unwrap_or_else(|| now_epoch_ms() / 1000.0);
+    format!(
+        "<{}>1 {} {} {} {} - {} {}",
+        priority,
+        format_rfc3339(timestamp),
+        hostname,
+        syslog_header_field(str_field("target"), SYSLOG_APP_NAME_MAX),
+        std::process::id(),
+        syslog_structured_data(record),
+        str_field("message")
+    )
+}
+
+/// Connection to the syslog server, owned by the delivery thread. A TCP
+/// connection that fails is dropped and reopened on the next write.
+struct SyslogSender {
+    config: SyslogConfig,
+    udp: Option<std::net::UdpSocket>,
+    tcp: Option<std::net::TcpStream>,
+    /// Whether the current outage has been logged.
+    failing: bool,
+}
+
+impl SyslogSender {
+    fn send(&mut self, message: &str) -> std::io::Result<()> {
+        match self.config.transport {
+            SyslogTransport::Udp => {
+                let socket = match self.udp {
+                    Some(ref socket) => socket,
+                    None => {
+                        let socket = std::net::UdpSocket::bind("0.0.0.0:0")?;
+                        socket.connect(&self.config.address)?;
+                        self.udp.insert(socket)
+                    }
+                };
+                socket.send(message.as_bytes()).map(|_| ())
+            }
+            SyslogTransport::Tcp => {
+                let stream = match self.tcp {
+                    Some(ref mut stream) => stream,
+                    None => {
+                        let stream = self.connect_tcp()?;
+                        self.tcp.insert(stream)
+                    }
+                };
+                // RFC 6587 octet counting, so messages may contain newlines.
+                let framed = format!("{} {}", message.len(), message);
+                let result = stream.write_all(framed.as_bytes());
+                if result.is_err() {
+                    self.tcp = None;
+                }
+                result
+            }
+        }
+    }
+
+    fn connect_tcp(&self) -> std::io::Result<std::net::TcpStream> {
+        use std::net::ToSocketAddrs;
+
+        let mut last_error = None;
+        for addr in self.config.address.to_socket_addrs()? {
+            match std::net::TcpStream::connect_timeout(&addr, SYSLOG_TCP_TIMEOUT) {
+                Ok(stream) => {
+                    stream.set_write_timeout(Some(SYSLOG_TCP_TIMEOUT))?;
+                    return Ok(stream);
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::NotFound, "address did not resolve")
+        }))
+    }
+
+    /// Send each record, counting the ones that fail. An outage is logged
+    /// once when it starts and once when sending works again. Returns the
+    /// last error if any record failed.
+    fn deliver(&mut self, records: Vec<serde_json::Value>, hostname: &str) -> std::result::Result<(), String> {
+        let mut result = Ok(());
+        for record in records {
+            let message = syslog_message(&record, self.config.facility, hostname);
+            match self.send(&message) {
+                Ok(()) if self.failing => {
+                    self.failing = false;
+                    eprintln!("[syslog] Sending to {} again", self.config.address);
+                }
+                Ok(()) => {}
+                Err(e) => {
+                    shared().pipeline.syslog_dropped.fetch_add(1, Ordering::Relaxed);
+                    if !self.failing {
+                        self.failing = true;
+                        eprintln!(
+                            "[syslog] Send to {} failed, dropping records until it recovers: {}",
+                            self.config.address, e
+                        );
+                    }
+                    result = Err(e.to_string());
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Output that sends log records to a syslog server from a background
+/// thread. Spans and metrics are ignored.
+struct SyslogProvider {
+    batches: BatchThread,
+}
+
+impl SyslogProvider {
+    fn new(config: SyslogConfig) -> Option<Self> {
+        let hostname = syslog_header_field(&host_name().unwrap_or_default(), 255);
+        let mut sender = SyslogSender {
+            config,
+            udp: None,
+            tcp: None,
+            failing: false,
+        };
+        let batches = BatchThread::spawn("syslog", SYSLOG_BATCH_SIZE, SYSLOG_FLUSH_INTERVAL, move |records| {
+            sender.deliver(records, &hostname)
+        })?;
+        Some(Self { batches })
+    }
+}
+
+impl TelemetryOutput for SyslogProvider {
+    fn name(&self) -> &str {
+        "syslog"
+    }
+
+    fn write_log(&mut self, record: &serde_json::Value) {
+        if !self.batches.send(record) {
+            shared().pipeline.syslog_dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn write_span(&mut self, _record: &serde_json::Value) {}
+
+    fn flush(&mut self) {
+        self.batches.flush();
+    }
+
+    fn health(&self) -> OutputHealth {
+        self.batches.health.health()
+    }
+}
+
+// ============================================================================
+// OTLP Provider — OpenTelemetry metrics export
+// ============================================================================
+
+use opentelemetry::KeyValue;
+use opentelemetry::metrics::{Counter, Gauge, Histogram, Meter, MeterProvider};
+use opentelemetry_otlp::{Protocol, WithExportConfig, WithHttpConfig, WithTonicConfig};
+use tonic::metadata::{MetadataKey, MetadataMap};
+use opentelemetry_sdk::metrics::{Aggregation, Instrument, SdkMeterProvider, Stream};
+use opentelemetry::trace::{
+    Span as _, SpanContext, SpanId, Status, TraceContextExt, TraceFlags, TraceId, TraceState,
+    Tracer as _, TracerProvider as _,
+};
+use opentelemetry::Context as OtelContext;
+use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+use opentelemetry::logs::{AnyValue, LogRecord as _, Logger as _, LoggerProvider as _, Severity};
+use opentelemetry_sdk::logs::{BatchConfigBuilder, BatchLogProcessor, SdkLogger, SdkLoggerProvider};
+
+/// Export interval shared by the OTLP signal pipelines.
+const OTLP_EXPORT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15);
+
+/// OTLP metrics config parsed from yeti-config.yaml
+struct OtlpConfig {
+    /// Output name: `otlp`, or `otlp:<name>` for an `otlpEndpoints` entry.
+    name: String,
+    endpoint: String,
+    protocol: OtlpProtocol,
+    service_name: String,
+    metrics_enabled: bool,
+    /// Export stored spans as OTLP traces.
+    traces_enabled: bool,
+    /// Export stored logs as OTLP log records.
+    logs_enabled: bool,
+    headers: Vec<(String, String)>,
+    timeout: std::time::Duration,
+    /// Cap on per-name instruments for forwarded custom metrics.
+    max_instruments: usize,
+    /// Export the pipeline's own counters as `telemetry.self.*` metrics.
+    self_metrics: bool,
+    /// Distinct attribute sets admitted per instrument.
+    cardinality_limit: usize,
+    cardinality_overflow: CardinalityOverflow,
+    /// Directory spooling failed export batches, when `otlpSpool` is on.
+    spool_dir: Option<PathBuf>,
+    spool_max_bytes: u64,
+    /// Retries of a failed metric export before it is spooled or dropped.
+    retry: ExportRetry,
+    /// Resource attributes besides `service.name`, sorted by key.
+    resource_attributes: Vec<(String, String)>,
+    /// Bucket boundaries (seconds) for `http.server.request.duration`;
+    /// `None` uses the SDK defaults.
+    histogram_buckets: Option<Vec<f64>>,
+    /// Span `fields` keys added as attributes on the HTTP request instruments.
+    metric_labels_from_fields: Vec<String>,
+    /// Distinct values kept per field label before the rest fold into
+    /// `__overflow__`.
+    metric_label_max_values: usize,
+    /// Consecutive failures that open the circuit breaker; 0 disables it.
+    breaker_threshold: u64,
+    breaker_cooldown_ms: u64,
+}
+
+impl OtlpConfig {
+    /// Effective settings for the config endpoint. Header values are always
+    /// masked since they typically carry credentials.
+    fn to_json(&self) -> serde_json::Value {
+        let headers: serde_json::Map<String, serde_json::Value> = self
+            .headers
+            .iter()
+            .map(|(k, _)| (k.clone(), json!("***")))
+            .collect();
+        json!({
+            "name": self.name,
+            "endpoint": self.endpoint,
+            "protocol": self.protocol.as_str(),
+            "serviceName": self.service_name,
+            "metrics": self.metrics_enabled,
+            "traces": self.traces_enabled,
+            "logs": self.logs_enabled,
+            "headers": headers,
+            "timeoutMs": self.timeout.as_millis() as u64,
+            "maxInstruments": self.max_instruments,
+            "selfMetrics": self.self_metrics,
+            "cardinalityLimit": self.cardinality_limit,
+            "cardinalityOverflow": self.cardinality_overflow.as_str(),
+            "spool": self.spool_dir.as_ref().map(|dir| dir.display().to_string()),
+            "spoolMaxMb": self.spool_max_bytes / (1024 * 1024),
+            "retryMaxAttempts": self.retry.max_attempts,
+            "retryBackoffMs": self.retry.initial_backoff.as_millis() as u64,
+            "retryMaxBackoffMs": self.retry.max_backoff.as_millis() as u64,
+            "histogramBuckets": self.histogram_buckets,
+            "metricLabelsFromFields": self.metric_labels_from_fields,
+            "metricLabelMaxValues": self.metric_label_max_values,
+            "breakerThreshold": self.breaker_threshold,
+            "breakerCooldownMs": self.breaker_cooldown_ms,
+            "resourceAttributes": self
+                .resource_attributes
+                .iter()
+                .map(|(k, v)| (k.clone(), json!(v)))
+                .collect::<serde_json::Map<_, _>>(),
+        })
+    }
+}
+
+/// OTLP output provider — exports HTTP metrics to an OTLP collector.
+///
+/// Lazily initializes the meter provider on first use (inside run_loop on the
+/// host's tokio runtime) to avoid dylib tokio spawn issues during on_ready().
+struct OtlpOutput {
+    config: OtlpConfig,
+    provider: Option<SdkMeterProvider>,
+    requests_total: Option<Counter<u64>>,
+    requests_duration: Option<Histogram<f64>>,
+    errors_total: Option<Counter<u64>>,
+    /// Set once the collector has accepted an export, by the probe or by
+    /// the metric exporter.
+    collector_ready: Arc<AtomicBool>,
+    /// Request observations held back until the collector is reachable.
+    startup_buffer: VecDeque<HttpRequestSample>,
+    startup_buffer_cap: usize,
+    /// Buffered observations evicted because the buffer was full.
+    startup_dropped: u64,
+    meter: Option<Meter>,
+    /// Gauges for forwarded custom metrics by name.
+    custom_instruments: HashMap<String, Gauge<f64>>,
+    /// Custom metric records refused because `max_instruments` was reached.
+    instruments_rejected: u64,
+    cardinality: CardinalityGuard,
+    /// Caps the distinct values of each `metricLabelsFromFields` label.
+    field_labels: FieldLabelLimiter,
+    /// Set once every enabled signal's provider has been built. Until then
+    /// setup is retried on each record the breaker lets through.
+    initialized: bool,
+    tracer_provider: Option<SdkTracerProvider>,
+    tracer: Option<SdkTracer>,
+    logger_provider: Option<SdkLoggerProvider>,
+    logger: Option<SdkLogger>,
+    /// Metric export failures, shared with the exporter.
+    export_failures: Arc<ExportFailures>,
+    /// Opened by failed setups and exports, shared with the exporter.
+    breaker: Arc<OtlpBreaker>,
+}
+
+impl OtlpOutput {
+    /// Parse OTLP config from yeti-config.yaml. Returns None if no endpoint configured.
+    ///
+    /// Precedence, highest first: `YETI_OTLP_*` env vars (see
+    /// [`YETI_OTLP_ENV`]), the `telemetry` section, the standard
+    /// OpenTelemetry env vars, then defaults.
+    ///
+    /// The standard OpenTelemetry env vars fill in anything the `telemetry`
+    /// section leaves unset, with signal-specific vars (`*_METRICS_*`) taking
+    /// precedence over the generic ones as the spec requires:
+    ///
+    /// - `OTEL_EXPORTER_OTLP_METRICS_ENDPOINT` / `OTEL_EXPORTER_OTLP_ENDPOINT`
+    /// - `OTEL_EXPORTER_OTLP_METRICS_HEADERS` / `OTEL_EXPORTER_OTLP_HEADERS`
+    /// - `OTEL_EXPORTER_OTLP_METRICS_TIMEOUT` / `OTEL_EXPORTER_OTLP_TIMEOUT` (ms,
+    ///   after `otlpTimeoutMs`)
+    /// - `OTEL_SERVICE_NAME`, then `service.name` in `OTEL_RESOURCE_ATTRIBUTES`
+    ///   (after `serviceName` and `resourceAttributes.service.name`)
+    /// - `OTEL_METRICS_EXPORTER=none` disables metrics
+    ///
+    /// Other `OTEL_RESOURCE_ATTRIBUTES` entries are picked up by the SDK's
+    /// resource builder.
+    ///
+    /// With `otlpEndpoints`, returns one output per entry instead; see
+    /// [`otlp_endpoint_sections`].
+    fn from_config(root_dir: &str) -> Vec<Self> {
+        let mut telemetry = read_telemetry_section(root_dir).unwrap_or(json!({}));
+        apply_yeti_otlp_env(&mut telemetry, &yeti_otlp_env());
+        if telemetry.get("otlpEndpoints").is_none() {
+            return Self::from_section(&telemetry, root_dir, "otlp", true).into_iter().collect();
+        }
+        otlp_endpoint_sections(&telemetry)
+            .into_iter()
+            .filter_map(|(name, section)| Self::from_section(&section, root_dir, &format!("otlp:{}", name), false))
+            .collect()
+    }
+
+    /// Build one output from a `telemetry` section. `env_headers` merges the
+    /// OpenTelemetry header env vars into `otlpHeaders`.
+    fn from_section(
+        telemetry: &serde_json::Value,
+        root_dir: &str,
+        name: &str,
+        env_headers: bool,
+    ) -> Option<Self> {
+        let endpoint = telemetry
+            .get("otlpEndpoint")
+            .and_then(|v| v.as_str())
+            .filter(|s| !s.is_empty())
+            .map(|s| s.to_string())
+            .or_else(|| otel_env("OTEL_EXPORTER_OTLP_METRICS_ENDPOINT"))
+            .or_else(|| otel_env("OTEL_EXPORTER_OTLP_ENDPOINT"))?;
+
+        let protocol = OtlpProtocol::from_config(telemetry);
+
+        let (service_name, resource_attributes) = otlp_resource(telemetry);
+
+        let metrics_enabled = telemetry
+            .get("metrics")
+            .and_then(|v| v.as_bool())
+            .unwrap_or_else(|| {
+                otel_env("OTEL_METRICS_EXPORTER").is_none_or(|e| e != "none")
+            });
+
+        let traces_enabled = telemetry
+            .get("traces")
+            .and_then(|v| v.as_bool())
+            .unwrap_or_else(|| otel_env("OTEL_TRACES_EXPORTER").is_some_and(|e| e == "otlp"));
+
+        let logs_enabled = telemetry
+            .get("logs")
+            .and_then(|v| v.as_bool())
+            .unwrap_or_else(|| otel_env("OTEL_LOGS_EXPORTER").is_some_and(|e| e == "otlp"));
+
+        // `otlpHeaders` entries win over env headers of the same name.
+        let mut headers = otlp_headers_from_config(telemetry);
+        let env_headers = otel_env("OTEL_EXPORTER_OTLP_METRICS_HEADERS")
+            .or_else(|| otel_env("OTEL_EXPORTER_OTLP_HEADERS"))
+            .filter(|_| env_headers)
+            .map(|h| parse_otel_pairs(&h))
+            .unwrap_or_default();
+        for (key, value) in env_headers {
+            if !headers.iter().any(|(k, _)| k.eq_ignore_ascii_case(&key)) {
+                headers.push((key, value));
+            }
+        }
+
+        let max_instruments = telemetry
+            .get("otlpMaxInstruments")
+            .and_then(|v| v.as_u64())
+            .filter(|n| *n > 0)
+            .unwrap_or(DEFAULT_OTLP_MAX_INSTRUMENTS as u64) as usize;
+
+        let self_metrics = telemetry
+            .get("selfMetrics")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+
+        let cardinality_limit = telemetry
+            .get("cardinalityLimit")
+            .and_then(|v| v.as_u64())
+            .filter(|n| *n > 0)
+            .unwrap_or(DEFAULT_CARDINALITY_LIMIT as u64) as usize;
+        let cardinality_overflow = match telemetry.get("cardinalityOverflow").and_then(|v| v.as_str()) {
+            None | Some("drop") => CardinalityOverflow::Drop,
+            Some("collapse") => CardinalityOverflow::Collapse,
+            Some(other) => {
+                eprintln!("[yeti-telemetry] Unknown cardinalityOverflow '{}', using 'drop'", other);
+                CardinalityOverflow::Drop
+            }
+        };
+
+        // Each destination spools and replays its own failed batches.
+        let spool_name = match name.strip_prefix("otlp:") {
+            Some(entry) => format!("otlp-spool-{}", entry),
+            None => "otlp-spool".to_string(),
+        };
+        let spool_dir = telemetry
+            .get("otlpSpool")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false)
+            .then(|| PathBuf::from(root_dir).join("logs").join(spool_name));
+        let spool_max_mb = telemetry
+            .get("otlpSpoolMaxMb")
+            .and_then(|v| v.as_u64())
+            .filter(|n| *n > 0)
+            .unwrap_or(DEFAULT_OTLP_SPOOL_MAX_MB);
+
+        let retry = ExportRetry::from_config(telemetry);
+        let histogram_buckets = telemetry.get("histogramBuckets").and_then(parse_histogram_buckets);
+
+        let metric_labels_from_fields = parse_metric_label_fields(telemetry.get("metricLabelsFromFields"));
+        let metric_label_max_values = telemetry
+            .get("metricLabelMaxValues")
+            .and_then(|v| v.as_u64())
+            .filter(|n| *n > 0)
+            .unwrap_or(DEFAULT_METRIC_LABEL_MAX_VALUES as u64) as usize;
+
+        let breaker_threshold = telemetry
+            .get("otlpBreakerThreshold")
+            .and_then(|v| v.as_u64())
+            .unwrap_or(DEFAULT_OTLP_BREAKER_THRESHOLD);
+        let breaker_cooldown_ms = telemetry
+            .get("otlpBreakerCooldownMs")
+            .and_then(|v| v.as_u64())
+            .filter(|n| *n > 0)
+            .unwrap_or(DEFAULT_OTLP_BREAKER_COOLDOWN_MS);
+
+        let startup_buffer_cap = telemetry
+            .get("otlpStartupBuffer")
+            .and_then(|v| v.as_u64())
+            .unwrap_or(DEFAULT_OTLP_STARTUP_BUFFER as u64) as usize;
+
+        let timeout_ms = telemetry
+            .get("otlpTimeoutMs")
+            .and_then(|v| v.as_u64())
+            .filter(|n| *n > 0)
+            .or_else(|| {
+                otel_env("OTEL_EXPORTER_OTLP_METRICS_TIMEOUT")
+                    .or_else(|| otel_env("OTEL_EXPORTER_OTLP_TIMEOUT"))
+                    .and_then(|t| t.parse::<u64>().ok())
+            })
+            .unwrap_or(10_000);
+
+        eprintln!(
+            "[yeti-telemetry] OTLP config: name={}, endpoint={}, protocol={}, service={}, metrics={}",
+            name,
+            endpoint,
+            protocol.as_str(),
+            service_name,
+            metrics_enabled
+        );
+
+        Some(Self {
+            config: OtlpConfig {
+                name: name.to_string(),
+                endpoint,
+                protocol,
+                service_name,
+                metrics_enabled,
+                traces_enabled,
+                logs_enabled,
+                headers,
+                timeout: std::time::Duration::from_millis(timeout_ms),
+                max_instruments,
+                self_metrics,
+                cardinality_limit,
+                cardinality_overflow,
+                spool_dir,
+                spool_max_bytes: spool_max_mb * 1024 * 1024,
+                retry,
+                resource_attributes,
+                histogram_buckets,
+                metric_labels_from_fields,
+                metric_label_max_values,
+                breaker_threshold,
+                breaker_cooldown_ms,
+            },
+            provider: None,
+            requests_total: None,
+            requests_duration: None,
+            errors_total: None,
+            collector_ready: Arc::new(AtomicBool::new(false)),
+            startup_buffer: VecDeque::new(),
+            startup_buffer_cap,
+            startup_dropped: 0,
+            meter: None,
+            custom_instruments: HashMap::new(),
+            instruments_rejected: 0,
+            cardinality: CardinalityGuard::new(cardinality_limit, cardinality_overflow),
+            field_labels: FieldLabelLimiter::new(metric_label_max_values),
+            initialized: false,
+            tracer_provider: None,
+            tracer: None,
+            logger_provider: None,
+            logger: None,
+            export_failures: Arc::new(ExportFailures::default()),
+            breaker: Arc::new(OtlpBreaker::new(breaker_threshold, breaker_cooldown_ms)),
+        })
+    }
+
+    /// Lazily initialize the OTLP providers for the enabled signals.
+    /// Called on first write inside the host's tokio runtime context.
+    ///
+    /// A signal whose exporter can't be built is retried on later writes,
+    /// each failed attempt counting toward the circuit breaker, so a bad
+    /// collector setup costs at most `breakerThreshold` attempts per cooldown.
+    fn ensure_initialized(&mut self) {
+        if self.initialized {
+            return;
+        }
+
+        let mut metadata = MetadataMap::new();
+        for (key, value) in &self.config.headers {
+            match (key.parse::<MetadataKey<_>>(), value.parse()) {
+                (Ok(k), Ok(v)) => {
+                    metadata.insert(k, v);
+                }
+                _ => eprintln!("[otlp-output] Skipping invalid OTLP header: {}", key),
+            }
+        }
+
+        let resource = opentelemetry_sdk::Resource::builder()
+            .with_attribute(KeyValue::new(
+                "service.name",
+                self.config.service_name.clone(),
+            ))
+            .with_attributes(
+                self.config
+                    .resource_attributes
+                    .iter()
+                    .map(|(k, v)| KeyValue::new(k.clone(), v.clone())),
+            )
+            .build();
+
+        if self.config.metrics_enabled && self.provider.is_none() {
+            self.init_metrics(metadata.clone(), resource.clone());
+        }
+        if self.config.traces_enabled && self.tracer.is_none() {
+            self.init_traces(metadata.clone(), resource.clone());
+        }
+        if self.config.logs_enabled && self.logger.is_none() {
+            self.init_logs(metadata, resource);
+        }
+
+        let missing = (self.config.metrics_enabled && self.provider.is_none())
+            || (self.config.traces_enabled && self.tracer.is_none())
+            || (self.config.logs_enabled && self.logger.is_none());
+        if missing {
+            self.breaker.failed();
+        } else {
+            self.initialized = true;
+            self.breaker.succeeded();
+        }
+    }
+
+    /// Whether the circuit breaker lets a record through; skipped records
+    /// are counted.
+    fn breaker_allows(&self) -> bool {
+        let allowed = self.breaker.allow();
+        if !allowed {
+            shared().pipeline.otlp_skipped.fetch_add(1, Ordering::Relaxed);
+        }
+        allowed
+    }
+
+    /// The gRPC channel an exporter sends on, or `None` under
+    /// `otlpProtocol: http`. `https://` endpoints use TLS with the system's
+    /// root certificates.
+    fn grpc_channel(&self) -> std::result::Result<Option<tonic::transport::Channel>, String> {
+        if self.config.protocol != OtlpProtocol::Grpc {
+            return Ok(None);
+        }
+        let endpoint = &self.config.endpoint;
+        let mut channel = tonic::transport::Channel::from_shared(endpoint.clone()).map_err(|e| e.to_string())?;
+        if endpoint.starts_with("https://") {
+            channel = channel
+                .tls_config(tonic::transport::ClientTlsConfig::new().with_native_roots())
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(Some(channel.timeout(self.config.timeout).connect_lazy()))
+    }
+
+    fn init_metrics(&mut self, metadata: MetadataMap, resource: opentelemetry_sdk::Resource) {
+        let builder = opentelemetry_otlp::MetricExporter::builder();
+        let channel = match self.grpc_channel() {
+            Ok(channel) => channel,
+            Err(e) => {
+                eprintln!("[otlp-output] Failed to create metric exporter: {}", e);
+                return;
+            }
+        };
+        let exporter = match channel.clone() {
+            Some(channel) => builder
+                .with_tonic()
+                .with_channel(channel)
+                .with_timeout(self.config.timeout)
+                .with_metadata(metadata.clone())
+                .build(),
+            None => builder
+                .with_http()
+                .with_protocol(Protocol::HttpBinary)
+                .with_endpoint(http_signal_url(&self.config.endpoint, "metrics"))
+                .with_timeout(self.config.timeout)
+                .with_headers(self.config.headers.iter().cloned().collect())
+                .build(),
+        };
+        let exporter = match exporter {
+            Ok(e) => e,
+            Err(e) => {
+                eprintln!("[otlp-output] Failed to create metric exporter: {}", e);
+                return;
+            }
+        };
+        let target = ExportTarget {
+            channel,
+            endpoint: self.config.endpoint.clone(),
+            metadata,
+            headers: self.config.headers.clone(),
+            timeout: self.config.timeout,
+        };
+        let exporter = SpoolingExporter {
+            inner: exporter,
+            retry: self.config.retry,
+            failures: Arc::clone(&self.export_failures),
+            breaker: Arc::clone(&self.breaker),
+            collector_ready: Arc::clone(&self.collector_ready),
+            spool: self.config.spool_dir.as_ref().map(|dir| {
+                Arc::new(OtlpSpool::new(dir.clone(), self.config.spool_max_bytes, target.clone()))
+            }),
+        };
+        self.spawn_collector_probe(target);
+
+        let reader = opentelemetry_sdk::metrics::PeriodicReader::builder(exporter)
+            .with_interval(OTLP_EXPORT_INTERVAL)
+            .build();
+
+        let mut builder = SdkMeterProvider::builder()
+            .with_reader(reader)
+            .with_resource(resource);
+        if let Some(boundaries) = self.config.histogram_buckets.clone() {
+            builder = builder.with_view(move |instrument: &Instrument| {
+                if instrument.name() != "http.server.request.duration" {
+                    return None;
+                }
+                Stream::builder()
+                    .with_aggregation(Aggregation::ExplicitBucketHistogram {
+                        boundaries: boundaries.clone(),
+                        record_min_max: true,
+                    })
+                    .build()
+                    .ok()
+            });
+        }
+        let provider = builder.build();
+
+        let meter = provider.meter("yeti-telemetry");
+
+        self.requests_total = Some(
+            meter
+                .u64_counter("http.server.requests")
+                .with_description("Total number of HTTP requests")
+                .build(),
+        );
+        self.requests_duration = Some(
+            meter
+                .f64_histogram("http.server.request.duration")
+                .with_description("HTTP request duration in seconds")
+                .with_unit("s")
+                .build(),
+        );
+        self.errors_total = Some(
+            meter
+                .u64_counter("http.server.errors")
+                .with_description("Total number of HTTP errors")
+                .build(),
+        );
+
+        if self.config.self_metrics {
+            register_self_metrics(&meter);
+        }
+
+        self.meter = Some(meter);
+        self.provider = Some(provider);
+        eprintln!(
+            "[otlp-output] Meter provider initialized (endpoint: {})",
+            self.config.endpoint
+        );
+    }
+
+    fn init_traces(&mut self, metadata: MetadataMap, resource: opentelemetry_sdk::Resource) {
+        let builder = opentelemetry_otlp::SpanExporter::builder();
+        let channel = match self.grpc_channel() {
+            Ok(channel) => channel,
+            Err(e) => {
+                eprintln!("[otlp-output] Failed to create span exporter: {}", e);
+                return;
+            }
+        };
+        let exporter = match channel {
+            Some(channel) => builder
+                .with_tonic()
+                .with_channel(channel)
+                .with_timeout(self.config.timeout)
+                .with_metadata(metadata)
+                .build(),
+            None => builder
+                .with_http()
+                .with_protocol(Protocol::HttpBinary)
+                .with_endpoint(http_signal_url(&self.config.endpoint, "traces"))
+                .with_timeout(self.config.timeout)
+                .with_headers(self.config.headers.iter().cloned().collect())
+                .build(),
+        };
+        let exporter = match exporter {
+            Ok(e) => e,
+            Err(e) => {
+                eprintln!("[otlp-output] Failed to create span exporter: {}", e);
+                return;
+            }
+        };
+
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(resource)
+            .build();
+        self.tracer = Some(provider.tracer("yeti-telemetry"));
+        self.tracer_provider = Some(provider);
+        eprintln!(
+            "[otlp-output] Tracer provider initialized (endpoint: {})",
+            self.config.endpoint
+        );
+    }
+
+    fn init_logs(&mut self, metadata: MetadataMap, resource: opentelemetry_sdk::Resource) {
+        let builder = opentelemetry_otlp::LogExporter::builder();
+        let channel = match self.grpc_channel() {
+            Ok(channel) => channel,
+            Err(e) => {
+                eprintln!("[otlp-output] Failed to create log exporter: {}", e);
+                return;
+            }
+        };
+        let exporter = match channel {
+            Some(channel) => builder
+                .with_tonic()
+                .with_channel(channel)
+                .with_timeout(self.config.timeout)
+                .with_metadata(metadata)
+                .build(),
+            None => builder
+                .with_http()
+                .with_protocol(Protocol::HttpBinary)
+                .with_endpoint(http_signal_url(&self.config.endpoint, "logs"))
+                .with_timeout(self.config.timeout)
+                .with_headers(self.config.headers.iter().cloned().collect())
+                .build(),
+        };
+        let exporter = match exporter {
+            Ok(e) => e,
+            Err(e) => {
+                eprintln!("[otlp-output] Failed to create log exporter: {}", e);
+                return;
+            }
+        };
+
+        // Flush batches on the same cadence as metric exports.
+        let processor = BatchLogProcessor::builder(exporter)
+            .with_batch_config(
+                BatchConfigBuilder::default()
+                    .with_scheduled_delay(OTLP_EXPORT_INTERVAL)
+                    .build(),
+            )
+            .build();
+        let provider = SdkLoggerProvider::builder()
+            .with_log_processor(processor)
+            .with_resource(resource)
+            .build();
+        self.logger = Some(provider.logger("yeti-telemetry"));
+        self.logger_provider = Some(provider);
+        eprintln!(
+            "[otlp-output] Logger provider initialized (endpoint: {})",
+            self.config.endpoint
+        );
+    }
+
+    /// Emit a stored log record as an OpenTelemetry log record: `level` maps
+    /// to severity, `message` to the body, and `target` plus the parsed
+    /// `fields` to attributes. The record time is used as both the event
+    /// and the observed timestamp.
+    fn export_log(&self, record: &serde_json::Value) {
+        let Some(ref logger) = self.logger else { return };
+
+        let otel = OtelLog::from_record(record);
+        let mut log = logger.create_log_record();
+        log.set_severity_number(otel.severity);
+        log.set_severity_text(otel.severity.name());
+        log.set_body(AnyValue::from(otel.body));
+        if let Some(ms) = otel.time_ms {
+            log.set_timestamp(epoch_ms_to_system_time(ms));
+            log.set_observed_timestamp(epoch_ms_to_system_time(ms));
+        }
+        if let Some((trace_id, span_id)) = otel.trace {
+            log.set_trace_context(trace_id, span_id, None);
+        }
+        for (key, value) in otel.attributes {
+            log.add_attribute(key, json_any_value(value));
+        }
+
+        logger.emit(log);
+    }
+
+    /// Re-emit a stored span as an OTLP span with its original timing and
+    /// ids. Spans without a usable start and end time are skipped rather
+    /// than exported with a made-up duration.
+    fn export_span(&self, record: &serde_json::Value) {
+        let Some(ref tracer) = self.tracer else { return };
+        let Some(otel) = OtelSpan::from_record(record) else { return };
+        let at = epoch_ms_to_system_time;
+
+        let mut builder = tracer
+            .span_builder(otel.name)
+            .with_start_time(at(otel.start_ms))
+            .with_attributes(otel.attributes);
+        if let Some(span_id) = otel.span_id {
+            builder = builder.with_span_id(span_id);
+        }
+        if otel.error {
+            builder = builder.with_status(Status::error(""));
+        }
+
+        let cx = match (otel.trace_id, otel.parent_id) {
+            (Some(trace_id), Some(parent_id)) => {
+                OtelContext::new().with_remote_span_context(SpanContext::new(
+                    trace_id,
+                    parent_id,
+                    TraceFlags::SAMPLED,
+                    true,
+                    TraceState::default(),
+                ))
+            }
+            (Some(trace_id), _) => {
+                builder = builder.with_trace_id(trace_id);
+                OtelContext::new()
+            }
+            _ => OtelContext::new(),
+        };
+
+        let mut span = builder.start_with_context(tracer, &cx);
+        span.end_with_timestamp(at(otel.end_ms));
+    }
+
+    /// Probe the collector with empty exports until one is accepted, then
+    /// mark it ready.
+    ///
+    /// When Yeti and the collector start together, exports before the collector
+    /// is up are lost. Observations are buffered until an export succeeds and
+    /// then replayed into the instruments, so the next export carries them. A
+    /// collector that accepts connections but rejects exports (a 503 while it
+    /// starts, bad credentials) keeps the buffer held. A real export that
+    /// succeeds first marks it ready too.
+    fn spawn_collector_probe(&self, target: ExportTarget) {
+        let ready = self.collector_ready.clone();
+        tokio::spawn(async move {
+            let mut delay = std::time::Duration::from_millis(250);
+            while !ready.load(Ordering::Relaxed) {
+                if target.accepts_export().await {
+                    ready.store(true, Ordering::Relaxed);
+                    eprintln!("[otlp-output] Collector at {} accepted an export", target.endpoint);
+                    return;
+                }
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(std::time::Duration::from_secs(10));
+            }
+        });
+    }
+
+    /// Record one HTTP request observation, buffering it while the collector
+    /// has not been reached yet.
+    fn record_sample(&mut self, sample: HttpRequestSample) {
+        if !self.collector_ready.load(Ordering::Relaxed) {
+            if self.startup_buffer_cap == 0 {
+                self.record_instruments(&sample);
+                return;
+            }
+            if self.startup_buffer.len() >= self.startup_buffer_cap {
+                self.startup_buffer.pop_front();
+                self.startup_dropped += 1;
+                shared().pipeline.otlp_buffer_dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            self.startup_buffer.push_back(sample);
+            return;
+        }
+
+        if !self.startup_buffer.is_empty() {
+            eprintln!(
+                "[otlp-output] Flushing {} buffered observations ({} dropped while waiting)",
+                self.startup_buffer.len(),
+                self.startup_dropped
+            );
+            for buffered in std::mem::take(&mut self.startup_buffer) {
+                self.record_instruments(&buffered);
+            }
+        }
+        self.record_instruments(&sample);
+    }
+
+    /// Gauge for a custom metric name, creating it on first use. At most
+    /// `otlpMaxInstruments` names get an instrument, protecting memory from
+    /// producers that emit unbounded metric names; records for further names
+    /// are dropped and counted.
+    ///
+    /// Instruments are never evicted: the SDK keeps each name's aggregator
+    /// for the life of the meter provider, so dropping our handle would free
+    /// nothing and let the cap be exceeded.
+    fn custom_gauge(&mut self, name: &str) -> Option<Gauge<f64>> {
+        if let Some(gauge) = self.custom_instruments.get(name) {
+            return Some(gauge.clone());
+        }
+
+        let meter = self.meter.as_ref()?;
+        if self.custom_instruments.len() >= self.config.max_instruments {
+            self.instruments_rejected += 1;
+            shared()
+                .pipeline
+                .otlp_instruments_rejected
+                .fetch_add(1, Ordering::Relaxed);
+            if self.instruments_rejected.is_power_of_two() {
+                eprintln!(
+                    "[otlp-output] Instrument limit ({}) reached, {} records for new metric names dropped so far",
+                    self.config.max_instruments, self.instruments_rejected
+                );
+            }
+            return None;
+        }
+
+        let gauge = meter.f64_gauge(name.to_string()).build();
+        self.custom_instruments.insert(name.to_string(), gauge.clone());
+        Some(gauge)
+    }
+
+    fn record_instruments(&mut self, sample: &HttpRequestSample) {
+        let mut attributes = vec![
+            KeyValue::new("http.method", sample.method.clone()),
+            KeyValue::new("http.route", sample.route.clone()),
+            KeyValue::new("http.status_code", sample.status.clone()),
+        ];
+        for (key, value) in &sample.labels {
+            let value = self.field_labels.admit(key, value);
+            attributes.push(KeyValue::new(key.clone(), value));
+        }
+        // The request instruments share one attribute set, so one budget.
+        let Some(attributes) = self.cardinality.admit("http.server", &attributes) else {
+            return;
+        };
+
+        if let Some(ref counter) = self.requests_total {
+            counter.add(1, attributes);
+        }
+
+        if let Some(ref histogram) = self.requests_duration {
+            histogram.record(sample.duration_secs, attributes);
+        }
+
+        if sample.is_error {
+            if let Some(ref counter) = self.errors_total {
+                counter.add(1, attributes);
+            }
+        }
+    }
+}
+
+/// Default number of distinct attribute sets admitted per OTLP instrument.
+const DEFAULT_CARDINALITY_LIMIT: usize = 2000;
+
+/// Default number of distinct values kept per `metricLabelsFromFields` label.
+const DEFAULT_METRIC_LABEL_MAX_VALUES: usize = 100;
+
+/// Value recorded for a field label once it has reached its distinct-value cap.
+const METRIC_LABEL_OVERFLOW: &str = "__overflow__";
+
+/// Attribute keys the HTTP request instruments set themselves.
+const HTTP_METRIC_ATTRIBUTES: [&str; 3] = ["http.method", "http.route", "http.status_code"];
+
+/// `metricLabelsFromFields`: a list of span `fields` keys. Duplicates and
+/// keys that would shadow the built-in request attributes are ignored.
+fn parse_metric_label_fields(value: Option<&serde_json::Value>) -> Vec<String> {
+    let Some(value) = value else { return Vec::new() };
+    let Some(entries) = value.as_array() else {
+        eprintln!("[yeti-telemetry] Ignoring metricLabelsFromFields (must be a list of field names)");
+        return Vec::new();
+    };
+    let mut fields: Vec<String> = Vec::new();
+    for entry in entries {
+        match entry.as_str().map(str::trim) {
+            Some(name) if HTTP_METRIC_ATTRIBUTES.contains(&name) => eprintln!(
+                "[yeti-telemetry] Ignoring metricLabelsFromFields entry '{}' (built-in attribute)",
+                name
+            ),
+            Some(name) if !name.is_empty() => {
+                if !fields.iter().any(|f| f == name) {
+                    fields.push(name.to_string());
+                }
+            }
+            _ => eprintln!("[yeti-telemetry] Ignoring metricLabelsFromFields entry: {}", entry),
+        }
+    }
+    fields
+}
+
+/// Tracks the distinct values seen per field label. Once a label has
+/// `max_values` of them, new values are recorded as `__overflow__`, so one
+/// runaway label can't multiply the request series without bound.
+struct FieldLabelLimiter {
+    max_values: usize,
+    seen: HashMap<String, std::collections::HashSet<String>>,
+}
+
+impl FieldLabelLimiter {
+    fn new(max_values: usize) -> Self {
+        Self {
+            max_values,
+            seen: HashMap::new(),
+        }
+    }
+
+    /// The value to record for `label`.
+    fn admit(&mut self, label: &str, value: &str) -> String {
+        let seen = self.seen.entry(label.to_string()).or_default();
+        if seen.contains(value) {
+            return value.to_string();
+        }
+        if seen.len() < self.max_values {
+            seen.insert(value.to_string());
+            return value.to_string();
+        }
+        if seen.insert(METRIC_LABEL_OVERFLOW.to_string()) {
+            eprintln!(
+                "[otlp-output] Label '{}' reached {} distinct values; further values are recorded as {}",
+                label, self.max_values, METRIC_LABEL_OVERFLOW
+            );
+        }
+        METRIC_LABEL_OVERFLOW.to_string()
+    }
+}
+
+/// What happens to a data point whose attribute set would exceed the
+/// instrument's cardinality limit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CardinalityOverflow {
+    /// Discard the data point.
+    Drop,
+    /// Record it without attributes, keeping the instrument's totals intact.
+    Collapse,
+}
+
+impl CardinalityOverflow {
+    fn as_str(self) -> &'static str {
+        match self {
+            CardinalityOverflow::Drop => "drop",
+            CardinalityOverflow::Collapse => "collapse",
+        }
+    }
+}
+
+/// Tracks the distinct attribute sets seen per instrument and applies the
+/// overflow policy once an instrument reaches its limit. Sets already
+/// admitted keep recording normally.
+struct CardinalityGuard {
+    limit: usize,
+    overflow: CardinalityOverflow,
+    seen: HashMap<String, std::collections::HashSet<u64>>,
+    overflowed: u64,
+}
+
+impl CardinalityGuard {
+    fn new(limit: usize, overflow: CardinalityOverflow) -> Self {
+        Self {
+            limit,
+            overflow,
+            seen: HashMap::new(),
+            overflowed: 0,
+        }
+    }
+
+    /// Attributes to record a data point with, or `None` to drop it.
+    fn admit<'a>(&mut self, instrument: &str, attributes: &'a [KeyValue]) -> Option<&'a [KeyValue]> {
+        if attributes.is_empty() {
+            return Some(attributes);
+        }
+        let key: String = attributes
+            .iter()
+            .map(|kv| format!("{}={}\u{1f}", kv.key, kv.value))
+            .collect();
+        let hash = sample_hash(&key);
+
+        let seen = self.seen.entry(instrument.to_string()).or_default();
+        if seen.contains(&hash) {
+            return Some(attributes);
+        }
+        if seen.len() < self.limit {
+            seen.insert(hash);
+            return Some(attributes);
+        }
+
+        self.overflowed += 1;
+        shared().pipeline.cardinality_overflows.fetch_add(1, Ordering::Relaxed);
+        if self.overflowed.is_power_of_two() {
+            eprintln!(
+                "[otlp-output] Cardinality limit ({}) reached for '{}', {} data points {} so far",
+                self.limit,
+                instrument,
+                self.overflowed,
+                match self.overflow {
+                    CardinalityOverflow::Drop => "dropped",
+                    CardinalityOverflow::Collapse => "collapsed",
+                }
+            );
+        }
+        match self.overflow {
+            CardinalityOverflow::Drop => None,
+            CardinalityOverflow::Collapse => Some(&[]),
+        }
+    }
+}
+
+/// Pipeline counters exported under `telemetry.self`: name suffix,
+/// description, unit, and accessor. Gauges are marked `false`.
+type PipelineField = fn(&PipelineCounters) -> &AtomicU64;
+const SELF_METRICS: &[(&str, &str, &str, PipelineField, bool)] = &[
+    ("events.future_clamped", "Events with future timestamps clamped to now", "{event}", |p| &p.future_clamped, true),
+    ("events.unknown_level", "Log and span events with an unrecognized level", "{event}", |p| &p.unknown_levels, true),
+    ("events.invalid_timestamp", "Events stored with the current time because theirs was missing or unparseable", "{event}", |p| &p.invalid_timestamps, true),
+    ("events.level_filtered", "Log events below their target's minimum level", "{event}", |p| &p.level_filtered, true),
+    ("events.short_span_filtered", "Spans shorter than minSpanDurationMs", "{event}", |p| &p.short_spans_filtered, true),
+    ("events.transform_dropped", "Events dropped by transformScript", "{event}", |p| &p.transform_dropped, true),
+    ("events.transform_errors", "Events transformScript failed on", "{event}", |p| &p.transform_errors, true),
+    ("events.rate_limited", "Log and span events dropped by per-target rate limits", "{event}", |p| &p.rate_limited, true),
+    ("sampling.adaptive_boosts", "Sampling boosts started by adaptiveSampling", "{boost}", |p| &p.adaptive_boosts, true),
+    ("events.deduplicated", "Repeated log lines collapsed into summaries", "{event}", |p| &p.logs_deduplicated, true),
+    ("storage.serialize_errors", "Records not stored because they couldn't be encoded", "{record}", |p| &p.serialize_errors, true),
+    ("mirror.failures", "Failed writes to mirror backends", "{operation}", |p| &p.mirror_failures, true),
+    ("mirror.dropped", "Mirror writes dropped because the mirror's queue was full", "{operation}", |p| &p.mirror_dropped, true),
+    ("output.failures", "Records an output failed to write", "{record}", |p| &p.output_failures, true),
+    ("file.bytes_written", "Bytes written to telemetry log files", "By", |p| &p.file_bytes_written, true),
+    ("file.dropped_no_writer", "Records dropped because a log file couldn't be opened", "{record}", |p| &p.file_dropped_no_writer, true),
+    ("otlp.buffer_dropped", "Observations dropped from the OTLP startup buffer", "{observation}", |p| &p.otlp_buffer_dropped, true),
+    ("otlp.export_failures", "Metric exports that failed after all retries", "{export}", |p| &p.otlp_export_failures, true),
+    ("otlp.skipped", "Records skipped by the OTLP output while its circuit breaker was open", "{record}", |p| &p.otlp_skipped, true),
+    ("otlp.spooled", "Failed export batches written to the disk spool", "{batch}", |p| &p.otlp_spooled, true),
+    ("otlp.spool_dropped", "Spooled batches dropped to stay within the size cap", "{batch}", |p| &p.otlp_spool_dropped, true),
+    ("otlp.instruments_rejected", "Custom metric records dropped because otlpMaxInstruments was reached", "{record}", |p| &p.otlp_instruments_rejected, true),
+    ("otlp.cardinality_overflows", "Data points dropped or collapsed past the cardinality limit", "{point}", |p| &p.cardinality_overflows, true),
+    ("elasticsearch.dropped", "Log records not indexed into Elasticsearch", "{record}", |p| &p.elasticsearch_dropped, true),
+    ("loki.dropped", "Log records not pushed to Loki", "{record}", |p| &p.loki_dropped, true),
+    ("kafka.dropped", "Records not published to Kafka", "{record}", |p| &p.kafka_dropped, true),
+    ("syslog.dropped", "Log records not sent to syslog", "{record}", |p| &p.syslog_dropped, true),
+    ("archive.uploads", "Rotated files uploaded to the archive", "{file}", |p| &p.archive_uploads, true),
+    ("archive.failures", "Archive uploads that failed after retries", "{file}", |p| &p.archive_failures, true),
+    ("output.ops_dropped", "File compressions and uploads dropped by a full queue", "{file}", |p| &p.output_ops_dropped, true),
+    ("queue.saturated", "Receives that found the event channel full", "{event}", |p| &p.queue_saturated, true),
+    ("pipeline.lag", "Delay between event timestamp and processing", "ms", |p| &p.lag_ms, false),
+    ("queue.depth", "Events waiting in the writer's channel", "{event}", |p| &p.queue_depth, false),
+    ("queue.high_water", "Deepest the writer's channel has been since startup", "{event}", |p| &p.queue_high_water, false),
+];
+
+/// Per-kind pipeline counters exported under `telemetry.self` with a `kind`
+/// attribute: name suffix, description, unit, and accessor.
+type KindField = fn(&KindCounters) -> &[AtomicU64; 3];
+const SELF_KIND_METRICS: &[(&str, &str, &str, KindField)] = &[
+    ("events.processed", "Events received from the core dispatch", "{event}", |k| &k.processed),
+    ("events.dropped", "Events dropped by sampling", "{event}", |k| &k.dropped),
+    ("storage.failures", "Failed table writes", "{record}", |k| &k.write_errors),
+];
+
+/// Register observable instruments reading the shared pipeline counters.
+/// They're sampled on each export interval, so they add no per-event work.
+fn register_self_metrics(meter: &Meter) {
+    for &(suffix, description, unit, field) in SELF_KIND_METRICS {
+        meter
+            .u64_observable_counter(format!("telemetry.self.{}", suffix))
+            .with_description(description)
+            .with_unit(unit)
+            .with_callback(move |obs| {
+                let counters = field(&shared().pipeline.by_kind);
+                for kind in RecordKind::ALL {
+                    obs.observe(
+                        counters[kind.index()].load(Ordering::Relaxed),
+                        &[KeyValue::new("kind", kind.as_str())],
+                    );
+                }
+            })
+            .build();
+    }
+    for &(suffix, description, unit, field, monotonic) in SELF_METRICS {
+        let name = format!("telemetry.self.{}", suffix);
+        if monotonic {
+            meter
+                .u64_observable_counter(name)
+                .with_description(description)
+                .with_unit(unit)
+                .with_callback(move |obs| {
+                    obs.observe(field(&shared().pipeline).load(Ordering::Relaxed), &[])
+                })
+                .build();
+        } else {
+            meter
+                .u64_observable_gauge(name)
+                .with_description(description)
+                .with_unit(unit)
+                .with_callback(move |obs| {
+                    obs.observe(field(&shared().pipeline).load(Ordering::Relaxed), &[])
+                })
+                .build();
+        }
+    }
+}
+
+/// Default cap on cached custom-metric instruments.
+const DEFAULT_OTLP_MAX_INSTRUMENTS: usize = 1000;
+
+/// Default number of request observations buffered before the collector is up.
+const DEFAULT_OTLP_STARTUP_BUFFER: usize = 1000;
+
+/// OTLP wire protocol, from `otlpProtocol` or `OTEL_EXPORTER_OTLP_PROTOCOL`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum OtlpProtocol {
+    /// gRPC via tonic (default)
+    Grpc,
+    /// Protobuf over HTTP POST to `<endpoint>/v1/<signal>`
+    HttpProtobuf,
+}
+
+impl OtlpProtocol {
+    fn from_config(telemetry: &serde_json::Value) -> Self {
+        let value = telemetry
+            .get("otlpProtocol")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string())
+            .or_else(|| otel_env("OTEL_EXPORTER_OTLP_METRICS_PROTOCOL"))
+            .or_else(|| otel_env("OTEL_EXPORTER_OTLP_PROTOCOL"));
+        match value.as_deref() {
+            None | Some("grpc") => Self::Grpc,
+            Some("http/protobuf") => Self::HttpProtobuf,
+            Some(other) => {
+                eprintln!(
+                    "[yeti-telemetry] WARNING: unknown otlpProtocol '{}' (expected grpc or http/protobuf), using grpc",
+                    other
+                );
+                Self::Grpc
+            }
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Grpc => "grpc",
+            Self::HttpProtobuf => "http/protobuf",
+        }
+    }
+}
+
+/// Per-signal URL for OTLP/HTTP. The endpoint is treated as the collector's
+/// base URL; a trailing `/v1/<signal>` is replaced, so either form works.
+fn http_signal_url(endpoint: &str, signal: &str) -> String {
+    let mut base = endpoint.trim_end_matches('/');
+    for known in ["metrics", "traces", "logs"] {
+        if let Some(stripped) = base.strip_suffix(&format!("/v1/{}", known)) {
+            base = stripped;
+            break;
+        }
+    }
+    format!("{}/v1/{}", base, signal)
+}
+
+/// Environment variables that override `telemetry` keys for OTLP export,
+/// so one image can run in several environments without editing files.
+/// `YETI_OTLP_HEADERS` uses the `key=value,...` format.
+const YETI_OTLP_ENV: &[(&str, &str, YetiEnvKind)] = &[
+    ("YETI_OTLP_ENDPOINT", "otlpEndpoint", YetiEnvKind::String),
+    ("YETI_OTLP_PROTOCOL", "otlpProtocol", YetiEnvKind::String),
+    ("YETI_OTLP_SERVICE_NAME", "serviceName", YetiEnvKind::String),
+    ("YETI_OTLP_HEADERS", "otlpHeaders", YetiEnvKind::Pairs),
+    ("YETI_OTLP_TIMEOUT_MS", "otlpTimeoutMs", YetiEnvKind::Number),
+    ("YETI_OTLP_METRICS_ENABLED", "metrics", YetiEnvKind::Bool),
+    ("YETI_OTLP_TRACES_ENABLED", "traces", YetiEnvKind::Bool),
+    ("YETI_OTLP_LOGS_ENABLED", "logs", YetiEnvKind::Bool),
+    ("YETI_OTLP_SELF_METRICS", "selfMetrics", YetiEnvKind::Bool),
+    ("YETI_OTLP_SPOOL", "otlpSpool", YetiEnvKind::Bool),
+];
+
+#[derive(Clone, Copy)]
+enum YetiEnvKind {
+    String,
+    Bool,
+    Number,
+    Pairs,
+}
+
+/// The `YETI_OTLP_*` variables of the process environment.
+fn yeti_otlp_env() -> HashMap<String, String> {
+    std::env::vars().filter(|(name, _)| name.starts_with("YETI_OTLP_")).collect()
+}
+
+/// Overwrite `telemetry` keys with the non-empty `YETI_OTLP_*` variables in
+/// `env`. A boolean variable that isn't `true`/`false`/`1`/`0`, or a numeric
+/// one that isn't a whole number, is ignored with a warning, leaving the
+/// `telemetry` value in place.
+fn apply_yeti_otlp_env(telemetry: &mut serde_json::Value, env: &HashMap<String, String>) {
+    if !telemetry.is_object() {
+        *telemetry = json!({});
+    }
+    for (var, key, kind) in YETI_OTLP_ENV {
+        let Some(raw) = env.get(*var).filter(|v| !v.trim().is_empty()) else { continue };
+        let value = match kind {
+            YetiEnvKind::String => json!(raw.trim()),
+            YetiEnvKind::Pairs => parse_otel_pairs(raw)
+                .into_iter()
+                .map(|(k, v)| (k, json!(v)))
+                .collect::<serde_json::Map<_, _>>()
+                .into(),
+            YetiEnvKind::Bool => match raw.trim().to_ascii_lowercase().as_str() {
+                "true" | "1" => json!(true),
+                "false" | "0" => json!(false),
+                _ => {
+                    eprintln!("[yeti-telemetry] Ignoring {}='{}' (expected true or false)", var, raw);
+                    continue;
+                }
+            },
+            YetiEnvKind::Number => match raw.trim().parse::<u64>() {
+                Ok(n) => json!(n),
+                Err(_) => {
+                    eprintln!("[yeti-telemetry] Ignoring {}='{}' (expected a whole number)", var, raw);
+                    continue;
+                }
+            },
+        };
+        telemetry[*key] = value;
+    }
+}
+
+/// Read a non-empty OpenTelemetry env var.
+fn otel_env(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.trim().is_empty())
+}
+
+/// The `otlpHeaders` map, with `${VAR}` references in values replaced from
+/// the environment. A header referencing an unset variable is skipped with a
+/// warning rather than sent with a blank credential.
+fn otlp_headers_from_config(telemetry: &serde_json::Value) -> Vec<(String, String)> {
+    let Some(map) = telemetry.get("otlpHeaders").and_then(|v| v.as_object()) else {
+        return Vec::new();
+    };
+    map.iter()
+        .filter_map(|(key, value)| {
+            let Some(value) = value.as_str() else {
+                eprintln!("[yeti-telemetry] Skipping otlpHeaders.{} (value must be a string)", key);
+                return None;
+            };
+            match interpolate_env(value) {
+                Ok(value) => Some((key.to_ascii_lowercase(), value)),
+                Err(var) => {
+                    eprintln!(
+                        "[yeti-telemetry] WARNING: skipping otlpHeaders.{}: environment variable {} is not set",
+                        key, var
+                    );
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// One `telemetry` section per `otlpEndpoints` entry, with its name. Each is
+/// the shared section with the entry's `endpoint`, `serviceName`, `headers`
+/// and `protocol` in place of `otlpEndpoint`, `serviceName`, `otlpHeaders`
+/// and `otlpProtocol`, so every other OTLP setting applies to all of them.
+/// Headers are never shared: an entry without `headers` sends none, so one
+/// destination's credentials can't reach another. Entries without an
+/// endpoint, and names that aren't unique or not made of letters, digits,
+/// `-` and `_`, are skipped with a warning.
+fn otlp_endpoint_sections(telemetry: &serde_json::Value) -> Vec<(String, serde_json::Value)> {
+    let Some(entries) = telemetry.get("otlpEndpoints").and_then(|v| v.as_array()) else {
+        eprintln!("[yeti-telemetry] Ignoring otlpEndpoints (must be a list)");
+        return Vec::new();
+    };
+    if telemetry.get("otlpEndpoint").is_some() {
+        eprintln!("[yeti-telemetry] WARNING: otlpEndpoint is ignored when otlpEndpoints is set");
+    }
+    let mut sections: Vec<(String, serde_json::Value)> = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let name = match entry.get("name") {
+            None => (i + 1).to_string(),
+            Some(name) => name.as_str().unwrap_or("").to_string(),
+        };
+        let valid_name = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid_name || sections.iter().any(|(n, _)| *n == name) {
+            eprintln!(
+                "[yeti-telemetry] Skipping otlpEndpoints[{}]: name must be unique and use only letters, digits, - and _",
+                i
+            );
+            continue;
+        }
+        let Some(endpoint) = entry.get("endpoint").and_then(|v| v.as_str()).filter(|e| !e.is_empty()) else {
+            eprintln!("[yeti-telemetry] Skipping otlpEndpoints[{}]: endpoint is required", i);
+            continue;
+        };
+        let mut section = telemetry.clone();
+        if let Some(map) = section.as_object_mut() {
+            map.remove("otlpEndpoints");
+            map.remove("otlpHeaders");
+            map.insert("otlpEndpoint".to_string(), json!(endpoint));
+            let keys = [("serviceName", "serviceName"), ("headers", "otlpHeaders"), ("protocol", "otlpProtocol")];
+            for (from, to) in keys {
+                if let Some(value) = entry.get(from) {
+                    map.insert(to.to_string(), value.clone());
+                }
+            }
+        }
+        sections.push((name, section));
+    }
+    sections
+}
+
+/// Effective OTLP settings: the single output's settings for `otlpEndpoint`,
+/// a list for `otlpEndpoints`, or null when OTLP is off.
+fn otlp_effective_json(outputs: &[OtlpOutput]) -> serde_json::Value {
+    match outputs {
+        [] => serde_json::Value::Null,
+        [single] if single.config.name == "otlp" => single.config.to_json(),
+        outputs => outputs.iter().map(|o| o.config.to_json()).collect(),
+    }
+}
+
+/// Circuit breaker state for the status response, shaped like
+/// [`otlp_effective_json`]: one object for `otlpEndpoint`, keyed by output
+/// name for `otlpEndpoints`.
+fn otlp_breakers_json() -> serde_json::Value {
+    match shared().otlp_breakers.get().map(Vec::as_slice) {
+        None | Some([]) => serde_json::Value::Null,
+        Some([(name, breaker)]) if name == "otlp" => breaker.to_json(),
+        Some(breakers) => breakers
+            .iter()
+            .map(|(name, breaker)| (name.clone(), breaker.to_json()))
+            .collect::<serde_json::Map<_, _>>()
+            .into(),
+    }
+}
+
+/// `service.name` and the other resource attributes for OTLP exports, from
+/// an OTLP-resolved `telemetry` section (`YETI_OTLP_*` applied). Attributes
+/// other than `service.name` are sorted by key.
+fn otlp_resource(telemetry: &serde_json::Value) -> (String, Vec<(String, String)>) {
+    let mut configured_attributes = resource_attributes_from_config(telemetry);
+    let configured_service_name = configured_attributes.remove("service.name");
+
+    let service_name = telemetry
+        .get("serviceName")
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string())
+        .or(configured_service_name)
+        .or_else(|| otel_env("OTEL_SERVICE_NAME"))
+        .or_else(|| {
+            otel_env("OTEL_RESOURCE_ATTRIBUTES").and_then(|attrs| {
+                parse_otel_pairs(&attrs)
+                    .into_iter()
+                    .find(|(k, _)| k == "service.name")
+                    .map(|(_, v)| v)
+            })
+        })
+        .unwrap_or_else(|| "yeti".to_string());
+
+    // Defaults for the standard keys, unless `OTEL_RESOURCE_ATTRIBUTES`
+    // (read by the SDK) or `resourceAttributes` sets them.
+    let env_attributes: Vec<String> = otel_env("OTEL_RESOURCE_ATTRIBUTES")
+        .map(|attrs| parse_otel_pairs(&attrs).into_iter().map(|(k, _)| k).collect())
+        .unwrap_or_default();
+    let host = host_name();
+    let build = shared().build_info.get();
+    let defaults = [
+        ("service.version", build.and_then(|b| b.version.clone())),
+        ("vcs.ref.head.revision", build.and_then(|b| b.commit.clone())),
+        ("deployment.environment", Some(active_environment())),
+        ("host.name", host.clone()),
+        (
+            "service.instance.id",
+            Some(format!("{}-{}", host.as_deref().unwrap_or("yeti"), std::process::id())),
+        ),
+    ];
+    for (key, value) in defaults {
+        if let Some(value) = value.filter(|_| !env_attributes.iter().any(|k| k == key)) {
+            configured_attributes.entry(key.to_string()).or_insert(value);
+        }
+    }
+    (service_name, configured_attributes.into_iter().collect())
+}
+
+/// OTLP JSON `resource` for `fileSchema: otel` files: the attributes the
+/// OTLP output attaches, including those the SDK reads from
+/// `OTEL_RESOURCE_ATTRIBUTES`, so files and exports agree.
+fn otel_file_resource(root_dir: &str) -> serde_json::Value {
+    let mut telemetry = read_telemetry_section(root_dir).unwrap_or(json!({}));
+    apply_yeti_otlp_env(&mut telemetry, &yeti_otlp_env());
+    let (service_name, configured) = otlp_resource(&telemetry);
+    let mut attributes: std::collections::BTreeMap<String, String> = otel_env("OTEL_RESOURCE_ATTRIBUTES")
+        .map(|attrs| parse_otel_pairs(&attrs).into_iter().collect())
+        .unwrap_or_default();
+    attributes.extend(configured);
+    attributes.insert("service.name".to_string(), service_name);
+    let attributes: Vec<KeyValue> = attributes.into_iter().map(|(k, v)| KeyValue::new(k, v)).collect();
+    json!({ "attributes": otlp_json_attributes(&attributes) })
+}
+
+/// `resourceAttributes` as strings, with `${VAR}` interpolated. Entries that
+/// aren't scalars or reference an unset variable are skipped with a warning.
+fn resource_attributes_from_config(
+    telemetry: &serde_json::Value,
+) -> std::collections::BTreeMap<String, String> {
+    let Some(map) = telemetry.get("resourceAttributes").and_then(|v| v.as_object()) else {
+        return Default::default();
+    };
+    map.iter()
+        .filter_map(|(key, value)| {
+            let value = match value {
+                serde_json::Value::String(s) => s.clone(),
+                serde_json::Value::Number(n) => n.to_string(),
+                serde_json::Value::Bool(b) => b.to_string(),
+                _ => {
+                    eprintln!(
+                        "[yeti-telemetry] Skipping resourceAttributes.{} (value must be a string, number or bool)",
+                        key
+                    );
+                    return None;
+                }
+            };
+            match interpolate_env(&value) {
+                Ok(value) => Some((key.clone(), value)),
+                Err(var) => {
+                    eprintln!(
+                        "[yeti-telemetry] WARNING: skipping resourceAttributes.{}: environment variable {} is not set",
+                        key, var
+                    );
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// `histogramBuckets`: a non-empty, strictly increasing list of finite
+/// boundaries. Anything else is ignored with a warning.
+fn parse_histogram_buckets(value: &serde_json::Value) -> Option<Vec<f64>> {
+    let buckets: Option<Vec<f64>> = value
+        .as_array()
+        .and_then(|items| items.iter().map(|v| v.as_f64().filter(|b| b.is_finite())).collect());
+    match buckets {
+        Some(b) if !b.is_empty() && b.windows(2).all(|w| w[0] < w[1]) => Some(b),
+        _ => {
+            eprintln!(
+                "[yeti-telemetry] Ignoring histogramBuckets (must be a non-empty, increasing list of numbers), using SDK defaults"
+            );
+            None
+        }
+    }
+}
+
+/// Build info file in the app root, e.g. `{"version": "1.4.2", "commit": "9f2c1e7"}`,
+/// typically generated by the deploy pipeline.
+const BUILD_INFO_FILE: &str = "build-info.json";
+
+/// Version and git commit of the running build, for correlating telemetry
+/// with deploys. Each comes from its env var (`YETI_BUILD_VERSION`,
+/// `YETI_BUILD_COMMIT`), else `build-info.json`, else the same variables at
+/// compile time, as a build script can stamp them with `cargo:rustc-env`.
+pub(crate) struct BuildInfo {
+    pub(crate) version: Option<String>,
+    pub(crate) commit: Option<String>,
+}
+
+impl BuildInfo {
+    fn load(root_dir: &str) -> Self {
+        let path = PathBuf::from(root_dir).join(BUILD_INFO_FILE);
+        let file: serde_json::Value = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                eprintln!("[yeti-telemetry] Ignoring {}: {}", path.display(), e);
+                serde_json::Value::Null
+            }),
+            Err(_) => serde_json::Value::Null,
+        };
+        let pick = |var: &str, key: &str, stamped: Option<&str>| {
+            let runtime = std::env::var(var).ok();
+            let value = [runtime.as_deref(), file.get(key).and_then(|v| v.as_str()), stamped]
+                .into_iter()
+                .flatten()
+                .map(str::trim)
+                .find(|v| !v.is_empty())
+                .map(str::to_string);
+            value
+        };
+        Self {
+            version: pick("YETI_BUILD_VERSION", "version", option_env!("YETI_BUILD_VERSION")),
+            commit: pick("YETI_BUILD_COMMIT", "commit", option_env!("YETI_BUILD_COMMIT")),
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        json!({ "version": self.version, "commit": self.commit })
+    }
+}
+
+/// This machine's hostname: `HOSTNAME`, else `/etc/hostname`.
+fn host_name() -> Option<String> {
+    std::env::var("HOSTNAME")
+        .ok()
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|h| h.trim().to_string())
+        .filter(|h| !h.is_empty())
+}
+
+/// Replace each `${VAR}` in `input` with the variable's value. Returns the
+/// name of the first unset variable as the error. An unterminated `${` is
+/// kept literally.
+fn interpolate_env(input: &str) -> std::result::Result<String, String> {
+    let mut out = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find("${") {
+        let Some(len) = rest[start + 2..].find('}') else { break };
+        let var = &rest[start + 2..start + 2 + len];
+        out.push_str(&rest[..start]);
+        out.push_str(&std::env::var(var).map_err(|_| var.to_string())?);
+        rest = &rest[start + 3 + len..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Parse the OTel env list format `key1=value1,key2=value2` (values may be
+/// percent-encoded), as used by `OTEL_EXPORTER_OTLP_HEADERS` and
+/// `OTEL_RESOURCE_ATTRIBUTES`. Malformed entries are skipped.
+fn parse_otel_pairs(raw: &str) -> Vec<(String, String)> {
+    raw.split(',')
+        .filter_map(|pair| {
+            let (key, value) = pair.split_once('=')?;
+            let key = key.trim();
+            if key.is_empty() {
+                return None;
+            }
+            Some((key.to_string(), percent_decode(value.trim())))
+        })
+        .collect()
+}
+
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+            if let Ok(b) = u8::from_str_radix(hex, 16) {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+// ============================================================================
+// OTLP Spool — failed metric exports persisted to disk and replayed
+// ============================================================================
+
+use opentelemetry_proto::tonic::collector::metrics::v1::metrics_service_client::MetricsServiceClient;
+use opentelemetry_proto::tonic::collector::metrics::v1::ExportMetricsServiceRequest;
+use opentelemetry_sdk::error::{OTelSdkError, OTelSdkResult};
+use opentelemetry_sdk::metrics::data::ResourceMetrics;
+use opentelemetry_sdk::metrics::exporter::PushMetricExporter;
+use opentelemetry_sdk::metrics::Temporality;
+use prost::Message;
+
+/// Default cap on the spool directory's total size.
+const DEFAULT_OTLP_SPOOL_MAX_MB: u64 = 100;
+
+/// Failed export batches on disk, one `ExportMetricsServiceRequest` protobuf
+/// per file. File names start with the spool time so a name sort is oldest
+/// first; batches survive restarts and are replayed in that order.
+struct OtlpSpool {
+    dir: PathBuf,
+    max_bytes: u64,
+    target: ExportTarget,
+    seq: AtomicU64,
+}
+
+/// Where and how batches are sent outside the SDK exporter, by spool replay
+/// and the readiness probe: the exporter's own collector settings, and for
+/// gRPC its channel, so they use the same connection and TLS as live exports.
+#[derive(Clone)]
+struct ExportTarget {
+    /// The metric exporter's gRPC channel; `None` under `otlpProtocol: http`.
+    channel: Option<tonic::transport::Channel>,
+    /// Collector endpoint for HTTP.
+    endpoint: String,
+    /// gRPC request metadata.
+    metadata: MetadataMap,
+    /// HTTP request headers.
+    headers: Vec<(String, String)>,
+    timeout: std::time::Duration,
+}
+
+/// Connection used to send batches to an `ExportTarget`.
+enum ExportClient {
+    Grpc(MetricsServiceClient<tonic::transport::Channel>),
+    Http(reqwest::blocking::Client),
+}
+
+impl OtlpSpool {
+    fn new(dir: PathBuf, max_bytes: u64, target: ExportTarget) -> Self {
+        if let Err(e) = fs::create_dir_all(&dir) {
+            eprintln!("[otlp-spool] Failed to create {}: {}", dir.display(), e);
+        }
+        let pending = Self::batches_in(&dir).len();
+        if pending > 0 {
+            eprintln!("[otlp-spool] {} spooled batches pending replay", pending);
+        }
+        Self {
+            dir,
+            max_bytes,
+            target,
+            seq: AtomicU64::new(0),
+        }
+    }
+
+    /// Persist a batch. Written to a temp file and renamed into place, so a
+    /// crash never leaves a partial batch that replay would pick up.
+    fn push(&self, request: &ExportMetricsServiceRequest) {
+        let name = format!(
+            "batch-{:013}-{:06}.pb",
+            now_epoch_ms() as u64,
+            self.seq.fetch_add(1, Ordering::Relaxed)
+        );
+        let tmp = self.dir.join(format!("{}.tmp", name));
+        let written = fs::write(&tmp, request.encode_to_vec())
+            .and_then(|_| fs::rename(&tmp, self.dir.join(&name)));
+        if let Err(e) = written {
+            let _ = fs::remove_file(&tmp);
+            eprintln!("[otlp-spool] Failed to spool batch: {}", e);
+            return;
+        }
+        shared().pipeline.otlp_spooled.fetch_add(1, Ordering::Relaxed);
+        self.enforce_cap();
+    }
+
+    /// Delete the oldest batches until the spool fits within `max_bytes`.
+    fn enforce_cap(&self) {
+        let batches = Self::batches_in(&self.dir);
+        let mut total: u64 = batches.iter().map(|(_, size)| size).sum();
+        for (path, size) in batches {
+            if total <= self.max_bytes {
+                break;
+            }
+            if fs::remove_file(&path).is_ok() {
+                total -= size;
+                shared().pipeline.otlp_spool_dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Spooled batch files with their sizes, oldest first.
+    fn batches_in(dir: &PathBuf) -> Vec<(PathBuf, u64)> {
+        let mut batches: Vec<(PathBuf, u64)> = match fs::read_dir(dir) {
+            Ok(entries) => entries
+                .flatten()
+                .filter(|e| e.path().extension().and_then(|x| x.to_str()) == Some("pb"))
+                .map(|e| (e.path(), e.metadata().map(|m| m.len()).unwrap_or(0)))
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        batches.sort();
+        batches
+    }
+
+    /// Resend spooled batches in order, stopping at the first failure so
+    /// the rest wait for the next export. Returns whether the spool is empty
+    /// afterwards.
+    async fn replay(&self) -> bool {
+        let batches = Self::batches_in(&self.dir);
+        if batches.is_empty() {
+            return true;
+        }
+        // Replay runs on the PeriodicReader's thread, so a blocking HTTP
+        // client is fine here.
+        let Some(mut client) = self.target.client() else { return false };
+
+        let mut sent = 0;
+        for (path, _) in &batches {
+            let batch = match fs::read(path)
+                .ok()
+                .and_then(|bytes| ExportMetricsServiceRequest::decode(bytes.as_slice()).ok())
+            {
+                Some(b) => b,
+                None => {
+                    eprintln!("[otlp-spool] Discarding unreadable batch {}", path.display());
+                    let _ = fs::remove_file(path);
+                    continue;
+                }
+            };
+            if !self.target.send(&mut client, batch).await {
+                break;
+            }
+            let _ = fs::remove_file(path);
+            sent += 1;
+        }
+        eprintln!("[otlp-spool] Replayed {} of {} spooled batches", sent, batches.len());
+        Self::batches_in(&self.dir).is_empty()
+    }
+}
+
+impl ExportTarget {
+    fn client(&self) -> Option<ExportClient> {
+        match &self.channel {
+            Some(channel) => Some(ExportClient::Grpc(MetricsServiceClient::new(channel.clone()))),
+            None => reqwest::blocking::Client::builder()
+                .timeout(self.timeout)
+                .build()
+                .ok()
+                .map(ExportClient::Http),
+        }
+    }
+
+    /// Send one batch, reporting whether the collector accepted it.
+    async fn send(&self, client: &mut ExportClient, batch: ExportMetricsServiceRequest) -> bool {
+        match client {
+            ExportClient::Grpc(client) => {
+                let mut request = tonic::Request::new(batch);
+                *request.metadata_mut() = self.metadata.clone();
+                request.set_timeout(self.timeout);
+                client.export(request).await.is_ok()
+            }
+            ExportClient::Http(client) => {
+                let mut request = client
+                    .post(http_signal_url(&self.endpoint, "metrics"))
+                    .header("content-type", "application/x-protobuf")
+                    .body(batch.encode_to_vec());
+                for (key, value) in &self.headers {
+                    request = request.header(key.as_str(), value.as_str());
+                }
+                request
+                    .send()
+                    .is_ok_and(|response| response.status().is_success())
+            }
+        }
+    }
+
+    /// Send an empty export and report whether the collector accepted it.
+    /// It goes through the same auth and routing as a real batch.
+    async fn accepts_export(&self) -> bool {
+        let empty = ExportMetricsServiceRequest::default();
+        if self.channel.is_some() {
+            let Some(mut client) = self.client() else { return false };
+            return self.send(&mut client, empty).await;
+        }
+        // The HTTP client blocks, so keep it off the runtime's workers.
+        let target = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let Some(mut client) = target.client() else { return false };
+            futures::executor::block_on(target.send(&mut client, empty))
+        })
+        .await
+        .unwrap_or(false)
+    }
+}
+
+/// Defaults for metric export retries.
+const DEFAULT_OTLP_RETRY_MAX_ATTEMPTS: u32 = 3;
+const DEFAULT_OTLP_RETRY_BACKOFF_MS: u64 = 500;
+const DEFAULT_OTLP_RETRY_MAX_BACKOFF_MS: u64 = 5_000;
+
+/// Minimum gap between warnings about a run of failed exports.
+const OTLP_FAILURE_WARN_INTERVAL_MS: u64 = 60_000;
+
+/// Retry policy for a metric export: up to `max_attempts` tries in total,
+/// waiting `initial_backoff` after the first failure and doubling up to
+/// `max_backoff`.
+#[derive(Clone, Copy)]
+struct ExportRetry {
+    max_attempts: u32,
+    initial_backoff: std::time::Duration,
+    max_backoff: std::time::Duration,
+}
+
+impl ExportRetry {
+    fn from_config(telemetry: &serde_json::Value) -> Self {
+        let ms = |key: &str, default: u64| {
+            telemetry.get(key).and_then(|v| v.as_u64()).unwrap_or(default)
+        };
+        let initial = ms("otlpRetryBackoffMs", DEFAULT_OTLP_RETRY_BACKOFF_MS);
+        Self {
+            max_attempts: telemetry
+                .get("otlpRetryMaxAttempts")
+                .and_then(|v| v.as_u64())
+                .filter(|n| *n > 0)
+                .unwrap_or(u64::from(DEFAULT_OTLP_RETRY_MAX_ATTEMPTS)) as u32,
+            initial_backoff: std::time::Duration::from_millis(initial),
+            max_backoff: std::time::Duration::from_millis(
+                ms("otlpRetryMaxBackoffMs", DEFAULT_OTLP_RETRY_MAX_BACKOFF_MS).max(initial),
+            ),
+        }
+    }
+}
+
+/// Consecutive failed exports, for rate-limited warnings and the output's
+/// health.
+#[derive(Default)]
+struct ExportFailures {
+    consecutive: AtomicU64,
+    last_warned_ms: AtomicU64,
+    last_error: Mutex<String>,
+}
+
+impl ExportFailures {
+    /// Warn on the first failure of a run, then at most once per
+    /// `OTLP_FAILURE_WARN_INTERVAL_MS` while it continues.
+    fn failed(&self, error: &dyn std::fmt::Display) {
+        shared().pipeline.otlp_export_failures.fetch_add(1, Ordering::Relaxed);
+        *lock(&self.last_error) = error.to_string();
+        let run = self.consecutive.fetch_add(1, Ordering::Relaxed) + 1;
+        let now = now_epoch_ms() as u64;
+        let last = self.last_warned_ms.load(Ordering::Relaxed);
+        if run == 1 || now.saturating_sub(last) >= OTLP_FAILURE_WARN_INTERVAL_MS {
+            self.last_warned_ms.store(now, Ordering::Relaxed);
+            eprintln!(
+                "[otlp-output] WARNING: metric export failed ({} in a row): {}",
+                run, error
+            );
+        }
+    }
+
+    fn succeeded(&self) {
+        let run = self.consecutive.swap(0, Ordering::Relaxed);
+        if run > 0 {
+            eprintln!("[otlp-output] Metric export recovered after {} failed exports", run);
+        }
+    }
+}
+
+/// Defaults for the OTLP circuit breaker.
+const DEFAULT_OTLP_BREAKER_THRESHOLD: u64 = 5;
+const DEFAULT_OTLP_BREAKER_COOLDOWN_MS: u64 = 30_000;
+
+/// Circuit breaker in front of the OTLP output, so a dead collector can't
+/// slow the writer down. After `threshold` consecutive failed setups or
+/// metric exports it opens, and records are skipped until `cooldown_ms` has
+/// passed. It then half-opens: records flow again, and the next failure
+/// reopens it while a success closes it. Metric exports keep running while
+/// it's open, so the first one that succeeds closes it early.
+pub(crate) struct OtlpBreaker {
+    threshold: u64,
+    cooldown_ms: u64,
+    consecutive: AtomicU64,
+    /// Epoch ms until which the breaker is open; 0 while closed.
+    open_until_ms: AtomicU64,
+    half_open: AtomicBool,
+    /// Times the breaker has opened since startup.
+    opened: AtomicU64,
+}
+
+impl OtlpBreaker {
+    fn new(threshold: u64, cooldown_ms: u64) -> Self {
+        Self {
+            threshold,
+            cooldown_ms,
+            consecutive: AtomicU64::new(0),
+            open_until_ms: AtomicU64::new(0),
+            half_open: AtomicBool::new(false),
+            opened: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether OTLP work may run now. The first call after the cooldown
+    /// half-opens the breaker.
+    fn allow(&self) -> bool {
+        let until = self.open_until_ms.load(Ordering::Relaxed);
+        if until == 0 {
+            return true;
+        }
+        if (now_epoch_ms() as u64) < until {
+            return false;
+        }
+        if !self.half_open.swap(true, Ordering::Relaxed) {
+            eprintln!("[otlp-output] Circuit breaker half-open, retrying the collector");
+        }
+        true
+    }
+
+    fn failed(&self) {
+        let run = self.consecutive.fetch_add(1, Ordering::Relaxed) + 1;
+        let half_open = self.half_open.swap(false, Ordering::Relaxed);
+        if self.threshold == 0 || !(half_open || run >= self.threshold) {
+            return;
+        }
+        let until = now_epoch_ms() as u64 + self.cooldown_ms;
+        let was_closed = self.open_until_ms.swap(until, Ordering::Relaxed) == 0;
+        if was_closed || half_open {
+            self.opened.fetch_add(1, Ordering::Relaxed);
+            eprintln!(
+                "[otlp-output] WARNING: circuit breaker open after {} failures in a row; skipping OTLP for {}ms",
+                run, self.cooldown_ms
+            );
+        }
+    }
+
+    fn succeeded(&self) {
+        self.consecutive.store(0, Ordering::Relaxed);
+        self.half_open.store(false, Ordering::Relaxed);
+        if self.open_until_ms.swap(0, Ordering::Relaxed) != 0 {
+            eprintln!("[otlp-output] Circuit breaker closed, collector recovered");
+        }
+    }
+
+    /// Open and still cooling down.
+    fn is_open(&self) -> bool {
+        let until = self.open_until_ms.load(Ordering::Relaxed);
+        until != 0 && (now_epoch_ms() as u64) < until
+    }
+
+    /// `closed`, `open`, or `halfOpen` once the cooldown has passed.
+    fn state(&self) -> &'static str {
+        if self.open_until_ms.load(Ordering::Relaxed) == 0 {
+            "closed"
+        } else if self.is_open() {
+            "open"
+        } else {
+            "halfOpen"
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> serde_json::Value {
+        let until = self.open_until_ms.load(Ordering::Relaxed);
+        json!({
+            "state": self.state(),
+            "consecutiveFailures": self.consecutive.load(Ordering::Relaxed),
+            "threshold": self.threshold,
+            "cooldownMs": self.cooldown_ms,
+            "openUntil": self.is_open().then(|| format_epoch_ms(until as f64)),
+            "timesOpened": self.opened.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// Wraps the OTLP exporter: retries failed exports with backoff, then spools
+/// batches that still fail. Spooled batches are replayed before each export,
+/// and while any remain the new batch is spooled behind them, so the
+/// collector always receives batches oldest first.
+struct SpoolingExporter {
+    inner: opentelemetry_otlp::MetricExporter,
+    retry: ExportRetry,
+    failures: Arc<ExportFailures>,
+    breaker: Arc<OtlpBreaker>,
+    /// The output's startup-buffer gate, opened by the first accepted export.
+    collector_ready: Arc<AtomicBool>,
+    spool: Option<Arc<OtlpSpool>>,
+}
+
+impl SpoolingExporter {
+    /// Export with retries. The PeriodicReader calls this on its own thread,
+    /// so backing off blocks only the next collection, never the writer.
+    async fn export_with_retry(&self, metrics: &ResourceMetrics) -> OTelSdkResult {
+        let mut backoff = self.retry.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            match self.inner.export(metrics).await {
+                Ok(()) => {
+                    self.failures.succeeded();
+                    self.breaker.succeeded();
+                    self.collector_ready.store(true, Ordering::Relaxed);
+                    return Ok(());
+                }
+                Err(e) if attempt >= self.retry.max_attempts => {
+                    self.failures.failed(&e);
+                    self.breaker.failed();
+                    return Err(e);
+                }
+                Err(_) => {
+                    std::thread::sleep(backoff);
+                    backoff = (backoff * 2).min(self.retry.max_backoff);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+impl PushMetricExporter for SpoolingExporter {
+    async fn export(&self, metrics: &ResourceMetrics) -> OTelSdkResult {
+        let Some(ref spool) = self.spool else {
+            return self.export_with_retry(metrics).await;
+        };
+        if !spool.replay().await {
+            spool.push(&ExportMetricsServiceRequest::from(metrics));
+            let e = OTelSdkError::InternalFailure("spooled batches could not be replayed".to_string());
+            self.failures.failed(&e);
+            self.breaker.failed();
+            return Err(e);
+        }
+        let result = self.export_with_retry(metrics).await;
+        if result.is_err() {
+            spool.push(&ExportMetricsServiceRequest::from(metrics));
+        }
+        result
+    }
+
+    fn force_flush(&self) -> OTelSdkResult {
+        self.inner.force_flush()
+    }
+
+    fn shutdown_with_timeout(&self, timeout: std::time::Duration) -> OTelSdkResult {
+        self.inner.shutdown_with_timeout(timeout)
+    }
+
+    fn temporality(&self) -> Temporality {
+        self.inner.temporality()
+    }
+}
+
+fn epoch_ms_to_system_time(ms: f64) -> SystemTime {
+    std::time::UNIX_EPOCH + std::time::Duration::from_micros((ms * 1000.0) as u64)
+}
+
+/// OpenTelemetry severity for a tracing level name.
+fn otel_severity(level: &str) -> Severity {
+    match level.to_ascii_uppercase().as_str() {
+        "TRACE" => Severity::Trace,
+        "DEBUG" => Severity::Debug,
+        "WARN" | "WARNING" => Severity::Warn,
+        "ERROR" => Severity::Error,
+        "FATAL" | "CRITICAL" => Severity::Fatal,
+        _ => Severity::Info,
+    }
+}
+
+/// A JSON value as a log attribute, keeping scalar types.
+fn json_any_value(value: serde_json::Value) -> AnyValue {
+    match value {
+        serde_json::Value::String(s) => AnyValue::from(s),
+        serde_json::Value::Bool(b) => AnyValue::from(b),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => AnyValue::from(i),
+            None => AnyValue::from(n.as_f64().unwrap_or(0.0)),
+        },
+        other => AnyValue::from(other.to_string()),
+    }
+}
+
+/// OTLP attributes from a JSON object; non-string values are stringified.
+fn json_attributes(map: &serde_json::Map<String, serde_json::Value>) -> Vec<KeyValue> {
+    map.iter()
+        .map(|(k, v)| match v {
+            serde_json::Value::String(s) => KeyValue::new(k.clone(), s.clone()),
+            other => KeyValue::new(k.clone(), other.to_string()),
+        })
+        .collect()
+}
+
+/// A record's `fields` string decoded as an object; empty when absent or
+/// not an object.
+fn record_fields(record: &serde_json::Value) -> serde_json::Map<String, serde_json::Value> {
+    record
+        .get("fields")
+        .and_then(|v| v.as_str())
+        .and_then(|s| serde_json::from_str::<serde_json::Value>(s).ok())
+        .and_then(|v| v.as_object().cloned())
+        .unwrap_or_default()
+}
+
+/// A stored log record mapped to OTLP log fields. Shared by the OTLP output
+/// and `fileSchema: otel` files so both carry the same data.
+struct OtelLog {
+    severity: Severity,
+    body: String,
+    time_ms: Option<f64>,
+    /// From `traceId`/`spanId` in `fields`, when both are valid.
+    trace: Option<(TraceId, SpanId)>,
+    /// `target`, then every `fields` entry with its JSON type.
+    attributes: Vec<(String, serde_json::Value)>,
+}
+
+impl OtelLog {
+    fn from_record(record: &serde_json::Value) -> Self {
+        let str_field = |key: &str| record.get(key).and_then(|v| v.as_str()).unwrap_or("");
+        let fields = record_fields(record);
+        let trace_id = fields.get("traceId").and_then(|v| v.as_str()).and_then(|s| TraceId::from_hex(s).ok());
+        let span_id = fields.get("spanId").and_then(|v| v.as_str()).and_then(|s| SpanId::from_hex(s).ok());
+        let mut attributes = vec![("target".to_string(), json!(str_field("target")))];
+        attributes.extend(fields);
+        Self {
+            severity: otel_severity(str_field("level")),
+            body: str_field("message").to_string(),
+            time_ms: record.get("timestamp").and_then(parse_stored_time),
+            trace: trace_id.zip(span_id),
+            attributes,
+        }
+    }
+}
+
+/// A stored span mapped to OTLP span fields, with its original timing and
+/// ids. Shared by the OTLP output and `fileSchema: otel` files.
+struct OtelSpan {
+    name: String,
+    start_ms: f64,
+    end_ms: f64,
+    trace_id: Option<TraceId>,
+    span_id: Option<SpanId>,
+    /// Only set for a valid (non-zero) parent id.
+    parent_id: Option<SpanId>,
+    attributes: Vec<KeyValue>,
+    /// `fields.status` is `ERROR`.
+    error: bool,
+}
+
+impl OtelSpan {
+    /// `None` for spans without a usable start and end time, which are
+    /// skipped rather than given a made-up duration.
+    fn from_record(record: &serde_json::Value) -> Option<Self> {
+        let time = |key: &str| record.get(key).and_then(parse_stored_time).filter(|ms| *ms > 0.0);
+        let (Some(start_ms), Some(end_ms)) = (time("startTime"), time("endTime")) else {
+            return None;
+        };
+        if end_ms < start_ms {
+            return None;
+        }
+
+        let fields = record_fields(record);
+        let id = |key: &str| {
+            [record.get(key), fields.get(key)]
+                .into_iter()
+                .flatten()
+                .filter_map(|v| v.as_str())
+                .find(|s| !s.is_empty())
+                .map(|s| s.to_string())
+        };
+
+        let mut attributes = vec![
+            KeyValue::new("code.namespace", record.get("target").and_then(|v| v.as_str()).unwrap_or("").to_string()),
+            KeyValue::new("level", record.get("level").and_then(|v| v.as_str()).unwrap_or("").to_string()),
+        ];
+        attributes.extend(json_attributes(&fields));
+
+        Some(Self {
+            name: record.get("name").and_then(|v| v.as_str()).unwrap_or("span").to_string(),
+            start_ms,
+            end_ms,
+            trace_id: id("traceId").and_then(|s| TraceId::from_hex(&s).ok()),
+            span_id: id("spanId").and_then(|s| SpanId::from_hex(&s).ok()),
+            parent_id: id("parentSpanId")
+                .and_then(|s| SpanId::from_hex(&s).ok())
+                .filter(|p| *p != SpanId::INVALID),
+            attributes,
+            error: fields.get("status").and_then(|v| v.as_str()) == Some("ERROR"),
+        })
+    }
+}
+
+/// A stored metric record as the OTLP output forwards it: a gauge named
+/// after the metric, with its `attributes`.
+struct OtelGauge {
+    name: String,
+    value: f64,
+    time_ms: Option<f64>,
+    attributes: Vec<KeyValue>,
+}
+
+impl OtelGauge {
+    /// `None` for records without a name.
+    fn from_record(record: &serde_json::Value) -> Option<Self> {
+        let name = record.get("name").and_then(|v| v.as_str()).filter(|n| !n.is_empty())?;
+        let attrs_str = record.get("attributes").and_then(|v| v.as_str()).unwrap_or("{}");
+        let attributes = serde_json::from_str::<serde_json::Value>(attrs_str)
+            .ok()
+            .and_then(|v| v.as_object().cloned())
+            .map(|attrs| json_attributes(&attrs))
+            .unwrap_or_default();
+        Some(Self {
+            name: name.to_string(),
+            value: record.get("value").and_then(|v| v.as_f64()).unwrap_or(0.0),
+            time_ms: record.get("timestamp").and_then(parse_stored_time),
+            attributes,
+        })
+    }
+}
+
+/// One `fileSchema: otel` line: the record as a single-item OTLP JSON export
+/// request (`resourceSpans`, `resourceLogs` or `resourceMetrics`), mapped
+/// like the OTLP output maps it. Ids are lowercase hex and 64-bit integers
+/// are strings, per the OTLP JSON encoding. `None` for records the OTLP
+/// output would skip.
+fn otlp_json_line(
+    event_type: &str,
+    record: &serde_json::Value,
+    resource: &serde_json::Value,
+) -> Option<serde_json::Value> {
+    let scope = json!({ "name": "yeti-telemetry" });
+    let line = match event_type {
+        "span" => {
+            let span = OtelSpan::from_record(record)?;
+            // Spans without their own ids get stable ones from the record id
+            // (a UUID, so 32 hex digits), where the SDK would pick random ones.
+            let record_hex: String = record
+                .get("id")
+                .and_then(|v| v.as_str())
+                .unwrap_or("")
+                .chars()
+                .filter(|c| c.is_ascii_hexdigit())
+                .collect();
+            let fallback = |len: usize| format!("{:0>len$}", &record_hex[record_hex.len().saturating_sub(len)..]);
+            let mut otlp_span = json!({
+                "traceId": span.trace_id.map(|t| t.to_string()).unwrap_or_else(|| fallback(32)),
+                "spanId": span.span_id.map(|s| s.to_string()).unwrap_or_else(|| fallback(16)),
+                "name": span.name,
+                "kind": 1,
+                "startTimeUnixNano": unix_nanos(span.start_ms),
+                "endTimeUnixNano": unix_nanos(span.end_ms),
+                "attributes": otlp_json_attributes(&span.attributes),
+                "status": if span.error { json!({ "code": 2 }) } else { json!({}) },
+            });
+            if let Some(parent_id) = span.parent_id {
+                otlp_span["parentSpanId"] = json!(parent_id.to_string());
+            }
+            json!({ "resourceSpans": [{
+                "resource": resource,
+                "scopeSpans": [{ "scope": scope, "spans": [otlp_span] }],
+            }] })
+        }
+        "log" => {
+            let log = OtelLog::from_record(record);
+            let mut otlp_log = json!({
+                "severityNumber": log.severity as i32,
+                "severityText": log.severity.name(),
+                "body": { "stringValue": log.body },
+                "attributes": log
+                    .attributes
+                    .iter()
+                    .map(|(key, value)| json!({ "key": key, "value": otlp_json_any_value(value) }))
+                    .collect::<Vec<_>>(),
+            });
+            if let Some(ms) = log.time_ms {
+                otlp_log["timeUnixNano"] = json!(unix_nanos(ms));
+                otlp_log["observedTimeUnixNano"] = json!(unix_nanos(ms));
+            }
+            if let Some((trace_id, span_id)) = log.trace {
+                otlp_log["traceId"] = json!(trace_id.to_string());
+                otlp_log["spanId"] = json!(span_id.to_string());
+            }
+            json!({ "resourceLogs": [{
+                "resource": resource,
+                "scopeLogs": [{ "scope": scope, "logRecords": [otlp_log] }],
+            }] })
+        }
+        "metric" => {
+            let gauge = OtelGauge::from_record(record)?;
+            let mut point = json!({
+                "asDouble": gauge.value,
+                "attributes": otlp_json_attributes(&gauge.attributes),
+            });
+            if let Some(ms) = gauge.time_ms {
+                point["timeUnixNano"] = json!(unix_nanos(ms));
+            }
+            json!({ "resourceMetrics": [{
+                "resource": resource,
+                "scopeMetrics": [{
+                    "scope": scope,
+                    "metrics": [{ "name": gauge.name, "gauge": { "dataPoints": [point] } }],
+                }],
+            }] })
+        }
+        _ => return None,
+    };
+    Some(line)
+}
+
+/// Epoch ms as an OTLP JSON `*UnixNano` string, rounded to the microsecond
+/// precision of stored timestamps.
+fn unix_nanos(ms: f64) -> String {
+    (((ms * 1000.0).round() as u64) as u128 * 1000).to_string()
+}
+
+/// OTLP JSON `KeyValue` list.
+fn otlp_json_attributes(attributes: &[KeyValue]) -> Vec<serde_json::Value> {
+    attributes
+        .iter()
+        .map(|kv| {
+            let value = match &kv.value {
+                opentelemetry::Value::String(s) => json!({ "stringValue": s.as_str() }),
+                opentelemetry::Value::Bool(b) => json!({ "boolValue": b }),
+                opentelemetry::Value::I64(i) => json!({ "intValue": i.to_string() }),
+                opentelemetry::Value::F64(f) => json!({ "doubleValue": f }),
+                other => json!({ "stringValue": other.to_string() }),
+            };
+            json!({ "key": kv.key.as_str(), "value": value })
+        })
+        .collect()
+}
+
+/// OTLP JSON `AnyValue` for a JSON value, keeping scalar types like
+/// `json_any_value`.
+fn otlp_json_any_value(value: &serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::String(s) => json!({ "stringValue": s }),
+        serde_json::Value::Bool(b) => json!({ "boolValue": b }),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => json!({ "intValue": i.to_string() }),
+            None => json!({ "doubleValue": n.as_f64().unwrap_or(0.0) }),
+        },
+        other => json!({ "stringValue": other.to_string() }),
+    }
+}
+
+impl Drop for OtlpOutput {
+    fn drop(&mut self) {
+        if let Some(provider) = self.logger_provider.take() {
+            eprintln!("[otlp-output] Shutting down logger provider");
+            if let Err(e) = provider.shutdown() {
+                eprintln!("[otlp-output] Shutdown error: {:?}", e);
+            }
+        }
+        if let Some(provider) = self.tracer_provider.take() {
+            eprintln!("[otlp-output] Shutting down tracer provider");
+            if let Err(e) = provider.shutdown() {
+                eprintln!("[otlp-output] Shutdown error: {:?}", e);
+            }
+        }
+        if let Some(provider) = self.provider.take() {
+            eprintln!("[otlp-output] Shutting down meter provider");
+            if let Err(e) = provider.shutdown() {
+                eprintln!("[otlp-output] Shutdown error: {:?}", e);
+            }
+        }
+    }
+}
+
+impl TelemetryOutput for OtlpOutput {
+    fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    fn write_log(&mut self, record: &serde_json::Value) {
+        if !self.breaker_allows() {
+            return;
+        }
+        self.ensure_initialized();
+        self.export_log(record);
+    }
+
+    fn write_span(&mut self, record: &serde_json::Value) {
+        if !self.breaker_allows() {
+            return;
+        }
+        self.ensure_initialized();
+        self.export_span(record);
+
+        if self.provider.is_none() {
+            return;
+        }
+
+        // Only record HTTP request spans as OTLP metrics
+        if let Some(sample) = HttpRequestSample::from_span(record, &self.config.metric_labels_from_fields) {
+            self.record_sample(sample);
+        }
+    }
+
+    /// Forward custom metric records as gauges named after the metric.
+    fn write_metric(&mut self, record: &serde_json::Value) {
+        if !self.breaker_allows() {
+            return;
+        }
+        self.ensure_initialized();
+
+        let Some(otel) = OtelGauge::from_record(record) else { return };
+        if let Some(gauge) = self.custom_gauge(&otel.name) {
+            if let Some(attributes) = self.cardinality.admit(&otel.name, &otel.attributes) {
+                gauge.record(otel.value, attributes);
+            }
+        }
+    }
+
+    /// Export pending log and span batches and collect metrics now, rather
+    /// than waiting for the next interval. Skipped while the breaker is open,
+    /// since a forced export would block on the dead collector.
+    fn flush(&mut self) {
+        if self.breaker.is_open() {
+            return;
+        }
+        if let Some(ref provider) = self.logger_provider {
+            if let Err(e) = provider.force_flush() {
+                eprintln!("[otlp-output] Log flush error: {:?}", e);
+            }
+        }
+        if let Some(ref provider) = self.tracer_provider {
+            if let Err(e) = provider.force_flush() {
+                eprintln!("[otlp-output] Trace flush error: {:?}", e);
+            }
+        }
+        if let Some(ref provider) = self.provider {
+            if let Err(e) = provider.force_flush() {
+                eprintln!("[otlp-output] Metric flush error: {:?}", e);
+            }
+        }
+    }
+
+    /// Based on metric exports, the only signal whose failures are seen.
+    fn health(&self) -> OutputHealth {
+        let failures = &self.export_failures;
+        OutputHealth::from_failures(failures.consecutive.load(Ordering::Relaxed), &lock(&failures.last_error))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// KvBackend over an ordered map, standing in for a table.
+    #[derive(Default)]
+    pub(crate) struct MemoryBackend {
+        entries: Mutex<std::collections::BTreeMap<Vec<u8>, Vec<u8>>>,
+    }
+
+    impl KvBackend for MemoryBackend {
+        fn put<'a>(&'a self, key: &'a [u8], value: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+            lock(&self.entries).insert(key.to_vec(), value.to_vec());
+            Box::pin(async { Ok(()) })
+        }
+
+        fn get<'a>(&'a self, key: &'a [u8]) -> BoxFuture<'a, Result<Option<Vec<u8>>>> {
+            let value = lock(&self.entries).get(key).cloned();
+            Box::pin(async move { Ok(value) })
+        }
+
+        fn delete<'a>(&'a self, key: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+            lock(&self.entries).remove(key);
+            Box::pin(async { Ok(()) })
+        }
+
+        fn scan_range<'a>(
+            &'a self,
+            start: &'a [u8],
+            end: &'a [u8],
+        ) -> BoxFuture<'a, Result<Vec<(Vec<u8>, Vec<u8>)>>> {
+            let entries = lock(&self.entries)
+                .range(start.to_vec()..end.to_vec())
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+            Box::pin(async move { Ok(entries) })
+        }
+    }
+
+    impl MemoryBackend {
+        /// Every stored record, in key order.
+        fn records(&self) -> Vec<serde_json::Value> {
+            lock(&self.entries)
+                .values()
+                .filter_map(|bytes| from_storage_bytes(bytes).ok())
+                .collect()
+        }
+    }
+
+    /// PubSub stand-in recording each notification's table and id.
+    #[derive(Default)]
+    struct RecordingNotifier {
+        notified: Mutex<Vec<(String, String)>>,
+    }
+
+    impl RecordNotifier for RecordingNotifier {
+        fn notify_update<'a>(
+            &'a self,
+            table: &'a str,
+            id: &'a str,
+            _record: &'a serde_json::Value,
+        ) -> Pin<Box<dyn std::future::Future<Output = ()> + Send + 'a>> {
+            lock(&self.notified).push((table.to_string(), id.to_string()));
+            Box::pin(async {})
+        }
+    }
+
+    /// A writer configured from a `telemetry` section, over in-memory
+    /// tables and its own shared state.
+    struct Harness {
+        writer: TelemetryWriter,
+        log: Arc<MemoryBackend>,
+        span: Arc<MemoryBackend>,
+        metric: Arc<MemoryBackend>,
+        notifier: Arc<RecordingNotifier>,
+        state: &'static SharedState,
+    }
+
+    impl Harness {
+        fn new(section: serde_json::Value) -> Self {
+            let root = std::env::temp_dir();
+            let mut config = TelemetryConfig::from_section(&section, root.to_str().unwrap_or("."));
+            let state: &'static SharedState = Box::leak(Box::new(SharedState::new()));
+            state.live.store(Arc::new(std::mem::take(&mut config.live)));
+            let (log, span, metric) = Default::default();
+            let notifier = Arc::new(RecordingNotifier::default());
+            let writer = TelemetryWriter::new(
+                Some(Arc::clone(&log) as Arc<dyn KvBackend>),
+                Some(Arc::clone(&span) as Arc<dyn KvBackend>),
+                Some(Arc::clone(&metric) as Arc<dyn KvBackend>),
+                Some(Arc::clone(&notifier) as Arc<dyn RecordNotifier>),
+                config,
+            )
+            .with_state(state);
+            Self { writer, log, span, metric, notifier, state }
+        }
+
+        /// Run each event through the pipeline and flush the table writes.
+        async fn process(&mut self, events: impl IntoIterator<Item = serde_json::Value>) {
+            for event in events {
+                self.writer.process_one(event).await;
+            }
+            self.writer.flush_all().await;
+        }
+    }
+
+    fn log_event(target: &str, fields: serde_json::Value) -> serde_json::Value {
+        json!({
+            "kind": "log",
+            "level": "INFO",
+            "target": target,
+            "message": "m",
+            "timestamp": now_epoch_ms(),
+            "fields": fields,
+        })
+    }
+
+    /// A stored record's `fields`, which tables hold as a JSON string.
+    fn stored_fields(record: &serde_json::Value) -> serde_json::Value {
+        serde_json::from_str(record["fields"].as_str().unwrap_or("{}")).unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn pipeline_stores_and_notifies_each_kind() {
+        let mut harness = Harness::new(json!({}));
+        let now = now_epoch_ms();
+        harness
+            .process([
+                log_event("app", json!({})),
+                json!({ "kind": "span", "name": "handle", "target": "app", "level": "INFO",
+                        "startTime": now - 5.0, "endTime": now, "durationMs": 5.0 }),
+                json!({ "kind": "metric", "name": "queue.depth", "value": 3.0, "timestamp": now }),
+            ])
+            .await;
+
+        assert_eq!(harness.log.records().len(), 1);
+        assert_eq!(harness.span.records().len(), 1);
+        assert_eq!(harness.metric.records()[0]["name"], "queue.depth");
+        let tables: Vec<String> = lock(&harness.notifier.notified).iter().map(|(t, _)| t.clone()).collect();
+        assert_eq!(tables, ["Log", "Span", "Metric"]);
+        assert_eq!(harness.state.pipeline.events_processed.load(Ordering::Relaxed), 3);
+    }
+
+    #[tokio::test]
+    async fn pipeline_applies_enrichment_without_overwriting() {
+        let mut harness = Harness::new(json!({
+            "enrichment": [{ "source": "service_id", "lookup": { "svc-1": { "team": "payments", "tier": "1" } } }],
+        }));
+        harness
+            .process([
+                log_event("app", json!({ "service_id": "svc-1" })),
+                log_event("app", json!({ "service_id": "svc-1", "team": "own" })),
+                log_event("app", json!({ "service_id": "unknown" })),
+            ])
+            .await;
+
+        let fields: Vec<_> = harness.log.records().iter().map(stored_fields).collect();
+        assert_eq!(fields[0]["team"], "payments");
+        assert_eq!(fields[0]["tier"], "1");
+        assert_eq!(fields[1]["team"], "own");
+        assert!(fields[2].get("team").is_none());
+    }
+
+    #[tokio::test]
+    async fn pipeline_runs_transform_script() {
+        let path = std::env::temp_dir().join(format!("yeti-telemetry-transform-{}.rhai", std::process::id()));
+        fs::write(
+            &path,
+            r#"fn transform(event) {
+                if event.target == "noise" { return (); }
+                event.fields.tagged = true;
+                event
+            }"#,
+        )
+        .unwrap();
+        let mut harness = Harness::new(json!({ "transformScript": path.to_str() }));
+        harness
+            .process([log_event("noise", json!({})), log_event("app", json!({}))])
+            .await;
+        let _ = fs::remove_file(&path);
+
+        let records = harness.log.records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["target"], "app");
+        assert_eq!(stored_fields(&records[0])["tagged"], true);
+        assert_eq!(harness.state.pipeline.transform_dropped.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn pipeline_samples_by_live_rate() {
+        let mut harness = Harness::new(json!({ "logSampleRate": 0.0 }));
+        harness
+            .process((0..10).map(|i| log_event("app", json!({ "trace_id": format!("{:032x}", i) }))))
+            .await;
+        assert!(harness.log.records().is_empty());
+        assert_eq!(harness.state.sampling.logs_seen.load(Ordering::Relaxed), 10);
+        assert_eq!(harness.state.sampling.logs_kept.load(Ordering::Relaxed), 0);
+
+        // A reload that raises the rate applies to the next event.
+        let live = LiveSettings {
+            log_sample_rate: 1.0,
+            ..LiveSettings::default()
+        };
+        harness.state.live.store(Arc::new(live));
+        harness.process([log_event("app", json!({}))]).await;
+        assert_eq!(harness.log.records().len(), 1);
+        assert_eq!(harness.state.sampling.logs_kept.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn collector_is_ready_only_once_an_export_is_accepted() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // A collector that takes connections but answers 503, then 200.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            for status in ["503 Service Unavailable", "200 OK"] {
+                let Ok((mut conn, _)) = listener.accept().await else { return };
+                let mut buf = [0u8; 4096];
+                let _ = conn.read(&mut buf).await;
+                let response = format!("HTTP/1.1 {}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n", status);
+                let _ = conn.write_all(response.as_bytes()).await;
             }
-        }
+        });
+        let target = ExportTarget {
+            channel: None,
+            endpoint,
+            metadata: MetadataMap::new(),
+            headers: Vec::new(),
+            timeout: std::time::Duration::from_secs(5),
+        };
+        assert!(!target.accepts_export().await);
+        assert!(target.accepts_export().await);
     }
-}
 
-fn today_string() -> String {
-    let now = SystemTime::now();
-    let d = now
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap_or_default();
-    let secs = d.as_secs();
-    let days = secs / 86400;
-    let (year, month, day) = days_to_date(days);
-    format!("{:04}-{:02}-{:02}", year, month, day)
-}
+    #[test]
+    fn http_output_body_is_gzipped_when_enabled() {
+        let records: Vec<_> = (0..50).map(|i| json!({ "id": i, "level": "INFO", "message": "repeated" })).collect();
+        let plain = loki_payload(&records).to_string().into_bytes();
 
-fn days_to_date(days_since_epoch: u64) -> (u64, u64, u64) {
-    // Algorithm from http://howardhinnant.github.io/date_algorithms.html
-    let z = days_since_epoch + 719468;
-    let era = z / 146097;
-    let doe = z - era * 146097;
-    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
-    let y = yoe + era * 400;
-    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
-    let mp = (5 * doy + 2) / 153;
-    let d = doy - (153 * mp + 2) / 5 + 1;
-    let m = if mp < 10 { mp + 3 } else { mp - 9 };
-    let y = if m <= 2 { y + 1 } else { y };
-    (y, m, d)
-}
+        let (body, encoding) = http_output_body(plain.clone(), true);
+        assert_eq!(encoding, Some("gzip"));
+        assert!(body.len() < plain.len());
+        let mut decoded = Vec::new();
+        std::io::Read::read_to_end(&mut flate2::read::GzDecoder::new(body.as_slice()), &mut decoded).unwrap();
+        assert_eq!(decoded, plain);
 
-impl TelemetryOutput for FileProvider {
-    fn write_log(&mut self, record: &serde_json::Value) {
-        self.write_event("log", record);
+        assert_eq!(http_output_body(plain.clone(), false), (plain, None));
     }
 
-    fn write_span(&mut self, record: &serde_json::Value) {
-        self.write_event("span", record);
+    #[test]
+    fn output_ops_queue_is_bounded_and_counts_overflow() {
+        let state: &'static SharedState = Box::leak(Box::new(SharedState::new()));
+        let ops = OutputOps::new(1, 1, state);
+        let (started_tx, started) = std::sync::mpsc::channel();
+        let (release, gate) = std::sync::mpsc::channel::<()>();
+        let (done_tx, done) = std::sync::mpsc::channel();
+
+        ops.submit("upload", PathBuf::from("a"), move |_| {
+            started_tx.send(()).unwrap();
+            gate.recv().unwrap();
+        });
+        started.recv().unwrap();
+        // The only worker is busy: one job fits in the queue, the next is dropped.
+        let queued = done_tx.clone();
+        ops.submit("upload", PathBuf::from("b"), move |path| queued.send(path.to_path_buf()).unwrap());
+        ops.submit("upload", PathBuf::from("c"), move |path| done_tx.send(path.to_path_buf()).unwrap());
+        assert_eq!(state.pipeline.output_ops_dropped.load(Ordering::Relaxed), 1);
+
+        release.send(()).unwrap();
+        assert_eq!(done.recv().unwrap(), PathBuf::from("b"));
+        assert!(done.recv_timeout(std::time::Duration::from_millis(100)).is_err());
     }
 
-    fn write_metric(&mut self, record: &serde_json::Value) {
-        self.write_event("metric", record);
+    fn seqs(records: &[serde_json::Value]) -> Vec<u64> {
+        records.iter().filter_map(|r| r["seq"].as_u64()).collect()
     }
-}
 
-// ============================================================================
-// OTLP Provider — OpenTelemetry metrics export
-// ============================================================================
+    #[test]
+    fn fractional_rate_limit_lets_an_event_through_per_period() {
+        let mut limiter = RateLimiter::default();
+        let start = 1_700_000_000_000.0;
+        assert!(limiter.allow("slow", 0.5, start));
+        assert!(!limiter.allow("slow", 0.5, start + 1_000.0));
+        assert!(limiter.allow("slow", 0.5, start + 2_000.0));
+        assert!(!limiter.allow("slow", 0.5, start + 2_500.0));
+        // Idle time refills the bucket to one event, never more.
+        assert!(limiter.allow("slow", 0.5, start + 60_000.0));
+        assert!(!limiter.allow("slow", 0.5, start + 60_000.0));
+    }
 
-use opentelemetry::KeyValue;
-use opentelemetry::metrics::{Counter, Histogram, MeterProvider};
-use opentelemetry_otlp::WithExportConfig;
-use opentelemetry_sdk::metrics::SdkMeterProvider;
+    #[tokio::test]
+    async fn purge_deletes_records_stored_before_cutoff_and_their_mirror_copies() {
+        let storage: Arc<dyn KvBackend> = Arc::new(MemoryBackend::default());
+        let mirror = Arc::new(MemoryBackend::default());
+        let mirrors = Mirrors {
+            metric: Some(MirrorQueue::new(mirror.clone(), shared())),
+            ..Mirrors::default()
+        };
+        let cutoff = 1_700_000_000_000.0;
+        let stored_at = [1_000.0, cutoff - 86_400_000.0, cutoff - 1.0, cutoff, cutoff + 5_000.0];
+        for (i, ms) in stored_at.into_iter().enumerate() {
+            let key = format!("{}-7000-8000-{:012x}", record_key_at(ms), i);
+            storage.put(key.as_bytes(), b"{}").await.unwrap();
+            mirror.put(key.as_bytes(), b"{}").await.unwrap();
+        }
 
-/// OTLP metrics config parsed from yeti-config.yaml
-struct OtlpConfig {
-    endpoint: String,
-    service_name: String,
-    metrics_enabled: bool,
-}
+        let cursor = &mut WriteTimeCursor::default();
+        let deleted = purge_before(&storage, cursor, cutoff, Some((&mirrors, RecordKind::Metric)), None).await.unwrap();
+        assert_eq!(deleted, 3);
+        assert_eq!(storage.scan_range(record_key_at(0.0).as_bytes(), RECORD_KEY_END).await.unwrap().len(), 2);
+        for _ in 0..100 {
+            if mirror.records().len() == 2 {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(mirror.records().len(), 2);
+    }
 
-/// OTLP output provider — exports HTTP metrics to an OTLP collector.
-///
-/// Lazily initializes the meter provider on first use (inside run_loop on the
-/// host's tokio runtime) to avoid dylib tokio spawn issues during on_ready().
-struct OtlpOutput {
-    config: OtlpConfig,
-    provider: Option<SdkMeterProvider>,
-    requests_total: Option<Counter<u64>>,
-    requests_duration: Option<Histogram<f64>>,
-    errors_total: Option<Counter<u64>>,
-}
+    #[tokio::test]
+    async fn expiry_sweep_deletes_short_ttl_records_before_the_longest_ttl_passes() {
+        let mut harness = Harness::new(json!({
+            "maxFutureSkew": 0,
+            "recordTtlRules": [{ "level": "DEBUG", "ttl": "1h" }, { "target": "audit", "ttl": "30d" }],
+        }));
+        let now = now_epoch_ms();
+        let written = now - 2.0 * 3_600_000.0;
+        let stored = [("DEBUG", "app", 3_600_000.0), ("INFO", "audit", 30.0 * 86_400_000.0)];
+        for (i, (level, target, ttl_ms)) in stored.into_iter().enumerate() {
+            let key = format!("{}-7000-8000-{:012x}", record_key_at(written), i);
+            let record = json!({ "level": level, "target": target, "expiresAt": format_epoch_ms(written + ttl_ms) });
+            harness.log.put(key.as_bytes(), &to_storage_bytes(&record).unwrap()).await.unwrap();
+        }
 
-impl OtlpOutput {
-    /// Parse OTLP config from yeti-config.yaml. Returns None if no endpoint configured.
-    fn from_config(root_dir: &str) -> Option<Self> {
-        let config_path = PathBuf::from(root_dir).join("yeti-config.yaml");
-        let contents = fs::read_to_string(&config_path).ok()?;
-        let yaml: serde_json::Value = serde_yaml::from_str(&contents).ok()?;
+        harness.writer.sweep_expired().await;
+        let remaining = harness.log.records();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0]["target"], "audit");
+    }
 
-        let telemetry = yaml.get("telemetry")?;
-        let endpoint = telemetry
-            .get("otlpEndpoint")
-            .and_then(|v| v.as_str())
-            .filter(|s| !s.is_empty())?
-            .to_string();
+    #[tokio::test]
+    async fn shutdown_flushes_buffered_file_lines_and_table_writes() {
+        let dir = std::env::temp_dir().join(format!("yeti-telemetry-flush-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let Harness { writer, log, state, .. } = Harness::new(json!({}));
+        let encoding = FileEncoding {
+            format: FileFormat::Jsonl,
+            envelope: FileEnvelope::Wrapped,
+            intern_fields: false,
+        };
+        // Nothing is flushed on a timer before shutdown.
+        let rotation = FileRotationConfig {
+            style: RotationStyle::Numbered { count: 1 },
+            flush_interval: std::time::Duration::from_secs(3600),
+            ..FileRotationConfig::default()
+        };
+        let ops = Arc::new(OutputOps::new(1, 1, state));
+        let rotated = RotatedFiles { compress: false, archive: None, ops };
+        let file = FileProvider::new(dir.clone(), "telemetry", encoding, rotation, rotated);
+        let writer = writer.add_output(Box::new(file));
 
-        let service_name = telemetry
-            .get("serviceName")
-            .and_then(|v| v.as_str())
-            .unwrap_or("yeti")
-            .to_string();
+        let (tx, rx) = tokio::sync::mpsc::channel(16);
+        for target in ["a", "b", "c"] {
+            tx.send(log_event(target, json!({}))).await.unwrap();
+        }
+        drop(tx);
+        writer.run_loop(rx).await;
 
-        let metrics_enabled = telemetry
-            .get("metrics")
-            .and_then(|v| v.as_bool())
-            .unwrap_or(true);
+        let lines: Vec<serde_json::Value> = fs::read_to_string(dir.join("telemetry.jsonl"))
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let targets: Vec<_> = lines.iter().map(|line| line["data"]["target"].as_str().unwrap()).collect();
+        assert_eq!(targets, ["a", "b", "c"]);
+        assert!(lines.iter().all(|line| line["type"] == "log"));
+        let mut stored: Vec<_> = log.records().iter().map(|r| r["target"].as_str().unwrap().to_string()).collect();
+        stored.sort();
+        assert_eq!(stored, ["a", "b", "c"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
 
-        eprintln!(
-            "[yeti-telemetry] OTLP config: endpoint={}, service={}, metrics={}",
-            endpoint, service_name, metrics_enabled
-        );
+    #[tokio::test]
+    async fn channel_events_are_counted_in_the_injected_state() {
+        let Harness { writer, log, span, notifier, state, .. } = Harness::new(json!({}));
+        let mut loud = log_event("app", json!({}));
+        loud["level"] = json!("LOUD");
+        let mut undated = log_event("app", json!({}));
+        undated["timestamp"] = json!("yesterday");
+        let end = now_epoch_ms() - 5_000.0;
+        let late = json!({ "kind": "span", "name": "handle", "target": "app", "level": "INFO",
+                           "startTime": end - 5.0, "endTime": end });
 
-        Some(Self {
-            config: OtlpConfig {
-                endpoint,
-                service_name,
-                metrics_enabled,
-            },
-            provider: None,
-            requests_total: None,
-            requests_duration: None,
-            errors_total: None,
-        })
+        let (tx, rx) = tokio::sync::mpsc::channel(16);
+        for event in [loud, undated, late] {
+            tx.send(event).await.unwrap();
+        }
+        drop(tx);
+        writer.run_loop(rx).await;
+
+        let pipeline = &state.pipeline;
+        assert_eq!(pipeline.events_processed.load(Ordering::Relaxed), 3);
+        assert_eq!(pipeline.unknown_levels.load(Ordering::Relaxed), 1);
+        assert_eq!(pipeline.invalid_timestamps.load(Ordering::Relaxed), 1);
+        assert!(pipeline.lag_ms.load(Ordering::Relaxed) >= 5_000);
+        assert!(pipeline.writer_started_ms.load(Ordering::Relaxed) > 0);
+        assert!(!pipeline.writer_running.load(Ordering::Relaxed));
+
+        let levels: Vec<_> = log.records().iter().map(|r| r["level"].as_str().unwrap().to_string()).collect();
+        assert_eq!(levels, ["INFO", "INFO"]);
+        assert_eq!(span.records().len(), 1);
+        let tables: Vec<String> = lock(&notifier.notified).iter().map(|(t, _)| t.clone()).collect();
+        assert_eq!(tables, ["Log", "Log", "Span"]);
     }
 
-    /// Lazily initialize the OTLP meter provider and instruments.
-    /// Called on first write_span() inside the host's tokio runtime context.
-    fn ensure_initialized(&mut self) {
-        if self.provider.is_some() {
-            return;
+    #[tokio::test]
+    async fn span_purge_deletes_the_index_entries_of_purged_spans() {
+        let spans: Arc<dyn KvBackend> = Arc::new(MemoryBackend::default());
+        let index = Arc::new(MemoryBackend::default());
+        let indexed = ["user".to_string()];
+        let cutoff = 1_700_000_000_000.0;
+        for (i, ms) in [cutoff - 60_000.0, cutoff + 60_000.0].into_iter().enumerate() {
+            let id = format!("{}-7000-8000-{:012x}", record_key_at(ms), i);
+            let span = json!({ "id": id, "fields": json!({ "user": format!("u{}", i) }).to_string() });
+            spans.put(id.as_bytes(), &to_storage_bytes(&span).unwrap()).await.unwrap();
+            for key in span_index_keys(&indexed, &json!({ "user": format!("u{}", i) }), &id) {
+                index.put(key.as_bytes(), &to_storage_bytes(&span_index_entry(&key, &id)).unwrap()).await.unwrap();
+            }
         }
 
-        if !self.config.metrics_enabled {
-            return;
-        }
+        let index_storage: Arc<dyn KvBackend> = index.clone();
+        let cursor = &mut WriteTimeCursor::default();
+        let deleted = purge_before(&spans, cursor, cutoff, None, Some((&index_storage, &indexed))).await.unwrap();
+        assert_eq!(deleted, 1);
+        let remaining: Vec<_> = index.records().iter().map(|e| e["id"].as_str().unwrap().to_string()).collect();
+        assert_eq!(remaining.len(), 1);
+        assert!(remaining[0].starts_with(&span_index_prefix("user", "u1")));
+    }
 
-        let exporter = match opentelemetry_otlp::MetricExporter::builder()
-            .with_tonic()
-            .with_endpoint(&self.config.endpoint)
-            .with_timeout(std::time::Duration::from_secs(10))
-            .build()
-        {
-            Ok(e) => e,
-            Err(e) => {
-                eprintln!("[otlp-output] Failed to create metric exporter: {}", e);
-                return;
+    #[tokio::test]
+    async fn metric_rollup_pages_through_the_points_of_each_window() {
+        let mut h = Harness::new(json!({ "metricRetention": { "rawDays": 1, "rollupInterval": "1h" } }));
+        let rollups = Arc::new(MemoryBackend::default());
+        h.writer = h.writer.with_rollup_storage(Some(Arc::clone(&rollups) as Arc<dyn KvBackend>));
+        let hour = 3_600_000.0;
+        let current = (now_epoch_ms() / hour).floor() * hour;
+        let mut n = 0;
+        for start in (0..=6).map(|hours_ago| current - hours_ago as f64 * hour) {
+            for value in [1.0, 3.0] {
+                let ms = (start + 600_000.0).min(now_epoch_ms());
+                let id = format!("{}-7000-8000-{:012x}", record_key_at(ms), n);
+                let point = json!({ "id": id, "name": "q", "timestamp": format_epoch_ms(ms), "value": value });
+                h.metric.put(id.as_bytes(), &to_storage_bytes(&point).unwrap()).await.unwrap();
+                n += 1;
             }
+        }
+
+        let retention = h.writer.config.metric_retention.unwrap();
+        h.writer.roll_up_metrics(&retention).await;
+        let records = rollups.records();
+        let windows: Vec<_> = records.iter().map(|r| r["windowStart"].as_str().unwrap().to_string()).collect();
+        let expected: Vec<_> = (1..=6).rev().map(|ago| format_epoch_ms(current - ago as f64 * hour)).collect();
+        assert_eq!(windows, expected);
+        assert!(records.iter().all(|r| r["count"] == json!(2) && r["value"] == json!(2.0)));
+    }
+
+    #[tokio::test]
+    async fn upstream_id_is_stored_beside_a_generated_key() {
+        let log = Arc::new(MemoryBackend::default());
+        let config = TelemetryConfig {
+            id_source: IdSource::Field("request_id".to_string()),
+            ..TelemetryConfig::default()
         };
+        let mut writer = TelemetryWriter::new(Some(log.clone()), None, None, None, config);
+        let event = json!({ "level": "INFO", "target": "app", "message": "m", "fields": { "request_id": "Req-42" } });
+        writer.store_log(&event, None).await;
+        writer.flush_all().await;
 
-        let reader = opentelemetry_sdk::metrics::PeriodicReader::builder(exporter)
-            .with_interval(std::time::Duration::from_secs(15))
-            .build();
+        let records = log.records();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0]["upstreamId"], "Req-42");
+        let id = records[0]["id"].as_str().unwrap();
+        assert!(id >= record_key_at(0.0).as_str() && id.as_bytes() < RECORD_KEY_END);
+    }
 
-        let resource = opentelemetry_sdk::Resource::builder()
-            .with_attribute(KeyValue::new(
-                "service.name",
-                self.config.service_name.clone(),
-            ))
-            .with_attribute(KeyValue::new(
-                "deployment.environment",
-                std::env::var("YETI_ENV").unwrap_or_else(|_| "development".to_string()),
-            ))
-            .build();
+    #[tokio::test]
+    async fn seq_is_consecutive_within_each_table() {
+        let log = Arc::new(MemoryBackend::default());
+        let error_log = Arc::new(MemoryBackend::default());
+        let span = Arc::new(MemoryBackend::default());
+        let mut writer = TelemetryWriter::new(
+            Some(log.clone()),
+            Some(span.clone()),
+            None,
+            None,
+            TelemetryConfig::default(),
+        )
+        .with_error_log_storage(Some(error_log.clone()));
 
-        let provider = SdkMeterProvider::builder()
-            .with_reader(reader)
-            .with_resource(resource)
-            .build();
+        for level in ["INFO", "ERROR", "INFO", "WARN", "DEBUG"] {
+            writer.store_log(&json!({ "level": level, "target": "app", "message": "m" }), None).await;
+        }
+        writer.flush_all().await;
 
-        let meter = provider.meter("yeti-telemetry");
+        assert_eq!(seqs(&log.records()), vec![1, 2, 3]);
+        assert_eq!(seqs(&error_log.records()), vec![1, 2]);
+        assert!(span.records().is_empty());
+    }
 
-        self.requests_total = Some(
-            meter
-                .u64_counter("http.server.requests")
-                .with_description("Total number of HTTP requests")
-                .build(),
+    #[test]
+    fn ids_increase_within_and_across_milliseconds() {
+        let clock = Arc::new(ManualClock::new(1_700_000_000_000));
+        let mut ids = IdGenerator::new(clock.clone());
+        let mut previous = ids.next_id();
+        for step in 0..100 {
+            if step % 10 == 0 {
+                clock.advance(1);
+            }
+            let id = ids.next_id();
+            assert!(id > previous, "{} not after {}", id, previous);
+            previous = id;
+        }
+    }
+
+    #[test]
+    fn ids_keep_increasing_when_the_counter_overflows() {
+        let clock = Arc::new(ManualClock::new(1_700_000_000_000));
+        let mut ids = IdGenerator::new(clock);
+        let mut previous = ids.next_id();
+        for _ in 0..0x2000 {
+            let id = ids.next_id();
+            assert!(id > previous);
+            previous = id;
+        }
+    }
+
+    #[test]
+    fn record_key_at_bounds_ids_by_write_time() {
+        let start = 1_700_000_000_000;
+        let clock = Arc::new(ManualClock::new(start));
+        let mut ids = IdGenerator::new(clock.clone());
+        let before = ids.next_id();
+        clock.advance(60_000);
+        let after = ids.next_id();
+
+        let bound = record_key_at((start + 60_000) as f64);
+        assert!(before < bound);
+        assert!(after.as_str() >= bound.as_str());
+        assert!(bound >= record_key_at(0.0) && bound.as_bytes() < RECORD_KEY_END);
+    }
+
+    #[test]
+    fn write_time_cursor_pages_are_capped_at_a_day() {
+        let horizon = 1_700_000_000_000.0;
+        let mut cursor = WriteTimeCursor::default();
+        let mut pages = 0;
+        let mut widest: f64 = 0.0;
+        while let Some((start, end)) = cursor.page(horizon) {
+            assert!(start < end && end <= horizon);
+            widest = widest.max(end - start);
+            cursor.advance(end, 0);
+            pages += 1;
+        }
+        assert_eq!(cursor.swept_ms, horizon);
+        assert_eq!(widest, EXPIRY_SWEEP_MAX_PAGE_MS);
+        // About one page per day of empty history, plus the doubling up to a day.
+        let days = (horizon / EXPIRY_SWEEP_MAX_PAGE_MS).ceil() as usize;
+        assert!(pages <= days + 12, "{} pages for {} days", pages, days);
+
+        // Data doesn't widen the page; a page over the per-sweep budget narrows it.
+        cursor.advance(horizon, 10);
+        assert_eq!(cursor.page_ms, EXPIRY_SWEEP_MAX_PAGE_MS);
+        cursor.advance(horizon, EXPIRY_SWEEP_MAX_RECORDS + 1);
+        assert_eq!(cursor.page_ms, EXPIRY_SWEEP_MAX_PAGE_MS / 2.0);
+    }
+
+    #[test]
+    fn epoch_ms_formats_as_rounded_secs_and_micros() {
+        assert_eq!(format_epoch_ms(0.0), "0.000000");
+        // 2024-02-29T00:00:00Z and 2024-12-31T23:59:59.999Z, then the new year.
+        assert_eq!(format_epoch_ms(1_709_164_800_000.0), "1709164800.000000");
+        assert_eq!(format_epoch_ms(1_735_689_599_999.0), "1735689599.999000");
+        assert_eq!(format_epoch_ms(1_735_689_600_000.0), "1735689600.000000");
+
+        assert_eq!(format_epoch_ms(999.9), "0.999900");
+        assert_eq!(format_epoch_ms(1000.0), "1.000000");
+        assert_eq!(format_epoch_ms(1000.5), "1.000500");
+        assert_eq!(format_epoch_ms(1000.9999), "1.001000");
+        assert_eq!(format_epoch_ms(1000.0004), "1.000000");
+        assert_eq!(format_epoch_ms(1_735_689_599_999.999_6), "1735689600.000000");
+
+        assert_eq!(format_epoch_ms(-1.0), "0.000000");
+        assert_eq!(format_epoch_ms(f64::NAN), "0.000000");
+    }
+
+    #[test]
+    fn period_end_is_read_from_dated_and_hourly_names() {
+        let day = |y, m, d| date_to_days(y, m, d) * 86400;
+        let end_of_day = day(2024, 3, 1);
+        assert_eq!(period_end_from_filename("telemetry", "telemetry-2024-02-29.jsonl"), Some(end_of_day));
+        assert_eq!(period_end_from_filename("telemetry", "telemetry-2024-02-29.jsonl.gz"), Some(end_of_day));
+        assert_eq!(period_end_from_filename("telemetry", "telemetry-2024-02-29.3.jsonl.gz"), Some(end_of_day));
+        let hourly = period_end_from_filename("telemetry", "telemetry-2024-02-29-00.jsonl");
+        assert_eq!(hourly, Some(end_of_day - 23 * 3600));
+        assert_eq!(period_end_from_filename("telemetry", "telemetry-2024-12-31-23.csv.gz"), Some(day(2025, 1, 1)));
+        assert_eq!(period_end_from_filename("errors", "errors-2024-02-29.jsonl"), Some(end_of_day));
+
+        for name in [
+            "telemetry.1.jsonl",
+            "telemetry.2.jsonl.gz",
+            "telemetry.jsonl",
+            "telemetry-0000-00-00.jsonl",
+            "telemetry-2024-13-01.jsonl",
+            "telemetry-2024-02-29-24.jsonl",
+            "telemetry-24-02-29.jsonl",
+            "errors-2024-02-29.jsonl",
+        ] {
+            assert_eq!(period_end_from_filename("telemetry", name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn cleanup_removes_files_past_retention_by_name_then_mtime() {
+        let dir = std::env::temp_dir().join(format!("yeti-telemetry-cleanup-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let at = |secs: u64| std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs);
+        let now = date_to_days(2024, 3, 10) * 86400;
+        // Two days' retention: files whose period ended before March 8 go.
+        let cutoff = now - 2 * 86400;
+        let files = [
+            ("telemetry-2024-03-07.jsonl", None, true),
+            ("telemetry-2024-03-07-23.jsonl.gz", None, true),
+            ("telemetry-2024-03-07-22.jsonl.gz", None, false),
+            ("telemetry-2024-03-06.jsonl.gz", None, false),
+            ("telemetry-2024-03-06.2.jsonl", None, false),
+            ("telemetry.1.jsonl", Some(cutoff), true),
+            ("telemetry.2.jsonl.gz", Some(cutoff - 1), false),
+            ("telemetry-2024-13-01.jsonl", Some(cutoff - 1), false),
+            ("telemetry-extra-2024-01-01.jsonl", None, true),
+            ("other-2024-01-01.jsonl", None, true),
+        ];
+        for (name, modified, _) in files {
+            let file = File::create(dir.join(name)).unwrap();
+            if let Some(secs) = modified {
+                file.set_modified(at(secs)).unwrap();
+            }
+        }
+
+        let ops = Arc::new(OutputOps::new(1, 1, Box::leak(Box::new(SharedState::new()))));
+        let rotated = RotatedFiles { compress: false, archive: None, ops };
+        let layout = FileLayout::Jsonl(FileEnvelope::Wrapped, false);
+        let rotation = FileRotationConfig::default();
+        let sink = FileSink::new(dir.clone(), "telemetry", layout, rotation, rotated, Arc::default());
+        sink.cleanup_files_before(at(now), 2);
+
+        for (name, _, kept) in files {
+            assert_eq!(dir.join(name).exists(), kept, "{}", name);
+        }
+        assert!(dir.join(sink.active_filename()).exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_only_when_needed() {
+        for (value, expected) in [
+            ("plain", "plain"),
+            ("", ""),
+            ("a,b", "\"a,b\""),
+            ("say \"hi\"", "\"say \"\"hi\"\"\""),
+            ("\"", "\"\"\"\""),
+            ("two\nlines", "\"two\nlines\""),
+            ("crlf\r\n", "\"crlf\r\n\""),
+            ("it's fine; really", "it's fine; really"),
+        ] {
+            let mut out = String::new();
+            push_csv_field(&mut out, value);
+            assert_eq!(out, expected, "{:?}", value);
+        }
+
+        let log = json!({
+            "id": "0190-a",
+            "timestamp": "1700000000.000000",
+            "level": "WARN",
+            "message": "disk \"/data\" at 91%, rising\nsee runbook",
+            "fields": "{\"path\":\"/data\"}",
+            "seq": 7,
+            "writerId": null,
+        });
+        assert_eq!(
+            csv_row(RecordKind::Log, &log),
+            "0190-a,1700000000.000000,WARN,,\"disk \"\"/data\"\" at 91%, rising\nsee runbook\",\
+             \"{\"\"path\"\":\"\"/data\"\"}\",7,,\r\n"
         );
-        self.requests_duration = Some(
-            meter
-                .f64_histogram("http.server.request.duration")
-                .with_description("HTTP request duration in seconds")
-                .with_unit("s")
-                .build(),
+    }
+
+    #[test]
+    fn csv_headers_list_each_kind_columns_in_order() {
+        let header = |kind| csv_columns(kind).join(",");
+        assert_eq!(header(RecordKind::Log), "id,timestamp,level,target,message,fields,seq,writerId,repeatCount");
+        assert_eq!(
+            header(RecordKind::Span),
+            "id,traceId,spanId,parentSpanId,name,target,level,startTime,endTime,durationMs,fields,seq,writerId"
         );
-        self.errors_total = Some(
-            meter
-                .u64_counter("http.server.errors")
-                .with_description("Total number of HTTP errors")
-                .build(),
+        assert_eq!(
+            header(RecordKind::Metric),
+            "id,name,value,attributes,timestamp,min,max,sum,count,windowStart,windowEnd,seq,writerId"
         );
 
-        self.provider = Some(provider);
-        eprintln!(
-            "[otlp-output] Meter provider initialized (endpoint: {})",
-            self.config.endpoint
+        // Each value lands under its own column.
+        for kind in [RecordKind::Log, RecordKind::Span, RecordKind::Metric] {
+            let columns = csv_columns(kind);
+            let record: serde_json::Value =
+                columns.iter().map(|c| (c.to_string(), json!(format!("<{}>", c)))).collect();
+            let row = csv_row(kind, &record);
+            let expected: Vec<_> = columns.iter().map(|c| format!("<{}>", c)).collect();
+            assert_eq!(row, format!("{}\r\n", expected.join(",")));
+        }
+    }
+
+    #[test]
+    fn otlp_json_lines_carry_resource_scope_and_nanosecond_times() {
+        let resource = json!({ "attributes": otlp_json_attributes(&[
+            KeyValue::new("deployment.environment", "staging"),
+            KeyValue::new("service.name", "checkout"),
+        ]) });
+        let resource_attributes = json!([
+            { "key": "deployment.environment", "value": { "stringValue": "staging" } },
+            { "key": "service.name", "value": { "stringValue": "checkout" } },
+        ]);
+        let scope = json!({ "name": "yeti-telemetry" });
+        let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+        let log = json!({
+            "id": "0190-a",
+            "timestamp": "1700000000.123456",
+            "level": "warning",
+            "target": "app::db",
+            "message": "slow query",
+            "fields": json!({ "traceId": trace_id, "spanId": "00f067aa0ba902b7" }).to_string(),
+        });
+        let line = otlp_json_line("log", &log, &resource).unwrap();
+        let resource_logs = &line["resourceLogs"][0];
+        assert_eq!(resource_logs["resource"]["attributes"], resource_attributes);
+        assert_eq!(resource_logs["scopeLogs"][0]["scope"], scope);
+        let record = &resource_logs["scopeLogs"][0]["logRecords"][0];
+        assert_eq!(record["timeUnixNano"], json!("1700000000123456000"));
+        assert_eq!(record["observedTimeUnixNano"], json!("1700000000123456000"));
+        assert_eq!(record["severityNumber"], json!(13));
+        assert_eq!(record["severityText"], json!("WARN"));
+        assert_eq!(record["body"], json!({ "stringValue": "slow query" }));
+        assert_eq!(record["traceId"], json!(trace_id));
+        assert_eq!(record["spanId"], json!("00f067aa0ba902b7"));
+
+        let span = json!({
+            "id": "0190-b",
+            "traceId": trace_id,
+            "spanId": "00f067aa0ba902b7",
+            "parentSpanId": "0000000000000000",
+            "name": "GET /cart",
+            "target": "app::http",
+            "level": "INFO",
+            "startTime": "1700000000.000001",
+            "endTime": "1700000000.250000",
+            "fields": json!({ "status": "ERROR" }).to_string(),
+        });
+        let line = otlp_json_line("span", &span, &resource).unwrap();
+        let resource_spans = &line["resourceSpans"][0];
+        assert_eq!(resource_spans["resource"]["attributes"], resource_attributes);
+        assert_eq!(resource_spans["scopeSpans"][0]["scope"], scope);
+        let otlp_span = &resource_spans["scopeSpans"][0]["spans"][0];
+        assert_eq!(otlp_span["startTimeUnixNano"], json!("1700000000000001000"));
+        assert_eq!(otlp_span["endTimeUnixNano"], json!("1700000000250000000"));
+        assert_eq!(otlp_span["traceId"], json!(trace_id));
+        assert_eq!(otlp_span["spanId"], json!("00f067aa0ba902b7"));
+        assert_eq!(otlp_span.get("parentSpanId"), None);
+        assert_eq!(otlp_span["status"], json!({ "code": 2 }));
+
+        let metric = json!({
+            "id": "0190-c",
+            "name": "queue.depth",
+            "value": 4.5,
+            "attributes": json!({ "queue": "mail" }).to_string(),
+            "timestamp": "1700000001.000000",
+        });
+        let line = otlp_json_line("metric", &metric, &resource).unwrap();
+        assert_eq!(
+            line,
+            json!({ "resourceMetrics": [{
+                "resource": { "attributes": resource_attributes },
+                "scopeMetrics": [{
+                    "scope": scope,
+                    "metrics": [{ "name": "queue.depth", "gauge": { "dataPoints": [{
+                        "asDouble": 4.5,
+                        "attributes": [{ "key": "queue", "value": { "stringValue": "mail" } }],
+                        "timeUnixNano": "1700000001000000000",
+                    }] } }],
+                }],
+            }] })
         );
     }
-}
 
-impl Drop for OtlpOutput {
-    fn drop(&mut self) {
-        if let Some(provider) = self.provider.take() {
-            eprintln!("[otlp-output] Shutting down meter provider");
-            if let Err(e) = provider.shutdown() {
-                eprintln!("[otlp-output] Shutdown error: {:?}", e);
+    #[test]
+    fn interned_fields_round_trip_and_shrink_repetitive_files() {
+        let records: Vec<_> = (0..200)
+            .map(|i| {
+                let fields = json!({
+                    "service": "checkout",
+                    "region": "eu-west-1",
+                    "env": "production",
+                    "sampled": i % 2 == 0,
+                    "request_id": format!("req-{:08}", i),
+                    "attempt": i % 3,
+                });
+                json!({
+                    "id": format!("0190-{:04}", i),
+                    "timestamp": "1700000000.000000",
+                    "level": "INFO",
+                    "message": "handled",
+                    "fields": fields.to_string(),
+                })
+            })
+            .collect();
+        // `fields` compared as objects: resolving may reorder the keys.
+        let decoded = |record: &serde_json::Value| {
+            let mut record = record.clone();
+            record["fields"] = stored_fields(&record);
+            record
+        };
+
+        for envelope in [FileEnvelope::Wrapped, FileEnvelope::Flattened] {
+            let (mut plain, mut interned) = (Vec::new(), Vec::new());
+            let mut interner = FieldInterner::default();
+            for record in &records {
+                push_json_line(&mut plain, &frame_file_line(&envelope, "log", record));
+                let mut copy = record.clone();
+                for entry in interner.intern(&mut copy) {
+                    push_json_line(&mut interned, &frame_file_line(&envelope, FIELD_DICT_TYPE, &entry));
+                }
+                push_json_line(&mut interned, &frame_file_line(&envelope, "log", &copy));
+            }
+            assert!(interned.len() < plain.len() * 3 / 4, "{} vs {} bytes", interned.len(), plain.len());
+
+            let mut dictionary = FieldDictionary::default();
+            let restored: Vec<_> = std::str::from_utf8(&interned)
+                .unwrap()
+                .lines()
+                .filter_map(|line| dictionary.resolve(serde_json::from_str(line).unwrap()))
+                .collect();
+            assert_eq!(restored.len(), records.len());
+            for (line, record) in restored.iter().zip(&records) {
+                let line = match envelope {
+                    FileEnvelope::Wrapped => line["data"].clone(),
+                    _ => {
+                        let mut line = line.clone();
+                        line.as_object_mut().unwrap().remove("_type");
+                        line
+                    }
+                };
+                assert_eq!(decoded(&line), decoded(record));
             }
         }
     }
-}
 
-impl TelemetryOutput for OtlpOutput {
-    fn write_log(&mut self, _record: &serde_json::Value) {
-        // Logs are persisted to tables; OTLP export focuses on metrics from spans.
+    #[test]
+    fn levels_normalize_aliases_in_any_case() {
+        let cases = [
+            ("TRACE", "TRACE"),
+            ("Verbose", "TRACE"),
+            ("dbg", "DEBUG"),
+            ("info", "INFO"),
+            ("Notice", "INFO"),
+            ("informational", "INFO"),
+            ("WARNING", "WARN"),
+            ("Wrn", "WARN"),
+            ("err", "ERROR"),
+            ("Fatal", "ERROR"),
+            ("CRIT", "ERROR"),
+            (" error ", "ERROR"),
+        ];
+        for (raw, level) in cases {
+            assert_eq!(normalize_level(raw), (level, true), "{}", raw);
+        }
+        for raw in ["loud", "", "warn!", "5"] {
+            assert_eq!(normalize_level(raw), ("INFO", false), "{}", raw);
+        }
     }
 
-    fn write_span(&mut self, record: &serde_json::Value) {
-        self.ensure_initialized();
+    #[test]
+    fn dates_from_epoch_days() {
+        assert_eq!(days_to_date(0), (1970, 1, 1));
+        assert_eq!(days_to_date(11_016), (2000, 2, 29));
+        assert_eq!(days_to_date(11_017), (2000, 3, 1));
+        assert_eq!(days_to_date(20_088), (2024, 12, 31));
+        assert_eq!(days_to_date(20_089), (2025, 1, 1));
 
-        // Only record HTTP request spans as OTLP metrics
-        let target = record.get("target").and_then(|v| v.as_str()).unwrap_or("");
-        if target != "http.request" {
-            return;
+        assert_eq!(date_to_string(0), "1970-01-01");
+        assert_eq!(date_to_string(11_016 * 86_400 + 86_399), "2000-02-29");
+        assert_eq!(date_to_string(20_088 * 86_400 + 43_200), "2024-12-31");
+    }
+
+    /// Fraction of `keys` kept at `rate`.
+    fn kept_fraction(keys: impl Iterator<Item = String>, rate: f64) -> f64 {
+        let (mut kept, mut total) = (0, 0);
+        for key in keys {
+            total += 1;
+            if sample_keep(&key, rate) {
+                kept += 1;
+            }
         }
+        kept as f64 / total as f64
+    }
 
-        let fields_str = record.get("fields").and_then(|v| v.as_str()).unwrap_or("{}");
-        let fields: serde_json::Value =
-            serde_json::from_str(fields_str).unwrap_or(json!({}));
+    #[test]
+    fn sampling_keeps_the_configured_fraction() {
+        const N: u64 = 100_000;
+        // Random 128-bit trace ids (xorshift64*), and sequential ones that a
+        // naive modulo would bias.
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        let mut random = move || {
+            state ^= state >> 12;
+            state ^= state << 25;
+            state ^= state >> 27;
+            state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+        };
+        let random_ids = (0..N).map(|_| format!("{:016x}{:016x}", random(), random()));
+        let sequential_ids = (0..N).map(|i| format!("{:032x}", i));
 
-        let method = fields
-            .get("http.method")
-            .and_then(|v| v.as_str())
-            .unwrap_or("UNKNOWN");
-        let route = fields
-            .get("http.route")
-            .and_then(|v| v.as_str())
-            .unwrap_or("/");
-        let status = fields
-            .get("http.status_code")
-            .and_then(|v| v.as_str())
-            .unwrap_or("0");
-        let is_error = fields
-            .get("status")
-            .and_then(|v| v.as_str())
-            == Some("ERROR");
+        for fraction in [kept_fraction(random_ids, 0.1), kept_fraction(sequential_ids, 0.1)] {
+            // Binomial standard deviation at N = 100k is ~0.00095; allow 5.
+            assert!((fraction - 0.1).abs() < 0.005, "kept {}", fraction);
+        }
+    }
 
-        let attributes = [
-            KeyValue::new("http.method", method.to_string()),
-            KeyValue::new("http.route", route.to_string()),
-            KeyValue::new("http.status_code", status.to_string()),
-        ];
+    #[test]
+    fn sampling_decision_is_deterministic_per_trace() {
+        let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+        let span = json!({ "name": "GET /", "traceId": trace_id });
+        let log = json!({ "message": "handled", "fields": { "trace_id": trace_id } });
+        assert_eq!(sampling_key(&span), trace_id);
+        assert_eq!(sampling_key(&log), trace_id);
 
-        if let Some(ref counter) = self.requests_total {
-            counter.add(1, &attributes);
+        for rate in [0.01, 0.1, 0.5, 0.9] {
+            let first = sample_keep(trace_id, rate);
+            assert!((0..10).all(|_| sample_keep(trace_id, rate) == first));
         }
+        assert!(!sample_keep(trace_id, 0.0));
+    }
 
-        // Calculate duration from durationMs field (stored in milliseconds)
-        let duration_ms = record
-            .get("durationMs")
-            .and_then(|v| v.as_f64())
-            .unwrap_or(0.0);
-        if let Some(ref histogram) = self.requests_duration {
-            histogram.record(duration_ms / 1000.0, &attributes);
-        }
+    fn env(vars: &[(&str, &str)]) -> HashMap<String, String> {
+        vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
 
-        if is_error {
-            if let Some(ref counter) = self.errors_total {
-                counter.add(1, &attributes);
-            }
-        }
+    #[test]
+    fn yeti_otlp_env_overrides_the_yaml_section() {
+        let yaml = json!({
+            "otlpEndpoint": "http://yaml:4317",
+            "serviceName": "from-yaml",
+            "metrics": true,
+            "otlpTimeoutMs": 5000,
+        });
+
+        let mut telemetry = yaml.clone();
+        apply_yeti_otlp_env(&mut telemetry, &env(&[]));
+        assert_eq!(telemetry, yaml);
+
+        let mut telemetry = yaml.clone();
+        apply_yeti_otlp_env(
+            &mut telemetry,
+            &env(&[
+                ("YETI_OTLP_ENDPOINT", " http://env:4317 "),
+                ("YETI_OTLP_SERVICE_NAME", ""),
+                ("YETI_OTLP_METRICS_ENABLED", "0"),
+                ("YETI_OTLP_HEADERS", "authorization=Bearer x,x-tenant=a"),
+                ("YETI_OTLP_TIMEOUT_MS", "2500"),
+                ("OTEL_SERVICE_NAME", "not-a-yeti-var"),
+            ]),
+        );
+        assert_eq!(
+            telemetry,
+            json!({
+                "otlpEndpoint": "http://env:4317",
+                "serviceName": "from-yaml",
+                "metrics": false,
+                "otlpTimeoutMs": 2500,
+                "otlpHeaders": { "authorization": "Bearer x", "x-tenant": "a" },
+            })
+        );
+
+        let mut telemetry = json!(null);
+        apply_yeti_otlp_env(&mut telemetry, &env(&[("YETI_OTLP_ENDPOINT", "http://env:4317")]));
+        assert_eq!(telemetry, json!({ "otlpEndpoint": "http://env:4317" }));
     }
 
-    fn write_metric(&mut self, _record: &serde_json::Value) {
-        // Custom metrics could be forwarded to OTLP here if needed.
+    #[test]
+    fn invalid_yeti_otlp_env_values_leave_the_yaml_value() {
+        let yaml = json!({ "otlpEndpoint": "http://yaml:4317", "traces": true, "otlpTimeoutMs": 5000 });
+        let mut telemetry = yaml.clone();
+        apply_yeti_otlp_env(
+            &mut telemetry,
+            &env(&[
+                ("YETI_OTLP_TIMEOUT_MS", "5s"),
+                ("YETI_OTLP_TRACES_ENABLED", "yes"),
+                ("YETI_OTLP_SPOOL", "maybe"),
+            ]),
+        );
+        assert_eq!(telemetry, yaml);
+
+        for (value, expected_ms) in [("2500", 2500), ("-1", 5000), ("soon", 5000), ("", 5000)] {
+            let mut telemetry = yaml.clone();
+            apply_yeti_otlp_env(&mut telemetry, &env(&[("YETI_OTLP_TIMEOUT_MS", value)]));
+            let output = OtlpOutput::from_section(&telemetry, "/nonexistent", "otlp", true).unwrap();
+            assert_eq!(output.config.timeout, std::time::Duration::from_millis(expected_ms), "{:?}", value);
+        }
     }
 }
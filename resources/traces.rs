@@ -0,0 +1,161 @@
+//! Traces Resource
+//!
+//! Turns the stored spans of a single trace into folded-stack output
+//! (`root;child;leaf <self-time>`) that flamegraph tools consume directly.
+
+use std::collections::HashMap;
+
+use yeti_core::prelude::*;
+
+use crate::telemetry::{max_future_skew_secs, now_epoch_ms, record_key_at, scan_records, shared, RECORD_KEY_END};
+
+/// Type alias required by compiler (filename → type name mapping)
+pub type Traces = TracesResource;
+
+/// How far back spans are searched when `since` is not given, in seconds.
+const DEFAULT_WINDOW_SECS: f64 = 3600.0;
+
+#[derive(Default)]
+pub struct TracesResource;
+
+impl Resource for TracesResource {
+    fn name(&self) -> &str {
+        "traces"
+    }
+
+    /// GET /yeti-telemetry/traces/{traceId}/flamegraph — folded stacks for one trace
+    ///
+    /// Values are span self-time in microseconds. Requires spans persisted with
+    /// `spanId`/`parentSpanId` linkage; traces without it are rejected. Only
+    /// spans stored since `since` (epoch seconds, default the last hour) are
+    /// searched, so the scan stays bounded.
+    get!(req, _ctx, {
+        let path = req.id().unwrap_or("");
+        let (trace_id, view) = path.split_once('/').unwrap_or((path, ""));
+        if trace_id.is_empty() || view != "flamegraph" {
+            return not_found("expected /traces/{traceId}/flamegraph");
+        }
+        let since = match req.query("since").filter(|s| !s.is_empty()) {
+            None => now_epoch_ms() / 1000.0 - DEFAULT_WINDOW_SECS,
+            Some(s) => match s.parse::<f64>() {
+                Ok(v) => v,
+                Err(_) => return bad_request("since must be epoch seconds"),
+            },
+        };
+
+        let storage = match shared().tables.get().and_then(|t| t.span.clone()) {
+            Some(s) => s,
+            None => return not_found("Span table not available"),
+        };
+
+        // Span ids carry their write time, so keys before the window's start
+        // (less the allowed skew) can't hold its spans.
+        let start = record_key_at((since - max_future_skew_secs()) * 1000.0);
+        let spans: Vec<serde_json::Value> =
+            scan_records(&storage, start.as_bytes(), RECORD_KEY_END)
+                .await?
+                .into_iter()
+                .filter(|r| r.get("traceId").and_then(|v| v.as_str()) == Some(trace_id))
+                .collect();
+        if spans.is_empty() {
+            return not_found("no spans recorded for trace since the given time");
+        }
+
+        match fold_stacks(&spans) {
+            Some(folded) => ok_text("text/plain; charset=utf-8", folded),
+            None => status_json(
+                422,
+                json!({
+                    "error": "trace has no span hierarchy (spans lack spanId/parentSpanId)",
+                    "traceId": trace_id,
+                }),
+            ),
+        }
+    });
+}
+
+/// A span reduced to what the folded-stack builder needs.
+struct FlameSpan<'a> {
+    name: String,
+    parent: Option<&'a str>,
+    duration_ms: f64,
+}
+
+/// Build folded stacks from a trace's spans, one line per span with its self
+/// time (duration minus direct children). Returns None when no span carries a
+/// `spanId`, i.e. there is no hierarchy to fold.
+fn fold_stacks(spans: &[serde_json::Value]) -> Option<String> {
+    let mut nodes: HashMap<&str, FlameSpan> = HashMap::new();
+    for span in spans {
+        let id = match span.get("spanId").and_then(|v| v.as_str()) {
+            Some(id) if !id.is_empty() => id,
+            _ => continue,
+        };
+        let name = span
+            .get("name")
+            .and_then(|v| v.as_str())
+            .unwrap_or("unknown");
+        nodes.insert(
+            id,
+            FlameSpan {
+                // Frames are ';'-separated and the value follows the last space
+                name: name.replace(';', ":").replace(' ', "_"),
+                parent: span
+                    .get("parentSpanId")
+                    .and_then(|v| v.as_str())
+                    .filter(|p| !p.is_empty()),
+                duration_ms: span
+                    .get("durationMs")
+                    .and_then(|v| v.as_f64())
+                    .unwrap_or(0.0),
+            },
+        );
+    }
+    if nodes.is_empty() {
+        return None;
+    }
+
+    let mut child_time: HashMap<&str, f64> = HashMap::new();
+    for node in nodes.values() {
+        if let Some(parent) = node.parent.filter(|p| nodes.contains_key(p)) {
+            *child_time.entry(parent).or_default() += node.duration_ms;
+        }
+    }
+
+    let mut lines: Vec<String> = nodes
+        .iter()
+        .map(|(id, node)| {
+            let self_ms = (node.duration_ms - child_time.get(id).copied().unwrap_or(0.0)).max(0.0);
+            format!(
+                "{} {}",
+                stack_path(&nodes, id),
+                (self_ms * 1000.0).round() as u64
+            )
+        })
+        .collect();
+    lines.sort();
+
+    let mut out = lines.join("\n");
+    out.push('\n');
+    Some(out)
+}
+
+/// Root-to-leaf frame path for a span. Parents missing from the trace make the
+/// span a root; the depth bound guards against cyclic parent links.
+fn stack_path(nodes: &HashMap<&str, FlameSpan>, id: &str) -> String {
+    let mut frames = Vec::new();
+    let mut current = Some(id);
+    while let Some(cur) = current {
+        let node = match nodes.get(cur) {
+            Some(n) => n,
+            None => break,
+        };
+        frames.push(node.name.as_str());
+        if frames.len() > nodes.len() {
+            break;
+        }
+        current = node.parent;
+    }
+    frames.reverse();
+    frames.join(";")
+}
@@ -63,14 +63,19 @@ export function useSSE<T>({ table, maxItems = 1000, paused = false, parse }: Use
       }
     })
 
-    // Server sends "event: update" for actual data
+    // Server sends "event: update" for actual data: one record, or an array
+    // of records when the table is busy and `sseCoalescing` is enabled
     es.addEventListener('update', (event: MessageEvent) => {
       if (pausedRef.current) return
       try {
-        const parsed = parse ? parse(event.data) : JSON.parse(event.data) as T
-        if (parsed) {
+        const data = JSON.parse(event.data)
+        const records: unknown[] = Array.isArray(data) ? data : [data]
+        const parsed = records
+          .map(r => parse ? parse(JSON.stringify(r)) : r as T)
+          .filter((r): r is T => r != null)
+        if (parsed.length > 0) {
           setItems(prev => {
-            const next = [...prev, parsed]
+            const next = [...prev, ...parsed]
             return next.length > maxItems ? next.slice(-maxItems) : next
           })
         }
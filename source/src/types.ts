@@ -5,10 +5,17 @@ export interface LogEntry {
   target: string
   message: string
   fields?: string
+  seq?: number
+  writerId?: string
+  expiresAt?: string
+  repeatCount?: number
 }
 
 export interface SpanEntry {
   id: string
+  traceId?: string
+  spanId?: string
+  parentSpanId?: string
   name: string
   target: string
   level: string
@@ -16,6 +23,9 @@ export interface SpanEntry {
   endTime?: string
   durationMs?: number
   fields?: string
+  seq?: number
+  writerId?: string
+  expiresAt?: string
 }
 
 export interface MetricEntry {
@@ -24,6 +34,14 @@ export interface MetricEntry {
   value: number
   attributes?: string
   timestamp: string
+  min?: number
+  max?: number
+  sum?: number
+  count?: number
+  windowStart?: string
+  windowEnd?: string
+  seq?: number
+  writerId?: string
 }
 
 export interface TelemetryStatus {